@@ -0,0 +1,50 @@
+//! Encode/decode throughput and wire size of `SignedChat` frames, JSON against CBOR.
+//!
+//! Run with `cargo bench --bench codec`; the wire sizes are printed before the timings.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use p2p_chat::chat::room::Room;
+use p2p_chat::crypto::CryptoManager;
+use p2p_chat::network::codec::Codec;
+use p2p_chat::network::tcp::encode_message;
+use p2p_chat::peer::{NetworkMessage, RoomMessage};
+
+/// A signed chat message of `len` characters
+fn signed_chat(len: usize) -> NetworkMessage {
+    let crypto = CryptoManager::new("bench-peer".to_string(), "Bench".to_string());
+    let content = "x".repeat(len);
+    NetworkMessage::SignedChat(crypto.sign_message(&content, 1_700_000_000).unwrap())
+}
+
+fn codecs(c: &mut Criterion) {
+    let lobby = Room::new("lobby");
+    for len in [16, 256, 4096] {
+        let msg = signed_chat(len);
+        let mut encode = c.benchmark_group(format!("encode/{len}"));
+        for codec in [Codec::Json, Codec::Cbor] {
+            let bytes = encode_message(codec, &lobby, &msg).unwrap();
+            println!(
+                "SignedChat with {len} characters: {codec} {} bytes",
+                bytes.len()
+            );
+            encode.throughput(Throughput::Bytes(bytes.len() as u64));
+            encode.bench_with_input(BenchmarkId::from_parameter(codec), &msg, |b, msg| {
+                b.iter(|| encode_message(codec, &lobby, black_box(msg)).unwrap())
+            });
+        }
+        encode.finish();
+
+        let mut decode = c.benchmark_group(format!("decode/{len}"));
+        for codec in [Codec::Json, Codec::Cbor] {
+            let bytes = encode_message(codec, &lobby, &msg).unwrap();
+            decode.throughput(Throughput::Bytes(bytes.len() as u64));
+            decode.bench_with_input(BenchmarkId::from_parameter(codec), &bytes, |b, bytes| {
+                b.iter(|| Codec::decode::<RoomMessage>(black_box(bytes)).unwrap())
+            });
+        }
+        decode.finish();
+    }
+}
+
+criterion_group!(benches, codecs);
+criterion_main!(benches);
@@ -0,0 +1,352 @@
+//! Builder module: Configures a `Peer` for embedding and runs it without the console.
+//!
+//! `PeerBuilder` collects the settings the command line offers (name, port, identity,
+//! discovery backends, rooms, invites, bootstrap peers, gossip, wire codecs and the data
+//! directory), plus rate limits, and builds a `Peer`. `PeerBuilder::start` runs it headless
+//! in the background and returns a `PeerHandle` to send messages, watch `ChatEvent`s, list
+//! peers and shut it down; the binary's own CLI and console display are never started.
+
+use crate::chat::event::ChatEvent;
+use crate::chat::moderation::Moderation;
+use crate::chat::net::discovery::DiscoveryKind;
+use crate::chat::net::limits::{Limits, RateLimiter};
+use crate::chat::room::RoomRegistry;
+use crate::chat::Peer;
+use crate::crypto::identity::Identity;
+use crate::crypto::invite::Invite;
+use crate::error::ChatError;
+use crate::network::codec::Codec;
+use crate::network::command::{HandlerRegistry, MessageHandler};
+use crate::network::protocol::{Hello, ProtocolState};
+use crate::network::transport::Transport;
+use crate::peer::PeerInfo;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+/// Settings for a new `Peer`
+#[derive(Default)]
+pub struct PeerBuilder {
+    name: String,
+    port: u16,
+    identity: Option<Identity>,
+    data_dir: Option<PathBuf>,
+    discovery: Option<Vec<DiscoveryKind>>,
+    rooms: Vec<String>,
+    invites: Vec<String>,
+    bootstrap_peers: Vec<String>,
+    gossip_ttl: Option<u8>,
+    codecs: Option<Vec<Codec>>,
+    limits: Option<Limits>,
+    transport: Option<Arc<dyn Transport>>,
+    handlers: HandlerRegistry,
+}
+
+impl PeerBuilder {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            ..Self::default()
+        }
+    }
+
+    /// TCP port to listen on
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Peer id and signing key to use, instead of the one in the data directory or a
+    /// fresh one
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Directory where the identity (and other state) is kept across restarts
+    pub fn data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(dir.into());
+        self
+    }
+
+    /// Discovery backends to run, replacing the default ones; empty disables discovery
+    pub fn discovery(mut self, kinds: impl IntoIterator<Item = DiscoveryKind>) -> Self {
+        self.discovery = Some(kinds.into_iter().collect());
+        self
+    }
+
+    /// Join a public room; the first room is the current one
+    pub fn room(mut self, id: impl Into<String>) -> Self {
+        self.rooms.push(id.into());
+        self
+    }
+
+    /// Join a private room from an invite token. The first invite is the current room when
+    /// no public room was given.
+    pub fn invite(mut self, token: impl Into<String>) -> Self {
+        self.invites.push(token.into());
+        self
+    }
+
+    /// Connect to a peer by `host:port` at startup
+    pub fn bootstrap_peer(mut self, addr: impl Into<String>) -> Self {
+        self.bootstrap_peers.push(addr.into());
+        self
+    }
+
+    /// Relay messages for peers that are not directly reachable, for up to `ttl` hops
+    pub fn gossip(mut self, ttl: u8) -> Self {
+        self.gossip_ttl = Some(ttl);
+        self
+    }
+
+    /// Wire codecs to offer, preferred first, instead of CBOR then JSON. Offering only
+    /// `Codec::Json` keeps every frame readable, for debugging.
+    pub fn codecs(mut self, codecs: impl IntoIterator<Item = Codec>) -> Self {
+        self.codecs = Some(codecs.into_iter().collect());
+        self
+    }
+
+    /// Flood protection settings, instead of the defaults
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Connect to other peers through `transport` instead of TCP, e.g. a simulated network
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Handle incoming messages of `kind` with `handler`: an application's own
+    /// `NetworkMessage::Custom` kind, or a built-in one to replace
+    pub fn handler(self, kind: impl Into<String>, handler: impl MessageHandler + 'static) -> Self {
+        self.handlers.register(kind, handler);
+        self
+    }
+
+    /// Build the peer without starting it
+    pub fn build(self) -> Result<Peer, ChatError> {
+        let identity = match (self.identity, &self.data_dir) {
+            (Some(identity), _) => identity,
+            (None, Some(dir)) => Identity::load_or_create(dir)?,
+            (None, None) => Identity::generate(),
+        };
+        let mut peer = Peer::with_identity(self.name, self.port, identity);
+        if let Some(dir) = &self.data_dir {
+            peer.moderation = Arc::new(Moderation::load(dir)?);
+        }
+        peer.data_dir = self.data_dir;
+        peer.bootstrap_peers = self.bootstrap_peers;
+        if let Some(discovery) = self.discovery {
+            peer.discovery = discovery;
+        }
+        if !self.rooms.is_empty() {
+            peer.rooms = Arc::new(RoomRegistry::new(&self.rooms)?);
+        }
+        for (i, token) in self.invites.iter().enumerate() {
+            let invite: Invite = token.parse()?;
+            let (room, _) = if i == 0 && self.rooms.is_empty() {
+                peer.rooms.join_private(&invite.room, invite.secret)?
+            } else {
+                peer.rooms.add_private(&invite.room, invite.secret)?
+            };
+            room.roles.set_founder(&invite.founder);
+            // Static discovery contacts the inviting member once we are listening
+            peer.bootstrap_peers.push(invite.bootstrap.to_string());
+        }
+        if let Some(ttl) = self.gossip_ttl {
+            peer.gossip.enable(ttl);
+        }
+        if let Some(codecs) = self.codecs {
+            let hello = Hello {
+                codecs,
+                ..Hello::local()
+            };
+            peer.protocols = Arc::new(ProtocolState::new(hello));
+        }
+        if let Some(limits) = self.limits {
+            peer.limits = Arc::new(RateLimiter::new(limits));
+        }
+        if let Some(transport) = self.transport {
+            peer.transport = transport;
+        }
+        peer.handlers = Arc::new(self.handlers);
+        Ok(peer)
+    }
+
+    /// Build the peer and run it headless in the background
+    pub fn start(self) -> Result<PeerHandle, ChatError> {
+        Ok(PeerHandle::spawn(self.build()?))
+    }
+}
+
+/// A peer running headless in the background
+pub struct PeerHandle {
+    peer: Peer,
+    task: JoinHandle<()>,
+}
+
+impl PeerHandle {
+    /// Run `peer` headless in the background
+    pub fn spawn(peer: Peer) -> Self {
+        let running = peer.clone();
+        let task = tokio::spawn(async move { running.run_headless().await });
+        Self { peer, task }
+    }
+
+    /// The running peer, for rooms, proposals and everything else
+    pub fn peer(&self) -> &Peer {
+        &self.peer
+    }
+
+    /// Send a signed message to the current room
+    pub async fn send(&self, content: &str) -> Result<(), ChatError> {
+        self.peer.broadcast_message(content).await
+    }
+
+    /// Receive every `ChatEvent` from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<ChatEvent> {
+        self.peer.events.subscribe()
+    }
+
+    /// Peers of the current room
+    pub async fn peers(&self) -> Vec<PeerInfo> {
+        self.peer
+            .room()
+            .peers
+            .lock()
+            .await
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Leave the network and wait for the peer to stop
+    pub async fn shutdown(self) -> Result<(), ChatError> {
+        self.peer.shutdown().await;
+        self.task
+            .await
+            .map_err(|e| ChatError::Unknown(format!("Peer task failed: {e}")))
+    }
+
+    /// Stop the peer without saying goodbye, like a crash, and wait for it
+    pub async fn kill(self) -> Result<(), ChatError> {
+        self.peer.stop().await;
+        self.task
+            .await
+            .map_err(|e| ChatError::Unknown(format!("Peer task failed: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::room::Room;
+    use crate::chat::room::DEFAULT_ROOM;
+    use crate::crypto::invite::RoomSecret;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_build_applies_settings() {
+        let invite = Invite {
+            room: "secret".to_string(),
+            secret: RoomSecret::generate(),
+            founder: vec![7; 32],
+            bootstrap: SocketAddr::from(([192, 168, 1, 10], 9000)),
+        };
+        let identity = Identity::generate();
+        let peer = PeerBuilder::new("Bot")
+            .port(9100)
+            .identity(identity.clone())
+            .discovery([DiscoveryKind::Udp])
+            .room("dev")
+            .room("ops")
+            .invite(invite.to_string())
+            .gossip(3)
+            .build()
+            .unwrap();
+        assert_eq!(peer.name, "Bot");
+        assert_eq!(peer.port, 9100);
+        assert_eq!(peer.peer_id, identity.peer_id);
+        assert_eq!(peer.crypto_manager.get_public_key(), identity.public_key());
+        assert_eq!(peer.discovery, vec![DiscoveryKind::Udp]);
+        assert_eq!(peer.room().id, "dev");
+        assert!(peer.rooms.get("secret").unwrap().is_private());
+        assert_eq!(peer.bootstrap_peers, vec!["192.168.1.10:9000"]);
+
+        let defaults = PeerBuilder::new("Bot").build().unwrap();
+        assert_eq!(defaults.room().id, DEFAULT_ROOM);
+        assert_ne!(defaults.peer_id, identity.peer_id);
+    }
+
+    #[tokio::test]
+    async fn test_custom_messages_reach_their_handler() {
+        use crate::network::command::HandlerContext;
+        use crate::network::sim::SimNetwork;
+        use crate::peer::NetworkMessage;
+
+        let network = SimNetwork::default();
+        let (tx, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let bob = PeerBuilder::new("Bob")
+            .transport(network.node([10, 0, 0, 2]))
+            .discovery([])
+            .handler("Ping", move |_: HandlerContext, _: Room, message| {
+                let tx = tx.clone();
+                async move {
+                    if let NetworkMessage::Custom { payload, .. } = message {
+                        let _ = tx.send(payload);
+                    }
+                    Ok(())
+                }
+            })
+            .start()
+            .unwrap();
+        let alice = PeerBuilder::new("Alice")
+            .transport(network.node([10, 0, 0, 1]))
+            .discovery([])
+            .start()
+            .unwrap();
+        while alice.peers().await.is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let ping = serde_json::json!({ "seq": 1 });
+        alice
+            .peer()
+            .broadcast_custom("Ping", ping.clone())
+            .await
+            .unwrap();
+        let payload = tokio::time::timeout(std::time::Duration::from_secs(2), received.recv())
+            .await
+            .unwrap();
+        assert_eq!(payload, Some(ping));
+        alice.shutdown().await.unwrap();
+        bob.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_headless_peer_shuts_down() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let handle = PeerBuilder::new("Bot")
+            .port(port)
+            .discovery([])
+            .start()
+            .unwrap();
+        while tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .is_err()
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(handle.peers().await.is_empty());
+        handle.shutdown().await.unwrap();
+        assert!(std::net::TcpListener::bind(("0.0.0.0", port)).is_ok());
+    }
+}
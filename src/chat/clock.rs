@@ -0,0 +1,151 @@
+//! Clock module: Hybrid logical clocks (HLC) for causal message ordering.
+//!
+//! Wall-clock timestamps alone cannot order chat messages: peers' clocks disagree, and a
+//! reply can arrive before the message it answers. A hybrid logical clock pairs the
+//! physical time with a logical counter that is advanced whenever a remote timestamp is
+//! observed, so a message always carries a larger timestamp than every message its author
+//! had seen when writing it.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Mutex;
+
+/// Remote clocks further ahead of ours than this are not allowed to drag our clock forward
+pub const MAX_CLOCK_DRIFT_MS: u64 = 60_000;
+
+/// A hybrid logical clock timestamp.
+///
+/// Ordering is by physical component, then logical counter, then node id as tie-breaker,
+/// which gives a total order consistent with causality.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct HlcTimestamp {
+    /// Physical component in milliseconds since the UNIX epoch
+    pub wall_ms: u64,
+    /// Logical counter for events within the same millisecond
+    pub logical: u32,
+    /// Peer that produced the timestamp
+    pub node_id: String,
+}
+
+impl fmt::Display for HlcTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}@{}", self.wall_ms, self.logical, self.node_id)
+    }
+}
+
+/// A peer's hybrid logical clock
+pub struct HybridClock {
+    node_id: String,
+    /// Last issued (wall_ms, logical) pair
+    last: Mutex<(u64, u32)>,
+}
+
+impl HybridClock {
+    /// Create a clock for the given peer
+    pub fn new(node_id: String) -> Self {
+        Self {
+            node_id,
+            last: Mutex::new((0, 0)),
+        }
+    }
+
+    /// Timestamp a local event (e.g. sending a message)
+    pub fn now(&self) -> HlcTimestamp {
+        self.tick_at(physical_now_ms())
+    }
+
+    /// Merge a remote timestamp into the clock (e.g. on receiving a message)
+    pub fn observe(&self, remote: &HlcTimestamp) -> HlcTimestamp {
+        self.observe_at(remote, physical_now_ms())
+    }
+
+    fn tick_at(&self, physical: u64) -> HlcTimestamp {
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        *last = if physical > last.0 {
+            (physical, 0)
+        } else {
+            (last.0, last.1 + 1)
+        };
+        self.stamp(*last)
+    }
+
+    fn observe_at(&self, remote: &HlcTimestamp, physical: u64) -> HlcTimestamp {
+        if remote.wall_ms > physical + MAX_CLOCK_DRIFT_MS {
+            eprintln!(
+                "⚠️  Ignoring clock from {}: {} ms ahead of ours",
+                remote.node_id,
+                remote.wall_ms - physical
+            );
+            return self.tick_at(physical);
+        }
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        let wall = last.0.max(remote.wall_ms).max(physical);
+        let logical = if wall == last.0 && wall == remote.wall_ms {
+            last.1.max(remote.logical) + 1
+        } else if wall == last.0 {
+            last.1 + 1
+        } else if wall == remote.wall_ms {
+            remote.logical + 1
+        } else {
+            0
+        };
+        *last = (wall, logical);
+        self.stamp(*last)
+    }
+
+    fn stamp(&self, (wall_ms, logical): (u64, u32)) -> HlcTimestamp {
+        HlcTimestamp {
+            wall_ms,
+            logical,
+            node_id: self.node_id.clone(),
+        }
+    }
+}
+
+fn physical_now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_ticks_are_monotonic() {
+        let clock = HybridClock::new("a".to_string());
+        let t1 = clock.tick_at(1000);
+        let t2 = clock.tick_at(1000);
+        let t3 = clock.tick_at(999); // physical clock went backwards
+        assert!(t1 < t2);
+        assert!(t2 < t3);
+        assert_eq!(t3.wall_ms, 1000);
+    }
+
+    #[test]
+    fn test_reply_orders_after_observed_message() {
+        // Bob's physical clock lags Alice's by 500 ms
+        let alice = HybridClock::new("alice".to_string());
+        let bob = HybridClock::new("bob".to_string());
+
+        let question = alice.tick_at(10_500);
+        bob.observe_at(&question, 10_000);
+        let reply = bob.tick_at(10_001);
+
+        assert!(question < reply);
+    }
+
+    #[test]
+    fn test_far_future_clock_is_ignored() {
+        let clock = HybridClock::new("a".to_string());
+        let remote = HlcTimestamp {
+            wall_ms: 1_000 + MAX_CLOCK_DRIFT_MS + 1,
+            logical: 0,
+            node_id: "evil".to_string(),
+        };
+        let observed = clock.observe_at(&remote, 1_000);
+        assert_eq!(observed.wall_ms, 1_000);
+    }
+}
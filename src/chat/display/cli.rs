@@ -5,51 +5,114 @@
 //! listing peers, sending messages, and quitting the application. Additionally, it manages the
 //! broadcasting of exit signals to all connected peers when a user decides to quit.
 
+use crate::chat::roles::Role;
+use crate::chat::room::Room;
 use crate::chat::Peer;
+use crate::crypto::invite::INVITE_PREFIX;
+use crate::crypto::threshold::{ProposalAction, VotingRule};
 use crate::error::ChatError;
-use crate::peer::NetworkMessage;
-use serde_json;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::TcpStream;
+use crate::network::protocol::{FEATURES, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, SOFTWARE};
+use crate::network::tcp::{Outgoing, SEND_TIMEOUT};
+use crate::peer::{NetworkMessage, PeerInfo};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
 use hex;
 
 pub async fn broadcast_exit(peer: &Peer) -> Result<(), ChatError> {
+    for room in peer.rooms.all() {
+        broadcast_room_exit(peer, &room).await?;
+    }
+    Ok(())
+}
+
+/// Tell the peers of a single room that we left it
+pub async fn broadcast_room_exit(peer: &Peer, room: &Room) -> Result<(), ChatError> {
     let exit_msg = NetworkMessage::Exit(peer.peer_id.clone());
-    let msg_bytes = serde_json::to_vec(&exit_msg)?;
-    let peers = peer.peers.lock().await;
-    for peer in peers.values() {
-        if let Ok(mut stream) = TcpStream::connect((peer.ip, peer.port)).await {
-            let _ = stream.write_all(&msg_bytes).await;
-            println!("Quit broadcasted to {} ({})", peer.name, peer.id);
+    let mut outgoing = Outgoing::new(room, &exit_msg);
+    let transport = peer.transport.clone();
+    let protocols = peer.protocols.clone();
+    // Not under the lock: a peer that does not answer must not hold up the room
+    let targets: Vec<PeerInfo> = room.peers.lock().await.values().cloned().collect();
+    for target in targets {
+        if peer
+            .moderation
+            .blocks_peer(&target.id, &peer.crypto_manager)
+            .await
+        {
+            continue;
+        }
+        let msg_bytes = outgoing.encode(protocols.codec_for(&target.id))?;
+        let sent = tokio::time::timeout(SEND_TIMEOUT, async {
+            let mut stream = transport.connect(target.socket_addr()).await?;
+            stream.write_all(msg_bytes).await
+        });
+        if let Ok(Ok(())) = sent.await {
+            println!(
+                "Quit of #{} broadcasted to {} ({})",
+                room.id, target.name, target.id
+            );
         }
     }
     Ok(())
 }
 
+/// Read stdin lines on a dedicated thread. A blocking read on the runtime's own pool would
+/// keep the runtime from shutting down while waiting for input.
+fn read_stdin_lines() -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel(16);
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if tx.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
 pub async fn start_cli_handler(peer: &Peer) -> Result<(), ChatError> {
     println!("\n📋 Commands:");
-    println!("  /list    - List discovered peers");
+    println!("  /list    - List discovered peers in the current room");
+    println!("  /join <room|invite> - Join a room (or switch to it), or a private room");
+    println!("  /part <room> - Leave a room");
+    println!("  /rooms   - List joined rooms");
+    println!("  /private <room> - Create a private room and print an invite to it");
+    println!("  /invite  - Print an invite to the current private room");
+    println!("  /connect <host:port> - Connect to a peer by address");
+    println!("  /block [peer] - Drop all traffic of a peer (name, id or key), or list blocked");
+    println!("  /unblock <peer> - Lift a block");
+    println!("  /mute [peer] - Hide a peer's chat but keep its votes, or list muted");
+    println!("  /unmute <peer> - Show a muted peer's chat again");
+    println!("  /ignore-unsigned [on|off] - Drop unsigned messages from everyone");
     println!("  /msg <message> - Send signed message to all peers");
     println!("  /unsigned <message> - Send unsigned message to all peers");
     println!("  /crypto  - Show cryptographic information");
     println!("  /propose <description> - Propose secure-only messaging upgrade");
+    println!("  /expel <peer> [reason] - Propose to expel a peer from the current room");
+    println!("  /role <peer|everyone> <role> - Give a role (owner, moderator, member, read-only)");
+    println!("  /propose-role <peer|everyone> <role> - Propose by vote to give a role");
+    println!("  /rule [rule] - Show or set the voting rule of your proposals (majority, 2/3, ...)");
     println!("  /vote <proposal_id> <approve|reject> - Vote on upgrade proposal");
     println!("  /proposals - List active upgrade proposals");
-    println!("  /status  - Show security status and proposals");
+    println!("  /status  - Show security status, proposals and rate limits");
+    println!("  /history - Show signed message history (synced from peers)");
     println!("  /quit    - Quit the application");
     println!("  Just type any message to broadcast it (signed by default)!\n");
 
-    let stdin = tokio::io::stdin();
-    let mut reader = BufReader::new(stdin);
-    let mut line = String::new();
-
+    let mut lines = read_stdin_lines();
     loop {
         print!("💬 ");
         std::io::Write::flush(&mut std::io::stdout()).unwrap();
-        line.clear();
-        if reader.read_line(&mut line).await? == 0 {
+        let line = tokio::select! {
+            line = lines.recv() => line,
+            _ = peer.cancel.cancelled() => None,
+        };
+        let Some(line) = line else {
             break;
-        }
+        };
         let input = line.trim();
         if input.is_empty() {
             continue;
@@ -65,29 +128,161 @@ pub async fn start_cli_handler(peer: &Peer) -> Result<(), ChatError> {
         let args = parts.next().unwrap_or("");
         match command {
             "/quit" => {
-                if let Err(e) = crate::chat::display::cli::broadcast_exit(peer).await {
-                    eprintln!("Error broadcasting exit: {e}");
-                }
+                // Returning shuts the peer down, which tells every room we left
                 println!("\u{1F44B} Now Goodbye!");
-                peer.shutdown().await;
+                break;
             }
             "/list" => {
-                let peers = peer.peers.lock().await;
+                let room = peer.room();
+                let protocols = &peer.protocols;
+                let (crypto_manager, moderation) = (&peer.crypto_manager, &peer.moderation);
+                let peers = room.peers.lock().await;
                 if peers.is_empty() {
-                    println!("📭 No peers discovered yet.");
+                    println!("📭 No peers discovered in #{} yet.", room.id);
                 } else {
-                    println!("👥 Discovered peers:");
+                    println!("👥 Discovered peers in #{}:", room.id);
                     for peer in peers.values() {
                         if !peer.is_valid() {
                             println!("  - Invalid peer: {peer:?}");
                             continue;
                         }
+                        let protocol = match protocols.get(&peer.id) {
+                            Some(capabilities) => format!(" [{capabilities}]"),
+                            None => String::new(),
+                        };
+                        let key = crypto_manager.public_key_of(&peer.id).await;
+                        let role = room.roles.role_of(key.as_deref());
+                        let status = match key {
+                            Some(key) if moderation.is_blocked(&key) => " ⛔ blocked",
+                            Some(key) if moderation.is_muted(&key) => " 🔇 muted",
+                            _ => "",
+                        };
                         println!(
-                            "  - {} ({}) at {}:{}",
+                            "  - {} ({}) at {}:{}, {role}{protocol}{status}",
                             peer.name, peer.id, peer.ip, peer.port
                         );
                     }
                 }
+                let own_role = room.roles.role_of(Some(&crypto_manager.get_public_key()));
+                println!("👤 You are {own_role} in #{}", room.id);
+            }
+            "/join" => {
+                let target = args.trim();
+                if target.is_empty() {
+                    println!("❌ Usage: /join <room|invite>");
+                    continue;
+                }
+                let joined = if target.starts_with(INVITE_PREFIX) {
+                    peer.join_invite(target).await
+                } else {
+                    peer.join_room(target).await
+                };
+                match joined {
+                    Ok(room) if room.is_private() => {
+                        println!("🔒 Now in private room #{}", room.id)
+                    }
+                    Ok(room) => println!("🏠 Now in #{}", room.id),
+                    Err(e) => eprintln!("❌ Failed to join: {e}"),
+                }
+            }
+            "/private" => {
+                if args.trim().is_empty() {
+                    println!("❌ Usage: /private <room>");
+                    continue;
+                }
+                match peer.create_private_room(args.trim()) {
+                    Ok(invite) => {
+                        println!("🔒 Created private room #{}", invite.room);
+                        println!("📨 Share this invite with the people you want in: {invite}");
+                    }
+                    Err(e) => eprintln!("❌ Failed to create private room: {e}"),
+                }
+            }
+            "/invite" => match peer.invite(&peer.room()) {
+                Ok(invite) => println!("📨 Invite to #{}: {invite}", invite.room),
+                Err(e) => eprintln!("❌ Cannot create an invite: {e}"),
+            },
+            "/part" => {
+                let id = if args.trim().is_empty() {
+                    peer.room().id
+                } else {
+                    args.trim().to_string()
+                };
+                match peer.part_room(&id).await {
+                    Ok(room) => println!("👋 Left #{}, now in #{}", room.id, peer.room().id),
+                    Err(e) => eprintln!("❌ Failed to leave {id}: {e}"),
+                }
+            }
+            "/rooms" => {
+                let current = peer.room().id;
+                println!("🏠 Joined rooms:");
+                for room in peer.rooms.all() {
+                    let marker = if room.id == current { "*" } else { " " };
+                    let lock = if room.is_private() { " 🔒" } else { "" };
+                    println!(
+                        "  {marker} #{}{lock} ({} peer(s))",
+                        room.id,
+                        room.peers.lock().await.len()
+                    );
+                }
+            }
+            "/connect" => {
+                if args.is_empty() {
+                    println!("❌ Usage: /connect <host:port>");
+                    continue;
+                }
+                match peer.connect(args.trim()).await {
+                    Ok(info) => println!("✅ Connected to {} ({})", info.name, info.id),
+                    Err(e) => eprintln!("❌ Failed to connect to {args}: {e}"),
+                }
+            }
+            "/block" | "/mute" if args.trim().is_empty() => {
+                let lists = peer.moderation.lists();
+                let (label, list) = match command {
+                    "/block" => ("Blocked", lists.blocked),
+                    _ => ("Muted", lists.muted),
+                };
+                if list.is_empty() {
+                    println!("📭 Nobody is {}", label.to_lowercase());
+                } else {
+                    println!("🚫 {label} peers:");
+                    for (key, name) in list {
+                        println!("  - {name} (key {})", &key[..16]);
+                    }
+                }
+            }
+            "/block" | "/unblock" | "/mute" | "/unmute" => {
+                let who = args.trim();
+                if who.is_empty() {
+                    println!("❌ Usage: {command} <peer>");
+                    continue;
+                }
+                let (result, done) = match command {
+                    "/block" => (peer.block(who).await, "⛔ Blocked"),
+                    "/unblock" => (peer.unblock(who).await, "✅ Unblocked"),
+                    "/mute" => (peer.mute(who).await, "🔇 Muted"),
+                    _ => (peer.unmute(who).await, "🔊 Unmuted"),
+                };
+                match result {
+                    Ok(name) => println!("{done} {name}"),
+                    Err(e) => eprintln!("❌ {e}"),
+                }
+            }
+            "/ignore-unsigned" => {
+                let ignore = match args.trim() {
+                    "" => !peer.moderation.ignores_unsigned(),
+                    "on" => true,
+                    "off" => false,
+                    _ => {
+                        println!("❌ Usage: /ignore-unsigned [on|off]");
+                        continue;
+                    }
+                };
+                match peer.moderation.set_ignore_unsigned(ignore) {
+                    Ok(()) if ignore => println!("🔕 Ignoring unsigned messages"),
+                    Ok(()) => println!("🔔 Showing unsigned messages again"),
+                    Err(e) => eprintln!("❌ Failed to save: {e}"),
+                }
             }
             "/crypto" => {
                 let identity = peer.crypto_manager.get_identity();
@@ -113,6 +308,71 @@ pub async fn start_cli_handler(peer: &Peer) -> Result<(), ChatError> {
                     Err(e) => eprintln!("❌ Failed to create upgrade proposal: {e}"),
                 }
             }
+            "/expel" => {
+                let (who, reason) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+                if who.is_empty() {
+                    println!("❌ Usage: /expel <peer> [reason]");
+                    continue;
+                }
+                match peer.propose_expulsion(who, reason).await {
+                    Ok(proposal_id) => {
+                        println!("✅ Expulsion proposal created; vote on it too");
+                        println!("📋 Proposal ID: {proposal_id}");
+                    }
+                    Err(e) => eprintln!("❌ Failed to propose expulsion: {e}"),
+                }
+            }
+            "/role" | "/propose-role" => {
+                let parts: Vec<&str> = args.split_whitespace().collect();
+                let [who, role] = parts[..] else {
+                    println!(
+                        "❌ Usage: {command} <peer|everyone> <owner|moderator|member|read-only>"
+                    );
+                    continue;
+                };
+                let role = match role.parse::<Role>() {
+                    Ok(role) => role,
+                    Err(e) => {
+                        println!("❌ {e}");
+                        continue;
+                    }
+                };
+                if command == "/role" {
+                    match peer.grant_role(who, role).await {
+                        Ok(subject) => println!("👑 {subject} is now {role}"),
+                        Err(e) => eprintln!("❌ Failed to give {who} the {role} role: {e}"),
+                    }
+                    continue;
+                }
+                match peer.propose_role(who, role).await {
+                    Ok(proposal_id) => {
+                        println!("✅ Role proposal created; vote on it too");
+                        println!("📋 Proposal ID: {proposal_id}");
+                    }
+                    Err(e) => eprintln!("❌ Failed to propose role: {e}"),
+                }
+            }
+            "/rule" => {
+                let rule = args.trim();
+                if rule.is_empty() {
+                    println!(
+                        "🗳️  Proposals in #{} are decided by {}",
+                        peer.room().id,
+                        peer.voting_rule().await
+                    );
+                    continue;
+                }
+                match rule.parse::<VotingRule>() {
+                    Ok(rule) => {
+                        peer.set_voting_rule(rule).await;
+                        println!(
+                            "🗳️  Your proposals in #{} are now decided by {rule}",
+                            peer.room().id
+                        );
+                    }
+                    Err(e) => println!("❌ {e}"),
+                }
+            }
             "/vote" => {
                 let parts: Vec<&str> = args.split_whitespace().collect();
                 if parts.len() != 2 {
@@ -149,8 +409,17 @@ pub async fn start_cli_handler(peer: &Peer) -> Result<(), ChatError> {
                     for proposal in proposals {
                         println!("  📋 ID: {}", proposal.proposal_id);
                         println!("    Proposed by: {} ({})", proposal.proposer_name, proposal.proposer_id);
+                        match &proposal.action {
+                            ProposalAction::Expel(target) => {
+                                println!("    Expels: {} ({})", target.name, target.peer_id);
+                            }
+                            ProposalAction::AssignRole { subject, role } => {
+                                println!("    Makes: {subject} {role}");
+                            }
+                            ProposalAction::SecureOnly => {}
+                        }
                         println!("    Description: {}", proposal.description);
-                        println!("    Required: {}/{} approvals", proposal.required_approvals, proposal.total_peers);
+                        println!("    Required: {}", proposal.threshold());
                         println!("    Created: {}", proposal.timestamp);
                         println!();
                     }
@@ -160,9 +429,66 @@ pub async fn start_cli_handler(peer: &Peer) -> Result<(), ChatError> {
                 let secure_enabled = peer.is_secure_only_enabled().await;
                 let proposals = peer.get_active_proposals().await;
                 
-                println!("🔐 Security Status:");
+                let room = peer.room();
+                println!(
+                    "🔐 Security Status of #{}{}:",
+                    room.id,
+                    if room.is_private() { " (private 🔒)" } else { "" }
+                );
+                println!("  Joined rooms: {}", peer.rooms.ids().join(", "));
+                println!(
+                    "  Protocol: {} to {} ({SOFTWARE}), features: {}",
+                    MIN_PROTOCOL_VERSION,
+                    PROTOCOL_VERSION,
+                    FEATURES
+                        .iter()
+                        .map(|feature| format!("{feature:?}"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
                 println!("  Secure-only messaging: {}", if secure_enabled { "✅ ENABLED" } else { "❌ DISABLED" });
                 println!("  Active proposals: {}", proposals.len());
+                println!("  Voting rule: {}", peer.voting_rule().await);
+                if peer.gossip.is_enabled() {
+                    println!(
+                        "  Gossip relay: ✅ ENABLED (TTL {}, {} relayed)",
+                        peer.gossip.ttl(),
+                        peer.gossip.relayed_count()
+                    );
+                } else {
+                    println!("  Gossip relay: ❌ DISABLED");
+                }
+                let limits = peer.limits.limits();
+                println!(
+                    "  Rate limits per source: connections {}, messages {}, proposals {}",
+                    limits.connections, limits.messages, limits.proposals
+                );
+                println!(
+                    "  Connections: {}/{} active ({} per source), {} strikes ban a source for {}s",
+                    peer.limits.active_connections(),
+                    limits.max_connections,
+                    limits.max_connections_per_source,
+                    limits.strikes_to_ban,
+                    limits.ban_duration.as_secs()
+                );
+                let offenders = peer.limits.offenders();
+                if !offenders.is_empty() {
+                    println!("\n🛡️  Offenders:");
+                    for offender in offenders {
+                        match offender.banned_for {
+                            Some(left) => println!(
+                                "  ⛔ {}: {} dropped, banned for {}s",
+                                offender.ip,
+                                offender.dropped,
+                                left.as_secs()
+                            ),
+                            None => println!(
+                                "  ⚠️  {}: {} dropped, {} strike(s)",
+                                offender.ip, offender.dropped, offender.strikes
+                            ),
+                        }
+                    }
+                }
                 
                 if !proposals.is_empty() {
                     println!("\n📋 Active Proposals:");
@@ -171,8 +497,22 @@ pub async fn start_cli_handler(peer: &Peer) -> Result<(), ChatError> {
                         let approval_count = votes.iter().filter(|v| v.approved).count();
                         let rejection_count = votes.iter().filter(|v| !v.approved).count();
                         
-                        println!("  📋 {}: {}/{} approvals, {} rejections", 
-                            proposal.proposal_id, approval_count, proposal.required_approvals, rejection_count);
+                        println!("  📋 {}: {} approvals of {}, {} rejections", 
+                            proposal.proposal_id, approval_count, proposal.threshold(), rejection_count);
+                    }
+                }
+            }
+            "/history" => {
+                let messages = peer.room().history.all_messages().await;
+                if messages.is_empty() {
+                    println!("📭 No message history yet");
+                } else {
+                    println!("📜 Message history ({} message(s)):", messages.len());
+                    for message in messages {
+                        println!(
+                            "  [{}] {}: {}",
+                            message.timestamp, message.signer_name, message.message
+                        );
                     }
                 }
             }
@@ -1,26 +1,165 @@
 //! Message display module: Responsible for displaying incoming messages to the user in the CLI.
 //!
-//! This module contains the `start_message_display` function, which listens for messages
-//! on a broadcast channel and prints them to the standard output. It is designed to be
+//! This module contains the `start_message_display` function, which subscribes to the
+//! peer's `ChatEvent`s and prints them to the standard output. It is designed to be
 //! run asynchronously, and it expects a reference to a `Peer` instance, which
-//! manages the underlying message sending and receiving.
+//! manages the underlying message sending and receiving. Chat messages arrive already in
+//! causal order, released by the peer's `CausalBuffer`. On shutdown the messages still held
+//! back are released and shown before it returns.
 
+use crate::chat::event::{ChatEvent, LeaveReason, Verification};
 use crate::chat::Peer;
+use crate::crypto::threshold::ProposalAction;
 use crate::error::ChatError;
 use tokio::sync::broadcast;
 
+/// Display text for an event
+pub fn render(event: &ChatEvent) -> String {
+    let room = event.room();
+    match event {
+        ChatEvent::MessageReceived {
+            from_name,
+            content,
+            verification,
+            from_history,
+            ..
+        } => match verification {
+            Verification::Verified if *from_history => {
+                format!("#{room} 📜 {from_name} said (history, verified): {content}")
+            }
+            Verification::Verified => format!("#{room} 🔐 {from_name} says (verified): {content}"),
+            Verification::Unsigned => format!("#{room} 📝 {from_name} says (unsigned): {content}"),
+            Verification::Invalid => {
+                format!("#{room} ⚠️  {from_name} says (INVALID SIGNATURE): {content}")
+            }
+            Verification::Failed(e) => {
+                format!("#{room} ❓ {from_name} says (verification failed: {e}): {content}")
+            }
+        },
+        ChatEvent::HistorySynced { count, .. } => {
+            format!("#{room} 📜 Synced {count} message(s) from history")
+        }
+        ChatEvent::PeerJoined { peer, .. } => format!(
+            "#{room} 🔗 {} ({}) joined from {}",
+            peer.name,
+            peer.id,
+            peer.socket_addr()
+        ),
+        ChatEvent::PeerLeft { peer, reason, .. } => match reason {
+            LeaveReason::Exited => format!("#{room} ❌ {} ({}) left", peer.name, peer.id),
+            LeaveReason::Lost => format!(
+                "#{room} ❌ {} ({}) is no longer advertised and was removed",
+                peer.name, peer.id
+            ),
+        },
+        ChatEvent::ProposalCreated { proposal, .. } => format!(
+            "#{room} 🔐 {} proposed {}: {} (ID: {}, needs {})",
+            proposal.proposer_name,
+            match &proposal.action {
+                ProposalAction::SecureOnly => "secure messaging upgrade".to_string(),
+                ProposalAction::Expel(target) => format!("to expel {}", target.name),
+                ProposalAction::AssignRole { subject, role } => {
+                    format!("to make {subject} {role}")
+                }
+            },
+            proposal.description,
+            proposal.proposal_id,
+            proposal.threshold()
+        ),
+        ChatEvent::VoteCast { vote, .. } => format!(
+            "#{room} 🗳️  {} voted {} on upgrade proposal {}",
+            vote.voter_name,
+            if vote.approved {
+                "✅ APPROVED"
+            } else {
+                "❌ REJECTED"
+            },
+            vote.proposal_id
+        ),
+        ChatEvent::PartialSignatureReceived { partial, .. } => format!(
+            "#{room} 🔐 {} provided partial signature for proposal {}",
+            partial.signer_name, partial.proposal_id
+        ),
+        ChatEvent::PeerExpelled { expulsion, .. } => format!(
+            "#{room} 🚫 {} was expelled by vote ({} approvals)",
+            expulsion.expelled().map_or("?", |target| &target.name),
+            expulsion.approvals.len()
+        ),
+        ChatEvent::RoleChanged {
+            subject, role, by, ..
+        } => format!(
+            "#{room} 👑 {subject} is now {role} ({})",
+            by.as_ref()
+                .map_or("by vote".to_string(), |name| format!("by {name}"))
+        ),
+        ChatEvent::ModeChanged { secure_only, .. } => format!(
+            "#{room} 🔐 Secure-only messaging {}",
+            if *secure_only { "enabled" } else { "disabled" }
+        ),
+        ChatEvent::DeliveryFailed { peer, error, .. } => {
+            format!("#{room} ⚠️  Could not deliver to {}: {error}", peer.name)
+        }
+    }
+}
+
 pub async fn start_message_display(peer: &Peer) -> Result<(), ChatError> {
-    let mut receiver = peer.message_sender.subscribe();
+    let mut receiver = peer.events.subscribe();
     loop {
-        match receiver.recv().await {
-            Ok(message) => {
-                println!("\n📨 {message}");
-            }
-            Err(broadcast::error::RecvError::Closed) => break,
-            Err(broadcast::error::RecvError::Lagged(_)) => {
-                eprintln!("Message display lagged, continuing...");
+        tokio::select! {
+            received = receiver.recv() => match received {
+                Ok(event) => {
+                    println!("\n📨 {}", render(&event));
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    eprintln!("Message display lagged, continuing...");
+                }
+            },
+            _ = peer.cancel.cancelled() => {
+                peer.causal_buffer.drain().await;
+                while let Ok(event) = receiver.try_recv() {
+                    println!("\n📨 {}", render(&event));
+                }
+                break;
             }
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_shows_verification() {
+        let message = |verification, from_history| ChatEvent::MessageReceived {
+            room: "dev".to_string(),
+            from_id: "a".to_string(),
+            from_name: "Alice".to_string(),
+            content: "hi".to_string(),
+            verification,
+            from_history,
+        };
+        assert_eq!(
+            render(&message(Verification::Verified, false)),
+            "#dev 🔐 Alice says (verified): hi"
+        );
+        assert_eq!(
+            render(&message(Verification::Verified, true)),
+            "#dev 📜 Alice said (history, verified): hi"
+        );
+        assert_eq!(
+            render(&message(Verification::Unsigned, false)),
+            "#dev 📝 Alice says (unsigned): hi"
+        );
+        assert!(render(&message(Verification::Invalid, false)).contains("INVALID SIGNATURE"));
+        assert_eq!(
+            render(&ChatEvent::ModeChanged {
+                room: "dev".to_string(),
+                secure_only: true
+            }),
+            "#dev 🔐 Secure-only messaging enabled"
+        );
+    }
+}
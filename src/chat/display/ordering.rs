@@ -0,0 +1,197 @@
+//! Ordering module: Holds incoming chat messages back briefly and releases them in causal order.
+//!
+//! Messages are fanned out over separate connections, so a reply can reach us before the
+//! message it answers. Every chat message carries a hybrid logical clock timestamp; the
+//! `CausalBuffer` merges it into our own clock and keeps the message for a short hold-back
+//! window, then publishes them as events sorted by their timestamps. `start_causal_flush`
+//! releases them as their window passes, with or without a console display.
+
+use crate::chat::clock::{HlcTimestamp, HybridClock};
+use crate::chat::event::{ChatEvent, EventSender};
+use crate::chat::Peer;
+use crate::error::ChatError;
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// How long a message is held back waiting for causally earlier messages
+pub const HOLD_BACK: Duration = Duration::from_millis(300);
+
+/// How often the buffer is checked for messages ready to be shown
+pub const FLUSH_INTERVAL: Duration = Duration::from_millis(50);
+
+struct Pending {
+    clock: HlcTimestamp,
+    /// Arrival order, to keep the heap ordering total for identical clocks
+    seq: u64,
+    received: Instant,
+    event: ChatEvent,
+}
+
+// Pending messages are ordered by clock, then arrival
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (&self.clock, self.seq).cmp(&(&other.clock, other.seq))
+    }
+}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+/// Reorders message events by their hybrid logical clock
+pub struct CausalBuffer {
+    clock: Arc<HybridClock>,
+    events: EventSender,
+    pending: Mutex<BinaryHeap<Reverse<Pending>>>,
+    next_seq: AtomicU64,
+}
+
+impl CausalBuffer {
+    pub fn new(clock: Arc<HybridClock>, events: EventSender) -> Self {
+        Self {
+            clock,
+            events,
+            pending: Mutex::new(BinaryHeap::new()),
+            next_seq: AtomicU64::new(0),
+        }
+    }
+
+    /// Queue a message event. Messages without a clock (older peers) are published at once.
+    pub async fn push(&self, clock: Option<HlcTimestamp>, event: ChatEvent) {
+        let Some(clock) = clock else {
+            let _ = self.events.send(event);
+            return;
+        };
+        self.clock.observe(&clock);
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        self.pending.lock().await.push(Reverse(Pending {
+            clock,
+            seq,
+            received: Instant::now(),
+            event,
+        }));
+    }
+
+    /// Release every message whose hold-back window has passed, lowest clock first.
+    ///
+    /// A message is only released once it is the earliest pending one, so a message that
+    /// arrived late but is causally earlier still overtakes the ones waiting behind it.
+    pub async fn flush(&self) -> usize {
+        self.release(|p| p.received.elapsed() >= HOLD_BACK).await
+    }
+
+    /// Release every pending message regardless of the hold-back window
+    pub async fn drain(&self) -> usize {
+        self.release(|_| true).await
+    }
+
+    async fn release(&self, ready: impl Fn(&Pending) -> bool) -> usize {
+        let mut pending = self.pending.lock().await;
+        let mut released = 0;
+        while pending.peek().is_some_and(|Reverse(p)| ready(p)) {
+            if let Some(Reverse(p)) = pending.pop() {
+                let _ = self.events.send(p.event);
+                released += 1;
+            }
+        }
+        released
+    }
+}
+
+/// Release held-back messages as their window passes; everything left is released when
+/// the peer shuts down
+pub async fn start_causal_flush(peer: &Peer) -> Result<(), ChatError> {
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = flush.tick() => {
+                peer.causal_buffer.flush().await;
+            }
+            _ = peer.cancel.cancelled() => {
+                peer.causal_buffer.drain().await;
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::event::Verification;
+    use tokio::sync::broadcast;
+
+    fn message(content: &str) -> ChatEvent {
+        ChatEvent::MessageReceived {
+            room: "lobby".to_string(),
+            from_id: "a".to_string(),
+            from_name: "Alice".to_string(),
+            content: content.to_string(),
+            verification: Verification::Verified,
+            from_history: false,
+        }
+    }
+
+    async fn content(receiver: &mut broadcast::Receiver<ChatEvent>) -> String {
+        match receiver.recv().await.unwrap() {
+            ChatEvent::MessageReceived { content, .. } => content,
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+
+    fn stamp(wall_ms: u64, node: &str) -> HlcTimestamp {
+        HlcTimestamp {
+            wall_ms,
+            logical: 0,
+            node_id: node.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reply_received_first_is_shown_second() {
+        let (sender, mut receiver) = broadcast::channel(10);
+        let buffer = CausalBuffer::new(Arc::new(HybridClock::new("me".to_string())), sender);
+
+        buffer
+            .push(Some(stamp(2_000, "bob")), message("reply"))
+            .await;
+        buffer
+            .push(Some(stamp(1_000, "alice")), message("question"))
+            .await;
+        assert_eq!(buffer.drain().await, 2);
+
+        assert_eq!(content(&mut receiver).await, "question");
+        assert_eq!(content(&mut receiver).await, "reply");
+    }
+
+    #[tokio::test]
+    async fn test_messages_wait_for_hold_back() {
+        let (sender, mut receiver) = broadcast::channel(10);
+        let buffer = CausalBuffer::new(Arc::new(HybridClock::new("me".to_string())), sender);
+
+        buffer
+            .push(Some(stamp(1_000, "alice")), message("held"))
+            .await;
+        buffer.push(None, message("legacy")).await;
+        assert_eq!(buffer.flush().await, 0);
+        assert_eq!(content(&mut receiver).await, "legacy");
+
+        tokio::time::sleep(HOLD_BACK).await;
+        assert_eq!(buffer.flush().await, 1);
+        assert_eq!(content(&mut receiver).await, "held");
+    }
+}
@@ -0,0 +1,111 @@
+//! Event module: Typed events describing what happens in the chat.
+//!
+//! Handlers publish a `ChatEvent` on `Peer::events` instead of display text. The CLI
+//! (`display::message_display`) is just one subscriber that renders them; history tools,
+//! bots, other front ends and tests can subscribe and match on events directly.
+
+use crate::chat::roles::{Role, RoleSubject};
+use crate::crypto::threshold::{Decision, PartialSignature, UpgradeProposal, UpgradeVote};
+use crate::peer::PeerInfo;
+use serde::Deserialize;
+use tokio::sync::broadcast;
+
+/// Channel chat events are published on
+pub type EventSender = broadcast::Sender<ChatEvent>;
+
+/// How a received message was authenticated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    /// Signed, and the signature checks out against the signer's key
+    Verified,
+    /// Sent without a signature
+    Unsigned,
+    /// Signed, but the signature does not match
+    Invalid,
+    /// The signature could not be checked, e.g. the key does not match the known one
+    Failed(String),
+}
+
+/// Why a peer left a room
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaveReason {
+    /// The peer said goodbye (quit or left the room)
+    Exited,
+    /// Discovery stopped seeing the peer
+    Lost,
+}
+
+/// Something that happened in one of the joined rooms. The `room` field is the local
+/// room name.
+#[derive(Debug, Clone)]
+pub enum ChatEvent {
+    /// A chat message from another peer
+    MessageReceived {
+        room: String,
+        from_id: String,
+        from_name: String,
+        content: String,
+        verification: Verification,
+        /// Imported through history sync instead of received live
+        from_history: bool,
+    },
+    /// Missing messages were imported from a peer's history
+    HistorySynced { room: String, count: usize },
+    /// A peer was added to a room after a handshake
+    PeerJoined { room: String, peer: PeerInfo },
+    /// A peer was removed from a room
+    PeerLeft {
+        room: String,
+        peer: PeerInfo,
+        reason: LeaveReason,
+    },
+    /// A secure-only upgrade was proposed, by us or by another peer
+    ProposalCreated {
+        room: String,
+        proposal: UpgradeProposal,
+    },
+    /// A vote on an upgrade proposal, by us or by another peer
+    VoteCast { room: String, vote: UpgradeVote },
+    /// A peer contributed a partial signature to a proposal
+    PartialSignatureReceived {
+        room: String,
+        partial: PartialSignature,
+    },
+    /// A peer was expelled from a room by vote, now or before we joined
+    PeerExpelled { room: String, expulsion: Decision },
+    /// A peer, or everyone, was given a role in a room by `by` or by vote
+    RoleChanged {
+        room: String,
+        subject: RoleSubject,
+        role: Role,
+        by: Option<String>,
+    },
+    /// Secure-only messaging was switched on or off in a room
+    ModeChanged { room: String, secure_only: bool },
+    /// A message could not be delivered to a peer
+    DeliveryFailed {
+        room: String,
+        peer: PeerInfo,
+        error: String,
+    },
+}
+
+impl ChatEvent {
+    /// The room the event happened in
+    pub fn room(&self) -> &str {
+        match self {
+            ChatEvent::MessageReceived { room, .. }
+            | ChatEvent::HistorySynced { room, .. }
+            | ChatEvent::PeerJoined { room, .. }
+            | ChatEvent::PeerLeft { room, .. }
+            | ChatEvent::ProposalCreated { room, .. }
+            | ChatEvent::VoteCast { room, .. }
+            | ChatEvent::PartialSignatureReceived { room, .. }
+            | ChatEvent::PeerExpelled { room, .. }
+            | ChatEvent::RoleChanged { room, .. }
+            | ChatEvent::ModeChanged { room, .. }
+            | ChatEvent::DeliveryFailed { room, .. } => room,
+        }
+    }
+}
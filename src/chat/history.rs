@@ -0,0 +1,388 @@
+//! History module: Keeps the signed messages seen by this peer so late joiners can catch up.
+//!
+//! Messages are indexed per author. When two peers discover each other they exchange a
+//! compact summary of per-author digests over time buckets, and each side requests every
+//! author's messages from the first bucket where the digests differ, so holes in the middle
+//! of a log are found as well as missing tails. Answers come in batches with a cursor to
+//! continue from, followed while they bring new messages and for at most
+//! `MAX_HISTORY_ROUNDS` requests. Authors we blocked or muted are not asked for. Imported
+//! messages are verified again before they are stored, against the key we pinned for their
+//! author, so history converges across the LAN without trusting the peer that relayed it.
+
+use crate::crypto::SignedMessage;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Maximum number of messages kept per author
+pub const MAX_MESSAGES_PER_AUTHOR: usize = 1000;
+
+/// Maximum number of messages sent in a single history response
+pub const MAX_HISTORY_BATCH: usize = 500;
+
+/// Width in seconds of the time buckets digested in a history summary
+pub const HISTORY_BUCKET_SECS: u64 = 600;
+
+/// Maximum number of follow-up requests made to a peer in one history sync
+pub const MAX_HISTORY_ROUNDS: usize = 32;
+
+/// Messages from a single author, ordered by (timestamp, message id)
+type AuthorLog = BTreeMap<(u64, String), SignedMessage>;
+
+/// Per-author summary exchanged during history sync
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryMark {
+    /// Timestamp of the newest message known from this author
+    pub latest: u64,
+    /// Number of messages known from this author
+    pub count: usize,
+    /// Digest of the message ids in every time bucket holding messages, by bucket start
+    pub buckets: BTreeMap<u64, u64>,
+}
+
+/// Where to continue an author's log: the messages after `(timestamp, after)`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryCursor {
+    pub timestamp: u64,
+    /// Id of the last message already sent at `timestamp`, empty to start at `timestamp`
+    #[serde(default)]
+    pub after: String,
+}
+
+impl HistoryCursor {
+    /// Every message at or after `timestamp`
+    pub fn from_time(timestamp: u64) -> Self {
+        Self {
+            timestamp,
+            after: String::new(),
+        }
+    }
+}
+
+/// One answer to a history request
+#[derive(Debug, Default)]
+pub struct HistoryBatch {
+    pub messages: Vec<SignedMessage>,
+    /// Where to continue if not everything fitted in this batch
+    pub next: Option<HashMap<String, HistoryCursor>>,
+}
+
+/// Stores signed chat messages indexed by author
+#[derive(Default)]
+pub struct HistoryStore {
+    /// Messages per signer
+    messages: Arc<RwLock<HashMap<String, AuthorLog>>>,
+    /// Follow-up requests made in the current sync with each peer
+    rounds: Arc<RwLock<HashMap<String, usize>>>,
+}
+
+impl HistoryStore {
+    /// Stable identifier of a signed message.
+    ///
+    /// Ed25519 signatures are deterministic, so the same signed message always maps to
+    /// the same id no matter which peer relayed it.
+    pub fn message_id(message: &SignedMessage) -> String {
+        hex::encode(&message.signature)
+    }
+
+    /// Start a history sync with `peer_id`, with no follow-up requests made yet
+    pub async fn start_sync(&self, peer_id: &str) {
+        self.rounds.write().await.insert(peer_id.to_string(), 0);
+    }
+
+    /// Count a follow-up request to `peer_id`. Returns `false` once the sync with it has
+    /// made `MAX_HISTORY_ROUNDS` of them.
+    pub async fn follow_up(&self, peer_id: &str) -> bool {
+        let mut rounds = self.rounds.write().await;
+        let made = rounds.entry(peer_id.to_string()).or_default();
+        if *made >= MAX_HISTORY_ROUNDS {
+            return false;
+        }
+        *made += 1;
+        true
+    }
+
+    /// Insert a message. Returns `false` if it was already known.
+    pub async fn insert(&self, message: SignedMessage) -> bool {
+        let key = (message.timestamp, Self::message_id(&message));
+        let mut messages = self.messages.write().await;
+        let author = messages.entry(message.signer_id.clone()).or_default();
+        if author.contains_key(&key) {
+            return false;
+        }
+        author.insert(key, message);
+        // Drop the oldest messages once the per-author cap is reached
+        while author.len() > MAX_MESSAGES_PER_AUTHOR {
+            author.pop_first();
+        }
+        true
+    }
+
+    /// Check whether a message is already stored
+    pub async fn contains(&self, message: &SignedMessage) -> bool {
+        let key = (message.timestamp, Self::message_id(message));
+        let messages = self.messages.read().await;
+        messages
+            .get(&message.signer_id)
+            .is_some_and(|author| author.contains_key(&key))
+    }
+
+    /// Compact summary of the local history: per author, the newest message, the number of
+    /// messages and a digest of every time bucket
+    pub async fn summary(&self) -> HashMap<String, HistoryMark> {
+        let messages = self.messages.read().await;
+        messages
+            .iter()
+            .filter_map(|(author, msgs)| {
+                let ((latest, _), _) = msgs.last_key_value()?;
+                let mut buckets = BTreeMap::new();
+                for ((timestamp, _), msg) in msgs {
+                    let start = timestamp - timestamp % HISTORY_BUCKET_SECS;
+                    *buckets.entry(start).or_insert(0) ^= digest(msg);
+                }
+                Some((
+                    author.clone(),
+                    HistoryMark {
+                        latest: *latest,
+                        count: msgs.len(),
+                        buckets,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    /// Compare a remote summary with ours and return, for every author where the remote
+    /// side may know messages we lack, the cursor from which we need them: the start of the
+    /// first bucket whose digest differs from ours.
+    pub async fn missing_from(
+        &self,
+        remote: &HashMap<String, HistoryMark>,
+    ) -> HashMap<String, HistoryCursor> {
+        let local = self.summary().await;
+        remote
+            .iter()
+            .filter_map(|(author, theirs)| {
+                let Some(ours) = local.get(author) else {
+                    return Some((author.clone(), HistoryCursor::default()));
+                };
+                let (start, _) = theirs
+                    .buckets
+                    .iter()
+                    .find(|(start, digest)| ours.buckets.get(start) != Some(digest))?;
+                Some((author.clone(), HistoryCursor::from_time(*start)))
+            })
+            .collect()
+    }
+
+    /// Messages from the given authors after the requested cursors, oldest first and at
+    /// most `MAX_HISTORY_BATCH` of them, with the cursors to continue from if there are more
+    pub async fn messages_since(&self, since: &HashMap<String, HistoryCursor>) -> HistoryBatch {
+        let messages = self.messages.read().await;
+        let mut pending: Vec<(&(u64, String), &String, &SignedMessage)> = since
+            .iter()
+            .filter_map(|(author, cursor)| Some((author, messages.get(author)?, cursor)))
+            .flat_map(|(author, msgs, cursor)| {
+                let after = (cursor.timestamp, cursor.after.clone());
+                msgs.range((Bound::Excluded(after), Bound::Unbounded))
+                    .map(move |(key, msg)| (key, author, msg))
+            })
+            .collect();
+        pending.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut next = None;
+        if pending.len() > MAX_HISTORY_BATCH {
+            // Continue every author that still has messages after this batch from the
+            // last one sent, or from where it was asked for if none were
+            let mut cursors: HashMap<String, HistoryCursor> = HashMap::new();
+            for (_, author, _) in &pending[MAX_HISTORY_BATCH..] {
+                cursors
+                    .entry((*author).clone())
+                    .or_insert_with(|| since[*author].clone());
+            }
+            for ((timestamp, id), author, _) in &pending[..MAX_HISTORY_BATCH] {
+                if let Some(cursor) = cursors.get_mut(*author) {
+                    *cursor = HistoryCursor {
+                        timestamp: *timestamp,
+                        after: id.clone(),
+                    };
+                }
+            }
+            pending.truncate(MAX_HISTORY_BATCH);
+            next = Some(cursors);
+        }
+        HistoryBatch {
+            messages: pending.into_iter().map(|(_, _, msg)| msg.clone()).collect(),
+            next,
+        }
+    }
+
+    /// All stored messages in causal order (wall-clock order for messages without a clock)
+    pub async fn all_messages(&self) -> Vec<SignedMessage> {
+        let messages = self.messages.read().await;
+        let mut result: Vec<SignedMessage> = messages
+            .values()
+            .flat_map(|msgs| msgs.values().cloned())
+            .collect();
+        result.sort_by(|a, b| {
+            let key = |m: &SignedMessage| {
+                m.clock
+                    .as_ref()
+                    .map(|c| c.wall_ms)
+                    .unwrap_or(m.timestamp * 1000)
+            };
+            key(a).cmp(&key(b)).then_with(|| a.clock.cmp(&b.clock))
+        });
+        result
+    }
+
+    /// Total number of stored messages
+    pub async fn len(&self) -> usize {
+        self.messages.read().await.values().map(|m| m.len()).sum()
+    }
+
+    /// Whether no messages are stored
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+}
+
+/// Digest of a message id, XORed over a bucket so the order of insertion does not matter
+fn digest(message: &SignedMessage) -> u64 {
+    let mut bytes = [0u8; 8];
+    for (byte, sig) in bytes.iter_mut().zip(&message.signature) {
+        *byte = *sig;
+    }
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::CryptoManager;
+
+    #[tokio::test]
+    async fn test_insert_deduplicates() {
+        let store = HistoryStore::default();
+        let alice = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let msg = alice.sign_message("hello", 100).unwrap();
+
+        assert!(store.insert(msg.clone()).await);
+        assert!(!store.insert(msg.clone()).await);
+        assert!(store.contains(&msg).await);
+        assert_eq!(store.len().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_summary_and_missing() {
+        let ours = HistoryStore::default();
+        let theirs = HistoryStore::default();
+        let alice = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let bob = CryptoManager::new("bob".to_string(), "Bob".to_string());
+
+        let a1 = alice.sign_message("a1", 100).unwrap();
+        let a2 = alice.sign_message("a2", 200).unwrap();
+        let b1 = bob.sign_message("b1", 150).unwrap();
+
+        ours.insert(a1.clone()).await;
+        theirs.insert(a1).await;
+        theirs.insert(a2).await;
+        theirs.insert(b1).await;
+
+        let missing = ours.missing_from(&theirs.summary().await).await;
+        assert_eq!(missing.len(), 2);
+        assert_eq!(missing.get("alice"), Some(&HistoryCursor::from_time(0)));
+        assert_eq!(missing.get("bob"), Some(&HistoryCursor::default()));
+
+        // The other way round, the differing bucket is asked for but holds nothing new
+        let back = theirs.missing_from(&ours.summary().await).await;
+        assert_eq!(back.len(), 1);
+        for msg in ours.messages_since(&back).await.messages {
+            assert!(!theirs.insert(msg).await);
+        }
+
+        let batch = theirs.messages_since(&missing).await;
+        assert!(batch.next.is_none());
+        for msg in batch.messages {
+            ours.insert(msg).await;
+        }
+        assert_eq!(ours.summary().await, theirs.summary().await);
+    }
+
+    #[tokio::test]
+    async fn test_holes_in_the_middle_are_found() {
+        let ours = HistoryStore::default();
+        let theirs = HistoryStore::default();
+        let alice = CryptoManager::new("alice".to_string(), "Alice".to_string());
+
+        // Same newest message and same count, but each side lacks one the other has
+        let first = alice.sign_message("first", 100).unwrap();
+        let only_ours = alice.sign_message("ours", 2000).unwrap();
+        let only_theirs = alice.sign_message("theirs", 3000).unwrap();
+        let last = alice.sign_message("last", 5000).unwrap();
+        for msg in [&first, &only_ours, &last] {
+            ours.insert(msg.clone()).await;
+        }
+        for msg in [&first, &only_theirs, &last] {
+            theirs.insert(msg.clone()).await;
+        }
+
+        let missing = ours.missing_from(&theirs.summary().await).await;
+        assert_eq!(missing.get("alice"), Some(&HistoryCursor::from_time(3000)));
+        for msg in theirs.messages_since(&missing).await.messages {
+            ours.insert(msg).await;
+        }
+        assert!(ours.contains(&only_theirs).await);
+        assert!(ours.missing_from(&theirs.summary().await).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_large_histories_arrive_in_batches() {
+        let ours = HistoryStore::default();
+        let theirs = HistoryStore::default();
+        let alice = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let bob = CryptoManager::new("bob".to_string(), "Bob".to_string());
+        // More than a batch, many of them in the same second
+        for i in 0..MAX_HISTORY_BATCH as u64 {
+            theirs
+                .insert(alice.sign_message(&format!("a{i}"), i / 100).unwrap())
+                .await;
+            theirs
+                .insert(bob.sign_message(&format!("b{i}"), 7).unwrap())
+                .await;
+        }
+
+        let mut since = ours.missing_from(&theirs.summary().await).await;
+        let mut batches = 0;
+        loop {
+            let batch = theirs.messages_since(&since).await;
+            assert!(batch.messages.len() <= MAX_HISTORY_BATCH);
+            for msg in batch.messages {
+                assert!(ours.insert(msg).await, "messages are never sent twice");
+            }
+            batches += 1;
+            match batch.next {
+                Some(next) => since = next,
+                None => break,
+            }
+        }
+        assert_eq!(batches, 2);
+        assert_eq!(ours.len().await, 2 * MAX_HISTORY_BATCH);
+        assert!(ours.missing_from(&theirs.summary().await).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_follow_ups_are_capped_per_sync() {
+        let history = HistoryStore::default();
+        for _ in 0..MAX_HISTORY_ROUNDS {
+            assert!(history.follow_up("bob").await);
+        }
+        assert!(!history.follow_up("bob").await);
+        assert!(history.follow_up("carol").await);
+        // A new sync starts counting again
+        history.start_sync("bob").await;
+        assert!(history.follow_up("bob").await);
+    }
+}
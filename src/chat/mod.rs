@@ -2,43 +2,112 @@
 //!
 //! This module defines the `Peer` struct, which represents a peer in the Chat network.
 //! It handles the initialization of the peer, starting of necessary services like TCP listener,
-//! mDNS discovery, heartbeat sending, and CLI handling. It also provides functionality to broadcast
+//! peer discovery, heartbeat sending, and CLI handling. It also provides functionality to broadcast
 //! messages to other peers.
 
+pub mod builder;
+pub mod clock;
+pub mod event;
+pub mod history;
+pub mod moderation;
+pub mod roles;
+pub mod room;
+
 pub mod net {
+    pub mod bootstrap;
     pub mod broadcast;
     pub mod discovery;
+    pub mod gossip;
     pub mod heartbeat;
+    pub mod limits;
     pub mod listener;
+    pub mod pex;
 }
 
 pub mod display {
     pub mod cli;
     pub mod message_display;
+    pub mod ordering;
 }
 
-use crate::crypto::{threshold::ThresholdManager, CryptoManager};
+pub use builder::{PeerBuilder, PeerHandle};
+
+use crate::crypto::identity::Identity;
+use crate::crypto::invite::{Invite, RoomSecret};
+use crate::crypto::threshold::{ProposalAction, TargetPeer, VotingRule};
+use crate::crypto::CryptoManager;
+use clock::HybridClock;
+use display::ordering::CausalBuffer;
+use net::discovery::{DiscoveryKind, DEFAULT_DISCOVERY};
+use net::gossip::GossipRelay;
+use net::limits::RateLimiter;
+use net::pex::PexState;
+use event::{ChatEvent, EventSender};
+use moderation::{Moderation, ModerationLists};
+use roles::{Action, Role, RoleGrant, RoleSubject};
+use room::{Room, RoomRegistry};
 use crate::error::ChatError;
+use crate::network::command::{HandlerContext, HandlerRegistry};
+use crate::network::interfaces;
+use crate::network::protocol::ProtocolState;
+use crate::network::transport::{TcpTransport, Transport};
 use crate::peer::PeerInfo;
 use colored::*;
 use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use uuid::Uuid;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
 #[derive(Clone)]
 pub struct Peer {
     pub peer_id: String,
     pub name: String,
     pub port: u16,
-    pub peers: Arc<Mutex<HashMap<String, PeerInfo>>>,
-    pub message_sender: tokio::sync::broadcast::Sender<String>,
+    /// Joined rooms, each with its own peers, proposals and history
+    pub rooms: Arc<RoomRegistry>,
+    /// Everything that happens in the joined rooms; the CLI display is one subscriber
+    pub events: EventSender,
     pub crypto_manager: Arc<CryptoManager>,
-    pub threshold_manager: Arc<ThresholdManager>,
+    pub clock: Arc<HybridClock>,
+    pub causal_buffer: Arc<CausalBuffer>,
+    pub gossip: Arc<GossipRelay>,
+    pub pex: Arc<PexState>,
+    /// How connections to other peers are made; TCP unless simulated
+    pub transport: Arc<dyn Transport>,
+    /// Handlers of incoming messages by kind; applications can add their own
+    pub handlers: Arc<HandlerRegistry>,
+    /// Protocol version and features negotiated with each peer
+    pub protocols: Arc<ProtocolState>,
+    /// Per-source rate limits, connection cap and bans protecting us from floods
+    pub limits: Arc<RateLimiter>,
+    /// Peers we blocked or muted, kept in the data directory
+    pub moderation: Arc<Moderation>,
+    /// Peers to connect to at startup, as `host:port`
+    pub bootstrap_peers: Vec<String>,
+    /// Discovery backends to run
+    pub discovery: Vec<DiscoveryKind>,
+    /// Where the identity and other state are kept across restarts; nothing is saved
+    /// when unset
+    pub data_dir: Option<PathBuf>,
+    /// Cancelled when the peer shuts down; every service stops when it fires
+    pub cancel: CancellationToken,
+    /// Background tasks (connections, discovery backends) `shutdown` waits for
+    pub tasks: TaskTracker,
+    /// Set once the exit has been broadcast, so shutting down twice only says goodbye once
+    said_goodbye: Arc<AtomicBool>,
 }
 
 impl Peer {
     pub fn new(name: String, port: u16) -> Self {
+        Self::with_identity(name, port, Identity::generate())
+    }
+
+    /// A peer with the given id and signing key, e.g. one loaded from its data directory
+    pub fn with_identity(name: String, port: u16, identity: Identity) -> Self {
         // Validate name and port
         let valid_name = name.trim();
         let name = if valid_name.is_empty() || valid_name.len() > 128 {
@@ -47,23 +116,38 @@ impl Peer {
             valid_name.to_string()
         };
         let port = if port == 0 { 8080 } else { port };
-        let peer_id = Uuid::new_v4().to_string();
-        let (message_sender, _) = tokio::sync::broadcast::channel(100);
+        let peer_id = identity.peer_id.clone();
+        let (events, _) = tokio::sync::broadcast::channel(100);
 
         // Initialize cryptographic identity
-        let crypto_manager = Arc::new(CryptoManager::new(peer_id.clone(), name.clone()));
+        let crypto_manager = Arc::new(identity.crypto_manager(name.clone()));
 
-        // Initialize threshold manager for secure-only messaging upgrades
-        let threshold_manager = Arc::new(ThresholdManager::default());
+        // Hybrid logical clock and display reordering for causal message order
+        let clock = Arc::new(HybridClock::new(peer_id.clone()));
+        let causal_buffer = Arc::new(CausalBuffer::new(clock.clone(), events.clone()));
 
         Self {
             peer_id,
             name,
             port,
-            peers: Arc::new(Mutex::new(HashMap::new())),
-            message_sender,
+            rooms: Arc::new(RoomRegistry::default()),
+            events,
             crypto_manager,
-            threshold_manager,
+            clock,
+            causal_buffer,
+            gossip: Arc::new(GossipRelay::default()),
+            pex: Arc::new(PexState::default()),
+            transport: Arc::new(TcpTransport),
+            handlers: Arc::new(HandlerRegistry::default()),
+            protocols: Arc::new(ProtocolState::default()),
+            limits: Arc::new(RateLimiter::default()),
+            moderation: Arc::new(Moderation::default()),
+            bootstrap_peers: Vec::new(),
+            discovery: DEFAULT_DISCOVERY.to_vec(),
+            data_dir: None,
+            cancel: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+            said_goodbye: Arc::new(AtomicBool::new(false)),
         }
     }
     pub async fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
@@ -74,6 +158,11 @@ impl Peer {
             "🔌 Listening on port: {}",
             self.port.to_string().bright_blue()
         );
+        println!(
+            "🏠 Rooms: {} (current: #{})",
+            self.rooms.ids().join(", ").bright_green(),
+            self.room().id
+        );
 
         // Display cryptographic identity
         let identity = self.crypto_manager.get_identity();
@@ -84,144 +173,546 @@ impl Peer {
         );
         println!("🔐 Full Key: {}", public_key_hex.bright_magenta());
 
+        self.serve(true).await;
+        Ok(())
+    }
+
+    /// Run the network services without the CLI or the console display, until the peer
+    /// shuts down. Embedders read `events` and act through the `Peer` (or a `PeerHandle`).
+    pub async fn run_headless(&self) {
+        self.serve(false).await;
+    }
+
+    async fn serve(&self, interactive: bool) {
         // Start all services concurrently
         let tcp_listener = net::listener::start_tcp_listener(self);
-        let mdns_discovery = net::discovery::start_mdns(Arc::new(self.clone()));
+        let backends = net::discovery::build_backends(&self.discovery, self);
+        let peer_discovery = net::discovery::start_discovery(Arc::new(self.clone()), backends);
         let heartbeat_sender = net::heartbeat::start_heartbeat(self);
-        let cli_handler = display::cli::start_cli_handler(self);
-        let message_display = display::message_display::start_message_display(self);
-
-        tokio::select! {
-            result = tcp_listener => {
-                if let Err(e) = result {
-                    eprintln!("TCP listener error: {e}");
-                    self.shutdown().await;
-                }
-            }
-            result = mdns_discovery => {
-                if let Err(e) = result {
-                    eprintln!("mDNS discovery error: {e}");
-                    self.shutdown().await;
-                }
+        let peer_exchange = net::pex::start_peer_exchange(Arc::new(self.clone()));
+        let causal_flush = display::ordering::start_causal_flush(self);
+        let console = async {
+            if interactive {
+                let cli_handler = display::cli::start_cli_handler(self);
+                let message_display = display::message_display::start_message_display(self);
+                tokio::join!(
+                    self.supervise("CLI handler", cli_handler),
+                    self.supervise("Message display", message_display),
+                );
             }
-            result = heartbeat_sender => {
-                if let Err(e) = result {
-                    eprintln!("Heartbeat sender error: {e}");
-                    self.shutdown().await;
-                }
-            }
-            result = cli_handler => {
-                if let Err(e) = result {
-                    eprintln!("CLI handler error: {e}");
-                    self.shutdown().await;
+        };
+
+        // Each service stops when `cancel` fires; one that ends on its own (an error, or
+        // the CLI quitting) shuts the others down too
+        tokio::join!(
+            self.supervise("TCP listener", tcp_listener),
+            self.supervise("Peer discovery", peer_discovery),
+            self.supervise("Peer exchange", peer_exchange),
+            self.supervise("Heartbeat sender", heartbeat_sender),
+            self.supervise("Causal flush", causal_flush),
+            console,
+        );
+        self.shutdown().await;
+    }
+
+    /// Run a service to completion, logging its error, then cancel the other services
+    async fn supervise<E: std::fmt::Display>(
+        &self,
+        name: &str,
+        service: impl std::future::Future<Output = Result<(), E>>,
+    ) {
+        if let Err(e) = service.await {
+            eprintln!("{name} error: {e}");
+        }
+        self.cancel.cancel();
+    }
+
+    /// Run `task` in the background until it ends or the peer shuts down; `shutdown`
+    /// waits for it
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        spawn_until_cancelled(&self.tasks, &self.cancel, task);
+    }
+
+    /// What incoming messages are handled with
+    pub fn handler_context(&self) -> HandlerContext {
+        HandlerContext {
+            rooms: self.rooms.clone(),
+            events: self.events.clone(),
+            peer_id: self.peer_id.clone(),
+            crypto_manager: self.crypto_manager.clone(),
+            causal_buffer: self.causal_buffer.clone(),
+            gossip: self.gossip.clone(),
+            pex: self.pex.clone(),
+            transport: self.transport.clone(),
+            protocols: self.protocols.clone(),
+            limits: self.limits.clone(),
+            moderation: self.moderation.clone(),
+            handlers: self.handlers.clone(),
+            cancel: self.cancel.clone(),
+            tasks: self.tasks.clone(),
+        }
+    }
+
+    /// The current room, where messages, proposals and votes go
+    pub fn room(&self) -> Room {
+        self.rooms.current()
+    }
+
+    /// Join a room (or switch to it if already joined) and make it current.
+    ///
+    /// Peers we already know from other rooms are asked whether they are in the new room
+    /// too; everyone else is found by discovery.
+    pub async fn join_room(&self, id: &str) -> Result<Room, ChatError> {
+        let (room, is_new) = self.rooms.join(id)?;
+        if is_new {
+            let mut known: HashMap<String, PeerInfo> = HashMap::new();
+            for other in self.rooms.all() {
+                for info in other.peers.lock().await.values() {
+                    known.entry(info.id.clone()).or_insert_with(|| info.clone());
                 }
             }
-            result = message_display => {
-                if let Err(e) = result {
-                    eprintln!("Message display error: {e}");
-                    self.shutdown().await;
-                }
+            for info in known.into_values() {
+                let peer = self.clone();
+                let room = room.clone();
+                self.spawn(async move {
+                    // Peers that are not in the room simply do not answer
+                    let _ = net::bootstrap::connect_to_candidates(&peer, &room, &info).await;
+                });
             }
         }
-        Ok(())
+        Ok(room)
+    }
+
+    /// Create a private room, make it current and return an invite to it. We claim the
+    /// room as its owner; members joining get the claim with the room's other decisions.
+    pub fn create_private_room(&self, id: &str) -> Result<Invite, ChatError> {
+        if self.rooms.get(id).is_some() {
+            return Err(ChatError::Unknown(format!("Already in a room named {id}")));
+        }
+        let (room, _) = self.rooms.join_private(id, RoomSecret::generate())?;
+        let own_key = self.crypto_manager.get_public_key();
+        room.roles.set_founder(&own_key);
+        let owner = RoleSubject::Peer(self.own_target());
+        let claim = RoleGrant::sign(&self.crypto_manager, id, owner, Role::Owner)?;
+        room.roles.grant(&claim)?;
+        self.invite(&room)
+    }
+
+    /// An invite to a private room, naming this peer as the member to contact
+    pub fn invite(&self, room: &Room) -> Result<Invite, ChatError> {
+        let secret = room.secret.clone().ok_or_else(|| {
+            ChatError::Unknown(format!("#{} is public; anyone can /join it", room.id))
+        })?;
+        let founder = room.roles.founder().ok_or_else(|| {
+            ChatError::Unknown(format!("The creator of #{} is not known", room.id))
+        })?;
+        let ip = interfaces::primary_address()
+            .or_else(interfaces::primary_address_v6)
+            .ok_or_else(|| ChatError::Network("No address to invite others to".to_string()))?;
+        Ok(Invite {
+            room: room.id.clone(),
+            secret,
+            founder,
+            bootstrap: SocketAddr::new(ip, self.port),
+        })
+    }
+
+    /// Join a private room from an invite token and make it current. The member named in
+    /// the invite is contacted in the background; discovery finds the others.
+    pub async fn join_invite(&self, token: &str) -> Result<Room, ChatError> {
+        let invite: Invite = token.parse()?;
+        let (room, _) = self.rooms.join_private(&invite.room, invite.secret)?;
+        room.roles.set_founder(&invite.founder);
+        let peer = self.clone();
+        let joined = room.clone();
+        self.spawn(async move {
+            let reached = net::bootstrap::connect_to(&peer, &joined, invite.bootstrap, None).await;
+            if let Err(e) = reached {
+                eprintln!(
+                    "Could not reach {} to join #{}: {e}",
+                    invite.bootstrap, joined.id
+                );
+            }
+        });
+        Ok(room)
+    }
+
+    /// Leave a room, telling its peers we are gone
+    pub async fn part_room(&self, id: &str) -> Result<Room, ChatError> {
+        let room = self.rooms.part(id)?;
+        display::cli::broadcast_room_exit(self, &room).await?;
+        Ok(room)
     }
+
+    /// Connect to a peer by `host:port` in the current room, without relying on discovery
+    pub async fn connect(&self, addr: &str) -> Result<PeerInfo, ChatError> {
+        net::bootstrap::connect_to_str(self, addr).await
+    }
+
     pub async fn broadcast_message(&self, content: &str) -> Result<(), ChatError> {
         net::broadcast::broadcast_message(self, content).await
     }
 
+    /// Block a peer given by name, peer id or hex public key: drop everything it sends and
+    /// stop sending to it. Returns its name.
+    pub async fn block(&self, who: &str) -> Result<String, ChatError> {
+        let (key, name) = self.resolve_key(who).await?;
+        self.moderation.block(&key, &name)?;
+        Ok(name)
+    }
+
+    /// Lift a block, by the name or key it is listed under or as for `block`
+    pub async fn unblock(&self, who: &str) -> Result<String, ChatError> {
+        let (key, name) = self
+            .listed_key(&self.moderation.lists().blocked, who)
+            .await?;
+        match self.moderation.unblock(&key)? {
+            true => Ok(name),
+            false => Err(ChatError::Unknown(format!("{name} is not blocked"))),
+        }
+    }
+
+    /// Hide the chat of a peer given as for `block`, still handling its proposals and
+    /// votes. Returns its name.
+    pub async fn mute(&self, who: &str) -> Result<String, ChatError> {
+        let (key, name) = self.resolve_key(who).await?;
+        self.moderation.mute(&key, &name)?;
+        Ok(name)
+    }
+
+    /// Show a muted peer's chat again, given as for `unblock`
+    pub async fn unmute(&self, who: &str) -> Result<String, ChatError> {
+        let (key, name) = self.listed_key(&self.moderation.lists().muted, who).await?;
+        match self.moderation.unmute(&key)? {
+            true => Ok(name),
+            false => Err(ChatError::Unknown(format!("{name} is not muted"))),
+        }
+    }
+
+    /// Public key and name of a peer given by name, peer id or hex public key
+    async fn resolve_key(&self, who: &str) -> Result<(Vec<u8>, String), ChatError> {
+        if let Some(key) = hex::decode(who).ok().filter(|key| key.len() == 32) {
+            return Ok((key, who[..8].to_string()));
+        }
+        for room in self.rooms.all() {
+            let peers = room.peers.lock().await;
+            let Some(info) = peers.values().find(|p| p.id == who || p.name == who) else {
+                continue;
+            };
+            let key = self.crypto_manager.public_key_of(&info.id).await;
+            return match key {
+                Some(key) => Ok((key, info.name.clone())),
+                None => Err(ChatError::Unknown(format!(
+                    "No key known for {} yet",
+                    info.name
+                ))),
+            };
+        }
+        Err(ChatError::Unknown(format!("Unknown peer {who}")))
+    }
+
+    /// Like `resolve_key`, but first by the name or key `who` is listed under in `list`
+    async fn listed_key(
+        &self,
+        list: &std::collections::BTreeMap<String, String>,
+        who: &str,
+    ) -> Result<(Vec<u8>, String), ChatError> {
+        match ModerationLists::find(list, who)? {
+            Some(key) => {
+                let name = list[&key].clone();
+                let key = hex::decode(key).map_err(|e| ChatError::Unknown(e.to_string()))?;
+                Ok((key, name))
+            }
+            None => self.resolve_key(who).await,
+        }
+    }
+
     /// Broadcast a message without cryptographic signing
     pub async fn broadcast_unsigned_message(&self, content: &str) -> Result<(), ChatError> {
         net::broadcast::broadcast_unsigned_message(self, content).await
     }
 
-    /// Create a proposal to enable secure-only messaging
+    /// Send an application-defined message of `kind` to the peers of the current room;
+    /// they handle it with the handler registered for `kind`
+    pub async fn broadcast_custom(
+        &self,
+        kind: &str,
+        payload: serde_json::Value,
+    ) -> Result<(), ChatError> {
+        net::broadcast::broadcast_custom(self, kind, payload).await
+    }
+
+    /// The rule proposals created in the current room are decided by
+    pub async fn voting_rule(&self) -> VotingRule {
+        self.room().threshold_manager.voting_rule().await
+    }
+
+    /// Decide the proposals we create in the current room from now on by `rule`
+    pub async fn set_voting_rule(&self, rule: VotingRule) {
+        self.room().threshold_manager.set_voting_rule(rule).await;
+    }
+
+    /// Create a proposal to enable secure-only messaging in the current room
     pub async fn propose_secure_upgrade(&self, description: &str) -> Result<String, ChatError> {
-        let peers_count = self.peers.lock().await.len();
-        let required_approvals = (peers_count / 2) + 1; // Simple majority rule
+        self.propose(description, ProposalAction::SecureOnly, None)
+            .await
+    }
+
+    /// Propose to expel a peer of the current room, given by name or peer id. Once the
+    /// other members approve under the room's voting rule, every peer drops and blocks it.
+    pub async fn propose_expulsion(&self, who: &str, reason: &str) -> Result<String, ChatError> {
+        let room = self.room();
+        let target = {
+            let peers = room.peers.lock().await;
+            let target = peers.values().find(|p| p.id == who || p.name == who);
+            target.cloned()
+        };
+        let target =
+            target.ok_or_else(|| ChatError::Unknown(format!("{who} is not in #{}", room.id)))?;
+        let public_key = self
+            .crypto_manager
+            .public_key_of(&target.id)
+            .await
+            .ok_or_else(|| ChatError::Unknown(format!("No key known for {} yet", target.name)))?;
+        let description = match reason.trim() {
+            "" => format!("Expel {}", target.name),
+            reason => format!("Expel {}: {reason}", target.name),
+        };
+        let target_id = target.id.clone();
+        let action = ProposalAction::Expel(TargetPeer {
+            peer_id: target.id,
+            name: target.name,
+            public_key,
+        });
+        // The target has no say: we and the other peers decide
+        self.propose(&description, action, Some(&target_id)).await
+    }
+
+    /// Give a peer of the current room, given by name or peer id, or `everyone`, a role.
+    /// Only owners and moderators may; returns who got the role.
+    pub async fn grant_role(&self, who: &str, role: Role) -> Result<RoleSubject, ChatError> {
+        let room = self.room();
+        let subject = self.role_subject(&room, who).await?;
+        let grant = RoleGrant::sign(&self.crypto_manager, &room.id, subject.clone(), role)?;
+        room.roles.grant(&grant)?;
+        let _ = self.events.send(ChatEvent::RoleChanged {
+            room: room.id.clone(),
+            subject: subject.clone(),
+            role,
+            by: Some(self.name.clone()),
+        });
+        net::broadcast::broadcast_role_grant(self, grant).await?;
+        Ok(subject)
+    }
+
+    /// Propose to give a peer of the current room, or `everyone`, a role by vote. This is
+    /// how a public room gets its first owner; once it has one, only a peer who could grant
+    /// the role may propose it.
+    pub async fn propose_role(&self, who: &str, role: Role) -> Result<String, ChatError> {
+        let room = self.room();
+        let subject = self.role_subject(&room, who).await?;
+        let own_key = self.crypto_manager.get_public_key();
+        room.roles
+            .check_change(&own_key, &self.name, &subject, role)?;
+        let description = format!("Make {subject} {role}");
+        let action = ProposalAction::AssignRole { subject, role };
+        self.propose(&description, action, None).await
+    }
+
+    /// Who `who` names in `room` for a role: `everyone`, us, or a peer with a known key
+    async fn role_subject(&self, room: &Room, who: &str) -> Result<RoleSubject, ChatError> {
+        if who == "everyone" || who == "*" {
+            return Ok(RoleSubject::Everyone);
+        }
+        if who == self.peer_id || who == self.name {
+            return Ok(RoleSubject::Peer(self.own_target()));
+        }
+        let target = {
+            let peers = room.peers.lock().await;
+            let target = peers.values().find(|p| p.id == who || p.name == who);
+            target.cloned()
+        };
+        let target =
+            target.ok_or_else(|| ChatError::Unknown(format!("{who} is not in #{}", room.id)))?;
+        let public_key = self
+            .crypto_manager
+            .public_key_of(&target.id)
+            .await
+            .ok_or_else(|| ChatError::Unknown(format!("No key known for {} yet", target.name)))?;
+        Ok(RoleSubject::Peer(TargetPeer {
+            peer_id: target.id,
+            name: target.name,
+            public_key,
+        }))
+    }
 
-        let proposal_id = self
+    /// Ourselves, as the subject of a role
+    fn own_target(&self) -> TargetPeer {
+        TargetPeer {
+            peer_id: self.peer_id.clone(),
+            name: self.name.clone(),
+            public_key: self.crypto_manager.get_public_key(),
+        }
+    }
+
+    /// Create a proposal in the current room and send it to the room's peers. We and the
+    /// peers allowed to vote, but for `except`, make up the electorate the room's voting
+    /// rule is applied to.
+    async fn propose(
+        &self,
+        description: &str,
+        action: ProposalAction,
+        except: Option<&str>,
+    ) -> Result<String, ChatError> {
+        let room = self.room();
+        let own_key = self.crypto_manager.get_public_key();
+        room.roles.require(Some(&own_key), Action::Propose)?;
+        let mut electorate = room.electorate(&self.crypto_manager).await;
+        if let Some(except) = except {
+            electorate.remove(except);
+        }
+        let mut rule = room.threshold_manager.voting_rule().await;
+        if let VotingRule::Weighted { total_weight } = &mut rule {
+            *total_weight = electorate.total_weight();
+        }
+        let proposal_id = room
             .threshold_manager
-            .create_proposal(
+            .create_proposal_with_rule(
                 self.peer_id.clone(),
                 self.name.clone(),
                 description.to_string(),
-                required_approvals,
-                peers_count + 1, // Include self
+                action,
+                rule,
+                electorate.len(),
             )
             .await?;
+        room.threshold_manager
+            .sign_proposal(&proposal_id, &self.crypto_manager)
+            .await?;
 
         // Broadcast the proposal to all peers
         net::broadcast::broadcast_upgrade_proposal(self, &proposal_id).await?;
 
-        println!("🔐 Created secure messaging upgrade proposal: {proposal_id}");
-        println!(
-            "📊 Requires {required_approvals}/{} approvals to enable",
-            peers_count + 1
-        );
+        if let Some(proposal) = room.threshold_manager.get_proposal(&proposal_id).await {
+            let _ = self.events.send(ChatEvent::ProposalCreated {
+                room: room.id.clone(),
+                proposal,
+            });
+        }
 
         Ok(proposal_id)
     }
 
-    /// Vote on an upgrade proposal
+    /// Vote on an upgrade proposal of the current room
     pub async fn vote_on_proposal(
         &self,
         proposal_id: &str,
         approved: bool,
     ) -> Result<(), ChatError> {
-        self.threshold_manager
-            .cast_vote(
+        let room = self.room();
+        let own_key = self.crypto_manager.get_public_key();
+        room.roles.require(Some(&own_key), Action::Vote)?;
+        let was_secure = room.threshold_manager.is_secure_only_enabled().await;
+        let electorate = room.electorate(&self.crypto_manager).await;
+        let passed = room
+            .threshold_manager
+            .cast_vote_in(
                 proposal_id,
                 self.peer_id.clone(),
                 self.name.clone(),
                 approved,
                 &self.crypto_manager,
+                Some(&electorate),
             )
             .await?;
 
-        let vote_text = if approved {
-            "✅ approved"
-        } else {
-            "❌ rejected"
-        };
-        println!("🗳️  {vote_text} upgrade proposal: {proposal_id}");
+        let votes = room.threshold_manager.get_proposal_votes(proposal_id).await;
+        if let Some(vote) = votes.into_iter().find(|v| v.voter_id == self.peer_id) {
+            let _ = self.events.send(ChatEvent::VoteCast {
+                room: room.id.clone(),
+                vote,
+            });
+        }
+        if !was_secure && room.threshold_manager.is_secure_only_enabled().await {
+            let _ = self.events.send(ChatEvent::ModeChanged {
+                room: room.id.clone(),
+                secure_only: true,
+            });
+        }
 
         // Broadcast the vote to all peers
         net::broadcast::broadcast_proposal_vote(self, proposal_id, approved).await?;
 
+        // Everyone else carries out the decision when our vote reaches them
+        if passed {
+            if let Some(decision) = room.threshold_manager.get_decision(proposal_id).await {
+                let ctx = self.handler_context();
+                crate::network::handlers::upgrade::apply_decision(&ctx, &room, &decision).await;
+            }
+        }
+
         Ok(())
     }
 
-    /// Check if secure-only messaging is currently enabled
+    /// Check if secure-only messaging is currently enabled in the current room
     pub async fn is_secure_only_enabled(&self) -> bool {
-        self.threshold_manager.is_secure_only_enabled().await
+        self.room().threshold_manager.is_secure_only_enabled().await
     }
 
-    /// Get active upgrade proposals
+    /// Get active upgrade proposals of the current room
     pub async fn get_active_proposals(&self) -> Vec<crate::crypto::threshold::UpgradeProposal> {
-        self.threshold_manager.get_active_proposals().await
+        self.room().threshold_manager.get_active_proposals().await
     }
 
-    /// Get votes for a specific proposal
+    /// Get votes for a specific proposal of the current room
     pub async fn get_proposal_votes(
         &self,
         proposal_id: &str,
     ) -> Vec<crate::crypto::threshold::UpgradeVote> {
-        self.threshold_manager.get_proposal_votes(proposal_id).await
+        self.room()
+            .threshold_manager
+            .get_proposal_votes(proposal_id)
+            .await
     }
 
+    /// Leave the network: tell every room we are gone, flush held-back messages, stop
+    /// every service and wait for background tasks to finish. Safe to call more than once.
     pub async fn shutdown(&self) {
-        let _ = crate::chat::display::cli::broadcast_exit(self).await;
+        if !self.said_goodbye.swap(true, Ordering::SeqCst) {
+            println!("Peer is shutting down gracefully...");
+            self.causal_buffer.drain().await;
+            if let Err(e) = display::cli::broadcast_exit(self).await {
+                eprintln!("Error broadcasting exit: {e}");
+            }
+        }
+        self.stop().await;
+    }
 
-        // TODO: Wait for all network tasks to finish (e.g., join handles)
-        // TODO: Close all open connections and resources
-        // You may want to set a shutdown flag and notify background tasks
-        println!("Peer is shutting down gracefully...");
-        // Give some time for messages to flush
-        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
-        std::process::exit(0);
+    /// Stop every service and wait for background tasks without telling anyone, as if
+    /// the process died; other peers only notice through discovery
+    pub async fn stop(&self) {
+        self.said_goodbye.store(true, Ordering::SeqCst);
+        self.cancel.cancel();
+        self.tasks.close();
+        self.tasks.wait().await;
     }
 }
 
+/// Spawn `task` on `tasks`, dropping it when `cancel` fires
+pub(crate) fn spawn_until_cancelled(
+    tasks: &TaskTracker,
+    cancel: &CancellationToken,
+    task: impl Future<Output = ()> + Send + 'static,
+) {
+    let cancel = cancel.clone();
+    tasks.spawn(async move {
+        tokio::select! {
+            _ = task => {}
+            _ = cancel.cancelled() => {}
+        }
+    });
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -236,6 +727,8 @@ mod tests {
             name: "TestPeer".to_string(),
             ip: IpAddr::from_str("127.0.0.1").unwrap(),
             port: 8080,
+            addresses: Vec::new(),
+            scope_id: 0,
         };
         assert_eq!(peer.name, "TestPeer");
         assert_eq!(peer.port, 8080);
@@ -247,4 +740,30 @@ mod tests {
         assert_eq!(peer.name, "Tester");
         assert_eq!(peer.port, 9000);
     }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_the_listener() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let peer = Peer::new("Tester".to_string(), port);
+        let stop = async {
+            while tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_err()
+            {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+            peer.shutdown().await;
+            // A second shutdown returns right away
+            peer.shutdown().await;
+        };
+        let (listener, ()) = tokio::join!(net::listener::start_tcp_listener(&peer), stop);
+        assert!(listener.is_ok());
+        assert!(peer.cancel.is_cancelled());
+        // The port was released
+        assert!(std::net::TcpListener::bind(("0.0.0.0", port)).is_ok());
+    }
 }
@@ -0,0 +1,363 @@
+//! Moderation module: The local user's block, mute and ignore lists.
+//!
+//! Lists are keyed by public key rather than peer id: a peer id is a UUID anyone can pick,
+//! while messages are signed with the key, so a blocked peer stays blocked whatever id it
+//! shows up with. Everything a blocked peer sends is dropped before dispatch and broadcasts
+//! skip it. A muted peer's chat is hidden, but its proposals, votes and signatures are still
+//! handled so governance keeps working. Ignoring unsigned messages drops unsigned chat from
+//! everyone. With a data directory the lists are saved to `moderation.json` and survive
+//! restarts.
+
+use crate::crypto::CryptoManager;
+use crate::error::ChatError;
+use crate::peer::NetworkMessage;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// File the lists are stored in, inside the data directory
+pub const MODERATION_FILE: &str = "moderation.json";
+
+/// Shortest prefix of a hex key an entry can be looked up by
+pub const MIN_KEY_PREFIX: usize = 8;
+
+/// Blocked and muted peers by hex public key, with the name they had when added
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModerationLists {
+    pub blocked: BTreeMap<String, String>,
+    pub muted: BTreeMap<String, String>,
+    pub ignore_unsigned: bool,
+}
+
+impl ModerationLists {
+    /// The key of the entry of `list` named `who`, or with `who` as its key or a prefix of
+    /// it at least `MIN_KEY_PREFIX` long. Fails, listing the candidates, if several entries
+    /// match.
+    pub fn find(list: &BTreeMap<String, String>, who: &str) -> Result<Option<String>, ChatError> {
+        let prefix = who.to_lowercase();
+        let by_key = |key: &String| prefix.len() >= MIN_KEY_PREFIX && key.starts_with(&prefix);
+        let matches: Vec<(&String, &String)> = list
+            .iter()
+            .filter(|(key, name)| name.as_str() == who || by_key(key))
+            .collect();
+        match matches[..] {
+            [] => Ok(None),
+            [(key, _)] => Ok(Some(key.clone())),
+            _ => {
+                let candidates: Vec<String> = matches
+                    .iter()
+                    .map(|(key, name)| format!("{name} ({})", &key[..MIN_KEY_PREFIX]))
+                    .collect();
+                Err(ChatError::Unknown(format!(
+                    "{who} matches {}; give more of the key",
+                    candidates.join(", ")
+                )))
+            }
+        }
+    }
+}
+
+/// The lists, saved on every change when a file is set
+#[derive(Debug, Default)]
+pub struct Moderation {
+    path: Option<PathBuf>,
+    lists: RwLock<ModerationLists>,
+}
+
+impl Moderation {
+    /// The lists saved in `dir`, or empty ones saved there on the first change
+    pub fn load(dir: &Path) -> Result<Self, ChatError> {
+        let path = dir.join(MODERATION_FILE);
+        let lists = if path.exists() {
+            serde_json::from_str(&std::fs::read_to_string(&path)?)?
+        } else {
+            ModerationLists::default()
+        };
+        Ok(Self {
+            path: Some(path),
+            lists: RwLock::new(lists),
+        })
+    }
+
+    pub fn lists(&self) -> ModerationLists {
+        self.read().clone()
+    }
+
+    /// Drop everything the owner of `key` sends and stop sending to it
+    pub fn block(&self, key: &[u8], name: &str) -> Result<(), ChatError> {
+        self.update(|lists| {
+            lists.blocked.insert(hex::encode(key), name.to_string());
+        })
+    }
+
+    /// Returns whether the key was blocked
+    pub fn unblock(&self, key: &[u8]) -> Result<bool, ChatError> {
+        self.update(|lists| lists.blocked.remove(&hex::encode(key)).is_some())
+    }
+
+    /// Hide the chat of the owner of `key`
+    pub fn mute(&self, key: &[u8], name: &str) -> Result<(), ChatError> {
+        self.update(|lists| {
+            lists.muted.insert(hex::encode(key), name.to_string());
+        })
+    }
+
+    /// Returns whether the key was muted
+    pub fn unmute(&self, key: &[u8]) -> Result<bool, ChatError> {
+        self.update(|lists| lists.muted.remove(&hex::encode(key)).is_some())
+    }
+
+    pub fn set_ignore_unsigned(&self, ignore: bool) -> Result<(), ChatError> {
+        self.update(|lists| lists.ignore_unsigned = ignore)
+    }
+
+    pub fn is_blocked(&self, key: &[u8]) -> bool {
+        self.read().blocked.contains_key(&hex::encode(key))
+    }
+
+    pub fn is_muted(&self, key: &[u8]) -> bool {
+        self.read().muted.contains_key(&hex::encode(key))
+    }
+
+    pub fn ignores_unsigned(&self) -> bool {
+        self.read().ignore_unsigned
+    }
+
+    /// Whether we stop sending to `peer_id`
+    pub async fn blocks_peer(&self, peer_id: &str, crypto_manager: &CryptoManager) -> bool {
+        let blocked = !self.read().blocked.is_empty();
+        blocked
+            && crypto_manager
+                .public_key_of(peer_id)
+                .await
+                .is_some_and(|key| self.is_blocked(&key))
+    }
+
+    /// `message` as it should be handled, or `None` to drop it. Senders are recognized by
+    /// the key the message carries or the key we know for their peer id.
+    pub async fn filter(
+        &self,
+        message: NetworkMessage,
+        crypto_manager: &CryptoManager,
+    ) -> Option<NetworkMessage> {
+        // Histories are relayed by anyone; only the entries of blocked and muted authors go
+        if let NetworkMessage::HistoryResponse {
+            peer_id,
+            messages,
+            next,
+        } = message
+        {
+            let messages = messages
+                .into_iter()
+                .filter(|m| !self.is_blocked(&m.public_key) && !self.is_muted(&m.public_key))
+                .collect();
+            return Some(NetworkMessage::HistoryResponse {
+                peer_id,
+                messages,
+                next,
+            });
+        }
+        let unsigned = matches!(&message, NetworkMessage::Chat(m) if m.signature.is_none());
+        if unsigned && self.ignores_unsigned() {
+            return None;
+        }
+
+        let mut keys = Vec::new();
+        if let Some(key) = carried_key(&message) {
+            keys.push(key.to_vec());
+        }
+        let mut senders: Vec<&str> = message.sender_id().into_iter().collect();
+        if let NetworkMessage::Gossip(envelope) = &message {
+            senders.push(&envelope.origin_id);
+        }
+        for sender in senders {
+            keys.extend(crypto_manager.public_key_of(sender).await);
+        }
+        if keys.iter().any(|key| self.is_blocked(key)) {
+            return None;
+        }
+        let chat = matches!(
+            message,
+            NetworkMessage::Chat(_) | NetworkMessage::SignedChat(_)
+        );
+        if chat && keys.iter().any(|key| self.is_muted(key)) {
+            return None;
+        }
+        Some(message)
+    }
+
+    /// Apply `change` and save the lists
+    fn update<R>(&self, change: impl FnOnce(&mut ModerationLists) -> R) -> Result<R, ChatError> {
+        let mut lists = self.lists.write().unwrap_or_else(|e| e.into_inner());
+        let result = change(&mut lists);
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(path, serde_json::to_string_pretty(&*lists)?)?;
+        }
+        Ok(result)
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, ModerationLists> {
+        self.lists.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The public key a message carries itself, if any
+pub(crate) fn carried_key(message: &NetworkMessage) -> Option<&[u8]> {
+    match message {
+        NetworkMessage::Discovery(signed) => Some(&signed.public_key),
+        NetworkMessage::Chat(m) => m.public_key.as_deref(),
+        NetworkMessage::SignedChat(signed) => Some(&signed.public_key),
+        NetworkMessage::IdentityAnnouncement { public_key, .. } => Some(public_key),
+        NetworkMessage::PartialSignature(signature) => Some(&signature.public_key),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::threshold::UpgradeVote;
+    use crate::peer::Message;
+
+    fn unsigned(from: &str) -> NetworkMessage {
+        NetworkMessage::Chat(Message {
+            from_id: from.to_string(),
+            from_name: from.to_string(),
+            content: "hi".to_string(),
+            timestamp: 1,
+            signature: None,
+            public_key: None,
+            clock: None,
+        })
+    }
+
+    fn vote(from: &str) -> NetworkMessage {
+        NetworkMessage::UpgradeVote(UpgradeVote {
+            proposal_id: "p".to_string(),
+            voter_id: from.to_string(),
+            voter_name: from.to_string(),
+            approved: true,
+            timestamp: 1,
+            signature: None,
+            public_key: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_blocked_and_muted_peers_are_filtered_by_key() {
+        let local = CryptoManager::new("me".to_string(), "Me".to_string());
+        let key = ed25519_dalek::SigningKey::generate(&mut rand::rngs::OsRng);
+        let mallory = CryptoManager::from_signing_key(
+            "mallory".to_string(),
+            "Mallory".to_string(),
+            key.clone(),
+        );
+        let bob = CryptoManager::new("bob".to_string(), "Bob".to_string());
+        for remote in [&mallory, &bob] {
+            let identity = remote.get_identity();
+            local
+                .add_known_peer(identity.peer_id.clone(), identity.public_key.clone())
+                .await
+                .unwrap();
+        }
+        let moderation = Moderation::default();
+        moderation
+            .block(&mallory.get_public_key(), "Mallory")
+            .unwrap();
+        moderation.mute(&bob.get_public_key(), "Bob").unwrap();
+
+        let from_mallory = NetworkMessage::SignedChat(mallory.sign_message("hi", 1).unwrap());
+        let from_bob = NetworkMessage::SignedChat(bob.sign_message("hi", 1).unwrap());
+        assert!(moderation.filter(from_mallory, &local).await.is_none());
+        assert!(moderation.filter(vote("mallory"), &local).await.is_none());
+        assert!(moderation
+            .filter(unsigned("mallory"), &local)
+            .await
+            .is_none());
+        // Muting hides chat but keeps governance
+        assert!(moderation.filter(from_bob, &local).await.is_none());
+        assert!(moderation.filter(vote("bob"), &local).await.is_some());
+        assert!(moderation.blocks_peer("mallory", &local).await);
+        assert!(!moderation.blocks_peer("bob", &local).await);
+
+        // A new peer id does not get around a block
+        let renamed = CryptoManager::from_signing_key("fresh".to_string(), "M".to_string(), key);
+        let from_renamed = NetworkMessage::SignedChat(renamed.sign_message("hi", 1).unwrap());
+        assert!(moderation.filter(from_renamed, &local).await.is_none());
+
+        let carol = CryptoManager::new("carol".to_string(), "Carol".to_string());
+        let history = NetworkMessage::HistoryResponse {
+            peer_id: "carol".to_string(),
+            messages: vec![
+                renamed.sign_message("blocked", 1).unwrap(),
+                bob.sign_message("muted", 1).unwrap(),
+                carol.sign_message("kept", 1).unwrap(),
+            ],
+            next: None,
+        };
+        match moderation.filter(history, &local).await {
+            Some(NetworkMessage::HistoryResponse { messages: kept, .. }) => {
+                assert_eq!(kept.len(), 1);
+                assert_eq!(kept[0].message, "kept");
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+
+        assert!(moderation.filter(unsigned("carol"), &local).await.is_some());
+        moderation.set_ignore_unsigned(true).unwrap();
+        assert!(moderation.filter(unsigned("carol"), &local).await.is_none());
+    }
+
+    #[test]
+    fn test_entries_are_found_by_name_or_a_long_enough_key_prefix() {
+        let moderation = Moderation::default();
+        let mut first = [0xab; 32];
+        first[31] = 1;
+        let mut second = [0xab; 32];
+        second[31] = 2;
+        moderation.block(&first, "Mallory").unwrap();
+        moderation.block(&second, "Eve").unwrap();
+        let blocked = moderation.lists().blocked;
+
+        assert_eq!(
+            ModerationLists::find(&blocked, "Eve").unwrap(),
+            Some(hex::encode(second))
+        );
+        assert_eq!(
+            ModerationLists::find(&blocked, &hex::encode(first).to_uppercase()).unwrap(),
+            Some(hex::encode(first))
+        );
+        // Too short to stand for a key
+        assert_eq!(ModerationLists::find(&blocked, "a").unwrap(), None);
+        assert_eq!(ModerationLists::find(&blocked, "").unwrap(), None);
+        // Ambiguous prefixes name the candidates
+        let error = ModerationLists::find(&blocked, "abababab").unwrap_err();
+        assert!(error.to_string().contains("Mallory"), "{error}");
+        assert!(error.to_string().contains("Eve"), "{error}");
+    }
+
+    #[test]
+    fn test_lists_survive_a_restart() {
+        let dir = std::env::temp_dir().join(format!("p2p-chat-{}", uuid::Uuid::new_v4()));
+        let moderation = Moderation::load(&dir).unwrap();
+        moderation.block(&[1; 32], "Mallory").unwrap();
+        moderation.mute(&[2; 32], "Bob").unwrap();
+        moderation.set_ignore_unsigned(true).unwrap();
+
+        let reloaded = Moderation::load(&dir).unwrap();
+        assert_eq!(reloaded.lists(), moderation.lists());
+        assert!(reloaded.is_blocked(&[1; 32]));
+        assert_eq!(
+            ModerationLists::find(&reloaded.lists().muted, "Bob").unwrap(),
+            Some(hex::encode([2; 32]))
+        );
+        assert!(reloaded.unblock(&[1; 32]).unwrap());
+        assert!(!Moderation::load(&dir).unwrap().is_blocked(&[1; 32]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,131 @@
+//! Bootstrap module: Connects to peers by address, without relying on mDNS.
+//!
+//! Used for every discovery backend, the `/connect` command and peer exchange
+//! candidates. Each connection starts with a direct discovery
+//! handshake in one room; the peer is added to that room's peers only once it has answered,
+//! after which we announce our identity, offer our history of the room and show it the
+//! expulsions decided there. Blocked peers are never added.
+
+use crate::chat::event::ChatEvent;
+use crate::chat::room::Room;
+use crate::chat::Peer;
+use crate::error::ChatError;
+use crate::network::handlers::history::send_history_summary;
+use crate::network::handlers::upgrade::{retry_decisions, send_decisions};
+use crate::network::tcp::handshake;
+use crate::peer::{link_local_scope, PeerInfo, MAX_PEER_ADDRESSES};
+use std::net::SocketAddr;
+
+/// Resolve a `host:port` string to a socket address
+pub async fn resolve(addr: &str) -> Result<SocketAddr, ChatError> {
+    tokio::net::lookup_host(addr)
+        .await
+        .map_err(|e| ChatError::Network(format!("Cannot resolve {addr}: {e}")))?
+        .next()
+        .ok_or_else(|| ChatError::Network(format!("No address found for {addr}")))
+}
+
+/// Handshake with the peer at `addr` in `room` and add it to the room's peer list.
+///
+/// When `expected_id` is given, the remote peer must answer with that id.
+pub async fn connect_to(
+    peer: &Peer,
+    room: &Room,
+    addr: SocketAddr,
+    expected_id: Option<&str>,
+) -> Result<PeerInfo, ChatError> {
+    let (info, capabilities) = handshake(
+        &*peer.transport,
+        addr,
+        room,
+        &peer.crypto_manager,
+        peer.protocols.local(),
+        peer.port,
+    )
+    .await?;
+    if info.id == peer.peer_id {
+        return Err(ChatError::Network(format!("{addr} is ourselves")));
+    }
+    // Blocked and expelled peers stay out, whichever side starts the handshake
+    if peer
+        .moderation
+        .blocks_peer(&info.id, &peer.crypto_manager)
+        .await
+    {
+        return Err(ChatError::Network(format!("{addr} is blocked")));
+    }
+    if let Some(expected) = expected_id {
+        if info.id != expected {
+            return Err(ChatError::Network(format!(
+                "{addr} answered as {} instead of {expected}",
+                info.id
+            )));
+        }
+    }
+    // Keep the address we reached it on as the primary one; the other advertised
+    // addresses stay as fallbacks
+    let mut addresses = info.addresses.clone();
+    if info.ip != addr.ip() {
+        addresses.insert(0, info.ip);
+    }
+    addresses.retain(|ip| *ip != addr.ip());
+    addresses.truncate(MAX_PEER_ADDRESSES);
+    let confirmed = PeerInfo {
+        ip: addr.ip(),
+        port: addr.port(),
+        addresses,
+        scope_id: link_local_scope(&addr).unwrap_or(0),
+        ..info
+    };
+    if !confirmed.is_valid() {
+        return Err(ChatError::Network(format!(
+            "{addr} answered with invalid peer info: {confirmed:?}"
+        )));
+    }
+
+    peer.protocols.record(&confirmed.id, capabilities);
+
+    let is_new = room
+        .peers
+        .lock()
+        .await
+        .insert(confirmed.id.clone(), confirmed.clone())
+        .is_none();
+    if is_new {
+        let _ = peer.events.send(ChatEvent::PeerJoined {
+            room: room.id.clone(),
+            peer: confirmed.clone(),
+        });
+        if let Err(e) = crate::chat::net::broadcast::send_identity(peer, room, &confirmed).await {
+            eprintln!("Failed to announce identity to {}: {e}", confirmed.name);
+        }
+        let peer_id = peer.peer_id.clone();
+        send_history_summary(&*peer.transport, &peer.protocols, room, peer_id, &confirmed).await;
+        send_decisions(&*peer.transport, &peer.protocols, room, &confirmed).await;
+        // Decisions we could not check may have been taken by the peer that just joined
+        retry_decisions(&peer.handler_context(), room).await;
+    }
+    Ok(confirmed)
+}
+
+/// Handshake with a known peer in `room` on each of its advertised addresses until one answers
+pub async fn connect_to_candidates(
+    peer: &Peer,
+    room: &Room,
+    info: &PeerInfo,
+) -> Result<PeerInfo, ChatError> {
+    let mut last_error = None;
+    for addr in info.candidate_addrs() {
+        match connect_to(peer, room, addr, Some(&info.id)).await {
+            Ok(confirmed) => return Ok(confirmed),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| ChatError::Network(format!("{} has no address", info.name))))
+}
+
+/// Resolve and connect to a `host:port` string in the current room (used by `/connect`)
+pub async fn connect_to_str(peer: &Peer, addr: &str) -> Result<PeerInfo, ChatError> {
+    let socket_addr = resolve(addr).await?;
+    connect_to(peer, &peer.room(), socket_addr, None).await
+}
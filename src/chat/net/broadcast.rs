@@ -1,16 +1,60 @@
+use crate::chat::event::ChatEvent;
+use crate::chat::roles::{Action, RoleGrant};
+use crate::chat::room::Room;
 use crate::chat::Peer;
 use crate::error::ChatError;
-use crate::peer::{Message, NetworkMessage};
+use crate::network::tcp::{send_message, Outgoing};
+use crate::peer::{Message, NetworkMessage, PeerInfo};
 use crate::crypto::CryptoError;
-use serde_json;
 use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
 
+/// Write `outgoing` to `target` on a fresh connection, in the codec negotiated with it;
+/// failures are published as `ChatEvent::DeliveryFailed`. Blocked peers are skipped.
+async fn deliver(peer: &Peer, room: &Room, target: &PeerInfo, outgoing: &mut Outgoing<'_>) -> bool {
+    if is_blocked(peer, target).await {
+        return false;
+    }
+    let bytes = match outgoing.encode(peer.protocols.codec_for(&target.id)) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            delivery_failed(peer, room, target, e);
+            return false;
+        }
+    };
+    let sent = async {
+        let mut stream = peer.transport.connect(target.socket_addr()).await?;
+        stream.write_all(bytes).await
+    };
+    match sent.await {
+        Ok(()) => true,
+        Err(e) => {
+            delivery_failed(peer, room, target, e);
+            false
+        }
+    }
+}
+
+async fn is_blocked(peer: &Peer, target: &PeerInfo) -> bool {
+    peer.moderation.blocks_peer(&target.id, &peer.crypto_manager).await
+}
+
+fn delivery_failed(peer: &Peer, room: &Room, target: &PeerInfo, error: impl std::fmt::Display) {
+    let _ = peer.events.send(ChatEvent::DeliveryFailed {
+        room: room.id.clone(),
+        peer: target.clone(),
+        error: error.to_string(),
+    });
+}
+
+/// Broadcast a message to the peers of the current room
 pub async fn broadcast_message(peer: &Peer, content: &str) -> Result<(), ChatError> {
+    let room = peer.room();
+    let own_key = peer.crypto_manager.get_public_key();
+    room.roles.require(Some(&own_key), Action::Post)?;
     // Check if secure-only messaging is enabled
-    if peer.threshold_manager.is_secure_only_enabled().await {
+    if room.threshold_manager.is_secure_only_enabled().await {
         println!("🔐 Secure-only messaging is enabled - all messages must be signed");
-        return broadcast_signed_message(peer, content).await;
+        return broadcast_signed_message(peer, &room, content).await;
     }
     
     let timestamp = std::time::SystemTime::now()
@@ -18,8 +62,12 @@ pub async fn broadcast_message(peer: &Peer, content: &str) -> Result<(), ChatErr
         .map_err(|e| ChatError::Unknown(e.to_string()))?
         .as_secs();
 
-    // Create a signed message for cryptographic authenticity
-    let signed_message = peer.crypto_manager.sign_message(content, timestamp)?;
+    // Create a signed message for cryptographic authenticity, stamped with our
+    // hybrid logical clock so receivers can order it causally
+    let signed_message = peer
+        .crypto_manager
+        .sign_message_with_clock(content, timestamp, peer.clock.now())?;
+    room.history.insert(signed_message.clone()).await;
     
     // Create both regular and signed message formats for compatibility
     let regular_message = Message {
@@ -29,16 +77,22 @@ pub async fn broadcast_message(peer: &Peer, content: &str) -> Result<(), ChatErr
         timestamp,
         signature: Some(signed_message.signature.clone()),
         public_key: Some(signed_message.public_key.clone()),
+        clock: signed_message.clock.clone(),
     };
     
-    let signed_network_msg = NetworkMessage::SignedChat(signed_message);
-    let regular_network_msg = NetworkMessage::Chat(regular_message);
+    // Wrapped in a gossip envelope when relaying is enabled
+    let signed_network_msg = peer
+        .gossip
+        .wrap(&peer.peer_id, NetworkMessage::SignedChat(signed_message));
+    let regular_network_msg = peer
+        .gossip
+        .wrap(&peer.peer_id, NetworkMessage::Chat(regular_message));
     
     // Send both message types for maximum compatibility
-    let signed_msg_bytes = serde_json::to_vec(&signed_network_msg)?;
-    let regular_msg_bytes = serde_json::to_vec(&regular_network_msg)?;
+    let mut signed_outgoing = Outgoing::new(&room, &signed_network_msg);
+    let mut regular_outgoing = Outgoing::new(&room, &regular_network_msg);
     
-    let peers = peer.peers.lock().await;
+    let peers = room.peers.lock().await;
     let mut successful_sends = 0;
     
     for peer_info in peers.values() {
@@ -46,18 +100,34 @@ pub async fn broadcast_message(peer: &Peer, content: &str) -> Result<(), ChatErr
             eprintln!("Skipping invalid peer: {peer_info:?}");
             continue;
         }
+        if is_blocked(peer, peer_info).await {
+            continue;
+        }
         
-        if let Ok(mut stream) = TcpStream::connect((peer_info.ip, peer_info.port)).await {
-            // Try to send signed message first, fallback to regular if needed
-            let send_result = if stream.write_all(&signed_msg_bytes).await.is_ok() {
-                Ok(())
-            } else {
-                stream.write_all(&regular_msg_bytes).await
+        let codec = peer.protocols.codec_for(&peer_info.id);
+        let (signed_msg_bytes, regular_msg_bytes) =
+            match (signed_outgoing.encode(codec), regular_outgoing.encode(codec)) {
+                (Ok(signed), Ok(regular)) => (signed, regular),
+                (Err(e), _) | (_, Err(e)) => {
+                    delivery_failed(peer, &room, peer_info, e);
+                    continue;
+                }
             };
-            
-            if send_result.is_ok() {
-                successful_sends += 1;
+        match peer.transport.connect(peer_info.socket_addr()).await {
+            Ok(mut stream) => {
+                // Try to send signed message first, fallback to regular if needed
+                let send_result = if stream.write_all(signed_msg_bytes).await.is_ok() {
+                    Ok(())
+                } else {
+                    stream.write_all(regular_msg_bytes).await
+                };
+
+                match send_result {
+                    Ok(()) => successful_sends += 1,
+                    Err(e) => delivery_failed(peer, &room, peer_info, e),
+                }
             }
+            Err(e) => delivery_failed(peer, &room, peer_info, e),
         }
     }
     
@@ -72,19 +142,25 @@ pub async fn broadcast_message(peer: &Peer, content: &str) -> Result<(), ChatErr
 }
 
 /// Broadcast a message with mandatory cryptographic signing
-async fn broadcast_signed_message(peer: &Peer, content: &str) -> Result<(), ChatError> {
+async fn broadcast_signed_message(peer: &Peer, room: &Room, content: &str) -> Result<(), ChatError> {
     let timestamp = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .map_err(|e| ChatError::Unknown(e.to_string()))?
         .as_secs();
 
-    // Create a signed message for cryptographic authenticity
-    let signed_message = peer.crypto_manager.sign_message(content, timestamp)?;
+    // Create a signed message for cryptographic authenticity, stamped with our
+    // hybrid logical clock so receivers can order it causally
+    let signed_message = peer
+        .crypto_manager
+        .sign_message_with_clock(content, timestamp, peer.clock.now())?;
+    room.history.insert(signed_message.clone()).await;
     
-    let signed_network_msg = NetworkMessage::SignedChat(signed_message);
-    let msg_bytes = serde_json::to_vec(&signed_network_msg)?;
+    let signed_network_msg = peer
+        .gossip
+        .wrap(&peer.peer_id, NetworkMessage::SignedChat(signed_message));
+    let mut outgoing = Outgoing::new(room, &signed_network_msg);
     
-    let peers = peer.peers.lock().await;
+    let peers = room.peers.lock().await;
     let mut successful_sends = 0;
     
     for peer_info in peers.values() {
@@ -93,10 +169,8 @@ async fn broadcast_signed_message(peer: &Peer, content: &str) -> Result<(), Chat
             continue;
         }
         
-        if let Ok(mut stream) = TcpStream::connect((peer_info.ip, peer_info.port)).await {
-            if stream.write_all(&msg_bytes).await.is_ok() {
-                successful_sends += 1;
-            }
+        if deliver(peer, room, peer_info, &mut outgoing).await {
+            successful_sends += 1;
         }
     }
     
@@ -112,8 +186,11 @@ async fn broadcast_signed_message(peer: &Peer, content: &str) -> Result<(), Chat
 
 /// Broadcast a message without cryptographic signing
 pub async fn broadcast_unsigned_message(peer: &Peer, content: &str) -> Result<(), ChatError> {
+    let room = peer.room();
+    let own_key = peer.crypto_manager.get_public_key();
+    room.roles.require(Some(&own_key), Action::Post)?;
     // Check if secure-only messaging is enabled
-    if peer.threshold_manager.is_secure_only_enabled().await {
+    if room.threshold_manager.is_secure_only_enabled().await {
         return Err(ChatError::Unknown("Cannot send unsigned messages when secure-only messaging is enabled".to_string()));
     }
     
@@ -130,12 +207,15 @@ pub async fn broadcast_unsigned_message(peer: &Peer, content: &str) -> Result<()
         timestamp,
         signature: None,
         public_key: None,
+        clock: Some(peer.clock.now()),
     };
     
-    let network_msg = NetworkMessage::Chat(unsigned_message);
-    let msg_bytes = serde_json::to_vec(&network_msg)?;
+    let network_msg = peer
+        .gossip
+        .wrap(&peer.peer_id, NetworkMessage::Chat(unsigned_message));
+    let mut outgoing = Outgoing::new(&room, &network_msg);
     
-    let peers = peer.peers.lock().await;
+    let peers = room.peers.lock().await;
     let mut successful_sends = 0;
     
     for peer_info in peers.values() {
@@ -144,10 +224,8 @@ pub async fn broadcast_unsigned_message(peer: &Peer, content: &str) -> Result<()
             continue;
         }
         
-        if let Ok(mut stream) = TcpStream::connect((peer_info.ip, peer_info.port)).await {
-            if stream.write_all(&msg_bytes).await.is_ok() {
-                successful_sends += 1;
-            }
+        if deliver(peer, &room, peer_info, &mut outgoing).await {
+            successful_sends += 1;
         }
     }
     
@@ -161,8 +239,9 @@ pub async fn broadcast_unsigned_message(peer: &Peer, content: &str) -> Result<()
     Ok(())
 }
 
-/// Broadcast the peer's identity with public key to all known peers
+/// Broadcast the peer's identity with public key to all known peers of the current room
 pub async fn broadcast_identity(peer: &Peer) -> Result<(), ChatError> {
+    let room = peer.room();
     let identity = peer.crypto_manager.get_identity();
     let network_msg = NetworkMessage::IdentityAnnouncement {
         peer_id: identity.peer_id.clone(),
@@ -170,8 +249,8 @@ pub async fn broadcast_identity(peer: &Peer) -> Result<(), ChatError> {
         public_key: identity.public_key.clone(),
     };
     
-    let msg_bytes = serde_json::to_vec(&network_msg)?;
-    let peers = peer.peers.lock().await;
+    let mut outgoing = Outgoing::new(&room, &network_msg);
+    let peers = room.peers.lock().await;
     let mut successful_sends = 0;
     
     for peer_info in peers.values() {
@@ -179,10 +258,8 @@ pub async fn broadcast_identity(peer: &Peer) -> Result<(), ChatError> {
             continue;
         }
         
-        if let Ok(mut stream) = TcpStream::connect((peer_info.ip, peer_info.port)).await {
-            if stream.write_all(&msg_bytes).await.is_ok() {
-                successful_sends += 1;
-            }
+        if deliver(peer, &room, peer_info, &mut outgoing).await {
+            successful_sends += 1;
         }
     }
     
@@ -193,15 +270,36 @@ pub async fn broadcast_identity(peer: &Peer) -> Result<(), ChatError> {
     Ok(())
 }
 
-/// Broadcast an upgrade proposal to all peers
+/// Announce our identity with public key to a single peer of `room`
+pub async fn send_identity(peer: &Peer, room: &Room, target: &PeerInfo) -> Result<(), ChatError> {
+    let identity = peer.crypto_manager.get_identity();
+    let network_msg = NetworkMessage::IdentityAnnouncement {
+        peer_id: identity.peer_id.clone(),
+        name: identity.name.clone(),
+        public_key: identity.public_key.clone(),
+    };
+    send_message(
+        &*peer.transport,
+        peer.protocols.codec_for(&target.id),
+        target.socket_addr(),
+        room,
+        &network_msg,
+    )
+    .await
+}
+
+/// Broadcast an upgrade proposal to all peers of the current room
 pub async fn broadcast_upgrade_proposal(peer: &Peer, proposal_id: &str) -> Result<(), ChatError> {
-    let proposal = peer.threshold_manager.get_proposal(proposal_id).await
+    let room = peer.room();
+    let proposal = room.threshold_manager.get_proposal(proposal_id).await
         .ok_or(ChatError::Unknown("Proposal not found".to_string()))?;
     
-    let network_msg = NetworkMessage::UpgradeRequest(proposal);
-    let msg_bytes = serde_json::to_vec(&network_msg)?;
+    let network_msg = peer
+        .gossip
+        .wrap(&peer.peer_id, NetworkMessage::UpgradeRequest(proposal));
+    let mut outgoing = Outgoing::new(&room, &network_msg);
     
-    let peers = peer.peers.lock().await;
+    let peers = room.peers.lock().await;
     let mut successful_sends = 0;
     
     for peer_info in peers.values() {
@@ -209,10 +307,8 @@ pub async fn broadcast_upgrade_proposal(peer: &Peer, proposal_id: &str) -> Resul
             continue;
         }
         
-        if let Ok(mut stream) = TcpStream::connect((peer_info.ip, peer_info.port)).await {
-            if stream.write_all(&msg_bytes).await.is_ok() {
-                successful_sends += 1;
-            }
+        if deliver(peer, &room, peer_info, &mut outgoing).await {
+            successful_sends += 1;
         }
     }
     
@@ -223,18 +319,64 @@ pub async fn broadcast_upgrade_proposal(peer: &Peer, proposal_id: &str) -> Resul
     Ok(())
 }
 
-/// Broadcast the vote to all peers
+/// Broadcast an application-defined message to all peers of the current room
+pub async fn broadcast_custom(
+    peer: &Peer,
+    kind: &str,
+    payload: serde_json::Value,
+) -> Result<(), ChatError> {
+    let room = peer.room();
+    let network_msg = peer.gossip.wrap(
+        &peer.peer_id,
+        NetworkMessage::Custom {
+            peer_id: peer.peer_id.clone(),
+            kind: kind.to_string(),
+            payload,
+        },
+    );
+    let mut outgoing = Outgoing::new(&room, &network_msg);
+
+    let peers = room.peers.lock().await;
+    for peer_info in peers.values() {
+        if peer_info.is_valid() {
+            deliver(peer, &room, peer_info, &mut outgoing).await;
+        }
+    }
+    Ok(())
+}
+
+/// Broadcast a role grant to all peers of the current room
+pub async fn broadcast_role_grant(peer: &Peer, grant: RoleGrant) -> Result<(), ChatError> {
+    let room = peer.room();
+    let network_msg = peer
+        .gossip
+        .wrap(&peer.peer_id, NetworkMessage::RoleGrant(grant));
+    let mut outgoing = Outgoing::new(&room, &network_msg);
+
+    let peers = room.peers.lock().await;
+    for peer_info in peers.values() {
+        if peer_info.is_valid() {
+            deliver(peer, &room, peer_info, &mut outgoing).await;
+        }
+    }
+    Ok(())
+}
+
+/// Broadcast the vote to all peers of the current room
 pub async fn broadcast_proposal_vote(peer: &Peer, proposal_id: &str, approved: bool) -> Result<(), ChatError> {
-    let votes = peer.threshold_manager.get_proposal_votes(proposal_id).await;
+    let room = peer.room();
+    let votes = room.threshold_manager.get_proposal_votes(proposal_id).await;
     let my_vote = votes.iter()
         .find(|v| v.voter_id == peer.peer_id)
         .ok_or(CryptoError::Unknown("Vote not found".to_string()))?;
     
     //TODO send the vote directly, instead of reading from state
-    let network_msg = NetworkMessage::UpgradeVote(my_vote.clone());
-    let msg_bytes = serde_json::to_vec(&network_msg)?;
+    let network_msg = peer
+        .gossip
+        .wrap(&peer.peer_id, NetworkMessage::UpgradeVote(my_vote.clone()));
+    let mut outgoing = Outgoing::new(&room, &network_msg);
     
-    let peers = peer.peers.lock().await;
+    let peers = room.peers.lock().await;
     let mut successful_sends = 0;
     
     for peer_info in peers.values() {
@@ -242,10 +384,8 @@ pub async fn broadcast_proposal_vote(peer: &Peer, proposal_id: &str, approved: b
             continue;
         }
         
-        if let Ok(mut stream) = TcpStream::connect((peer_info.ip, peer_info.port)).await {
-            if stream.write_all(&msg_bytes).await.is_ok() {
-                successful_sends += 1;
-            }
+        if deliver(peer, &room, peer_info, &mut outgoing).await {
+            successful_sends += 1;
         }
     }
     
@@ -270,6 +410,8 @@ mod tests {
             name: "Peer1".to_string(),
             ip: IpAddr::from_str("192.168.1.10").unwrap(),
             port: 9000,
+            addresses: Vec::new(),
+            scope_id: 0,
         };
         assert!(valid_peer.is_valid());
 
@@ -278,6 +420,8 @@ mod tests {
             name: "".to_string(),
             ip: IpAddr::from_str("0.0.0.0").unwrap(),
             port: 0,
+            addresses: Vec::new(),
+            scope_id: 0,
         };
         assert!(!invalid_peer.is_valid());
     }
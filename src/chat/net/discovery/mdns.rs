@@ -0,0 +1,294 @@
+//! mDNS discovery backend: Advertises the peer as a `_chat._udp` service and browses for others.
+//!
+//! The advertisement runs on a blocking thread (`libmdns::Responder`), with one service per
+//! joined public room. Its TXT record carries the room, the peer's id, name, addresses, port,
+//! public key and a signature over them with an expiry, and is re-registered before it expires
+//! or when the set of joined rooms changes. Discovery listens to `mdns::discover` responses
+//! and only reports peers whose TXT record is complete and whose SRV port and A/AAAA
+//! records match what was signed; the signature itself is checked before the peer is
+//! contacted.
+
+use super::{Discovery, DiscoveryEvent, LocalPeer};
+use crate::crypto::SignedPeerInfo;
+use crate::error::ChatError;
+use crate::network::interfaces;
+use crate::peer::PeerInfo;
+use async_trait::async_trait;
+use futures_util::{pin_mut, stream::StreamExt};
+use libmdns;
+use mdns::{Record, RecordKind, Response};
+use std::{net::IpAddr, time::Duration};
+use tokio::sync::mpsc;
+
+const SERVICE_NAME: &str = "_chat._udp.local";
+
+/// Lifetime of the signed TXT record, in seconds
+const ADVERT_LIFETIME: u64 = 600;
+
+/// How often the advertisement is re-signed and re-registered
+const ADVERT_REFRESH: Duration = Duration::from_secs(300);
+
+/// How often the joined rooms are checked for changes
+const ROOM_POLL: Duration = Duration::from_secs(5);
+
+/// How often the advertising thread checks whether the peer is shutting down
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+/// Longest string allowed in a TXT record
+const MAX_TXT_LEN: usize = 255;
+
+/// Discovers peers with multicast DNS
+pub struct MdnsDiscovery;
+
+#[async_trait]
+impl Discovery for MdnsDiscovery {
+    fn name(&self) -> &'static str {
+        "mDNS"
+    }
+
+    async fn run(
+        self: Box<Self>,
+        local: LocalPeer,
+        events: mpsc::Sender<DiscoveryEvent>,
+    ) -> Result<(), ChatError> {
+        // Spawn advertisement in a blocking thread
+        let advert = local.clone();
+        let advertiser = tokio::task::spawn_blocking(move || {
+            let responder = match libmdns::Responder::new() {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("[ERROR] Failed to create mDNS responder: {e}");
+                    return;
+                }
+            };
+            let mut rooms = Vec::new();
+            let mut registered_at: Option<std::time::Instant> = None;
+            let mut _services = Vec::new();
+            while !advert.cancel.is_cancelled() {
+                // Re-sign before the previous records expire or when rooms were joined or
+                // left; dropping the old services unregisters them
+                let current = advert.rooms.public_ids();
+                let due = registered_at.is_none_or(|at| at.elapsed() >= ADVERT_REFRESH);
+                if due || current != rooms {
+                    rooms = current;
+                    registered_at = Some(std::time::Instant::now());
+                    _services = rooms
+                        .iter()
+                        .filter_map(|room| match signed_txt(&advert, room) {
+                            Ok(txt) => {
+                                let txt: Vec<&str> = txt.iter().map(String::as_str).collect();
+                                Some(responder.register(
+                                    "_chat._udp".to_owned(),
+                                    format!("{}-{}-{}", advert.name, room, advert.id),
+                                    advert.port,
+                                    &txt,
+                                ))
+                            }
+                            Err(e) => {
+                                eprintln!("[ERROR] Failed to build mDNS advertisement: {e}");
+                                None
+                            }
+                        })
+                        .collect();
+                }
+                let slept = std::time::Instant::now();
+                while slept.elapsed() < ROOM_POLL && !advert.cancel.is_cancelled() {
+                    std::thread::sleep(CANCEL_POLL);
+                }
+            }
+            // Dropping the services on return unregisters them
+        });
+
+        // Discovery
+        let stream = mdns::discover::all(SERVICE_NAME, Duration::from_secs(15))
+            .map_err(|e| ChatError::Network(e.to_string()))?
+            .listen();
+        pin_mut!(stream);
+        loop {
+            let response = tokio::select! {
+                response = stream.next() => response,
+                _ = local.cancel.cancelled() => None,
+            };
+            let Some(Ok(response)) = response else {
+                break;
+            };
+            let Some((room, announcement)) = parse_response(&response) else {
+                continue;
+            };
+            // Ignore self and rooms we are not in
+            if announcement.info.id == local.id || local.rooms.get_public(&room).is_none() {
+                continue;
+            }
+            if events
+                .send(DiscoveryEvent::PeerAnnounced(room, announcement))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        let _ = advertiser.await;
+        Ok(())
+    }
+}
+
+/// TXT record entries advertising the local peer in `room`, signed with its key
+fn signed_txt(local: &LocalPeer, room: &str) -> Result<Vec<String>, ChatError> {
+    let ip = interfaces::primary_address()
+        .ok_or_else(|| ChatError::Network("No usable local address".to_string()))?;
+    // The responder answers on every interface, so advertise all their addresses, as many
+    // as fit into a single TXT string
+    let mut addresses = interfaces::other_addresses(ip);
+    while format_addrs(&addresses).len() > MAX_TXT_LEN {
+        addresses.pop();
+    }
+    let signed = local.crypto_manager.sign_peer_info(
+        PeerInfo {
+            id: local.id.clone(),
+            name: local.name.clone(),
+            ip,
+            port: local.port,
+            addresses,
+            scope_id: 0,
+        },
+        ADVERT_LIFETIME,
+    );
+    Ok(to_txt(room, &signed))
+}
+
+fn format_addrs(addresses: &[IpAddr]) -> String {
+    let addrs: Vec<String> = addresses.iter().map(IpAddr::to_string).collect();
+    format!("addrs={}", addrs.join(","))
+}
+
+fn to_txt(room: &str, signed: &SignedPeerInfo) -> Vec<String> {
+    vec![
+        "app=p2pchat".to_string(),
+        format!("room={room}"),
+        format!("peer_id={}", signed.info.id),
+        format!("name={}", signed.info.name),
+        format!("ip={}", signed.info.ip),
+        format!("port={}", signed.info.port),
+        format_addrs(&signed.info.addresses),
+        format!("expires={}", signed.expires_at),
+        format!("pk={}", hex::encode(&signed.public_key)),
+        format!("sig={}", hex::encode(&signed.signature)),
+    ]
+}
+
+/// Rebuild the room and signed announcement from TXT entries. Every field is required.
+fn from_txt(txts: &[String]) -> Option<(String, SignedPeerInfo)> {
+    let field = |key: &str| {
+        txts.iter()
+            .find_map(|txt| txt.strip_prefix(key)?.strip_prefix('='))
+    };
+    if field("app")? != "p2pchat" {
+        return None;
+    }
+    let room = field("room")?.to_string();
+    let signed = SignedPeerInfo {
+        info: PeerInfo {
+            id: field("peer_id")?.to_string(),
+            name: field("name")?.to_string(),
+            ip: field("ip")?.parse().ok()?,
+            port: field("port")?.parse().ok()?,
+            addresses: field("addrs")?
+                .split(',')
+                .filter(|ip| !ip.is_empty())
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .ok()?,
+            scope_id: 0,
+        },
+        expires_at: field("expires")?.parse().ok()?,
+        public_key: hex::decode(field("pk")?).ok()?,
+        signature: hex::decode(field("sig")?).ok()?,
+    };
+    Some((room, signed))
+}
+
+/// Build a signed announcement from an mDNS response, skipping incomplete or inconsistent
+/// records. Nothing is filled in from other sources: the name, address and port must all
+/// be the ones the peer signed.
+fn parse_response(response: &Response) -> Option<(String, SignedPeerInfo)> {
+    let (room, announcement) = response.records().find_map(|r| match &r.kind {
+        RecordKind::TXT(txts) => from_txt(txts),
+        _ => None,
+    })?;
+    let srv_port = response.records().find_map(|r| match &r.kind {
+        RecordKind::SRV { port, .. } => Some(*port),
+        _ => None,
+    });
+    if srv_port != Some(announcement.info.port) {
+        eprintln!(
+            "⚠️  Warning: mDNS service port does not match the signed port of {}",
+            announcement.info.name
+        );
+        return None;
+    }
+    // At least one signed address must be one the responder actually answers for
+    if !response
+        .records()
+        .filter_map(to_ip_addr)
+        .any(|ip| announcement.info.advertises(ip))
+    {
+        eprintln!(
+            "⚠️  Warning: mDNS records do not include the signed address of {}",
+            announcement.info.name
+        );
+        return None;
+    }
+    if !announcement.info.is_valid() {
+        eprintln!(
+            "⚠️  Warning: Discovered peer has invalid PeerInfo. {:?}",
+            announcement.info
+        );
+        return None;
+    }
+    Some((room, announcement))
+}
+
+fn to_ip_addr(record: &Record) -> Option<IpAddr> {
+    match record.kind {
+        RecordKind::A(addr) => Some(addr.into()),
+        RecordKind::AAAA(addr) => Some(addr.into()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::CryptoManager;
+
+    #[test]
+    fn test_txt_round_trip() {
+        let manager = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let signed = manager.sign_peer_info(
+            PeerInfo {
+                id: "alice".to_string(),
+                name: "Alice".to_string(),
+                ip: "192.168.1.20".parse().unwrap(),
+                port: 9000,
+                addresses: vec!["10.0.0.20".parse().unwrap()],
+                scope_id: 0,
+            },
+            ADVERT_LIFETIME,
+        );
+        let txt = to_txt("dev", &signed);
+        assert!(txt.iter().all(|entry| entry.len() <= MAX_TXT_LEN));
+
+        let (room, parsed) = from_txt(&txt).unwrap();
+        assert_eq!(room, "dev");
+        assert!(parsed.verify().is_ok());
+        assert_eq!(parsed.info.name, "Alice");
+        assert_eq!(parsed.info.addresses, signed.info.addresses);
+
+        // Records without a signature are not filled in from elsewhere
+        let unsigned: Vec<String> = txt
+            .into_iter()
+            .filter(|entry| !entry.starts_with("sig="))
+            .collect();
+        assert!(from_txt(&unsigned).is_none());
+    }
+}
@@ -0,0 +1,236 @@
+//! In-memory discovery backend: Peers in the same process find each other through a shared
+//! registry. Intended for tests and embedded setups where no real network discovery is wanted.
+//! Peers register with the public rooms they are in when the backend starts and are reported in
+//! every room they share with the local peer.
+
+use super::{Discovery, DiscoveryEvent, LocalPeer};
+use crate::error::ChatError;
+use crate::peer::PeerInfo;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
+
+#[derive(Debug, Clone)]
+enum RegistryEvent {
+    Joined(PeerInfo, Vec<String>),
+    Left(String, Vec<String>),
+}
+
+/// A registered peer and the rooms it is in
+type Entry = (PeerInfo, Vec<String>);
+
+/// Shared registry of in-process peers, with the rooms each of them is in
+#[derive(Clone)]
+pub struct MemoryRegistry {
+    peers: Arc<Mutex<HashMap<String, Entry>>>,
+    events: broadcast::Sender<RegistryEvent>,
+}
+
+impl Default for MemoryRegistry {
+    fn default() -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            peers: Arc::new(Mutex::new(HashMap::new())),
+            events,
+        }
+    }
+}
+
+impl MemoryRegistry {
+    /// Remove a peer from the registry; the others receive `PeerLost`
+    pub fn leave(&self, peer_id: &str) {
+        let removed = self
+            .peers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(peer_id);
+        if let Some((_, rooms)) = removed {
+            let _ = self
+                .events
+                .send(RegistryEvent::Left(peer_id.to_string(), rooms));
+        }
+    }
+}
+
+/// Discovery through a `MemoryRegistry`
+pub struct MemoryDiscovery {
+    registry: MemoryRegistry,
+    /// Address advertised for the local peer
+    ip: IpAddr,
+}
+
+impl MemoryDiscovery {
+    pub fn new(registry: MemoryRegistry, ip: IpAddr) -> Self {
+        Self { registry, ip }
+    }
+}
+
+#[async_trait]
+impl Discovery for MemoryDiscovery {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn run(
+        self: Box<Self>,
+        local: LocalPeer,
+        events: mpsc::Sender<DiscoveryEvent>,
+    ) -> Result<(), ChatError> {
+        let me = PeerInfo {
+            id: local.id.clone(),
+            name: local.name.clone(),
+            ip: self.ip,
+            port: local.port,
+            addresses: Vec::new(),
+            scope_id: 0,
+        };
+        let my_rooms = local.rooms.public_ids();
+        // Subscribe before joining so no announcement is missed
+        let mut updates = self.registry.events.subscribe();
+        let existing: Vec<Entry> = {
+            let mut peers = self
+                .registry
+                .peers
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            peers.insert(me.id.clone(), (me.clone(), my_rooms.clone()));
+            peers.values().cloned().collect()
+        };
+        let _ = self
+            .registry
+            .events
+            .send(RegistryEvent::Joined(me, my_rooms));
+
+        for (info, rooms) in existing.into_iter().filter(|(p, _)| p.id != local.id) {
+            for room in shared_rooms(&local, &rooms) {
+                if events
+                    .send(DiscoveryEvent::PeerFound(room, info.clone()))
+                    .await
+                    .is_err()
+                {
+                    return Ok(());
+                }
+            }
+        }
+        loop {
+            let update = tokio::select! {
+                update = updates.recv() => update,
+                _ = local.cancel.cancelled() => {
+                    self.registry.leave(&local.id);
+                    return Ok(());
+                }
+            };
+            let found: Vec<DiscoveryEvent> = match update {
+                Ok(RegistryEvent::Joined(info, rooms)) if info.id != local.id => {
+                    shared_rooms(&local, &rooms)
+                        .map(|room| DiscoveryEvent::PeerFound(room, info.clone()))
+                        .collect()
+                }
+                Ok(RegistryEvent::Left(id, rooms)) if id != local.id => {
+                    shared_rooms(&local, &rooms)
+                        .map(|room| DiscoveryEvent::PeerLost(room, id.clone()))
+                        .collect()
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            };
+            for event in found {
+                if events.send(event).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// The rooms among `rooms` that the local peer has joined
+fn shared_rooms<'a>(
+    local: &'a LocalPeer,
+    rooms: &'a [String],
+) -> impl Iterator<Item = String> + 'a {
+    rooms
+        .iter()
+        .filter(|room| local.rooms.get_public(room).is_some())
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::room::RoomRegistry;
+    use crate::crypto::CryptoManager;
+    use std::str::FromStr;
+
+    fn local(id: &str, port: u16, rooms: &[&str]) -> LocalPeer {
+        let rooms: Vec<String> = rooms.iter().map(|r| r.to_string()).collect();
+        LocalPeer {
+            id: id.to_string(),
+            name: id.to_string(),
+            port,
+            crypto_manager: Arc::new(CryptoManager::new(id.to_string(), id.to_string())),
+            rooms: Arc::new(RoomRegistry::new(&rooms).unwrap()),
+            cancel: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_peers_find_each_other() {
+        let registry = MemoryRegistry::default();
+        let ip = IpAddr::from_str("10.0.0.1").unwrap();
+        let (a_tx, mut a_rx) = mpsc::channel(8);
+        let (b_tx, mut b_rx) = mpsc::channel(8);
+
+        tokio::spawn(
+            Box::new(MemoryDiscovery::new(registry.clone(), ip))
+                .run(local("a", 1, &["lobby", "dev"]), a_tx),
+        );
+        tokio::spawn(
+            Box::new(MemoryDiscovery::new(registry.clone(), ip))
+                .run(local("b", 2, &["dev", "ops"]), b_tx),
+        );
+
+        // Only the shared room is reported
+        let found_by_a = a_rx.recv().await.unwrap();
+        let found_by_b = b_rx.recv().await.unwrap();
+        assert!(
+            matches!(found_by_a, DiscoveryEvent::PeerFound(ref r, ref p) if r == "dev" && p.id == "b")
+        );
+        assert!(
+            matches!(found_by_b, DiscoveryEvent::PeerFound(ref r, ref p) if r == "dev" && p.id == "a")
+        );
+
+        registry.leave("b");
+        assert!(matches!(
+            a_rx.recv().await.unwrap(),
+            DiscoveryEvent::PeerLost(ref r, ref id) if r == "dev" && id == "b"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_leaves_the_registry() {
+        let registry = MemoryRegistry::default();
+        let ip = IpAddr::from_str("10.0.0.1").unwrap();
+        let (a_tx, mut a_rx) = mpsc::channel(8);
+        let (b_tx, _b_rx) = mpsc::channel(8);
+        let b = local("b", 2, &["dev"]);
+        let cancel = b.cancel.clone();
+
+        tokio::spawn(
+            Box::new(MemoryDiscovery::new(registry.clone(), ip)).run(local("a", 1, &["dev"]), a_tx),
+        );
+        let b_run = tokio::spawn(Box::new(MemoryDiscovery::new(registry.clone(), ip)).run(b, b_tx));
+        assert!(matches!(
+            a_rx.recv().await.unwrap(),
+            DiscoveryEvent::PeerFound(_, ref p) if p.id == "b"
+        ));
+
+        cancel.cancel();
+        assert!(b_run.await.unwrap().is_ok());
+        assert!(matches!(
+            a_rx.recv().await.unwrap(),
+            DiscoveryEvent::PeerLost(ref r, ref id) if r == "dev" && id == "b"
+        ));
+    }
+}
@@ -0,0 +1,261 @@
+//! Peer discovery module: Pluggable discovery backends feeding a single event stream.
+//!
+//! Every backend implements the `Discovery` trait: it advertises the local peer in its own
+//! way, once per joined room, and reports `DiscoveryEvent`s (peer found, address found, peer
+//! lost) on a channel. `start_discovery` runs the selected backends side by side and applies
+//! their events to the peer list of the room they name. New peers are only added after a
+//! direct discovery handshake in that room, whichever backend reported them.
+//! Private rooms are never advertised by name; only the UDP backend advertises them, with
+//! adverts that only members can recognize.
+
+pub mod mdns;
+pub mod memory;
+pub mod static_list;
+pub mod udp;
+
+use crate::chat::event::{ChatEvent, LeaveReason};
+use crate::chat::net::bootstrap::{connect_to, connect_to_candidates};
+use crate::chat::room::{Room, RoomRegistry};
+use crate::chat::Peer;
+use crate::crypto::{CryptoManager, SignedPeerInfo};
+use crate::error::ChatError;
+use crate::peer::PeerInfo;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Events reported by discovery backends. The first field of room-scoped events is the
+/// room id; events for rooms we have not joined are ignored.
+#[derive(Debug, Clone)]
+pub enum DiscoveryEvent {
+    /// A peer advertised itself in a room with its full peer info
+    PeerFound(String, PeerInfo),
+    /// A peer advertised itself in a room with peer info signed by its own key; the
+    /// signature is checked before the peer is contacted
+    PeerAnnounced(String, SignedPeerInfo),
+    /// An address that may host a peer (e.g. from a static list); its identity and rooms
+    /// are learned through a handshake in each joined room
+    AddressFound(SocketAddr),
+    /// An address advertised for a room without naming the peer (private room adverts);
+    /// the handshake proves who is there
+    RoomAddressFound(String, SocketAddr),
+    /// A peer is no longer advertised in a room
+    PeerLost(String, String),
+}
+
+/// What the local peer advertises about itself
+#[derive(Clone)]
+pub struct LocalPeer {
+    pub id: String,
+    pub name: String,
+    pub port: u16,
+    /// Used by backends that sign their announcements
+    pub crypto_manager: Arc<CryptoManager>,
+    /// Rooms to advertise; backends read it on every announcement, so joins and parts
+    /// show up without restarting them
+    pub rooms: Arc<RoomRegistry>,
+    /// Fires when the peer shuts down; backends stop advertising and return
+    pub cancel: CancellationToken,
+}
+
+impl From<&Peer> for LocalPeer {
+    fn from(peer: &Peer) -> Self {
+        Self {
+            id: peer.peer_id.clone(),
+            name: peer.name.clone(),
+            port: peer.port,
+            crypto_manager: peer.crypto_manager.clone(),
+            rooms: peer.rooms.clone(),
+            cancel: peer.cancel.clone(),
+        }
+    }
+}
+
+/// A discovery backend
+#[async_trait]
+pub trait Discovery: Send {
+    /// Short name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Advertise `local` and report discovered peers on `events` until the backend stops
+    /// or `local.cancel` fires
+    async fn run(
+        self: Box<Self>,
+        local: LocalPeer,
+        events: mpsc::Sender<DiscoveryEvent>,
+    ) -> Result<(), ChatError>;
+}
+
+/// Backends selectable from the command line and config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DiscoveryKind {
+    /// Multicast DNS service advertisement (`_chat._udp`)
+    Mdns,
+    /// Signed UDP broadcast and multicast announcements
+    Udp,
+    /// Static list of bootstrap peers
+    Static,
+}
+
+/// Backends used when nothing is configured
+pub const DEFAULT_DISCOVERY: &[DiscoveryKind] = &[
+    DiscoveryKind::Mdns,
+    DiscoveryKind::Udp,
+    DiscoveryKind::Static,
+];
+
+/// Build the backends for the given kinds, plus the one the peer's transport brings along
+pub fn build_backends(kinds: &[DiscoveryKind], peer: &Peer) -> Vec<Box<dyn Discovery>> {
+    let mut backends: Vec<Box<dyn Discovery>> = Vec::new();
+    for kind in kinds {
+        match kind {
+            DiscoveryKind::Mdns => backends.push(Box::new(mdns::MdnsDiscovery)),
+            DiscoveryKind::Udp => backends.push(Box::new(udp::UdpDiscovery::default())),
+            DiscoveryKind::Static => backends.push(Box::new(static_list::StaticDiscovery::new(
+                peer.bootstrap_peers.clone(),
+            ))),
+        }
+    }
+    backends.extend(peer.transport.discovery());
+    backends
+}
+
+/// Run the given backends and apply their events to the peer list
+pub async fn start_discovery(
+    peer: Arc<Peer>,
+    backends: Vec<Box<dyn Discovery>>,
+) -> Result<(), ChatError> {
+    let (events_tx, mut events) = mpsc::channel(64);
+    let local = LocalPeer::from(peer.as_ref());
+    for backend in backends {
+        let name = backend.name();
+        let events_tx = events_tx.clone();
+        let local = local.clone();
+        peer.tasks.spawn(async move {
+            if let Err(e) = backend.run(local, events_tx).await {
+                eprintln!("[ERROR] {name} discovery stopped: {e}");
+            }
+        });
+    }
+    // `events_tx` stays alive here, so the loop keeps running after every backend ends;
+    // peers can still join through `/connect` and peer exchange.
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = peer.cancel.cancelled() => None,
+        };
+        let Some(event) = event else {
+            return Ok(());
+        };
+        handle_event(&peer, event).await;
+    }
+}
+
+async fn handle_event(peer: &Arc<Peer>, event: DiscoveryEvent) {
+    match event {
+        DiscoveryEvent::PeerFound(room, info) => {
+            if let Some(room) = peer.rooms.get_public(&room) {
+                connect_discovered(peer, room, info).await;
+            }
+        }
+        DiscoveryEvent::PeerAnnounced(room, signed) => {
+            let Some(room) = peer.rooms.get_public(&room) else {
+                return;
+            };
+            if signed.info.id == peer.peer_id
+                || room.peers.lock().await.contains_key(&signed.info.id)
+            {
+                return;
+            }
+            if let Err(e) = peer.crypto_manager.verify_peer_info(&signed).await {
+                eprintln!(
+                    "⚠️  Warning: Dropping announcement from {} ({}): {e}",
+                    signed.info.name, signed.info.ip
+                );
+                return;
+            }
+            connect_discovered(peer, room, signed.info).await;
+        }
+        DiscoveryEvent::AddressFound(addr) => {
+            for room in peer.rooms.all() {
+                connect_address(peer, room, addr).await;
+            }
+        }
+        DiscoveryEvent::RoomAddressFound(room, addr) => {
+            if let Some(room) = peer.rooms.get(&room) {
+                connect_address(peer, room, addr).await;
+            }
+        }
+        DiscoveryEvent::PeerLost(room, peer_id) => {
+            let Some(room) = peer.rooms.get_public(&room) else {
+                return;
+            };
+            let removed = room.peers.lock().await.remove(&peer_id);
+            if let Some(info) = removed {
+                let _ = peer.events.send(ChatEvent::PeerLeft {
+                    room: room.id.clone(),
+                    peer: info,
+                    reason: LeaveReason::Lost,
+                });
+            }
+        }
+    }
+}
+
+/// Handshake with whoever is at `addr` in `room`, unless a peer of the room is known there
+async fn connect_address(peer: &Arc<Peer>, room: Room, addr: SocketAddr) {
+    let known = room
+        .peers
+        .lock()
+        .await
+        .values()
+        .any(|p| p.ip == addr.ip() && p.port == addr.port());
+    if known {
+        return;
+    }
+    let connecting = peer.clone();
+    peer.spawn(async move {
+        if let Err(e) = connect_to(&connecting, &room, addr, None).await {
+            eprintln!("Peer at {addr} unavailable in #{}: {e}", room.id);
+        }
+    });
+}
+
+/// Handshake with a peer reported by a backend, unless it is ourselves or already known
+async fn connect_discovered(peer: &Arc<Peer>, room: Room, info: PeerInfo) {
+    if info.id == peer.peer_id || room.peers.lock().await.contains_key(&info.id) {
+        return;
+    }
+    if !info.is_valid() {
+        eprintln!("⚠️  Warning: Discovered peer has invalid PeerInfo. {info:?}");
+        return;
+    }
+    let connecting = peer.clone();
+    peer.spawn(async move {
+        if let Err(e) = connect_to_candidates(&connecting, &room, &info).await {
+            eprintln!(
+                "[ERROR] Could not connect to discovered peer {} in #{}: {e}",
+                info.name, room.id
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discovery_kind_names() {
+        use clap::ValueEnum;
+        let kind = DiscoveryKind::from_str("udp", true).unwrap();
+        assert_eq!(kind, DiscoveryKind::Udp);
+        let parsed: Vec<DiscoveryKind> =
+            serde_json::from_str(r#"["mdns", "udp", "static"]"#).unwrap();
+        assert_eq!(parsed, DEFAULT_DISCOVERY);
+    }
+}
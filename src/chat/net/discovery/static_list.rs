@@ -0,0 +1,67 @@
+//! Static discovery backend: A fixed list of bootstrap peers (`--peer` and the config file).
+//!
+//! Addresses are resolved and reported as `DiscoveryEvent::AddressFound`; the peer's
+//! identity is learned through the handshake. Addresses are re-reported periodically so
+//! bootstrap peers that start later are still joined.
+
+use super::{Discovery, DiscoveryEvent, LocalPeer};
+use crate::chat::net::bootstrap::resolve;
+use crate::error::ChatError;
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration};
+
+/// Delay between attempts to reach bootstrap peers
+pub const BOOTSTRAP_RETRY: Duration = Duration::from_secs(15);
+
+/// Number of times each bootstrap peer is tried
+pub const BOOTSTRAP_ATTEMPTS: usize = 20;
+
+/// Reports a fixed list of `host:port` addresses
+pub struct StaticDiscovery {
+    addrs: Vec<String>,
+}
+
+impl StaticDiscovery {
+    pub fn new(addrs: Vec<String>) -> Self {
+        Self { addrs }
+    }
+}
+
+#[async_trait]
+impl Discovery for StaticDiscovery {
+    fn name(&self) -> &'static str {
+        "static"
+    }
+
+    async fn run(
+        self: Box<Self>,
+        local: LocalPeer,
+        events: mpsc::Sender<DiscoveryEvent>,
+    ) -> Result<(), ChatError> {
+        if self.addrs.is_empty() {
+            return Ok(());
+        }
+        for _ in 0..BOOTSTRAP_ATTEMPTS {
+            for addr in &self.addrs {
+                match resolve(addr).await {
+                    Ok(socket_addr) => {
+                        if events
+                            .send(DiscoveryEvent::AddressFound(socket_addr))
+                            .await
+                            .is_err()
+                        {
+                            return Ok(());
+                        }
+                    }
+                    Err(e) => eprintln!("Bootstrap peer {addr}: {e}"),
+                }
+            }
+            tokio::select! {
+                _ = sleep(BOOTSTRAP_RETRY) => {}
+                _ = local.cancel.cancelled() => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+}
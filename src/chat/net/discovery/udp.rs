@@ -0,0 +1,585 @@
+//! UDP discovery backend: Signed announcements broadcast and multicast on the LAN.
+//!
+//! Every peer periodically sends its `SignedPeerInfo`, once per joined room, to the IPv4
+//! broadcast address and to IPv4 and IPv6 multicast groups on `DISCOVERY_PORT`, and listens
+//! on the same port. This needs no mDNS
+//! responder, so it keeps working on networks that block one. Multicast copies are sent
+//! out of every interface, each advertising that interface's address first. An announcement
+//! is only accepted when its signature verifies and it was sent from one of the addresses
+//! it advertises.
+//! Announcements for rooms we have not joined are ignored. Heartbeats sent to the same port
+//! refresh a peer's liveness; peers that stay silent for `PEER_TIMEOUT` are reported as lost.
+//!
+//! Private rooms are advertised with a `PrivateAnnouncement` instead: a fresh nonce, a port
+//! and a MAC under the room secret, naming neither the room nor the peer. Members that can
+//! check the MAC report the sender's address for that room; the handshake does the rest.
+
+use super::{Discovery, DiscoveryEvent, LocalPeer};
+use crate::crypto::invite::{RoomSecret, ADVERT_PROOF};
+use crate::crypto::SignedPeerInfo;
+use crate::error::ChatError;
+use crate::network::interfaces;
+use crate::peer::{link_local_scope, NetworkMessage, PeerInfo};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio::time::{interval, Duration};
+
+/// UDP port used for announcements and heartbeats
+pub const DISCOVERY_PORT: u16 = 9999;
+
+/// Multicast group announcements are sent to, for networks that filter broadcasts
+pub const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 42, 99);
+
+/// Link-local IPv6 multicast group for announcements and heartbeats
+pub const MULTICAST_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0x42, 0x99);
+
+/// How often the local peer is announced
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Silence after which a peer is reported as lost
+pub const PEER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Lifetime of a signed announcement, in seconds
+const ANNOUNCEMENT_LIFETIME: u64 = 30;
+
+const APP_TAG: &str = "p2pchat";
+
+/// Datagram announcing a peer in a room
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UdpAnnouncement {
+    pub app: String,
+    pub room: String,
+    pub peer: SignedPeerInfo,
+}
+
+/// Datagram advertising a member of a private room. Only members can check the MAC, and
+/// the random nonce keeps outsiders from telling which adverts belong together.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrivateAnnouncement {
+    pub app: String,
+    pub nonce: String,
+    pub expires_at: u64,
+    pub port: u16,
+    pub mac: String,
+}
+
+impl PrivateAnnouncement {
+    fn new(secret: &RoomSecret, port: u16) -> Self {
+        let nonce: [u8; 16] = rand::random();
+        let expires_at = unix_now() + ANNOUNCEMENT_LIFETIME;
+        let mac = secret.mac(
+            ADVERT_PROOF,
+            &[&nonce, &expires_at.to_be_bytes(), &port.to_be_bytes()],
+        );
+        Self {
+            app: APP_TAG.to_string(),
+            nonce: hex::encode(nonce),
+            expires_at,
+            port,
+            mac: hex::encode(mac),
+        }
+    }
+
+    /// Whether this advert was made with `secret` and has not expired
+    fn is_for(&self, secret: &RoomSecret) -> bool {
+        let now = unix_now();
+        let (Ok(nonce), Ok(mac)) = (hex::decode(&self.nonce), hex::decode(&self.mac)) else {
+            return false;
+        };
+        self.app == APP_TAG
+            && self.port > 0
+            && (now..=now + ANNOUNCEMENT_LIFETIME).contains(&self.expires_at)
+            && secret.verify(
+                ADVERT_PROOF,
+                &[
+                    &nonce,
+                    &self.expires_at.to_be_bytes(),
+                    &self.port.to_be_bytes(),
+                ],
+                &mac,
+            )
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Datagrams accepted on the discovery port
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Datagram {
+    Announcement(UdpAnnouncement),
+    Private(PrivateAnnouncement),
+    Network(NetworkMessage),
+}
+
+/// Discovers peers with signed UDP announcements
+pub struct UdpDiscovery {
+    port: u16,
+}
+
+impl Default for UdpDiscovery {
+    fn default() -> Self {
+        Self {
+            port: DISCOVERY_PORT,
+        }
+    }
+}
+
+impl UdpDiscovery {
+    /// Use a different discovery port (all peers on the LAN must agree on it)
+    pub fn with_port(port: u16) -> Self {
+        Self { port }
+    }
+}
+
+/// Bind the IPv4 discovery port so several local peers can share it, and join the
+/// multicast group
+fn bind_v4(port: u16) -> Result<UdpSocket, ChatError> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+    if let Err(e) = socket.join_multicast_v4(&MULTICAST_GROUP, &Ipv4Addr::UNSPECIFIED) {
+        // Broadcast still works without multicast
+        eprintln!("[WARN] Could not join multicast group {MULTICAST_GROUP}: {e}");
+    }
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// Bind the IPv6 discovery port and join the link-local multicast group
+fn bind_v6(port: u16) -> Result<UdpSocket, ChatError> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(true)?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+    socket.join_multicast_v6(&MULTICAST_GROUP_V6, 0)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+/// Sign a fresh announcement for `room` advertising `ip` first and our other interface
+/// addresses after it
+fn announcement(local: &LocalPeer, room: &str, ip: IpAddr) -> Result<Vec<u8>, ChatError> {
+    let info = PeerInfo {
+        id: local.id.clone(),
+        name: local.name.clone(),
+        ip,
+        port: local.port,
+        addresses: interfaces::other_addresses(ip),
+        scope_id: 0,
+    };
+    Ok(serde_json::to_vec(&UdpAnnouncement {
+        app: APP_TAG.to_string(),
+        room: room.to_string(),
+        peer: local
+            .crypto_manager
+            .sign_peer_info(info, ANNOUNCEMENT_LIFETIME),
+    })?)
+}
+
+/// Announce the local peer: one broadcast from the primary address, one multicast per
+/// IPv4 interface advertising that interface's own address first, and one IPv6 multicast
+async fn announce(socket: &UdpSocket, socket_v6: Option<&UdpSocket>, local: &LocalPeer, port: u16) {
+    for iface in interfaces::local_interfaces() {
+        let IpAddr::V4(ip) = iface.ip else {
+            continue;
+        };
+        if let Err(e) = SockRef::from(socket).set_multicast_if_v4(&ip) {
+            eprintln!("Cannot multicast on {}: {e}", iface.name);
+            continue;
+        }
+        let target = SocketAddr::from((MULTICAST_GROUP, port));
+        send_announcement(socket, local, target, iface.ip).await;
+    }
+    if let Some(primary) = interfaces::primary_address() {
+        let target = SocketAddr::from((Ipv4Addr::BROADCAST, port));
+        send_announcement(socket, local, target, primary).await;
+    }
+    if let (Some(socket_v6), Some(primary)) = (socket_v6, interfaces::primary_address_v6()) {
+        let target = SocketAddr::from((MULTICAST_GROUP_V6, port));
+        send_announcement(socket_v6, local, target, primary).await;
+    }
+}
+
+/// Send one announcement per joined room to `target`
+async fn send_announcement(socket: &UdpSocket, local: &LocalPeer, target: SocketAddr, ip: IpAddr) {
+    for room in local.rooms.all() {
+        let bytes = match &room.secret {
+            Some(secret) => serde_json::to_vec(&PrivateAnnouncement::new(secret, local.port))
+                .map_err(ChatError::from),
+            None => announcement(local, &room.id, ip),
+        };
+        match bytes {
+            Ok(bytes) => {
+                if let Err(e) = socket.send_to(&bytes, target).await {
+                    eprintln!("Failed to send UDP announcement to {target}: {e}");
+                }
+            }
+            Err(e) => eprintln!("Failed to build UDP announcement: {e}"),
+        }
+    }
+}
+
+/// Whether `ip` is one of this host's own addresses
+fn is_local_address(ip: IpAddr) -> bool {
+    let ip = ip.to_canonical();
+    ip.is_loopback()
+        || interfaces::local_interfaces()
+            .iter()
+            .any(|iface| iface.ip == ip)
+}
+
+/// Receive on an optional socket; never completes when the socket is missing
+async fn recv_optional(
+    socket: Option<&UdpSocket>,
+    buf: &mut [u8],
+) -> std::io::Result<(usize, SocketAddr)> {
+    match socket {
+        Some(socket) => socket.recv_from(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Check an announcement before it refreshes anything: signed by the key it carries, not
+/// expired, and sent from the address it advertises
+fn accept(announcement: &UdpAnnouncement, from: SocketAddr, local_id: &str) -> bool {
+    let info = &announcement.peer.info;
+    announcement.app == APP_TAG
+        && info.id != local_id
+        && info.advertises(from.ip())
+        && info.is_valid()
+        && announcement.peer.verify().is_ok()
+}
+
+/// Announcements seen so far per room and peer id, used to report new and lost peers
+struct Liveness<'a> {
+    local: &'a LocalPeer,
+    last_seen: HashMap<(String, String), Instant>,
+    /// Private room members are only known by address until the handshake
+    private_seen: HashMap<(String, SocketAddr), Instant>,
+    events: &'a mpsc::Sender<DiscoveryEvent>,
+}
+
+impl Liveness<'_> {
+    /// Handle one datagram. Returns `false` once nobody listens to our events anymore.
+    async fn receive(&mut self, bytes: &[u8], from: SocketAddr) -> bool {
+        let Ok(datagram) = serde_json::from_slice::<Datagram>(bytes) else {
+            return true;
+        };
+        match datagram {
+            Datagram::Announcement(mut a) => {
+                if a.peer.info.id == self.local.id || self.local.rooms.get_public(&a.room).is_none()
+                {
+                    return true;
+                }
+                if !accept(&a, from, &self.local.id) {
+                    eprintln!("⚠️  Warning: Dropping invalid UDP announcement from {from}");
+                    return true;
+                }
+                // Link-local senders are reachable through the interface we heard them on
+                if let Some(scope_id) = link_local_scope(&from) {
+                    a.peer.info.scope_id = scope_id;
+                }
+                // Only report a peer when it (re)appears; announcements from known peers
+                // just refresh their liveness.
+                if self
+                    .last_seen
+                    .insert((a.room.clone(), a.peer.info.id.clone()), Instant::now())
+                    .is_none()
+                {
+                    return self
+                        .events
+                        .send(DiscoveryEvent::PeerAnnounced(a.room, a.peer))
+                        .await
+                        .is_ok();
+                }
+            }
+            Datagram::Private(advert) => {
+                let mut addr = from;
+                addr.set_port(advert.port);
+                if advert.port == self.local.port && is_local_address(from.ip()) {
+                    return true;
+                }
+                let Some(room) = self
+                    .local
+                    .rooms
+                    .all()
+                    .into_iter()
+                    .find(|room| room.secret.as_ref().is_some_and(|s| advert.is_for(s)))
+                else {
+                    return true;
+                };
+                if self
+                    .private_seen
+                    .insert((room.id.clone(), addr), Instant::now())
+                    .is_none()
+                {
+                    return self
+                        .events
+                        .send(DiscoveryEvent::RoomAddressFound(room.id, addr))
+                        .await
+                        .is_ok();
+                }
+            }
+            Datagram::Network(NetworkMessage::Heartbeat(id)) => {
+                for ((_, peer_id), seen) in self.last_seen.iter_mut() {
+                    if *peer_id == id {
+                        *seen = Instant::now();
+                    }
+                }
+            }
+            Datagram::Network(_) => {}
+        }
+        true
+    }
+
+    /// Report peers that stayed silent too long. Returns `false` once nobody listens.
+    async fn expire(&mut self) -> bool {
+        // Private adverts that stop are simply forgotten, so they are reported again if
+        // they come back; the member itself is dropped through its room like any peer
+        self.private_seen
+            .retain(|_, seen| seen.elapsed() <= PEER_TIMEOUT);
+        let lost: Vec<(String, String)> = self
+            .last_seen
+            .iter()
+            .filter(|(_, seen)| seen.elapsed() > PEER_TIMEOUT)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in lost {
+            self.last_seen.remove(&key);
+            let (room, id) = key;
+            if self
+                .events
+                .send(DiscoveryEvent::PeerLost(room, id))
+                .await
+                .is_err()
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[async_trait]
+impl Discovery for UdpDiscovery {
+    fn name(&self) -> &'static str {
+        "UDP"
+    }
+
+    async fn run(
+        self: Box<Self>,
+        local: LocalPeer,
+        events: mpsc::Sender<DiscoveryEvent>,
+    ) -> Result<(), ChatError> {
+        let socket = bind_v4(self.port)?;
+        let socket_v6 = match bind_v6(self.port) {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                eprintln!("[WARN] IPv6 discovery unavailable: {e}");
+                None
+            }
+        };
+        let mut liveness = Liveness {
+            local: &local,
+            last_seen: HashMap::new(),
+            private_seen: HashMap::new(),
+            events: &events,
+        };
+        let mut ticker = interval(ANNOUNCE_INTERVAL);
+        let mut buf = vec![0u8; 4096];
+        let mut buf_v6 = vec![0u8; 4096];
+
+        loop {
+            let running = tokio::select! {
+                _ = ticker.tick() => {
+                    announce(&socket, socket_v6.as_ref(), &local, self.port).await;
+                    liveness.expire().await
+                }
+                received = socket.recv_from(&mut buf) => {
+                    let (len, from) = received?;
+                    liveness.receive(&buf[..len], from).await
+                }
+                received = recv_optional(socket_v6.as_ref(), &mut buf_v6) => {
+                    let (len, from) = received?;
+                    liveness.receive(&buf_v6[..len], from).await
+                }
+                _ = local.cancel.cancelled() => false,
+            };
+            if !running {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::CryptoManager;
+
+    fn signed(ip: &str) -> UdpAnnouncement {
+        let manager = CryptoManager::new("a".to_string(), "Alice".to_string());
+        let info = PeerInfo {
+            id: "a".to_string(),
+            name: "Alice".to_string(),
+            ip: ip.parse().unwrap(),
+            port: 9000,
+            addresses: Vec::new(),
+            scope_id: 0,
+        };
+        UdpAnnouncement {
+            app: APP_TAG.to_string(),
+            room: crate::chat::room::DEFAULT_ROOM.to_string(),
+            peer: manager.sign_peer_info(info, ANNOUNCEMENT_LIFETIME),
+        }
+    }
+
+    #[test]
+    fn test_datagram_kinds() {
+        let announcement = serde_json::to_vec(&signed("192.168.1.20")).unwrap();
+        assert!(matches!(
+            serde_json::from_slice::<Datagram>(&announcement).unwrap(),
+            Datagram::Announcement(_)
+        ));
+        let heartbeat = serde_json::to_vec(&NetworkMessage::Heartbeat("a".to_string())).unwrap();
+        assert!(matches!(
+            serde_json::from_slice::<Datagram>(&heartbeat).unwrap(),
+            Datagram::Network(NetworkMessage::Heartbeat(_))
+        ));
+    }
+
+    #[test]
+    fn test_accept_checks_source_and_signature() {
+        let from: SocketAddr = "192.168.1.20:9999".parse().unwrap();
+        let announcement = signed("192.168.1.20");
+        assert!(accept(&announcement, from, "me"));
+        // Our own announcement echoed back
+        assert!(!accept(&announcement, from, "a"));
+        // Replayed from another host
+        assert!(!accept(
+            &announcement,
+            "192.168.1.66:9999".parse().unwrap(),
+            "me"
+        ));
+
+        // Renamed by someone without the key
+        let mut spoofed = signed("192.168.1.20");
+        spoofed.peer.info.name = "Mallory".to_string();
+        assert!(!accept(&spoofed, from, "me"));
+
+        // Signed by a different key than the one carried
+        let mut swapped = signed("192.168.1.20");
+        swapped.peer.public_key =
+            CryptoManager::new("m".to_string(), "Mallory".to_string()).get_public_key();
+        assert!(!accept(&swapped, from, "me"));
+    }
+
+    #[tokio::test]
+    async fn test_announcements_are_scoped_to_joined_rooms() {
+        let local = LocalPeer {
+            id: "me".to_string(),
+            name: "Me".to_string(),
+            port: 9001,
+            crypto_manager: std::sync::Arc::new(CryptoManager::new(
+                "me".to_string(),
+                "Me".to_string(),
+            )),
+            rooms: std::sync::Arc::new(
+                crate::chat::room::RoomRegistry::new(&["dev".to_string()]).unwrap(),
+            ),
+            cancel: Default::default(),
+        };
+        let (events, mut received) = mpsc::channel(8);
+        let mut liveness = Liveness {
+            local: &local,
+            last_seen: HashMap::new(),
+            private_seen: HashMap::new(),
+            events: &events,
+        };
+        let from: SocketAddr = "192.168.1.20:9999".parse().unwrap();
+
+        // Not in the lobby: ignored
+        let lobby = serde_json::to_vec(&signed("192.168.1.20")).unwrap();
+        assert!(liveness.receive(&lobby, from).await);
+        assert!(received.try_recv().is_err());
+
+        let mut dev = signed("192.168.1.20");
+        dev.room = "dev".to_string();
+        let dev = serde_json::to_vec(&dev).unwrap();
+        assert!(liveness.receive(&dev, from).await);
+        assert!(matches!(
+            received.try_recv().unwrap(),
+            DiscoveryEvent::PeerAnnounced(ref room, ref p) if room == "dev" && p.info.id == "a"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_private_adverts_only_open_with_the_secret() {
+        let secret = RoomSecret::generate();
+        let rooms = crate::chat::room::RoomRegistry::default();
+        rooms.add_private("team", secret.clone()).unwrap();
+        let local = LocalPeer {
+            id: "me".to_string(),
+            name: "Me".to_string(),
+            port: 9001,
+            crypto_manager: std::sync::Arc::new(CryptoManager::new(
+                "me".to_string(),
+                "Me".to_string(),
+            )),
+            rooms: std::sync::Arc::new(rooms),
+            cancel: Default::default(),
+        };
+        let (events, mut received) = mpsc::channel(8);
+        let mut liveness = Liveness {
+            local: &local,
+            last_seen: HashMap::new(),
+            private_seen: HashMap::new(),
+            events: &events,
+        };
+        let from: SocketAddr = "192.168.1.20:9999".parse().unwrap();
+
+        // Adverts name neither the room nor the peer
+        let advert = PrivateAnnouncement::new(&secret, 9000);
+        let bytes = serde_json::to_vec(&advert).unwrap();
+        let text = String::from_utf8_lossy(&bytes);
+        assert!(!text.contains("team") && !text.contains(&secret.room_tag()));
+        assert!(!advert.is_for(&RoomSecret::generate()));
+
+        let other = serde_json::to_vec(&PrivateAnnouncement::new(&RoomSecret::generate(), 9000));
+        assert!(liveness.receive(&other.unwrap(), from).await);
+        assert!(received.try_recv().is_err());
+
+        let mut tampered = advert.clone();
+        tampered.port = 9002;
+        let tampered = serde_json::to_vec(&tampered).unwrap();
+        assert!(liveness.receive(&tampered, from).await);
+        assert!(received.try_recv().is_err());
+
+        assert!(liveness.receive(&bytes, from).await);
+        assert!(matches!(
+            received.try_recv().unwrap(),
+            DiscoveryEvent::RoomAddressFound(ref room, addr)
+                if room == "team" && addr == "192.168.1.20:9000".parse().unwrap()
+        ));
+        // Repeated adverts only refresh
+        assert!(liveness.receive(&bytes, from).await);
+        assert!(received.try_recv().is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! Gossip module: Optional multi-hop relay for peers without a direct TCP path to each other.
+//!
+//! When gossip is enabled, outgoing room traffic is wrapped in a `GossipEnvelope` carrying a
+//! unique message id and a hop budget (TTL). Every peer that receives an envelope it has not
+//! seen before, and would accept the payload of itself, re-forwards it to its own neighbours
+//! with the TTL decremented. The payload is forwarded untouched, so signed messages stay
+//! verifiable end to end whichever relay delivered them.
+
+use crate::peer::{GossipEnvelope, NetworkMessage};
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// Default number of hops an envelope may travel
+pub const DEFAULT_GOSSIP_TTL: u8 = 4;
+
+/// Upper bound for the TTL accepted from the network
+pub const MAX_GOSSIP_TTL: u8 = 16;
+
+/// Number of message ids remembered for deduplication
+const SEEN_CAPACITY: usize = 4096;
+
+/// Tracks gossip settings and the ids of envelopes already seen
+pub struct GossipRelay {
+    enabled: AtomicBool,
+    ttl: AtomicU8,
+    relayed: AtomicU64,
+    seen: Mutex<SeenIds>,
+}
+
+#[derive(Default)]
+struct SeenIds {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl Default for GossipRelay {
+    fn default() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            ttl: AtomicU8::new(DEFAULT_GOSSIP_TTL),
+            relayed: AtomicU64::new(0),
+            seen: Mutex::new(SeenIds::default()),
+        }
+    }
+}
+
+impl GossipRelay {
+    /// Turn on wrapping and relaying with the given hop budget
+    pub fn enable(&self, ttl: u8) {
+        self.ttl
+            .store(ttl.clamp(1, MAX_GOSSIP_TTL), Ordering::Relaxed);
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether gossip relaying is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Hop budget used for envelopes we originate
+    pub fn ttl(&self) -> u8 {
+        self.ttl.load(Ordering::Relaxed)
+    }
+
+    /// Number of envelopes re-forwarded by this peer
+    pub fn relayed_count(&self) -> u64 {
+        self.relayed.load(Ordering::Relaxed)
+    }
+
+    /// Wrap a message we originate. Returns the message unchanged when gossip is disabled.
+    pub fn wrap(&self, origin_id: &str, msg: NetworkMessage) -> NetworkMessage {
+        if !self.is_enabled() {
+            return msg;
+        }
+        let envelope = GossipEnvelope {
+            message_id: Uuid::new_v4().to_string(),
+            origin_id: origin_id.to_string(),
+            from_id: origin_id.to_string(),
+            ttl: self.ttl(),
+            payload: Box::new(msg),
+        };
+        self.mark_seen(&envelope.message_id);
+        NetworkMessage::Gossip(envelope)
+    }
+
+    /// Record a message id. Returns `false` if it had been seen before.
+    pub fn mark_seen(&self, message_id: &str) -> bool {
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        if !seen.ids.insert(message_id.to_string()) {
+            return false;
+        }
+        seen.order.push_back(message_id.to_string());
+        while seen.order.len() > SEEN_CAPACITY {
+            if let Some(oldest) = seen.order.pop_front() {
+                seen.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Build the envelope to forward to our neighbours, if it still has hops left
+    pub fn next_hop(&self, envelope: &GossipEnvelope, our_id: &str) -> Option<GossipEnvelope> {
+        if !self.is_enabled() || envelope.ttl <= 1 {
+            return None;
+        }
+        self.relayed.fetch_add(1, Ordering::Relaxed);
+        Some(GossipEnvelope {
+            message_id: envelope.message_id.clone(),
+            origin_id: envelope.origin_id.clone(),
+            from_id: our_id.to_string(),
+            ttl: envelope.ttl.min(MAX_GOSSIP_TTL) - 1,
+            payload: envelope.payload.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_disabled_is_passthrough() {
+        let relay = GossipRelay::default();
+        let msg = relay.wrap("me", NetworkMessage::Heartbeat("me".to_string()));
+        assert!(matches!(msg, NetworkMessage::Heartbeat(_)));
+    }
+
+    #[test]
+    fn test_dedup_and_ttl() {
+        let relay = GossipRelay::default();
+        relay.enable(2);
+        let NetworkMessage::Gossip(envelope) =
+            relay.wrap("origin", NetworkMessage::Heartbeat("origin".to_string()))
+        else {
+            panic!("expected gossip envelope");
+        };
+        // Our own envelope is already marked as seen
+        assert!(!relay.mark_seen(&envelope.message_id));
+
+        let relay_b = GossipRelay::default();
+        relay_b.enable(DEFAULT_GOSSIP_TTL);
+        assert!(relay_b.mark_seen(&envelope.message_id));
+        let hop = relay_b.next_hop(&envelope, "b").unwrap();
+        assert_eq!(hop.ttl, 1);
+        assert_eq!(hop.from_id, "b");
+        assert_eq!(hop.origin_id, "origin");
+        // TTL exhausted: no further forwarding
+        assert!(relay_b.next_hop(&hop, "c").is_none());
+    }
+}
@@ -1,19 +1,35 @@
+use crate::chat::net::discovery::udp::{DISCOVERY_PORT, MULTICAST_GROUP_V6};
 use crate::chat::Peer;
 use crate::error::ChatError;
 use crate::peer::NetworkMessage;
 use serde_json;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use tokio::net::UdpSocket;
 use tokio::time::{sleep, Duration};
 
 pub async fn start_heartbeat(peer: &Peer) -> Result<(), ChatError> {
     let socket = UdpSocket::bind("0.0.0.0:0").await?;
     socket.set_broadcast(true)?;
+    // IPv6 has no broadcast: heartbeats go to the discovery multicast group instead
+    let socket_v6 = UdpSocket::bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)))
+        .await
+        .ok();
+    let target = SocketAddr::from((Ipv4Addr::BROADCAST, DISCOVERY_PORT));
+    let target_v6 = SocketAddr::from((MULTICAST_GROUP_V6, DISCOVERY_PORT));
     loop {
         let heartbeat = NetworkMessage::Heartbeat(peer.peer_id.clone());
         let msg_bytes = serde_json::to_vec(&heartbeat)?;
-        if let Err(e) = socket.send_to(&msg_bytes, "255.255.255.255:9999").await {
+        if let Err(e) = socket.send_to(&msg_bytes, target).await {
             eprintln!("Failed to send heartbeat: {e}");
         }
-        sleep(Duration::from_secs(10)).await;
+        if let Some(socket_v6) = &socket_v6 {
+            if let Err(e) = socket_v6.send_to(&msg_bytes, target_v6).await {
+                eprintln!("Failed to send IPv6 heartbeat: {e}");
+            }
+        }
+        tokio::select! {
+            _ = sleep(Duration::from_secs(10)) => {}
+            _ = peer.cancel.cancelled() => return Ok(()),
+        }
     }
 }
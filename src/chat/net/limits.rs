@@ -0,0 +1,559 @@
+//! Limits module: Per-source rate limits, a connection cap and a temporary ban list.
+//!
+//! Every source address gets token buckets for new connections, messages and upgrade
+//! proposals. Whatever exceeds a bucket is dropped before any handler sees it and counts as
+//! a strike against the source; a source collecting too many strikes is banned for a while,
+//! and its connections are closed as soon as they are accepted. The number of connections
+//! handled at once is capped too, in total and per source, so a flood cannot spawn an
+//! unbounded number of tasks and a single source cannot take every slot.
+
+use crate::peer::NetworkMessage;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Duration, Instant};
+
+/// Strikes older than this are forgotten
+pub const STRIKE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Sources tracked at most; quiet ones are forgotten first
+const MAX_SOURCES: usize = 4096;
+
+/// A token bucket: up to `burst` at once, refilled at `per_second`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.per_second >= 1.0 {
+            write!(f, "{}/s, burst {}", self.per_second, self.burst)
+        } else {
+            write!(
+                f,
+                "1 per {:.0}s, burst {}",
+                1.0 / self.per_second,
+                self.burst
+            )
+        }
+    }
+}
+
+/// Flood protection settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    /// New connections per source
+    pub connections: RateLimit,
+    /// Messages per source, handshakes excepted
+    pub messages: RateLimit,
+    /// Upgrade proposals per source, relayed ones included
+    pub proposals: RateLimit,
+    /// Connections handled at once, from all sources
+    pub max_connections: usize,
+    /// Connections handled at once from a single source
+    pub max_connections_per_source: usize,
+    /// Strikes within `STRIKE_WINDOW` after which a source is banned
+    pub strikes_to_ban: u32,
+    /// How long a ban lasts
+    pub ban_duration: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            connections: RateLimit {
+                burst: 60,
+                per_second: 30.0,
+            },
+            messages: RateLimit {
+                burst: 60,
+                per_second: 20.0,
+            },
+            proposals: RateLimit {
+                burst: 3,
+                per_second: 1.0 / 30.0,
+            },
+            max_connections: 256,
+            max_connections_per_source: 16,
+            strikes_to_ban: 100,
+            ban_duration: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Why a connection or message was turned away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    Banned,
+    TooManyConnections,
+    RateLimited,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refusal::Banned => write!(f, "banned"),
+            Refusal::TooManyConnections => write!(f, "too many connections"),
+            Refusal::RateLimited => write!(f, "rate limited"),
+        }
+    }
+}
+
+/// A source that had something dropped, for `/status`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Offender {
+    pub ip: IpAddr,
+    /// Connections and messages dropped so far
+    pub dropped: u64,
+    /// Strikes towards a ban
+    pub strikes: u32,
+    /// Time left on its ban, if banned
+    pub banned_for: Option<Duration>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        }
+    }
+
+    /// Take a token if one is left
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(f64::from(limit.burst));
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct Source {
+    connections: Bucket,
+    messages: Bucket,
+    proposals: Bucket,
+    dropped: u64,
+    strikes: u32,
+    last_strike: Option<Instant>,
+    banned_until: Option<Instant>,
+    /// Connections from this source being handled right now
+    open: usize,
+}
+
+impl Source {
+    fn new(limits: &Limits, now: Instant) -> Self {
+        Self {
+            connections: Bucket::full(&limits.connections, now),
+            messages: Bucket::full(&limits.messages, now),
+            proposals: Bucket::full(&limits.proposals, now),
+            dropped: 0,
+            strikes: 0,
+            last_strike: None,
+            banned_until: None,
+            open: 0,
+        }
+    }
+
+    fn is_banned(&self, now: Instant) -> bool {
+        self.banned_until.is_some_and(|until| until > now)
+    }
+
+    /// Whether the source did anything wrong recently or still has connections open
+    fn is_quiet(&self, now: Instant) -> bool {
+        !self.is_banned(now)
+            && self.open == 0
+            && self
+                .last_strike
+                .is_none_or(|last| now.duration_since(last) > STRIKE_WINDOW)
+    }
+
+    /// Count a drop and a strike against `ip`, banning it once the strikes add up
+    fn strike(&mut self, ip: IpAddr, limits: &Limits, now: Instant) {
+        self.dropped += 1;
+        if self
+            .last_strike
+            .is_some_and(|last| now.duration_since(last) > STRIKE_WINDOW)
+        {
+            self.strikes = 0;
+        }
+        self.strikes += 1;
+        self.last_strike = Some(now);
+        if self.strikes >= limits.strikes_to_ban {
+            self.strikes = 0;
+            self.banned_until = Some(now + limits.ban_duration);
+            eprintln!(
+                "⚠️  Warning: Banning {ip} for {}s, it keeps exceeding the rate limits",
+                limits.ban_duration.as_secs()
+            );
+        }
+    }
+}
+
+type Sources = Arc<Mutex<HashMap<IpAddr, Source>>>;
+
+fn lock(sources: &Sources) -> std::sync::MutexGuard<'_, HashMap<IpAddr, Source>> {
+    sources.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A connection slot, held while the connection is handled
+pub struct ConnectionPermit {
+    ip: IpAddr,
+    sources: Sources,
+    _slot: OwnedSemaphorePermit,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        if let Some(source) = lock(&self.sources).get_mut(&self.ip) {
+            source.open = source.open.saturating_sub(1);
+        }
+    }
+}
+
+/// Rate limits, connection slots and bans of a peer
+pub struct RateLimiter {
+    limits: Limits,
+    slots: Arc<Semaphore>,
+    sources: Sources,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(Limits::default())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(limits.max_connections)),
+            limits,
+            sources: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Let a new connection from `ip` in. The connection holds one of the slots until the
+    /// returned permit is dropped. A source already holding its share of the slots gets a
+    /// strike.
+    pub fn admit(&self, ip: IpAddr) -> Result<ConnectionPermit, Refusal> {
+        let limit = self.limits.connections;
+        self.check(ip, |source, now| source.connections.take(&limit, now))?;
+        let slot = self
+            .slots
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| Refusal::TooManyConnections)?;
+        let now = Instant::now();
+        let mut sources = self.lock();
+        let source = sources
+            .entry(ip)
+            .or_insert_with(|| Source::new(&self.limits, now));
+        if source.open >= self.limits.max_connections_per_source {
+            source.strike(ip, &self.limits, now);
+            return Err(Refusal::TooManyConnections);
+        }
+        source.open += 1;
+        Ok(ConnectionPermit {
+            ip,
+            sources: self.sources.clone(),
+            _slot: slot,
+        })
+    }
+
+    /// Count a strike against `ip` for misbehaving in a way the buckets do not see, like
+    /// keeping a connection open without sending anything
+    pub fn strike(&self, ip: IpAddr) {
+        let now = Instant::now();
+        let mut sources = self.lock();
+        let source = sources
+            .entry(ip)
+            .or_insert_with(|| Source::new(&self.limits, now));
+        source.strike(ip, &self.limits, now);
+    }
+
+    /// Whether a message from `ip` may be handled. Discovery handshakes are only limited
+    /// as connections; proposals also count against the proposal limit.
+    pub fn allow_message(&self, ip: IpAddr, message: &NetworkMessage) -> Result<(), Refusal> {
+        if matches!(message, NetworkMessage::Discovery(_)) {
+            return Ok(());
+        }
+        let (messages, proposals) = (self.limits.messages, self.limits.proposals);
+        let proposal = is_proposal(message);
+        self.check(ip, |source, now| {
+            source.messages.take(&messages, now)
+                && (!proposal || source.proposals.take(&proposals, now))
+        })
+    }
+
+    /// Turn `ip` away for `duration`
+    pub fn ban(&self, ip: IpAddr, duration: Duration) {
+        let now = Instant::now();
+        let mut sources = self.lock();
+        let source = sources
+            .entry(ip)
+            .or_insert_with(|| Source::new(&self.limits, now));
+        source.banned_until = Some(now + duration);
+    }
+
+    /// Lift the ban of `ip` and forget its strikes
+    pub fn unban(&self, ip: IpAddr) {
+        if let Some(source) = self.lock().get_mut(&ip) {
+            source.banned_until = None;
+            source.strikes = 0;
+        }
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        self.lock()
+            .get(&ip)
+            .is_some_and(|source| source.is_banned(now))
+    }
+
+    /// Connections being handled right now
+    pub fn active_connections(&self) -> usize {
+        self.limits.max_connections - self.slots.available_permits()
+    }
+
+    /// Sources that had something dropped, banned ones first, then by number of drops
+    pub fn offenders(&self) -> Vec<Offender> {
+        let now = Instant::now();
+        let mut offenders: Vec<Offender> = self
+            .lock()
+            .iter()
+            .filter(|(_, source)| source.dropped > 0 || source.is_banned(now))
+            .map(|(ip, source)| Offender {
+                ip: *ip,
+                dropped: source.dropped,
+                strikes: source.strikes,
+                banned_for: source
+                    .banned_until
+                    .filter(|until| *until > now)
+                    .map(|until| until - now),
+            })
+            .collect();
+        offenders.sort_by(|a, b| {
+            (b.banned_for.is_some(), b.dropped).cmp(&(a.banned_for.is_some(), a.dropped))
+        });
+        offenders
+    }
+
+    /// Refuse banned sources, and count a strike when `take` finds a bucket empty
+    fn check(
+        &self,
+        ip: IpAddr,
+        take: impl FnOnce(&mut Source, Instant) -> bool,
+    ) -> Result<(), Refusal> {
+        let now = Instant::now();
+        let mut sources = self.lock();
+        if sources.len() >= MAX_SOURCES && !sources.contains_key(&ip) {
+            sources.retain(|_, source| !source.is_quiet(now));
+        }
+        let source = sources
+            .entry(ip)
+            .or_insert_with(|| Source::new(&self.limits, now));
+        if source.is_banned(now) {
+            source.dropped += 1;
+            return Err(Refusal::Banned);
+        }
+        if take(source, now) {
+            return Ok(());
+        }
+        source.strike(ip, &self.limits, now);
+        Err(Refusal::RateLimited)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<IpAddr, Source>> {
+        lock(&self.sources)
+    }
+}
+
+/// Whether `message` is an upgrade proposal, possibly relayed
+fn is_proposal(message: &NetworkMessage) -> bool {
+    match message {
+        NetworkMessage::UpgradeRequest(_) => true,
+        NetworkMessage::Gossip(envelope) => {
+            matches!(*envelope.payload, NetworkMessage::UpgradeRequest(_))
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::threshold::UpgradeProposal;
+
+    fn limits() -> Limits {
+        Limits {
+            connections: RateLimit {
+                burst: 2,
+                per_second: 1.0,
+            },
+            messages: RateLimit {
+                burst: 3,
+                per_second: 1.0,
+            },
+            proposals: RateLimit {
+                burst: 1,
+                per_second: 0.1,
+            },
+            max_connections: 2,
+            max_connections_per_source: 1,
+            strikes_to_ban: 3,
+            ban_duration: Duration::from_secs(60),
+        }
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last])
+    }
+
+    fn chat() -> NetworkMessage {
+        NetworkMessage::Exit("flooder".to_string())
+    }
+
+    fn proposal() -> NetworkMessage {
+        NetworkMessage::UpgradeRequest(UpgradeProposal {
+            proposal_id: "p".to_string(),
+            proposer_id: "flooder".to_string(),
+            proposer_name: "Flooder".to_string(),
+            description: "spam".to_string(),
+            required_approvals: 1,
+            total_peers: 2,
+            timestamp: 0,
+            action: Default::default(),
+            rule: None,
+            proposer_key: None,
+            signature: None,
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_buckets_drop_excess_and_refill() {
+        let limiter = RateLimiter::new(Limits {
+            strikes_to_ban: 10,
+            ..limits()
+        });
+        for _ in 0..3 {
+            assert_eq!(limiter.allow_message(ip(1), &chat()), Ok(()));
+        }
+        assert_eq!(
+            limiter.allow_message(ip(1), &chat()),
+            Err(Refusal::RateLimited)
+        );
+        // Other sources have their own buckets
+        assert_eq!(limiter.allow_message(ip(2), &chat()), Ok(()));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(limiter.allow_message(ip(1), &chat()), Ok(()));
+        assert_eq!(
+            limiter.allow_message(ip(1), &chat()),
+            Err(Refusal::RateLimited)
+        );
+
+        // Proposals are limited much more tightly than other messages
+        tokio::time::advance(Duration::from_secs(3)).await;
+        assert_eq!(limiter.allow_message(ip(1), &proposal()), Ok(()));
+        assert!(limiter.allow_message(ip(1), &proposal()).is_err());
+        assert_eq!(limiter.allow_message(ip(1), &chat()), Ok(()));
+
+        let offenders = limiter.offenders();
+        assert_eq!(offenders.len(), 1);
+        assert_eq!((offenders[0].ip, offenders[0].dropped), (ip(1), 3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_repeat_offenders_are_banned_for_a_while() {
+        let limiter = RateLimiter::new(limits());
+        let mut refused = Vec::new();
+        for _ in 0..6 {
+            refused.push(limiter.admit(ip(1)).err());
+        }
+        assert_eq!(
+            refused,
+            [
+                None,
+                None,
+                Some(Refusal::RateLimited),
+                Some(Refusal::RateLimited),
+                Some(Refusal::RateLimited),
+                Some(Refusal::Banned),
+            ]
+        );
+        assert!(limiter.is_banned(ip(1)));
+        assert_eq!(limiter.allow_message(ip(1), &chat()), Err(Refusal::Banned));
+        let offender = &limiter.offenders()[0];
+        assert_eq!(offender.banned_for, Some(Duration::from_secs(60)));
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert!(!limiter.is_banned(ip(1)));
+        assert!(limiter.admit(ip(1)).is_ok());
+
+        limiter.ban(ip(2), Duration::from_secs(10));
+        assert_eq!(limiter.admit(ip(2)).err(), Some(Refusal::Banned));
+        limiter.unban(ip(2));
+        assert!(limiter.admit(ip(2)).is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_connections_are_capped() {
+        let limiter = RateLimiter::new(limits());
+        let first = limiter.admit(ip(1)).unwrap();
+        let _second = limiter.admit(ip(2)).unwrap();
+        assert_eq!(limiter.active_connections(), 2);
+        assert_eq!(
+            limiter.admit(ip(3)).err(),
+            Some(Refusal::TooManyConnections)
+        );
+        drop(first);
+        assert_eq!(limiter.active_connections(), 1);
+        assert!(limiter.admit(ip(3)).is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_one_source_cannot_take_every_slot() {
+        let limiter = RateLimiter::new(Limits {
+            max_connections: 3,
+            ..limits()
+        });
+        let held = limiter.admit(ip(1)).unwrap();
+        assert_eq!(
+            limiter.admit(ip(1)).err(),
+            Some(Refusal::TooManyConnections)
+        );
+        // Others still get in, and the source may connect again once its slot is free
+        let _other = limiter.admit(ip(2)).unwrap();
+        drop(held);
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(limiter.admit(ip(1)).is_ok());
+        assert_eq!(limiter.offenders()[0].strikes, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_strikes_from_outside_the_buckets_ban_too() {
+        let limiter = RateLimiter::new(limits());
+        for _ in 0..3 {
+            limiter.strike(ip(1));
+        }
+        assert!(limiter.is_banned(ip(1)));
+    }
+}
@@ -1,44 +1,47 @@
 //! TCP listener module: Listens for incoming TCP connections from peers and delegates connection handling.
 //!
-//! This module is responsible for starting a TCP listener on a specified port,
-//! accepting incoming TCP connections, and spawning a new task to handle each
+//! This module is responsible for starting a listener on a specified port through the
+//! peer's `Transport` (TCP on a dual-stack socket unless simulated),
+//! accepting incoming connections, and spawning a new task to handle each
 //! connection. It utilizes the `handle_tcp_connection` function from the
-//! `network::tcp` module to process the connections.
+//! `network::tcp` module to process the connections. Connections from banned or flooding
+//! sources, and those beyond the cap on concurrent connections, are closed right away. The
+//! listener stops accepting when the peer shuts down, and connections still being read are
+//! closed.
 
 use crate::chat::Peer;
 use crate::network::tcp::handle_tcp_connection;
 use colored::*;
-use tokio::net::TcpListener;
 
 pub async fn start_tcp_listener(peer: &Peer) -> Result<(), Box<dyn std::error::Error>> {
-    let addr = format!("0.0.0.0:{}", peer.port);
-    let listener = TcpListener::bind(&addr).await?;
+    let mut listener = peer.transport.listen(peer.port).await?;
     println!(
         "🔗 TCP listener started on port {}",
         peer.port.to_string().bright_blue()
     );
 
     loop {
-        let (stream, addr) = listener.accept().await?;
-        let peers = peer.peers.clone();
-        let message_sender = peer.message_sender.clone();
-        let peer_id = peer.peer_id.clone();
-
-        let crypto_manager = peer.crypto_manager.clone();
-        let threshold_manager = peer.threshold_manager.clone();
-        tokio::spawn(async move {
-            if let Err(e) = handle_tcp_connection(
-                stream,
-                addr,
-                peers,
-                message_sender,
-                peer_id,
-                threshold_manager,
-                crypto_manager,
-            )
-            .await
-            {
-                eprintln!("Error handling TCP connection from {addr}: {e}");
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = peer.cancel.cancelled() => return Ok(()),
+        };
+        // Dropping the stream closes it
+        let permit = match peer.limits.admit(addr.ip().to_canonical()) {
+            Ok(permit) => permit,
+            Err(_) => continue,
+        };
+        let context = peer.handler_context();
+        let cancel = peer.cancel.clone();
+        peer.tasks.spawn(async move {
+            let _permit = permit;
+            let handled = handle_tcp_connection(stream, addr, context);
+            tokio::select! {
+                result = handled => {
+                    if let Err(e) = result {
+                        eprintln!("Error handling TCP connection from {addr}: {e}");
+                    }
+                }
+                _ = cancel.cancelled() => {}
             }
         });
     }
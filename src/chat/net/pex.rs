@@ -0,0 +1,247 @@
+//! Peer exchange (PEX) module: Lets connected peers share their peer lists.
+//!
+//! mDNS fails silently on networks that filter multicast. With peer exchange, finding a
+//! single peer is enough to join the whole mesh: every peer periodically sends its list of
+//! known peers to its neighbours. Received entries are validated and queued as candidates,
+//! and each candidate is only added to its room's peer list after a direct discovery
+//! handshake with it succeeds. Peer lists are exchanged per room, so they never reveal who
+//! is in the other rooms we joined.
+
+use crate::chat::net::bootstrap::connect_to_candidates;
+use crate::chat::room::Room;
+use crate::chat::Peer;
+use crate::error::ChatError;
+use crate::network::tcp::send_message;
+use crate::peer::{NetworkMessage, PeerInfo};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::{Mutex, Notify};
+use tokio::time::{interval, Duration};
+
+/// How often our peer list is shared with our neighbours
+pub const PEX_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Maximum number of entries accepted from (and sent in) a single exchange
+pub const MAX_PEX_ENTRIES: usize = 64;
+
+/// A candidate is keyed by the room it was shared in and its peer id
+type CandidateKey = (String, String);
+
+/// Candidates learned through peer exchange, waiting for a direct handshake
+#[derive(Default)]
+pub struct PexState {
+    candidates: Mutex<HashMap<CandidateKey, PeerInfo>>,
+    /// Candidates currently being contacted, so each is only tried once at a time
+    in_flight: Mutex<HashSet<CandidateKey>>,
+    notify: Notify,
+}
+
+impl PexState {
+    /// Queue validated candidates shared in `room` for a handshake
+    pub async fn queue(&self, room: &str, entries: Vec<PeerInfo>) {
+        if entries.is_empty() {
+            return;
+        }
+        let mut candidates = self.candidates.lock().await;
+        for entry in entries {
+            candidates.insert((room.to_string(), entry.id.clone()), entry);
+        }
+        self.notify.notify_one();
+    }
+
+    /// Take every queued candidate, with the room it was shared in
+    pub async fn take_candidates(&self) -> Vec<(String, PeerInfo)> {
+        self.candidates
+            .lock()
+            .await
+            .drain()
+            .map(|((room, _), info)| (room, info))
+            .collect()
+    }
+
+    /// Number of candidates waiting for a handshake
+    pub async fn pending_count(&self) -> usize {
+        self.candidates.lock().await.len()
+    }
+}
+
+/// Keep only entries worth a handshake: valid, not ourselves and not already known
+pub fn filter_entries(
+    entries: Vec<PeerInfo>,
+    peer_id: &str,
+    known: &HashMap<String, PeerInfo>,
+) -> Vec<PeerInfo> {
+    entries
+        .into_iter()
+        .take(MAX_PEX_ENTRIES)
+        .filter(|e| e.id != peer_id && !known.contains_key(&e.id) && e.is_valid())
+        .collect()
+}
+
+/// Share our peer list periodically and handshake with candidates as they arrive
+pub async fn start_peer_exchange(peer: Arc<Peer>) -> Result<(), ChatError> {
+    let mut share = interval(PEX_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = share.tick() => {
+                for room in peer.rooms.all() {
+                    share_peer_list(&peer, &room).await;
+                }
+            }
+            _ = peer.pex.notify.notified() => {}
+            _ = peer.cancel.cancelled() => return Ok(()),
+        }
+        for (room_id, candidate) in peer.pex.take_candidates().await {
+            // The room may have been left since the candidate was queued
+            let Some(room) = peer.rooms.get(&room_id) else {
+                continue;
+            };
+            let key = (room_id, candidate.id.clone());
+            if !peer.pex.in_flight.lock().await.insert(key.clone()) {
+                continue;
+            }
+            let confirming = peer.clone();
+            peer.spawn(async move {
+                confirm_candidate(&confirming, &room, &candidate).await;
+                confirming.pex.in_flight.lock().await.remove(&key);
+            });
+        }
+    }
+}
+
+/// Send every neighbour in `room` the list of the other peers we know there
+async fn share_peer_list(peer: &Peer, room: &Room) {
+    let known: Vec<PeerInfo> = room
+        .peers
+        .lock()
+        .await
+        .values()
+        .filter(|p| p.is_valid())
+        .cloned()
+        .collect();
+    for target in &known {
+        let entries: Vec<PeerInfo> = known
+            .iter()
+            .filter(|p| p.id != target.id)
+            .take(MAX_PEX_ENTRIES)
+            .cloned()
+            .collect();
+        if entries.is_empty() {
+            continue;
+        }
+        let msg = NetworkMessage::PeerExchange {
+            peer_id: peer.peer_id.clone(),
+            peers: entries,
+        };
+        let codec = peer.protocols.codec_for(&target.id);
+        let addr = target.socket_addr();
+        let sent = send_message(&*peer.transport, codec, addr, room, &msg);
+        if let Err(e) = sent.await {
+            eprintln!("Failed to share peer list with {}: {e}", target.name);
+        }
+    }
+}
+
+/// Handshake with a candidate and add it to the room's peers if it answers as itself
+async fn confirm_candidate(peer: &Peer, room: &Room, candidate: &PeerInfo) {
+    if let Err(e) = connect_to_candidates(peer, room, candidate).await {
+        eprintln!("PEX handshake with {} failed: {e}", candidate.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::room::DEFAULT_ROOM;
+    use crate::network::protocol::Hello;
+    use crate::network::transport::TcpTransport;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    fn info(id: &str, ip: &str, port: u16) -> PeerInfo {
+        PeerInfo {
+            id: id.to_string(),
+            name: id.to_string(),
+            ip: IpAddr::from_str(ip).unwrap(),
+            port,
+            addresses: Vec::new(),
+            scope_id: 0,
+        }
+    }
+
+    #[test]
+    fn test_filter_entries() {
+        let mut known = HashMap::new();
+        known.insert("bob".to_string(), info("bob", "192.168.1.3", 9000));
+
+        let entries = vec![
+            info("me", "192.168.1.2", 9000),   // ourselves
+            info("bob", "192.168.1.3", 9000),  // already known
+            info("carol", "127.0.0.1", 9000),  // loopback, invalid
+            info("dave", "192.168.1.5", 0),    // invalid port
+            info("erin", "192.168.1.6", 9000), // new and valid
+        ];
+        let candidates = filter_entries(entries, "me", &known);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].id, "erin");
+    }
+
+    #[tokio::test]
+    async fn test_handshake_returns_remote_info() {
+        let remote = Peer::new("Remote".to_string(), 9000);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = remote.clone();
+        remote.spawn(async move {
+            let (stream, from) = listener.accept().await.unwrap();
+            let context = server.handler_context();
+            let _ =
+                crate::network::tcp::handle_tcp_connection(Box::new(stream), from, context).await;
+        });
+
+        let local = crate::crypto::CryptoManager::new("local-id".to_string(), "Local".to_string());
+        let (info, capabilities) = crate::network::tcp::handshake(
+            &TcpTransport,
+            addr,
+            &Room::new(DEFAULT_ROOM),
+            &local,
+            &Hello::local(),
+            9001,
+        )
+        .await
+        .unwrap();
+        assert_eq!(info.id, remote.peer_id);
+        assert_eq!(info.name, "Remote");
+        assert_eq!(info.port, addr.port());
+        assert_eq!(capabilities.software, crate::network::protocol::SOFTWARE);
+        // The remote side negotiated the same with us
+        let theirs = remote.protocols.get("local-id").unwrap();
+        assert_eq!(theirs.protocol, capabilities.protocol);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_requires_shared_room() {
+        let remote = Peer::new("Remote".to_string(), 9000);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = remote.clone();
+        remote.spawn(async move {
+            let (stream, from) = listener.accept().await.unwrap();
+            let context = server.handler_context();
+            let _ =
+                crate::network::tcp::handle_tcp_connection(Box::new(stream), from, context).await;
+        });
+
+        let local = crate::crypto::CryptoManager::new("local-id".to_string(), "Local".to_string());
+        assert!(crate::network::tcp::handshake(
+            &TcpTransport,
+            addr,
+            &Room::new("elsewhere"),
+            &local,
+            &Hello::local(),
+            9001
+        )
+        .await
+        .is_err());
+    }
+}
@@ -0,0 +1,741 @@
+//! Roles module: Who may do what in a room.
+//!
+//! Rooms start without roles: everyone is a member and may chat, propose and vote. A room
+//! gets an owner by a governance decision (`/propose-role`) or, for a private room, by its
+//! creator claiming it; invites name the creator, so no other member can claim it first.
+//! Owners and moderators then hand out roles with signed `RoleGrant`s: an owner may give any
+//! role, a moderator may only move peers below moderator between member and read-only. Role
+//! changes decided by vote must be proposed by someone who could have granted them. Once a
+//! room has an owner only moderators and owners may create proposals. Read-only peers may
+//! neither chat nor vote, so giving everyone the read-only role turns the room into an
+//! announcement room where only those given a role may post.
+//!
+//! Roles are keyed by public key like the moderation lists, and every peer checks the grants
+//! it receives against the roles it already knows, so a peer cannot promote itself.
+
+use crate::crypto::threshold::{Decision, ProposalAction, TargetPeer, UpgradeProposal};
+use crate::crypto::{verify_signature, CryptoError, CryptoManager};
+use crate::peer::NetworkMessage;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+/// A member's role in a room, from least to most trusted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// May read, but neither chat nor vote
+    ReadOnly,
+    /// May chat and vote
+    Member,
+    /// May also create proposals and give peers below moderator the member or read-only role
+    Moderator,
+    /// May do anything, including making moderators and owners
+    Owner,
+}
+
+impl Role {
+    /// How much the approvals of a peer with this role weigh under a weighted voting rule
+    pub fn weight(self) -> usize {
+        match self {
+            Role::ReadOnly => 0,
+            Role::Member => 1,
+            Role::Moderator => 2,
+            Role::Owner => 3,
+        }
+    }
+
+    /// Whether a peer with this role may change the role of a peer from `current` to `role`
+    pub fn may_assign(self, current: Role, role: Role) -> bool {
+        match self {
+            Role::Owner => true,
+            Role::Moderator => current < Role::Moderator && role < Role::Moderator,
+            Role::Member | Role::ReadOnly => false,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::ReadOnly => "read-only",
+            Role::Member => "member",
+            Role::Moderator => "moderator",
+            Role::Owner => "owner",
+        })
+    }
+}
+
+impl FromStr for Role {
+    type Err = RoleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "read-only" | "readonly" | "read_only" => Ok(Role::ReadOnly),
+            "member" => Ok(Role::Member),
+            "moderator" | "mod" => Ok(Role::Moderator),
+            "owner" => Ok(Role::Owner),
+            _ => Err(RoleError::UnknownRole(s.to_string())),
+        }
+    }
+}
+
+/// Who a role is given to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RoleSubject {
+    Peer(TargetPeer),
+    /// Everyone without a role of their own
+    Everyone,
+}
+
+impl fmt::Display for RoleSubject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoleSubject::Peer(peer) => f.write_str(&peer.name),
+            RoleSubject::Everyone => f.write_str("everyone"),
+        }
+    }
+}
+
+/// What a role is needed for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Post,
+    Vote,
+    Propose,
+}
+
+impl Action {
+    /// The action `message` performs, if it needs a role at all
+    pub fn of(message: &NetworkMessage) -> Option<Action> {
+        match message {
+            NetworkMessage::Chat(_) | NetworkMessage::SignedChat(_) => Some(Action::Post),
+            NetworkMessage::UpgradeVote(_) => Some(Action::Vote),
+            NetworkMessage::UpgradeRequest(_) => Some(Action::Propose),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Action::Post => "post",
+            Action::Vote => "vote",
+            Action::Propose => "create proposals",
+        })
+    }
+}
+
+/// A role given by an owner or moderator, signed by them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RoleGrant {
+    pub room: String,
+    pub subject: RoleSubject,
+    pub role: Role,
+    pub granter_id: String,
+    pub granter_name: String,
+    #[serde(with = "serde_bytes")]
+    pub granter_key: Vec<u8>,
+    /// Unix time in milliseconds, so later grants replace earlier ones
+    pub timestamp: u64,
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
+
+impl RoleGrant {
+    /// A grant of `role` to `subject` in `room`, signed with our key
+    pub fn sign(
+        crypto_manager: &CryptoManager,
+        room: &str,
+        subject: RoleSubject,
+        role: Role,
+    ) -> Result<Self, CryptoError> {
+        let identity = crypto_manager.get_identity();
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|e| CryptoError::Unknown(e.to_string()))?
+            .as_millis() as u64;
+        let mut grant = Self {
+            room: room.to_string(),
+            subject,
+            role,
+            granter_id: identity.peer_id.clone(),
+            granter_name: identity.name.clone(),
+            granter_key: identity.public_key.clone(),
+            timestamp,
+            signature: Vec::new(),
+        };
+        grant.signature = crypto_manager
+            .sign_message(&grant.signed_data(), timestamp)?
+            .signature;
+        Ok(grant)
+    }
+
+    /// Check the signature against the granter's key
+    pub fn verify(&self) -> Result<(), CryptoError> {
+        verify_signature(
+            &self.granter_key,
+            &self.signed_data(),
+            self.timestamp,
+            &self.signature,
+        )
+    }
+
+    /// What the granter signs
+    fn signed_data(&self) -> String {
+        let subject = match &self.subject {
+            RoleSubject::Peer(peer) => hex::encode(&peer.public_key),
+            RoleSubject::Everyone => "*".to_string(),
+        };
+        format!(
+            "role:{}:{subject}:{}:{}",
+            self.room, self.role, self.granter_id
+        )
+    }
+}
+
+/// How a role came to be, kept so peers joining later can replay it
+#[derive(Debug, Clone)]
+pub enum RoleSource {
+    Grant(RoleGrant),
+    Decision(Decision),
+}
+
+/// The roles of one room
+#[derive(Debug, Default)]
+pub struct RoomRoles {
+    room: String,
+    state: RwLock<RoleState>,
+}
+
+#[derive(Debug, Default)]
+struct RoleState {
+    /// Key of the only peer that may name itself the first owner: the creator of a private
+    /// room
+    founder: Option<Vec<u8>>,
+    /// Roles given to single peers, by hex public key
+    members: BTreeMap<String, Assigned>,
+    /// Role of everyone else, `Member` unless set
+    everyone: Option<Role>,
+    /// When the role of everyone was last granted
+    everyone_since: u64,
+    sources: Vec<RoleSource>,
+}
+
+#[derive(Debug, Clone)]
+struct Assigned {
+    name: String,
+    role: Role,
+    /// When the role was last granted, 0 if by decision
+    since: u64,
+}
+
+impl RoomRoles {
+    /// No roles yet in `room`
+    pub fn new(room: &str) -> Self {
+        Self {
+            room: room.to_string(),
+            state: RwLock::default(),
+        }
+    }
+
+    /// Let the owner of `key`, the creator of a private room as named by the invite we
+    /// joined it by, claim the room while it has no owner. The first founder named stays.
+    pub fn set_founder(&self, key: &[u8]) {
+        self.write().founder.get_or_insert_with(|| key.to_vec());
+    }
+
+    /// Key of the peer that may claim the room, if any
+    pub fn founder(&self) -> Option<Vec<u8>> {
+        self.read().founder.clone()
+    }
+
+    pub fn has_owner(&self) -> bool {
+        let state = self.read();
+        state.members.values().any(|a| a.role == Role::Owner) || state.everyone == Some(Role::Owner)
+    }
+
+    /// Role of the owner of `key`, or of everyone when the key is unknown
+    pub fn role_of(&self, key: Option<&[u8]>) -> Role {
+        let state = self.read();
+        key.and_then(|key| state.members.get(&hex::encode(key)))
+            .map(|a| a.role)
+            .or(state.everyone)
+            .unwrap_or(Role::Member)
+    }
+
+    /// Role of everyone without a role of their own
+    pub fn everyone(&self) -> Role {
+        self.read().everyone.unwrap_or(Role::Member)
+    }
+
+    /// Peers given a role of their own: name, hex public key and role
+    pub fn members(&self) -> Vec<(String, String, Role)> {
+        let state = self.read();
+        state
+            .members
+            .iter()
+            .map(|(key, a)| (a.name.clone(), key.clone(), a.role))
+            .collect()
+    }
+
+    /// The least role needed for `action`
+    pub fn required(&self, action: Action) -> Role {
+        match action {
+            Action::Post | Action::Vote => Role::Member,
+            Action::Propose if self.has_owner() => Role::Moderator,
+            Action::Propose => Role::Member,
+        }
+    }
+
+    /// Whether the owner of `key` may perform `action`
+    pub fn require(&self, key: Option<&[u8]>, action: Action) -> Result<(), RoleError> {
+        let role = self.role_of(key);
+        let required = self.required(action);
+        if role < required {
+            return Err(RoleError::NotAllowed {
+                room: self.room.clone(),
+                action,
+                role,
+                required,
+            });
+        }
+        Ok(())
+    }
+
+    /// Apply a signed grant if its granter may give it. Returns whether it changed
+    /// anything; replays and grants older than the role they would replace are ignored.
+    pub fn grant(&self, grant: &RoleGrant) -> Result<bool, RoleError> {
+        if grant.room != self.room {
+            return Err(RoleError::InvalidGrant(format!(
+                "grant is for #{}, not #{}",
+                grant.room, self.room
+            )));
+        }
+        grant
+            .verify()
+            .map_err(|e| RoleError::InvalidGrant(e.to_string()))?;
+
+        if !self.has_owner() {
+            // The creator of a private room claims it
+            let founding = self.founder().as_ref() == Some(&grant.granter_key)
+                && grant.role == Role::Owner
+                && matches!(&grant.subject, RoleSubject::Peer(peer) if peer.public_key == grant.granter_key);
+            if !founding {
+                return Err(RoleError::InvalidGrant(format!(
+                    "#{} has no owner to grant roles",
+                    self.room
+                )));
+            }
+        } else {
+            self.check_authority(
+                &grant.granter_key,
+                &grant.granter_name,
+                &grant.subject,
+                grant.role,
+            )?;
+        }
+
+        let mut state = self.write();
+        let since = match &grant.subject {
+            RoleSubject::Peer(peer) => state
+                .members
+                .get(&hex::encode(&peer.public_key))
+                .map_or(0, |a| a.since),
+            RoleSubject::Everyone => state.everyone_since,
+        };
+        if grant.timestamp <= since {
+            return Ok(false);
+        }
+        match &grant.subject {
+            RoleSubject::Peer(peer) => {
+                state.members.insert(
+                    hex::encode(&peer.public_key),
+                    Assigned {
+                        name: peer.name.clone(),
+                        role: grant.role,
+                        since: grant.timestamp,
+                    },
+                );
+            }
+            RoleSubject::Everyone => {
+                state.everyone = Some(grant.role);
+                state.everyone_since = grant.timestamp;
+            }
+        }
+        state.sources.push(RoleSource::Grant(grant.clone()));
+        Ok(true)
+    }
+
+    /// Whether the owner of `key`, named `name`, may propose to make `subject` `role`. A
+    /// room without an owner may only elect one, by a majority of its members whatever the
+    /// voting rule (see `ProposalAction::min_approvals`); after that the proposer needs the
+    /// authority to grant the role.
+    pub fn check_change(
+        &self,
+        key: &[u8],
+        name: &str,
+        subject: &RoleSubject,
+        role: Role,
+    ) -> Result<(), RoleError> {
+        if self.has_owner() {
+            return self.check_authority(key, name, subject, role);
+        }
+        if role == Role::Owner && matches!(subject, RoleSubject::Peer(_)) {
+            return Ok(());
+        }
+        Err(RoleError::InvalidGrant(format!(
+            "#{} has no owner yet; elect one first",
+            self.room
+        )))
+    }
+
+    /// Whether the role change `proposal` asks for, if any, may be decided by vote: it must
+    /// be signed by its proposer, who must be allowed to propose it (see `check_change`)
+    pub fn check_proposal(&self, proposal: &UpgradeProposal) -> Result<(), RoleError> {
+        let ProposalAction::AssignRole { subject, role } = &proposal.action else {
+            return Ok(());
+        };
+        let key = proposal
+            .proposer_key
+            .as_deref()
+            .filter(|_| proposal.verify())
+            .ok_or_else(|| {
+                RoleError::InvalidGrant(format!(
+                    "proposal to make {subject} {role} is not signed by its proposer"
+                ))
+            })?;
+        self.check_change(key, &proposal.proposer_name, subject, *role)
+    }
+
+    /// Apply a role change decided by vote if its proposer may propose it (see
+    /// `check_proposal`); the caller checks the votes with `Decision::verify`. Returns
+    /// whether it was a role change.
+    pub fn decide(&self, decision: &Decision) -> Result<bool, RoleError> {
+        let ProposalAction::AssignRole { subject, role } = &decision.proposal.action else {
+            return Ok(false);
+        };
+        self.check_proposal(&decision.proposal)?;
+        let mut state = self.write();
+        match subject {
+            RoleSubject::Peer(peer) => {
+                let key = hex::encode(&peer.public_key);
+                let since = state.members.get(&key).map_or(0, |a| a.since);
+                state.members.insert(
+                    key,
+                    Assigned {
+                        name: peer.name.clone(),
+                        role: *role,
+                        since,
+                    },
+                );
+            }
+            RoleSubject::Everyone => state.everyone = Some(*role),
+        }
+        state.sources.push(RoleSource::Decision(decision.clone()));
+        Ok(true)
+    }
+
+    /// Grants and decisions applied so far, oldest first
+    pub fn sources(&self) -> Vec<RoleSource> {
+        self.read().sources.clone()
+    }
+
+    /// Whether the owner of `key`, named `name`, may make `subject` `role` by the roles
+    /// known now
+    fn check_authority(
+        &self,
+        key: &[u8],
+        name: &str,
+        subject: &RoleSubject,
+        role: Role,
+    ) -> Result<(), RoleError> {
+        let granter = self.role_of(Some(key));
+        let allowed = match subject {
+            RoleSubject::Peer(peer) => {
+                granter.may_assign(self.role_of(Some(&peer.public_key)), role)
+            }
+            RoleSubject::Everyone => granter == Role::Owner,
+        };
+        if !allowed {
+            return Err(RoleError::InvalidGrant(format!(
+                "{name} is {granter} and may not make {subject} {role}"
+            )));
+        }
+        Ok(())
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, RoleState> {
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, RoleState> {
+        self.state.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Errors of role checks and grants
+#[derive(Debug, thiserror::Error)]
+pub enum RoleError {
+    #[error("Only a {required} or above may {action} in #{room}, not a {role}")]
+    NotAllowed {
+        room: String,
+        action: Action,
+        role: Role,
+        required: Role,
+    },
+    #[error("Invalid role grant: {0}")]
+    InvalidGrant(String),
+    #[error("Unknown role {0}; use owner, moderator, member or read-only")]
+    UnknownRole(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::threshold::{Electorate, ThresholdManager, VotingRule};
+
+    fn target(crypto: &CryptoManager) -> RoleSubject {
+        let identity = crypto.get_identity();
+        RoleSubject::Peer(TargetPeer {
+            peer_id: identity.peer_id.clone(),
+            name: identity.name.clone(),
+            public_key: identity.public_key.clone(),
+        })
+    }
+
+    #[test]
+    fn test_role_names_and_order() {
+        assert_eq!("read-only".parse::<Role>().unwrap(), Role::ReadOnly);
+        assert_eq!("Moderator".parse::<Role>().unwrap(), Role::Moderator);
+        assert!("admin".parse::<Role>().is_err());
+        assert_eq!(Role::ReadOnly.to_string(), "read-only");
+        assert!(Role::ReadOnly < Role::Member && Role::Moderator < Role::Owner);
+
+        assert!(Role::Owner.may_assign(Role::Owner, Role::Member));
+        assert!(Role::Moderator.may_assign(Role::Member, Role::ReadOnly));
+        assert!(!Role::Moderator.may_assign(Role::Member, Role::Moderator));
+        assert!(!Role::Moderator.may_assign(Role::Moderator, Role::Member));
+        assert!(!Role::Member.may_assign(Role::ReadOnly, Role::Member));
+    }
+
+    #[test]
+    fn test_grants_follow_the_granters_role() {
+        let alice = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let bob = CryptoManager::new("bob".to_string(), "Bob".to_string());
+        let carol = CryptoManager::new("carol".to_string(), "Carol".to_string());
+        let roles = RoomRoles::new("team");
+        let key = |crypto: &CryptoManager| Some(crypto.get_public_key());
+        roles.set_founder(&alice.get_public_key());
+
+        // Nobody but the founder can claim an ownerless room, and only for itself
+        let usurp = RoleGrant::sign(&bob, "team", target(&alice), Role::Owner).unwrap();
+        assert!(roles.grant(&usurp).is_err());
+        let usurp = RoleGrant::sign(&bob, "team", target(&bob), Role::Owner).unwrap();
+        assert!(roles.grant(&usurp).is_err());
+        // Naming another founder later changes nothing
+        roles.set_founder(&bob.get_public_key());
+        assert!(roles.grant(&usurp).is_err());
+        let claim = RoleGrant::sign(&alice, "team", target(&alice), Role::Owner).unwrap();
+        assert!(roles.grant(&claim).unwrap());
+        assert!(!roles.grant(&claim).unwrap());
+        assert!(roles.has_owner());
+        assert_eq!(roles.role_of(key(&alice).as_deref()), Role::Owner);
+
+        // Members cannot hand out roles, moderators only below their own
+        let promote = RoleGrant::sign(&bob, "team", target(&bob), Role::Moderator).unwrap();
+        assert!(roles.grant(&promote).is_err());
+        let promote = RoleGrant::sign(&alice, "team", target(&bob), Role::Moderator).unwrap();
+        assert!(roles.grant(&promote).unwrap());
+        let silence = RoleGrant::sign(&bob, "team", target(&carol), Role::ReadOnly).unwrap();
+        assert!(roles.grant(&silence).unwrap());
+        let demote = RoleGrant::sign(&bob, "team", target(&alice), Role::Member).unwrap();
+        assert!(roles.grant(&demote).is_err());
+        assert_eq!(roles.role_of(key(&carol).as_deref()), Role::ReadOnly);
+
+        // Forged, foreign and stale grants are refused
+        let mut forged = RoleGrant::sign(&alice, "team", target(&carol), Role::Owner).unwrap();
+        forged.role = Role::Member;
+        assert!(roles.grant(&forged).is_err());
+        let foreign = RoleGrant::sign(&alice, "lobby", target(&carol), Role::Member).unwrap();
+        assert!(roles.grant(&foreign).is_err());
+        let mut stale = RoleGrant::sign(&alice, "team", target(&bob), Role::Member).unwrap();
+        stale.timestamp = promote.timestamp;
+        stale.signature = alice
+            .sign_message(&stale.signed_data(), stale.timestamp)
+            .unwrap()
+            .signature;
+        assert!(!roles.grant(&stale).unwrap());
+        assert_eq!(roles.role_of(key(&bob).as_deref()), Role::Moderator);
+        assert_eq!(roles.sources().len(), 3);
+        assert_eq!(roles.members().len(), 3);
+    }
+
+    #[test]
+    fn test_announcement_rooms_and_proposals() {
+        let alice = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let bob = CryptoManager::new("bob".to_string(), "Bob".to_string());
+        let roles = RoomRoles::new("news");
+        let alice_key = alice.get_public_key();
+        roles.set_founder(&alice_key);
+        let bob_key = bob.get_public_key();
+
+        // Without an owner everyone may do everything
+        assert!(roles.require(Some(&bob_key), Action::Propose).is_ok());
+        assert!(roles.require(None, Action::Post).is_ok());
+
+        let claim = RoleGrant::sign(&alice, "news", target(&alice), Role::Owner).unwrap();
+        roles.grant(&claim).unwrap();
+        let everyone = RoleGrant::sign(&alice, "news", RoleSubject::Everyone, Role::ReadOnly);
+        assert!(roles.grant(&everyone.unwrap()).unwrap());
+
+        assert!(roles.require(Some(&alice_key), Action::Post).is_ok());
+        assert!(roles.require(Some(&alice_key), Action::Propose).is_ok());
+        assert!(roles.require(Some(&bob_key), Action::Post).is_err());
+        assert!(roles.require(Some(&bob_key), Action::Vote).is_err());
+        assert!(roles.require(None, Action::Post).is_err());
+
+        let writer = RoleGrant::sign(&alice, "news", target(&bob), Role::Member).unwrap();
+        roles.grant(&writer).unwrap();
+        assert!(roles.require(Some(&bob_key), Action::Post).is_ok());
+        assert!(matches!(
+            roles.require(Some(&bob_key), Action::Propose),
+            Err(RoleError::NotAllowed {
+                required: Role::Moderator,
+                ..
+            })
+        ));
+    }
+
+    /// A decision carrying out `proposer`'s proposal to make `subject` `role`. Roles do not
+    /// look at the votes; `Decision::verify` does.
+    fn decision(proposer: &CryptoManager, subject: RoleSubject, role: Role) -> Decision {
+        let identity = proposer.get_identity();
+        let mut proposal = UpgradeProposal {
+            proposal_id: format!("{subject}-{role}"),
+            proposer_id: identity.peer_id.clone(),
+            proposer_name: identity.name.clone(),
+            timestamp: 1,
+            description: String::new(),
+            required_approvals: 1,
+            total_peers: 1,
+            action: ProposalAction::AssignRole { subject, role },
+            rule: None,
+            proposer_key: None,
+            signature: None,
+        };
+        proposal.sign(proposer).unwrap();
+        Decision {
+            proposal,
+            approvals: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_role_decisions_need_authority() {
+        let alice = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let bob = CryptoManager::new("bob".to_string(), "Bob".to_string());
+        let carol = CryptoManager::new("carol".to_string(), "Carol".to_string());
+        let roles = RoomRoles::new("lobby");
+
+        // An ownerless room can only elect an owner, whoever proposes it
+        let silence = decision(&bob, RoleSubject::Everyone, Role::ReadOnly);
+        assert!(roles.decide(&silence).is_err());
+        let mut unsigned = decision(&bob, target(&alice), Role::Owner);
+        unsigned.proposal.signature = None;
+        assert!(roles.decide(&unsigned).is_err());
+        assert!(roles
+            .decide(&decision(&bob, target(&alice), Role::Owner))
+            .unwrap());
+
+        // After that, proposers need the authority to grant the role themselves
+        let promote = decision(&bob, target(&carol), Role::Moderator);
+        assert!(roles.decide(&promote).is_err());
+        let mut forged = decision(&bob, target(&carol), Role::Moderator);
+        forged.proposal.proposer_key = Some(alice.get_public_key());
+        assert!(roles.decide(&forged).is_err());
+        let promote = decision(&alice, target(&bob), Role::Moderator);
+        assert!(roles.decide(&promote).unwrap());
+        assert!(roles
+            .decide(&decision(&bob, target(&carol), Role::ReadOnly))
+            .unwrap());
+        assert!(roles
+            .decide(&decision(&bob, target(&alice), Role::Member))
+            .is_err());
+        assert_eq!(roles.role_of(Some(&alice.get_public_key())), Role::Owner);
+        assert_eq!(roles.role_of(Some(&carol.get_public_key())), Role::ReadOnly);
+    }
+
+    #[test]
+    fn test_public_rooms_are_not_claimed() {
+        let alice = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let roles = RoomRoles::new("lobby");
+        let claim = RoleGrant::sign(&alice, "lobby", target(&alice), Role::Owner).unwrap();
+        assert!(roles.grant(&claim).is_err());
+        assert!(!roles.has_owner());
+    }
+
+    #[tokio::test]
+    async fn test_lone_members_cannot_claim_ownership() {
+        let peers: Vec<CryptoManager> = ["alice", "bob", "carol"]
+            .iter()
+            .map(|id| CryptoManager::new(id.to_string(), id.to_string()))
+            .collect();
+        let [_alice, bob, carol] = &peers[..] else {
+            unreachable!()
+        };
+        let mut electorate = Electorate::default();
+        for crypto in &peers {
+            let identity = crypto.get_identity();
+            electorate.insert(identity.peer_id.clone(), identity.public_key.clone(), 1);
+        }
+        let roles = RoomRoles::new("lobby");
+
+        // Bob asks for his own approval only, and gives it
+        let mut claim = decision(bob, target(bob), Role::Owner).proposal;
+        claim.rule = Some(VotingRule::Fixed { required: 1 });
+        claim.total_peers = 3;
+        claim.sign(bob).unwrap();
+        assert!(roles.check_proposal(&claim).is_ok());
+        let manager = ThresholdManager::default();
+        manager.insert_received_proposal(claim.clone()).await;
+        let approved = manager
+            .cast_vote_in(
+                &claim.proposal_id,
+                "bob".to_string(),
+                "bob".to_string(),
+                true,
+                bob,
+                Some(&electorate),
+            )
+            .await
+            .unwrap();
+        assert!(!approved);
+        let self_approved = Decision {
+            proposal: claim.clone(),
+            approvals: manager.get_proposal_votes(&claim.proposal_id).await,
+        };
+        assert!(self_approved.verify(Some(&electorate)).is_err());
+
+        // It takes a majority of the room
+        let approved = manager
+            .cast_vote_in(
+                &claim.proposal_id,
+                "carol".to_string(),
+                "carol".to_string(),
+                true,
+                carol,
+                Some(&electorate),
+            )
+            .await
+            .unwrap();
+        assert!(approved);
+        let elected = manager.get_decision(&claim.proposal_id).await.unwrap();
+        assert!(elected.verify(Some(&electorate)).is_ok());
+        assert!(roles.decide(&elected).unwrap());
+        assert_eq!(roles.role_of(Some(&bob.get_public_key())), Role::Owner);
+    }
+}
@@ -0,0 +1,378 @@
+//! Room module: Named rooms, each with its own peers, governance state and history.
+//!
+//! Every message on the wire is tagged with the room it belongs to (see `RoomMessage`).
+//! A process can be in several rooms at once; one of them is the current room, which is
+//! where typed messages, proposals and votes go. Peers, secure-only proposals and signed
+//! history never leak from one room into another.
+//!
+//! Private rooms are joined through an invite token carrying the room secret. On the wire
+//! they go by a tag derived from the secret, never by name, and only peers proving they
+//! know the secret are let in (see `crypto::invite`).
+//!
+//! Each room also has its own roles (see `chat::roles`); the creator of a private room may
+//! claim it as its owner.
+
+use crate::chat::history::HistoryStore;
+use crate::chat::roles::{Action, RoomRoles};
+use crate::crypto::invite::RoomSecret;
+use crate::crypto::threshold::{Electorate, ThresholdManager};
+use crate::crypto::CryptoManager;
+use crate::error::ChatError;
+use crate::peer::PeerInfo;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use tokio::sync::Mutex;
+
+/// Room joined when no `--room` is given
+pub const DEFAULT_ROOM: &str = "lobby";
+
+/// Longest room name accepted
+pub const MAX_ROOM_NAME: usize = 64;
+
+/// Whether `name` is a usable room name: short, and made of letters, digits, `-`, `_` or `.`
+pub fn is_valid_room_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_ROOM_NAME
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// State kept for a single room
+#[derive(Clone)]
+pub struct Room {
+    pub id: String,
+    /// How the room is named in frames: the id for public rooms, a tag derived from the
+    /// secret for private ones
+    pub wire_id: String,
+    /// Shared secret of a private room
+    pub secret: Option<RoomSecret>,
+    pub peers: Arc<Mutex<HashMap<String, PeerInfo>>>,
+    pub threshold_manager: Arc<ThresholdManager>,
+    pub history: Arc<HistoryStore>,
+    pub roles: Arc<RoomRoles>,
+}
+
+impl Room {
+    pub fn new(id: &str) -> Self {
+        let roles = Arc::new(RoomRoles::new(id));
+        Self {
+            id: id.to_string(),
+            wire_id: id.to_string(),
+            secret: None,
+            peers: Arc::new(Mutex::new(HashMap::new())),
+            threshold_manager: Arc::new(ThresholdManager::with_roles(roles.clone())),
+            history: Arc::new(HistoryStore::default()),
+            roles,
+        }
+    }
+
+    /// A private room protected by `secret`
+    pub fn private(id: &str, secret: RoomSecret) -> Self {
+        Self {
+            wire_id: secret.room_tag(),
+            secret: Some(secret),
+            ..Self::new(id)
+        }
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    /// Who votes in the room as far as we know: ourselves and the peers of the room whose
+    /// key we pinned, unless their role keeps them from voting or they were expelled
+    pub async fn electorate(&self, crypto_manager: &CryptoManager) -> Electorate {
+        let identity = crypto_manager.get_identity();
+        let mut candidates = vec![(identity.peer_id.clone(), Some(identity.public_key.clone()))];
+        let ids: Vec<String> = self.peers.lock().await.keys().cloned().collect();
+        for id in ids {
+            let key = crypto_manager.public_key_of(&id).await;
+            candidates.push((id, key));
+        }
+        let mut electorate = Electorate::default();
+        for (id, key) in candidates {
+            let Some(key) = key else {
+                continue;
+            };
+            let may_vote = self.roles.require(Some(&key), Action::Vote).is_ok();
+            if may_vote && !self.threshold_manager.is_expelled(&id).await {
+                let weight = self.roles.role_of(Some(&key)).weight();
+                electorate.insert(id, key, weight);
+            }
+        }
+        electorate
+    }
+}
+
+/// The rooms this process has joined, and which one is current
+pub struct RoomRegistry {
+    rooms: RwLock<BTreeMap<String, Room>>,
+    current: RwLock<String>,
+}
+
+impl Default for RoomRegistry {
+    fn default() -> Self {
+        let mut rooms = BTreeMap::new();
+        rooms.insert(DEFAULT_ROOM.to_string(), Room::new(DEFAULT_ROOM));
+        Self {
+            rooms: RwLock::new(rooms),
+            current: RwLock::new(DEFAULT_ROOM.to_string()),
+        }
+    }
+}
+
+impl RoomRegistry {
+    /// Start with the given rooms; the first one becomes current
+    pub fn new(ids: &[String]) -> Result<Self, ChatError> {
+        let Some(first) = ids.first() else {
+            return Ok(Self::default());
+        };
+        let mut rooms = BTreeMap::new();
+        for id in ids {
+            if !is_valid_room_name(id) {
+                return Err(invalid_name(id));
+            }
+            rooms.insert(id.clone(), Room::new(id));
+        }
+        Ok(Self {
+            rooms: RwLock::new(rooms),
+            current: RwLock::new(first.clone()),
+        })
+    }
+
+    /// Join a room (if not joined yet) and make it current
+    pub fn join(&self, id: &str) -> Result<(Room, bool), ChatError> {
+        if !is_valid_room_name(id) {
+            return Err(invalid_name(id));
+        }
+        let (room, is_new) = {
+            let mut rooms = self.rooms.write().unwrap_or_else(|e| e.into_inner());
+            match rooms.get(id) {
+                Some(room) => (room.clone(), false),
+                None => {
+                    let room = Room::new(id);
+                    rooms.insert(id.to_string(), room.clone());
+                    (room, true)
+                }
+            }
+        };
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = id.to_string();
+        Ok((room, is_new))
+    }
+
+    /// Join a private room without making it current. Joining it again with the same
+    /// secret is a no-op; a different secret, or a public room of that name, is an error.
+    pub fn add_private(&self, id: &str, secret: RoomSecret) -> Result<(Room, bool), ChatError> {
+        if !is_valid_room_name(id) {
+            return Err(invalid_name(id));
+        }
+        let mut rooms = self.rooms.write().unwrap_or_else(|e| e.into_inner());
+        match rooms.get(id) {
+            Some(room) if room.secret.as_ref() == Some(&secret) => Ok((room.clone(), false)),
+            Some(_) => Err(ChatError::Unknown(format!(
+                "Already in a different room named {id}; /part it first"
+            ))),
+            None => {
+                let room = Room::private(id, secret);
+                rooms.insert(id.to_string(), room.clone());
+                Ok((room, true))
+            }
+        }
+    }
+
+    /// Join a private room and make it current
+    pub fn join_private(&self, id: &str, secret: RoomSecret) -> Result<(Room, bool), ChatError> {
+        let joined = self.add_private(id, secret)?;
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = id.to_string();
+        Ok(joined)
+    }
+
+    /// Replace the secret of a joined private room, e.g. after a member was expelled. The
+    /// room keeps its peers, proposals and history but goes by a new tag, and old invites
+    /// stop working.
+    pub fn rekey(&self, id: &str, secret: RoomSecret) -> Result<Room, ChatError> {
+        let mut rooms = self.rooms.write().unwrap_or_else(|e| e.into_inner());
+        let room = rooms
+            .get_mut(id)
+            .filter(|room| room.is_private())
+            .ok_or_else(|| ChatError::Unknown(format!("Not in a private room named {id}")))?;
+        room.wire_id = secret.room_tag();
+        room.secret = Some(secret);
+        Ok(room.clone())
+    }
+
+    /// Leave a room. The last room cannot be left; leaving the current room makes another
+    /// joined room current.
+    pub fn part(&self, id: &str) -> Result<Room, ChatError> {
+        let mut rooms = self.rooms.write().unwrap_or_else(|e| e.into_inner());
+        if !rooms.contains_key(id) {
+            return Err(ChatError::Unknown(format!("Not in room {id}")));
+        }
+        if rooms.len() == 1 {
+            return Err(ChatError::Unknown(
+                "Cannot leave the last room; use /quit instead".to_string(),
+            ));
+        }
+        let room = rooms.remove(id).ok_or_else(|| invalid_name(id))?;
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        if *current == id {
+            if let Some(next) = rooms.keys().next() {
+                *current = next.clone();
+            }
+        }
+        Ok(room)
+    }
+
+    /// A joined room by id
+    pub fn get(&self, id: &str) -> Option<Room> {
+        self.rooms
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(id)
+            .cloned()
+    }
+
+    /// A joined room by the name it goes by on the wire
+    pub fn by_wire_id(&self, wire_id: &str) -> Option<Room> {
+        self.rooms
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .find(|room| room.wire_id == wire_id)
+            .cloned()
+    }
+
+    /// The current room
+    pub fn current(&self) -> Room {
+        let id = self
+            .current
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let rooms = self.rooms.read().unwrap_or_else(|e| e.into_inner());
+        match rooms.get(&id) {
+            Some(room) => room.clone(),
+            // `part` always moves `current` to a remaining room
+            None => rooms
+                .values()
+                .next()
+                .cloned()
+                .unwrap_or_else(|| Room::new(&id)),
+        }
+    }
+
+    /// Ids of all joined rooms
+    pub fn ids(&self) -> Vec<String> {
+        self.rooms
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Ids of the joined rooms that may be advertised by name
+    pub fn public_ids(&self) -> Vec<String> {
+        self.rooms
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .filter(|room| !room.is_private())
+            .map(|room| room.id.clone())
+            .collect()
+    }
+
+    /// A joined public room by id. Public adverts never reach private rooms, even one
+    /// with the same name.
+    pub fn get_public(&self, id: &str) -> Option<Room> {
+        self.get(id).filter(|room| !room.is_private())
+    }
+
+    /// All joined rooms
+    pub fn all(&self) -> Vec<Room> {
+        self.rooms
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+fn invalid_name(id: &str) -> ChatError {
+    ChatError::Unknown(format!(
+        "Invalid room name '{id}': use up to {MAX_ROOM_NAME} letters, digits, '-', '_' or '.'"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_room_names() {
+        assert!(is_valid_room_name("lobby"));
+        assert!(is_valid_room_name("team-42.dev"));
+        assert!(!is_valid_room_name(""));
+        assert!(!is_valid_room_name("with space"));
+        assert!(!is_valid_room_name(&"a".repeat(MAX_ROOM_NAME + 1)));
+    }
+
+    #[test]
+    fn test_join_and_part() {
+        let rooms = RoomRegistry::default();
+        assert_eq!(rooms.current().id, DEFAULT_ROOM);
+
+        let (dev, is_new) = rooms.join("dev").unwrap();
+        assert!(is_new);
+        assert_eq!(rooms.current().id, "dev");
+        assert_eq!(rooms.ids(), vec!["dev", "lobby"]);
+        // Each room keeps its own state
+        assert!(!Arc::ptr_eq(&dev.peers, &rooms.get("lobby").unwrap().peers));
+
+        let (_, is_new) = rooms.join("lobby").unwrap();
+        assert!(!is_new);
+        assert_eq!(rooms.current().id, "lobby");
+
+        rooms.part("lobby").unwrap();
+        assert_eq!(rooms.current().id, "dev");
+        assert!(rooms.part("dev").is_err());
+        assert!(rooms.join("no spaces").is_err());
+    }
+
+    #[test]
+    fn test_private_rooms() {
+        let rooms = RoomRegistry::default();
+        let secret = RoomSecret::generate();
+        let (team, is_new) = rooms.join_private("team", secret.clone()).unwrap();
+        assert!(is_new && team.is_private());
+        assert_eq!(rooms.current().id, "team");
+        // The name never goes on the wire
+        assert_ne!(team.wire_id, "team");
+        assert!(rooms.get("team").is_some());
+        assert!(rooms.by_wire_id("team").is_none());
+        assert_eq!(rooms.by_wire_id(&team.wire_id).unwrap().id, "team");
+        assert_eq!(rooms.by_wire_id(DEFAULT_ROOM).unwrap().id, DEFAULT_ROOM);
+        assert_eq!(rooms.public_ids(), vec![DEFAULT_ROOM]);
+        assert!(rooms.get_public("team").is_none());
+
+        let (_, is_new) = rooms.add_private("team", secret.clone()).unwrap();
+        assert!(!is_new);
+
+        // A new secret keeps the state but changes the tag
+        let rotated = RoomSecret::generate();
+        let rekeyed = rooms.rekey("team", rotated.clone()).unwrap();
+        assert_eq!(rekeyed.wire_id, rotated.room_tag());
+        assert!(Arc::ptr_eq(&rekeyed.peers, &team.peers));
+        assert!(rooms.by_wire_id(&team.wire_id).is_none());
+        assert_eq!(rooms.by_wire_id(&rekeyed.wire_id).unwrap().id, "team");
+        assert!(rooms.rekey(DEFAULT_ROOM, RoomSecret::generate()).is_err());
+        assert!(rooms.add_private("team", secret).is_err());
+        rooms.add_private("team", rotated).unwrap();
+        assert!(rooms.add_private("team", RoomSecret::generate()).is_err());
+        assert!(rooms
+            .add_private(DEFAULT_ROOM, RoomSecret::generate())
+            .is_err());
+    }
+}
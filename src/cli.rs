@@ -1,4 +1,7 @@
+use crate::chat::net::discovery::DiscoveryKind;
+use crate::chat::net::gossip::DEFAULT_GOSSIP_TTL;
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "p2p_chat")]
@@ -18,5 +21,34 @@ pub enum Commands {
         /// Your display name
         #[arg(short, long, default_value = "Anonymous")]
         name: String,
+        /// Relay messages for peers that are not directly reachable (multi-hop gossip)
+        #[arg(long)]
+        gossip: bool,
+        /// Maximum number of hops a gossiped message may travel
+        #[arg(long, default_value_t = DEFAULT_GOSSIP_TTL)]
+        gossip_ttl: u8,
+        /// Peer to connect to at startup, as host:port (repeatable)
+        #[arg(long = "peer", value_name = "HOST:PORT")]
+        peers: Vec<String>,
+        /// Room to join, repeatable; the first one is the current room (default: lobby)
+        #[arg(long = "room", value_name = "ROOM")]
+        rooms: Vec<String>,
+        /// Invite token of a private room to join, repeatable; the first one is the current
+        /// room unless --room is given
+        #[arg(long = "invite", value_name = "TOKEN")]
+        invites: Vec<String>,
+        /// Discovery backends to run, comma separated (default: mdns,udp,static)
+        #[arg(long, value_enum, value_delimiter = ',')]
+        discovery: Vec<DiscoveryKind>,
+        /// Directory keeping the identity across restarts (a new identity each start if unset)
+        #[arg(long, value_name = "DIR")]
+        data_dir: Option<PathBuf>,
+        /// Only speak JSON on the wire, readable for debugging (default: CBOR with peers
+        /// supporting it)
+        #[arg(long)]
+        json_wire: bool,
+        /// Path to a JSON config file (defaults to ./p2p-chat.json if present)
+        #[arg(long, value_name = "PATH")]
+        config: Option<PathBuf>,
     },
 }
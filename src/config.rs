@@ -0,0 +1,70 @@
+//! Config module: Loads optional settings from a JSON config file.
+//!
+//! Every field is optional, so an empty object (`{}`) is a valid config. Command line
+//! flags are merged on top of what the file provides.
+
+use crate::chat::net::discovery::DiscoveryKind;
+use crate::error::ChatError;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Config file looked up in the working directory when `--config` is not given
+pub const DEFAULT_CONFIG_FILE: &str = "p2p-chat.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Peers to connect to at startup, as `host:port`
+    pub bootstrap_peers: Vec<String>,
+    /// Discovery backends to run; empty means the built-in default
+    pub discovery: Vec<DiscoveryKind>,
+    /// Rooms to join at startup; empty means the default room
+    pub rooms: Vec<String>,
+    /// Directory keeping the identity across restarts
+    pub data_dir: Option<PathBuf>,
+    /// Only speak JSON on the wire, for debugging
+    pub json_wire: bool,
+}
+
+impl Config {
+    /// Load a config file
+    pub fn load(path: &Path) -> Result<Self, ChatError> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            ChatError::Unknown(format!("Failed to read config {}: {e}", path.display()))
+        })?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Load the given config file, or the default one if it exists
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self, ChatError> {
+        match path {
+            Some(path) => Self::load(path),
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::load(Path::new(DEFAULT_CONFIG_FILE))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config =
+            serde_json::from_str(r#"{"bootstrap_peers": ["192.168.1.10:9000"]}"#).unwrap();
+        assert_eq!(config.bootstrap_peers, vec!["192.168.1.10:9000"]);
+
+        let empty: Config = serde_json::from_str("{}").unwrap();
+        assert!(empty.bootstrap_peers.is_empty());
+        assert!(empty.discovery.is_empty());
+
+        let config: Config = serde_json::from_str(r#"{"discovery": ["udp"]}"#).unwrap();
+        assert_eq!(config.discovery, vec![DiscoveryKind::Udp]);
+
+        let config: Config = serde_json::from_str(r#"{"rooms": ["dev", "ops"]}"#).unwrap();
+        assert_eq!(config.rooms, vec!["dev", "ops"]);
+    }
+}
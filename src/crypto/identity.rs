@@ -0,0 +1,121 @@
+//! Persistent peer identities.
+//!
+//! An identity is a peer id with its Ed25519 signing key. Without one, every start gets a
+//! fresh id and key, so other peers see a new peer each time. Saving it in a data directory
+//! keeps the same id and public key across restarts.
+
+use super::{CryptoError, CryptoManager};
+use ed25519_dalek::SigningKey;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use uuid::Uuid;
+
+/// File the identity is stored in, inside the data directory
+pub const IDENTITY_FILE: &str = "identity.json";
+
+/// A peer id and the key it signs with
+#[derive(Clone)]
+pub struct Identity {
+    pub peer_id: String,
+    signing_key: SigningKey,
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the signing key itself
+        f.debug_struct("Identity")
+            .field("peer_id", &self.peer_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// On-disk form, with the key hex encoded
+#[derive(Serialize, Deserialize)]
+struct StoredIdentity {
+    peer_id: String,
+    secret_key: String,
+}
+
+impl Identity {
+    /// A fresh random peer id and keypair
+    pub fn generate() -> Self {
+        Self {
+            peer_id: Uuid::new_v4().to_string(),
+            signing_key: SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    /// Our public key as bytes
+    pub fn public_key(&self) -> Vec<u8> {
+        self.signing_key.verifying_key().to_bytes().to_vec()
+    }
+
+    /// A crypto manager signing as this identity under `name`
+    pub fn crypto_manager(&self, name: String) -> CryptoManager {
+        CryptoManager::from_signing_key(self.peer_id.clone(), name, self.signing_key.clone())
+    }
+
+    /// Load the identity stored at `path`
+    pub fn load(path: &Path) -> Result<Self, CryptoError> {
+        let contents = std::fs::read_to_string(path)?;
+        let stored: StoredIdentity = serde_json::from_str(&contents)
+            .map_err(|e| CryptoError::Unknown(format!("{}: {e}", path.display())))?;
+        let secret: [u8; 32] = hex::decode(&stored.secret_key)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| CryptoError::Unknown(format!("{}: invalid key", path.display())))?;
+        Ok(Self {
+            peer_id: stored.peer_id,
+            signing_key: SigningKey::from_bytes(&secret),
+        })
+    }
+
+    /// Save the identity to `path`, readable by the owner only
+    pub fn save(&self, path: &Path) -> Result<(), CryptoError> {
+        let stored = StoredIdentity {
+            peer_id: self.peer_id.clone(),
+            secret_key: hex::encode(self.signing_key.to_bytes()),
+        };
+        let contents = serde_json::to_string_pretty(&stored)
+            .map_err(|e| CryptoError::Unknown(e.to_string()))?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        std::io::Write::write_all(&mut options.open(path)?, contents.as_bytes())?;
+        Ok(())
+    }
+
+    /// Load the identity stored in `dir`, creating and saving a new one the first time
+    pub fn load_or_create(dir: &Path) -> Result<Self, CryptoError> {
+        let path = dir.join(IDENTITY_FILE);
+        if path.exists() {
+            return Self::load(&path);
+        }
+        std::fs::create_dir_all(dir)?;
+        let identity = Self::generate();
+        identity.save(&path)?;
+        Ok(identity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_survives_a_restart() {
+        let dir = std::env::temp_dir().join(format!("p2p-chat-{}", Uuid::new_v4()));
+        let first = Identity::load_or_create(&dir).unwrap();
+        let second = Identity::load_or_create(&dir).unwrap();
+        assert_eq!(first.peer_id, second.peer_id);
+        assert_eq!(first.public_key(), second.public_key());
+        assert!(!format!("{first:?}").contains(&hex::encode(first.signing_key.to_bytes())));
+
+        let manager = second.crypto_manager("Alice".to_string());
+        assert_eq!(manager.get_public_key(), first.public_key());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
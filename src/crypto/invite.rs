@@ -0,0 +1,283 @@
+//! Private room secrets and invite tokens.
+//!
+//! A private room is protected by a random 32-byte secret shared through invite tokens. The
+//! secret never goes on the wire: the room is addressed by a tag derived from it, and
+//! members prove they know it with HMAC-SHA256 over fresh handshake data. Outsiders only
+//! ever see opaque tags and MACs, never the room name.
+//!
+//! When a member is expelled the secret is replaced. The new one is sealed for each
+//! remaining member under a secret shared with just that member (see
+//! `CryptoManager::shared_secret`), so the expelled peer never learns it.
+
+use super::CryptoError;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+/// Prefix of every invite token
+pub const INVITE_PREFIX: &str = "p2pchat-invite:";
+
+/// Length of a room secret in bytes
+pub const SECRET_LEN: usize = 32;
+
+/// Proof sent by a peer asking to join a private room
+pub const JOIN_PROOF: &str = "join";
+/// Proof sent back by the member accepting the join
+pub const ACCEPT_PROOF: &str = "accept";
+/// MAC on discovery adverts for a private room
+pub const ADVERT_PROOF: &str = "advert";
+/// MAC on every other message for a private room, sent by its members
+pub const MEMBER_PROOF: &str = "member";
+
+/// Length of a sealed secret: the encrypted secret followed by its MAC
+pub const SEALED_LEN: usize = SECRET_LEN * 2;
+
+const ROOM_TAG: &str = "room-tag";
+const SEAL_PAD: &str = "seal-pad";
+const SEAL_TAG: &str = "seal-tag";
+const INVITE_VERSION: u8 = 2;
+/// Length of an Ed25519 public key
+const KEY_LEN: usize = 32;
+
+/// Shared secret of a private room
+#[derive(Clone, PartialEq, Eq)]
+pub struct RoomSecret([u8; SECRET_LEN]);
+
+impl fmt::Debug for RoomSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the secret itself
+        f.write_str("RoomSecret(..)")
+    }
+}
+
+impl RoomSecret {
+    /// A fresh random secret
+    pub fn generate() -> Self {
+        let mut bytes = [0u8; SECRET_LEN];
+        OsRng.fill_bytes(&mut bytes);
+        Self(bytes)
+    }
+
+    /// HMAC-SHA256 of `parts` under the secret, separated by `label` so a MAC made for one
+    /// purpose is never accepted for another
+    pub fn mac(&self, label: &str, parts: &[&[u8]]) -> Vec<u8> {
+        self.keyed(label, parts).finalize().into_bytes().to_vec()
+    }
+
+    /// Check a MAC made with `mac`, in constant time
+    pub fn verify(&self, label: &str, parts: &[&[u8]], mac: &[u8]) -> bool {
+        self.keyed(label, parts).verify_slice(mac).is_ok()
+    }
+
+    /// Name of the room on the wire. `~` never appears in room names, so a tag cannot
+    /// collide with a public room.
+    pub fn room_tag(&self) -> String {
+        format!("~{}", hex::encode(&self.mac(ROOM_TAG, &[])[..16]))
+    }
+
+    /// Encrypt `secret` under this secret, bound to `context`. The pad is derived from the
+    /// context, so a secret must only ever seal one thing per context.
+    pub fn seal(&self, secret: &RoomSecret, context: &[&[u8]]) -> Vec<u8> {
+        let pad = self.mac(SEAL_PAD, context);
+        let mut sealed: Vec<u8> = secret.0.iter().zip(&pad).map(|(a, b)| a ^ b).collect();
+        let tag = self.mac(SEAL_TAG, &[context, &[&sealed]].concat());
+        sealed.extend_from_slice(&tag);
+        sealed
+    }
+
+    /// The secret sealed with `seal` under this secret and `context`, if the MAC checks out
+    pub fn open(&self, sealed: &[u8], context: &[&[u8]]) -> Option<RoomSecret> {
+        if sealed.len() != SEALED_LEN {
+            return None;
+        }
+        let (encrypted, tag) = sealed.split_at(SECRET_LEN);
+        if !self.verify(SEAL_TAG, &[context, &[encrypted]].concat(), tag) {
+            return None;
+        }
+        let pad = self.mac(SEAL_PAD, context);
+        let mut secret = [0u8; SECRET_LEN];
+        for (i, byte) in secret.iter_mut().enumerate() {
+            *byte = encrypted[i] ^ pad[i];
+        }
+        Some(Self(secret))
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; SECRET_LEN]) -> Self {
+        Self(bytes)
+    }
+
+    fn keyed(&self, label: &str, parts: &[&[u8]]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts any key length");
+        mac.update(b"p2pchat/");
+        mac.update(label.as_bytes());
+        // Length-prefix every part so the concatenation is unambiguous
+        for part in parts {
+            mac.update(&(part.len() as u32).to_be_bytes());
+            mac.update(part);
+        }
+        mac
+    }
+}
+
+/// Everything needed to join a private room: its name, its secret, the key of its creator
+/// and a member to contact
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invite {
+    pub room: String,
+    pub secret: RoomSecret,
+    /// Public key of the room's creator, the only peer that may claim it as owner
+    pub founder: Vec<u8>,
+    pub bootstrap: SocketAddr,
+}
+
+impl fmt::Display for Invite {
+    /// Compact token: `p2pchat-invite:` followed by base64url of
+    /// `version | secret | room length | room | founder key | 4 or 6 | ip | port`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = vec![INVITE_VERSION];
+        bytes.extend_from_slice(&self.secret.0);
+        bytes.push(self.room.len() as u8);
+        bytes.extend_from_slice(self.room.as_bytes());
+        bytes.extend_from_slice(&self.founder);
+        match self.bootstrap.ip() {
+            IpAddr::V4(ip) => {
+                bytes.push(4);
+                bytes.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                bytes.push(6);
+                bytes.extend_from_slice(&ip.octets());
+            }
+        }
+        bytes.extend_from_slice(&self.bootstrap.port().to_be_bytes());
+        write!(f, "{INVITE_PREFIX}{}", URL_SAFE_NO_PAD.encode(bytes))
+    }
+}
+
+impl FromStr for Invite {
+    type Err = CryptoError;
+
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| CryptoError::InvalidInvite(reason.to_string());
+        let encoded = token
+            .trim()
+            .strip_prefix(INVITE_PREFIX)
+            .ok_or_else(|| invalid("missing prefix"))?;
+        let bytes = URL_SAFE_NO_PAD
+            .decode(encoded)
+            .map_err(|_| invalid("not base64"))?;
+        let mut reader = bytes.as_slice();
+        let mut take = |n: usize| -> Result<&[u8], CryptoError> {
+            if reader.len() < n {
+                return Err(invalid("truncated"));
+            }
+            let (head, tail) = reader.split_at(n);
+            reader = tail;
+            Ok(head)
+        };
+
+        if take(1)?[0] != INVITE_VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        let mut secret = [0u8; SECRET_LEN];
+        secret.copy_from_slice(take(SECRET_LEN)?);
+        let room_len = take(1)?[0] as usize;
+        let room = std::str::from_utf8(take(room_len)?)
+            .map_err(|_| invalid("room is not UTF-8"))?
+            .to_string();
+        let founder = take(KEY_LEN)?.to_vec();
+        let ip = match take(1)?[0] {
+            4 => {
+                let octets: [u8; 4] = take(4)?.try_into().map_err(|_| invalid("truncated"))?;
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            6 => {
+                let octets: [u8; 16] = take(16)?.try_into().map_err(|_| invalid("truncated"))?;
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return Err(invalid("unknown address family")),
+        };
+        let port = u16::from_be_bytes(take(2)?.try_into().map_err(|_| invalid("truncated"))?);
+        if !reader.is_empty() {
+            return Err(invalid("trailing bytes"));
+        }
+        Ok(Self {
+            room,
+            secret: RoomSecret(secret),
+            founder,
+            bootstrap: SocketAddr::new(ip, port),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invite_round_trip() {
+        let invite = Invite {
+            room: "team".to_string(),
+            secret: RoomSecret::generate(),
+            founder: vec![7; KEY_LEN],
+            bootstrap: "192.168.1.20:9000".parse().unwrap(),
+        };
+        let token = invite.to_string();
+        assert!(token.starts_with(INVITE_PREFIX));
+        assert_eq!(token.parse::<Invite>().unwrap(), invite);
+
+        let v6 = Invite {
+            bootstrap: "[fe80::1]:9000".parse().unwrap(),
+            ..invite
+        };
+        assert_eq!(v6.to_string().parse::<Invite>().unwrap(), v6);
+
+        assert!("p2pchat-invite:AAAA".parse::<Invite>().is_err());
+        assert!(token[..token.len() - 4].parse::<Invite>().is_err());
+    }
+
+    #[test]
+    fn test_proofs_need_the_secret() {
+        let secret = RoomSecret::generate();
+        let other = RoomSecret::generate();
+        let mac = secret.mac(JOIN_PROOF, &[b"room", b"hello"]);
+        assert!(secret.verify(JOIN_PROOF, &[b"room", b"hello"], &mac));
+        // Wrong secret, wrong purpose or different data
+        assert!(!other.verify(JOIN_PROOF, &[b"room", b"hello"], &mac));
+        assert!(!secret.verify(ACCEPT_PROOF, &[b"room", b"hello"], &mac));
+        assert!(!secret.verify(JOIN_PROOF, &[b"roomhello"], &mac));
+
+        assert_ne!(secret.room_tag(), other.room_tag());
+        assert!(secret.room_tag().starts_with('~'));
+    }
+
+    #[test]
+    fn test_sealed_secrets_open_for_the_recipient_only() {
+        let alice = crate::crypto::CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let bob = crate::crypto::CryptoManager::new("bob".to_string(), "Bob".to_string());
+        let eve = crate::crypto::CryptoManager::new("eve".to_string(), "Eve".to_string());
+        let to_bob = alice.shared_secret(&bob.get_public_key()).unwrap();
+        let from_alice = bob.shared_secret(&alice.get_public_key()).unwrap();
+        assert_eq!(to_bob, from_alice);
+        assert_ne!(to_bob, eve.shared_secret(&alice.get_public_key()).unwrap());
+
+        let secret = RoomSecret::generate();
+        let context: [&[u8]; 2] = [b"room", b"bob"];
+        let sealed = to_bob.seal(&secret, &context);
+        assert_eq!(sealed.len(), SEALED_LEN);
+        assert_eq!(from_alice.open(&sealed, &context), Some(secret));
+        assert!(from_alice.open(&sealed, &[b"room", b"eve"]).is_none());
+        let eves = eve.shared_secret(&alice.get_public_key()).unwrap();
+        assert!(eves.open(&sealed, &context).is_none());
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert!(from_alice.open(&tampered, &context).is_none());
+        assert!(alice.shared_secret(&[0; 32]).is_err());
+    }
+}
@@ -4,6 +4,8 @@
 //! to ensure message authenticity and integrity in the peer-to-peer network.
 //! It also includes threshold signature support for secure-only messaging upgrades.
 
+use crate::chat::clock::HlcTimestamp;
+use crate::peer::PeerInfo;
 use ed25519_dalek::{SigningKey, VerifyingKey, Signature, Signer, Verifier};
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
@@ -11,12 +13,17 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+pub mod identity;
+pub mod invite;
 pub mod threshold;
 
+use invite::RoomSecret;
+
 /// Represents a cryptographic identity for a peer
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CryptoIdentity {
     /// The public key as bytes
+    #[serde(with = "serde_bytes")]
     pub public_key: Vec<u8>,
     /// The peer ID this identity belongs to
     pub peer_id: String,
@@ -30,8 +37,10 @@ pub struct SignedMessage {
     /// The original message content
     pub message: String,
     /// The signature of the message
+    #[serde(with = "serde_bytes")]
     pub signature: Vec<u8>,
     /// The public key of the signer
+    #[serde(with = "serde_bytes")]
     pub public_key: Vec<u8>,
     /// The peer ID of the signer
     pub signer_id: String,
@@ -39,6 +48,72 @@ pub struct SignedMessage {
     pub signer_name: String,
     /// Timestamp when the message was signed
     pub timestamp: u64,
+    /// Hybrid logical clock used for causal ordering (absent from older peers)
+    #[serde(default)]
+    pub clock: Option<HlcTimestamp>,
+}
+
+impl SignedMessage {
+    /// Check the signature against the key the message carries, without trusting or
+    /// remembering that key
+    pub fn verify(&self) -> Result<(), CryptoError> {
+        if !self.clock_names_signer() {
+            return Err(CryptoError::VerificationFailed);
+        }
+        let payload = signing_payload(&self.message, self.timestamp, self.clock.as_ref());
+        verify_payload(&self.public_key, &payload, &self.signature)
+    }
+
+    /// Whether the clock, if any, was produced by the signer. A clock naming another peer
+    /// would let the signer pass its message off as ordered by someone else.
+    fn clock_names_signer(&self) -> bool {
+        self.clock
+            .as_ref()
+            .is_none_or(|clock| clock.node_id == self.signer_id)
+    }
+}
+
+/// Peer info signed by the peer it describes, carried by discovery announcements
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedPeerInfo {
+    /// The announced peer
+    pub info: PeerInfo,
+    /// The public key of the announced peer
+    #[serde(with = "serde_bytes")]
+    pub public_key: Vec<u8>,
+    /// Unix time (seconds) after which the announcement is no longer valid
+    pub expires_at: u64,
+    /// Signature over the peer info and expiry
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
+
+/// Longest lifetime accepted for a signed announcement, to bound replays
+pub const MAX_ANNOUNCEMENT_LIFETIME: u64 = 3600;
+
+impl SignedPeerInfo {
+    /// Check the signature against the embedded public key and the expiry against our clock
+    pub fn verify(&self) -> Result<(), CryptoError> {
+        let now = unix_now();
+        if self.expires_at < now {
+            return Err(CryptoError::MessageTooOld);
+        }
+        if self.expires_at > now + MAX_ANNOUNCEMENT_LIFETIME {
+            return Err(CryptoError::VerificationFailed);
+        }
+        let public_key_array: [u8; 32] = self.public_key.as_slice()
+            .try_into()
+            .map_err(|_| CryptoError::InvalidPublicKey)?;
+        let verifying_key = VerifyingKey::from_bytes(&public_key_array)
+            .map_err(|_| CryptoError::InvalidPublicKey)?;
+        let signature_array: [u8; 64] = self.signature.as_slice()
+            .try_into()
+            .map_err(|_| CryptoError::InvalidSignature)?;
+        let signature = Signature::from_bytes(&signature_array);
+        verifying_key
+            .verify(&peer_info_payload(&self.info, self.expires_at), &signature)
+            .map_err(|_| CryptoError::VerificationFailed)
+    }
 }
 
 /// Manages cryptographic operations for a peer
@@ -56,7 +131,11 @@ pub struct CryptoManager {
 impl CryptoManager {
     /// Create a new crypto manager with a fresh Ed25519 keypair
     pub fn new(peer_id: String, name: String) -> Self {
-        let signing_key = SigningKey::generate(&mut OsRng);
+        Self::from_signing_key(peer_id, name, SigningKey::generate(&mut OsRng))
+    }
+
+    /// Create a crypto manager signing with an existing key
+    pub fn from_signing_key(peer_id: String, name: String, signing_key: SigningKey) -> Self {
         let verifying_key = signing_key.verifying_key();
         
         let identity = CryptoIdentity {
@@ -85,9 +164,29 @@ impl CryptoManager {
 
     /// Sign a message with the peer's private key
     pub fn sign_message(&self, message: &str, timestamp: u64) -> Result<SignedMessage, CryptoError> {
+        self.sign(message, timestamp, None)
+    }
+
+    /// Sign a message together with its hybrid logical clock, so the causal position
+    /// of the message cannot be altered by relaying peers
+    pub fn sign_message_with_clock(
+        &self,
+        message: &str,
+        timestamp: u64,
+        clock: HlcTimestamp,
+    ) -> Result<SignedMessage, CryptoError> {
+        self.sign(message, timestamp, Some(clock))
+    }
+
+    fn sign(
+        &self,
+        message: &str,
+        timestamp: u64,
+        clock: Option<HlcTimestamp>,
+    ) -> Result<SignedMessage, CryptoError> {
         // Create a message to sign that includes timestamp to prevent replay attacks
-        let message_to_sign = format!("{message}:{timestamp}");
-        let signature = self.signing_key.sign(message_to_sign.as_bytes());
+        let message_to_sign = signing_payload(message, timestamp, clock.as_ref());
+        let signature = self.signing_key.sign(&message_to_sign);
         
         Ok(SignedMessage {
             message: message.to_string(),
@@ -96,11 +195,27 @@ impl CryptoManager {
             signer_id: self.identity.peer_id.clone(),
             signer_name: self.identity.name.clone(),
             timestamp,
+            clock,
         })
     }
 
+    /// Sign our own peer info for a discovery announcement valid for `lifetime` seconds
+    pub fn sign_peer_info(&self, info: PeerInfo, lifetime: u64) -> SignedPeerInfo {
+        let expires_at = unix_now() + lifetime.min(MAX_ANNOUNCEMENT_LIFETIME);
+        let signature = self.signing_key.sign(&peer_info_payload(&info, expires_at));
+        SignedPeerInfo {
+            info,
+            public_key: self.verifying_key.to_bytes().to_vec(),
+            expires_at,
+            signature: signature.to_bytes().to_vec(),
+        }
+    }
+
     /// Verify a signed message
     pub async fn verify_message(&self, signed_msg: &SignedMessage) -> Result<bool, CryptoError> {
+        if !signed_msg.clock_names_signer() {
+            return Ok(false);
+        }
         // Check if we know the signer's public key
         let verifying_key = {
             let known_keys = self.known_keys.read().await;
@@ -126,7 +241,11 @@ impl CryptoManager {
         };
 
         // Reconstruct the message that was signed
-        let message_to_verify = format!("{}:{}", signed_msg.message, signed_msg.timestamp);
+        let message_to_verify = signing_payload(
+            &signed_msg.message,
+            signed_msg.timestamp,
+            signed_msg.clock.as_ref(),
+        );
         
         // Convert signature bytes back to Signature
         let signature_array: [u8; 64] = signed_msg.signature.as_slice()
@@ -135,10 +254,27 @@ impl CryptoManager {
         let signature = Signature::from_bytes(&signature_array);
 
         // Verify the signature
-        Ok(verifying_key.verify(message_to_verify.as_bytes(), &signature).is_ok())
+        Ok(verifying_key.verify(&message_to_verify, &signature).is_ok())
     }
 
-    /// Add a known peer's public key to the cache
+    /// Verify a message relayed by someone other than its signer, e.g. in a history batch,
+    /// against the key pinned for the signer only. Relayed messages never pin a key, so
+    /// those of signers we do not know yet fail.
+    pub async fn verify_relayed(&self, signed_msg: &SignedMessage) -> Result<(), CryptoError> {
+        match self.public_key_of(&signed_msg.signer_id).await {
+            Some(key) if key == signed_msg.public_key => signed_msg.verify(),
+            Some(_) => Err(CryptoError::KeyMismatch),
+            None => Err(CryptoError::Unknown(format!(
+                "no key pinned for {}",
+                signed_msg.signer_id
+            ))),
+        }
+    }
+
+    /// Add a known peer's public key to the cache.
+    ///
+    /// The first key seen for a peer ID is kept: announcing a different key for a known
+    /// peer fails with `CryptoError::KeyMismatch`, so nobody can take over its identity.
     pub async fn add_known_peer(&self, peer_id: String, public_key: Vec<u8>) -> Result<(), CryptoError> {
         let public_key_array: [u8; 32] = public_key.as_slice()
             .try_into()
@@ -146,8 +282,21 @@ impl CryptoManager {
         let verifying_key = VerifyingKey::from_bytes(&public_key_array)
             .map_err(|_| CryptoError::InvalidPublicKey)?;
         
-        self.known_keys.write().await.insert(peer_id, verifying_key);
-        Ok(())
+        let mut known_keys = self.known_keys.write().await;
+        match known_keys.get(&peer_id) {
+            Some(known) if *known != verifying_key => Err(CryptoError::KeyMismatch),
+            _ => {
+                known_keys.insert(peer_id, verifying_key);
+                Ok(())
+            }
+        }
+    }
+
+    /// Verify a signed discovery announcement and remember the key it was signed with
+    pub async fn verify_peer_info(&self, signed: &SignedPeerInfo) -> Result<(), CryptoError> {
+        signed.verify()?;
+        self.add_known_peer(signed.info.id.clone(), signed.public_key.clone())
+            .await
     }
 
     /// Check if a message is recent (within a reasonable time window)
@@ -160,12 +309,111 @@ impl CryptoManager {
         current_time.saturating_sub(timestamp) <= max_age_seconds
     }
 
+    /// A secret only we and the owner of `public_key` can compute: X25519 between our key
+    /// and theirs, both Ed25519 keys taken to their Montgomery form
+    pub fn shared_secret(&self, public_key: &[u8]) -> Result<RoomSecret, CryptoError> {
+        let public_key_array: [u8; 32] = public_key
+            .try_into()
+            .map_err(|_| CryptoError::InvalidPublicKey)?;
+        let theirs = VerifyingKey::from_bytes(&public_key_array)
+            .map_err(|_| CryptoError::InvalidPublicKey)?;
+        let shared = theirs
+            .to_montgomery()
+            .mul_clamped(self.signing_key.to_scalar_bytes())
+            .to_bytes();
+        // Low-order keys would give a secret anyone can compute
+        if shared == [0u8; 32] {
+            return Err(CryptoError::InvalidPublicKey);
+        }
+        Ok(RoomSecret::from_bytes(shared))
+    }
+
+    /// The public key we know for `peer_id`, if any
+    pub async fn public_key_of(&self, peer_id: &str) -> Option<Vec<u8>> {
+        let known_keys = self.known_keys.read().await;
+        known_keys.get(peer_id).map(|key| key.to_bytes().to_vec())
+    }
+
     /// Get the number of known peer keys
     pub async fn known_peers_count(&self) -> usize {
         self.known_keys.read().await.len()
     }
 }
 
+/// Domain tag opening every message signature, so it cannot be mistaken for another kind
+/// of signature made with the same key
+const MESSAGE_DOMAIN: &[u8] = b"p2p-chat/message/v2";
+
+/// Bytes covered by a message signature: the domain tag, then every field with its length
+/// in front, so no field value can shift into its neighbour. Signatures made by peers using
+/// the older `message:timestamp` format no longer verify.
+fn signing_payload(message: &str, timestamp: u64, clock: Option<&HlcTimestamp>) -> Vec<u8> {
+    fn field(payload: &mut Vec<u8>, bytes: &[u8]) {
+        payload.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+        payload.extend_from_slice(bytes);
+    }
+
+    let mut payload = Vec::new();
+    field(&mut payload, MESSAGE_DOMAIN);
+    field(&mut payload, message.as_bytes());
+    field(&mut payload, &timestamp.to_be_bytes());
+    // Without a clock the payload ends here, shorter than any payload with one
+    if let Some(clock) = clock {
+        field(&mut payload, &clock.wall_ms.to_be_bytes());
+        field(&mut payload, &clock.logical.to_be_bytes());
+        field(&mut payload, clock.node_id.as_bytes());
+    }
+    payload
+}
+
+/// Check a signature made with `CryptoManager::sign_message` against the key it claims,
+/// without trusting or remembering that key
+pub fn verify_signature(
+    public_key: &[u8],
+    message: &str,
+    timestamp: u64,
+    signature: &[u8],
+) -> Result<(), CryptoError> {
+    let payload = signing_payload(message, timestamp, None);
+    verify_payload(public_key, &payload, signature)
+}
+
+/// Check a signature over `payload` against `public_key`
+fn verify_payload(public_key: &[u8], payload: &[u8], signature: &[u8]) -> Result<(), CryptoError> {
+    let public_key_array: [u8; 32] = public_key
+        .try_into()
+        .map_err(|_| CryptoError::InvalidPublicKey)?;
+    let verifying_key =
+        VerifyingKey::from_bytes(&public_key_array).map_err(|_| CryptoError::InvalidPublicKey)?;
+    let signature_array: [u8; 64] = signature
+        .try_into()
+        .map_err(|_| CryptoError::InvalidSignature)?;
+    verifying_key
+        .verify(payload, &Signature::from_bytes(&signature_array))
+        .map_err(|_| CryptoError::VerificationFailed)
+}
+
+/// Bytes covered by a peer info signature. Encoded as a JSON array so no field value can
+/// shift into its neighbour.
+fn peer_info_payload(info: &PeerInfo, expires_at: u64) -> Vec<u8> {
+    serde_json::to_vec(&(
+        &info.id,
+        &info.name,
+        info.ip,
+        info.port,
+        &info.addresses,
+        expires_at,
+    ))
+    .unwrap_or_default()
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
 /// Errors that can occur during cryptographic operations
 #[derive(Debug, thiserror::Error)]
 pub enum CryptoError {
@@ -177,6 +425,10 @@ pub enum CryptoError {
     VerificationFailed,
     #[error("Message is too old")]
     MessageTooOld,
+    #[error("Public key does not match the key known for this peer")]
+    KeyMismatch,
+    #[error("Invalid invite token: {0}")]
+    InvalidInvite(String),
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
@@ -233,6 +485,141 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_clock_is_covered_by_signature() {
+        let manager = CryptoManager::new("test-peer".to_string(), "TestPeer".to_string());
+        let clock = HlcTimestamp {
+            wall_ms: 1234567890000,
+            logical: 3,
+            node_id: "test-peer".to_string(),
+        };
+
+        let mut signed_msg = manager
+            .sign_message_with_clock("Hello", 1234567890, clock)
+            .unwrap();
+
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            assert!(manager.verify_message(&signed_msg).await.unwrap());
+            // Moving the message earlier in causal order must break the signature
+            signed_msg.clock.as_mut().unwrap().logical = 0;
+            assert!(!manager.verify_message(&signed_msg).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_clock_must_name_the_signer() {
+        let manager = CryptoManager::new("test-peer".to_string(), "TestPeer".to_string());
+        let clock = HlcTimestamp {
+            wall_ms: 1234567890000,
+            logical: 3,
+            node_id: "someone-else".to_string(),
+        };
+
+        // Correctly signed, but ordered as if another peer had produced it
+        let signed_msg = manager
+            .sign_message_with_clock("Hello", 1234567890, clock)
+            .unwrap();
+        assert!(signed_msg.verify().is_err());
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            assert!(!manager.verify_message(&signed_msg).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_signing_payload_fields_cannot_shift() {
+        // Under `message:timestamp` both would have signed "a:1:2"
+        assert_ne!(
+            signing_payload("a:1", 2, None),
+            signing_payload("a", 1, None)
+        );
+        let clock = HlcTimestamp {
+            wall_ms: 2,
+            logical: 0,
+            node_id: "x".to_string(),
+        };
+        assert_ne!(
+            signing_payload("a", 1, Some(&clock)),
+            signing_payload("a", 1, None)
+        );
+    }
+
+    #[test]
+    fn test_signed_peer_info() {
+        let manager = CryptoManager::new("test-peer".to_string(), "TestPeer".to_string());
+        let info = PeerInfo {
+            id: "test-peer".to_string(),
+            name: "TestPeer".to_string(),
+            ip: "192.168.1.20".parse().unwrap(),
+            port: 9000,
+            addresses: Vec::new(),
+            scope_id: 0,
+        };
+
+        let signed = manager.sign_peer_info(info, 30);
+        assert!(signed.verify().is_ok());
+
+        // Redirecting the announcement to another address breaks the signature
+        let mut hijacked = signed.clone();
+        hijacked.info.ip = "192.168.1.66".parse().unwrap();
+        assert!(hijacked.verify().is_err());
+
+        // So does extending its lifetime
+        let mut extended = signed;
+        extended.expires_at += 60;
+        assert!(extended.verify().is_err());
+    }
+
+    #[test]
+    fn test_known_key_cannot_be_replaced() {
+        let manager = CryptoManager::new("me".to_string(), "Me".to_string());
+        let alice = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let mallory = CryptoManager::new("mallory".to_string(), "Mallory".to_string());
+        let info = PeerInfo {
+            id: "alice".to_string(),
+            name: "Alice".to_string(),
+            ip: "192.168.1.20".parse().unwrap(),
+            port: 9000,
+            addresses: Vec::new(),
+            scope_id: 0,
+        };
+
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let genuine = alice.sign_peer_info(info.clone(), 30);
+            assert!(manager.verify_peer_info(&genuine).await.is_ok());
+            // Correctly signed, but with another key than the one pinned for "alice"
+            let hijack = mallory.sign_peer_info(info, 30);
+            assert!(matches!(
+                manager.verify_peer_info(&hijack).await,
+                Err(CryptoError::KeyMismatch)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_relayed_messages_do_not_pin_keys() {
+        let manager = CryptoManager::new("me".to_string(), "Me".to_string());
+        let alice = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let mallory = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let genuine = alice.sign_message("hi", 1).unwrap();
+
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            // Signers we do not know are not trusted, nor remembered
+            assert!(manager.verify_relayed(&genuine).await.is_err());
+            assert!(manager.public_key_of("alice").await.is_none());
+
+            manager
+                .add_known_peer("alice".to_string(), alice.get_public_key())
+                .await
+                .unwrap();
+            assert!(manager.verify_relayed(&genuine).await.is_ok());
+            let forged = mallory.sign_message("hi", 1).unwrap();
+            assert!(matches!(
+                manager.verify_relayed(&forged).await,
+                Err(CryptoError::KeyMismatch)
+            ));
+        });
+    }
+
     #[test]
     fn test_message_age_validation() {
         let manager = CryptoManager::new("test-peer".to_string(), "TestPeer".to_string());
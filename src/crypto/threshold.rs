@@ -3,14 +3,33 @@
 //! This module implements a lightweight M-of-N threshold signature scheme
 //! for approving network-wide security upgrades. It uses Ed25519-based
 //! partial signatures that can be combined to form a valid group approval.
+//!
+//! A proposal enables secure-only messaging, expels a peer or gives members of the room a
+//! role. Approved expulsions and role changes are kept as a `Decision`: the proposal with
+//! the signed approvals that carried it, which anyone can check without having seen the
+//! vote.
+//!
+//! Every proposal records the `VotingRule` it is decided by: a simple majority, a 2/3
+//! supermajority, unanimity, a fixed number of approvals, or a majority of the electorate's
+//! weight where owners weigh 3, moderators 2 and members 1.
+//!
+//! Inside a room every peer applies the rule to its own `Electorate`, the members it knows
+//! and the keys it pinned for them, rather than to the electorate the proposer counted.
+//! Only signed votes of those members count, so made-up identities cannot carry a proposal.
+//! A fixed rule may ask for fewer approvals than a majority to enable secure-only messaging,
+//! but expulsions and role changes always need a majority of the members we know.
 
-use crate::crypto::{CryptoError, CryptoManager};
+use crate::chat::roles::{Role, RoleSubject, RoomRoles};
+use crate::crypto::{verify_signature, CryptoError, CryptoManager};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+/// How many decisions we keep while waiting to learn who took them
+pub const MAX_PENDING_DECISIONS: usize = 32;
+
 /// Represents a proposal to enable secure-only messaging
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpgradeProposal {
@@ -28,6 +47,274 @@ pub struct UpgradeProposal {
     pub required_approvals: usize,
     /// Total number of peers in the network (N in M-of-N)
     pub total_peers: usize,
+    /// What happens once the proposal is approved (secure-only for older peers)
+    #[serde(default)]
+    pub action: ProposalAction,
+    /// How approvals are counted. Older peers send none and count `required_approvals`.
+    #[serde(default)]
+    pub rule: Option<VotingRule>,
+    /// Key the proposer signed the proposal with. Older peers send unsigned proposals.
+    #[serde(default, with = "serde_bytes")]
+    pub proposer_key: Option<Vec<u8>>,
+    #[serde(default, with = "serde_bytes")]
+    pub signature: Option<Vec<u8>>,
+}
+
+impl UpgradeProposal {
+    /// What the proposer signs
+    fn signed_data(&self) -> String {
+        let action = serde_json::to_string(&self.action).unwrap_or_default();
+        format!(
+            "proposal:{}:{}:{}:{}:{}:{action}:{:?}",
+            self.proposal_id,
+            self.proposer_id,
+            self.description,
+            self.required_approvals,
+            self.total_peers,
+            self.rule
+        )
+    }
+
+    /// Sign the proposal as its proposer
+    pub fn sign(&mut self, crypto_manager: &CryptoManager) -> Result<(), CryptoError> {
+        let signature = crypto_manager.sign_message(&self.signed_data(), self.timestamp)?;
+        self.signature = Some(signature.signature);
+        self.proposer_key = Some(signature.public_key);
+        Ok(())
+    }
+
+    /// Whether the proposal is signed, and the signature checks out against its key
+    pub fn verify(&self) -> bool {
+        let (Some(signature), Some(public_key)) = (&self.signature, &self.proposer_key) else {
+            return false;
+        };
+        verify_signature(public_key, &self.signed_data(), self.timestamp, signature).is_ok()
+    }
+
+    /// Approvals, or approval weight under a weighted rule, that carry the proposal
+    pub fn required(&self) -> usize {
+        match &self.rule {
+            Some(rule) => rule.required(self.total_peers),
+            None => self.required_approvals,
+        }
+    }
+
+    /// The threshold as shown to users, e.g. `2/3 approvals (simple majority)`
+    pub fn threshold(&self) -> String {
+        match &self.rule {
+            Some(rule @ VotingRule::Weighted { total_weight }) => {
+                format!("{}/{total_weight} vote weight ({rule})", self.required())
+            }
+            Some(rule) => format!(
+                "{}/{} approvals ({rule})",
+                self.required(),
+                self.total_peers
+            ),
+            None => format!("{}/{} approvals", self.required(), self.total_peers),
+        }
+    }
+
+    /// Approvals, or approval weight, that carry the proposal among `electorate`: its rule
+    /// applied to the voters we know but the peer it would expel, and never less than its
+    /// action takes (see `ProposalAction::min_approvals`). Proposals of older peers, which
+    /// have no rule, need a simple majority.
+    pub fn required_in(&self, electorate: &Electorate) -> usize {
+        let voters = electorate.without_target(self);
+        let required = match self.rule.unwrap_or_default() {
+            VotingRule::Weighted { .. } => voters.total_weight() / 2 + 1,
+            rule => rule
+                .required(voters.len())
+                .max(self.action.min_approvals(voters.len())),
+        };
+        required.max(1)
+    }
+
+    /// What the approvals of voters in `electorate` add up to: one each, or the weight of
+    /// their role under a weighted rule. Approvals of anyone else count for nothing.
+    fn tally_in(&self, approvals: &[UpgradeVote], electorate: &Electorate) -> usize {
+        let weighted = matches!(self.rule, Some(VotingRule::Weighted { .. }));
+        approvals
+            .iter()
+            .filter_map(|v| electorate.weight_of(v))
+            .map(|weight| if weighted { weight } else { 1 })
+            .sum()
+    }
+
+    /// What `approvals` add up to under the proposal's rule: one each, or the weight of
+    /// each voter's role in `roles` under a weighted rule
+    fn tally(&self, approvals: &[UpgradeVote], roles: Option<&RoomRoles>) -> usize {
+        match (&self.rule, roles) {
+            (Some(VotingRule::Weighted { .. }), Some(roles)) => approvals
+                .iter()
+                .map(|v| roles.role_of(v.public_key.as_deref()).weight())
+                .sum(),
+            _ => approvals.len(),
+        }
+    }
+}
+
+/// The peers allowed to vote in a room as far as we know them: the key pinned for each and
+/// the weight of their role
+#[derive(Debug, Clone, Default)]
+pub struct Electorate {
+    voters: HashMap<String, (Vec<u8>, usize)>,
+}
+
+impl Electorate {
+    /// Add `peer_id`, known by `public_key`, with a vote weighing `weight`
+    pub fn insert(&mut self, peer_id: String, public_key: Vec<u8>, weight: usize) {
+        self.voters.insert(peer_id, (public_key, weight));
+    }
+
+    pub fn remove(&mut self, peer_id: &str) {
+        self.voters.remove(peer_id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.voters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.voters.is_empty()
+    }
+
+    pub fn contains(&self, peer_id: &str) -> bool {
+        self.voters.contains_key(peer_id)
+    }
+
+    /// Whether `peer_id` is one of ours and `public_key` the key we know for it
+    pub fn knows(&self, peer_id: &str, public_key: Option<&[u8]>) -> bool {
+        self.voters
+            .get(peer_id)
+            .is_some_and(|(key, _)| Some(key.as_slice()) == public_key)
+    }
+
+    /// Total weight of the voters' roles
+    pub fn total_weight(&self) -> usize {
+        self.voters.values().map(|(_, weight)| weight).sum()
+    }
+
+    /// Weight of the voter of `vote`, if it is one of ours and the vote carries the key we
+    /// know for it
+    pub fn weight_of(&self, vote: &UpgradeVote) -> Option<usize> {
+        let (key, weight) = self.voters.get(&vote.voter_id)?;
+        (vote.public_key.as_ref() == Some(key)).then_some(*weight)
+    }
+
+    /// The voters deciding `proposal`: everyone but the peer it would expel
+    fn without_target(&self, proposal: &UpgradeProposal) -> Electorate {
+        let ProposalAction::Expel(target) = &proposal.action else {
+            return self.clone();
+        };
+        let voters = self
+            .voters
+            .iter()
+            .filter(|(id, (key, _))| **id != target.peer_id && *key != target.public_key)
+            .map(|(id, voter)| (id.clone(), voter.clone()))
+            .collect();
+        Electorate { voters }
+    }
+}
+
+/// How many approvals a proposal needs out of an electorate of N
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VotingRule {
+    /// More than half of N
+    #[default]
+    SimpleMajority,
+    /// At least two thirds of N
+    Supermajority,
+    /// All of N
+    Unanimous,
+    /// A fixed number of approvals, whatever N
+    Fixed { required: usize },
+    /// More than half of the electorate's total weight, each voter weighing as much as
+    /// their role (see `Role::weight`)
+    Weighted { total_weight: usize },
+}
+
+impl VotingRule {
+    /// Approvals, or approval weight, needed out of an electorate of `total`. A fixed rule
+    /// asks for its number whatever `total`; proposals asking for none are refused.
+    pub fn required(&self, total: usize) -> usize {
+        match self {
+            VotingRule::SimpleMajority => total / 2 + 1,
+            VotingRule::Supermajority => (2 * total).div_ceil(3).max(1),
+            VotingRule::Unanimous => total.max(1),
+            VotingRule::Fixed { required } => *required,
+            VotingRule::Weighted { total_weight } => total_weight / 2 + 1,
+        }
+    }
+}
+
+impl std::fmt::Display for VotingRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VotingRule::SimpleMajority => f.write_str("simple majority"),
+            VotingRule::Supermajority => f.write_str("2/3 supermajority"),
+            VotingRule::Unanimous => f.write_str("unanimity"),
+            VotingRule::Fixed { required } => write!(f, "{required} approvals"),
+            VotingRule::Weighted { .. } => f.write_str("role-weighted majority"),
+        }
+    }
+}
+
+impl std::str::FromStr for VotingRule {
+    type Err = CryptoError;
+
+    /// `majority`, `supermajority` (or `2/3`), `unanimous`, `weighted`, or a number of
+    /// approvals. The weight of a weighted rule is filled in when a proposal is made.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "majority" | "simple" => Ok(VotingRule::SimpleMajority),
+            "supermajority" | "2/3" => Ok(VotingRule::Supermajority),
+            "unanimous" | "unanimity" | "all" => Ok(VotingRule::Unanimous),
+            "weighted" => Ok(VotingRule::Weighted { total_weight: 0 }),
+            other => match other.parse() {
+                Ok(0) => Err(CryptoError::Unknown(
+                    "A proposal needs at least one approval".to_string(),
+                )),
+                Ok(required) => Ok(VotingRule::Fixed { required }),
+                Err(_) => Err(CryptoError::Unknown(format!(
+                    "Unknown voting rule {s}; use majority, supermajority, unanimous, \
+                     weighted or a number of approvals"
+                ))),
+            },
+        }
+    }
+}
+
+/// What an approved proposal does
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum ProposalAction {
+    /// Only accept signed messages from now on
+    #[default]
+    SecureOnly,
+    /// Remove a peer from the room for good
+    Expel(TargetPeer),
+    /// Give a peer, or everyone without a role of their own, a role in the room
+    AssignRole { subject: RoleSubject, role: Role },
+}
+
+impl ProposalAction {
+    /// Approvals the action needs out of `voters` whatever the rule: expelling a peer or
+    /// changing roles takes a majority of them
+    pub fn min_approvals(&self, voters: usize) -> usize {
+        match self {
+            ProposalAction::SecureOnly => 1,
+            _ => voters / 2 + 1,
+        }
+    }
+}
+
+/// The peer a proposal is about
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TargetPeer {
+    pub peer_id: String,
+    pub name: String,
+    /// The key the peer is blocked by, whatever id it comes back with
+    #[serde(with = "serde_bytes")]
+    pub public_key: Vec<u8>,
 }
 
 /// A peer's vote on an upgrade proposal
@@ -44,7 +331,98 @@ pub struct UpgradeVote {
     /// Timestamp when the vote was cast
     pub timestamp: u64,
     /// Optional signature for vote authenticity
+    #[serde(default, with = "serde_bytes")]
     pub signature: Option<Vec<u8>>,
+    /// Key the signature was made with
+    #[serde(default, with = "serde_bytes")]
+    pub public_key: Option<Vec<u8>>,
+}
+
+impl UpgradeVote {
+    /// What the voter signs
+    fn signed_data(&self) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            self.proposal_id, self.voter_id, self.approved, self.timestamp
+        )
+    }
+
+    /// Whether the vote is signed, and the signature checks out against its key
+    pub fn verify(&self) -> bool {
+        let (Some(signature), Some(public_key)) = (&self.signature, &self.public_key) else {
+            return false;
+        };
+        verify_signature(public_key, &self.signed_data(), self.timestamp, signature).is_ok()
+    }
+}
+
+/// An approved expulsion or role change, shown to peers joining later so the expelled peer
+/// cannot simply come back and roles agree across the room
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Decision {
+    pub proposal: UpgradeProposal,
+    /// The signed approvals that met the threshold
+    pub approvals: Vec<UpgradeVote>,
+}
+
+impl Decision {
+    /// The expelled peer, if this is an expulsion
+    pub fn expelled(&self) -> Option<&TargetPeer> {
+        match &self.proposal.action {
+            ProposalAction::Expel(target) => Some(target),
+            _ => None,
+        }
+    }
+
+    /// Check that its proposer signed the proposal and enough approvals carried it, each
+    /// signed by a different key other than the expelled peer's.
+    ///
+    /// With an `electorate` the proposer must be one of its voters, signing with the key we
+    /// know for them, and only approvals of its voters count towards the threshold it sets
+    /// (see `UpgradeProposal::required_in`). Without one the decision is only checked
+    /// against its own threshold, which anyone could have made up along with the voters.
+    pub fn verify(&self, electorate: Option<&Electorate>) -> Result<(), CryptoError> {
+        let proposal = &self.proposal;
+        if proposal.action == ProposalAction::SecureOnly {
+            return Err(CryptoError::Unknown("Not a decision".to_string()));
+        }
+        if !proposal.verify() {
+            return Err(CryptoError::Unknown(format!(
+                "Proposal {} is not signed by its proposer",
+                proposal.proposal_id
+            )));
+        }
+        let proposer_key = proposal.proposer_key.as_deref();
+        if electorate.is_some_and(|e| !e.knows(&proposal.proposer_id, proposer_key)) {
+            return Err(CryptoError::Unknown(format!(
+                "Proposal {} comes from {}, who is not a member we know",
+                proposal.proposal_id, proposal.proposer_name
+            )));
+        }
+        let counted = counted_approvals(
+            &self.proposal,
+            self.expelled(),
+            &self.approvals,
+            &HashSet::new(),
+        );
+        let (tally, required) = match electorate {
+            Some(electorate) => (
+                self.proposal.tally_in(&counted, electorate),
+                self.proposal.required_in(electorate),
+            ),
+            None => (
+                self.proposal.tally(&counted, None),
+                self.proposal.required().max(1),
+            ),
+        };
+        if tally < required {
+            return Err(CryptoError::Unknown(format!(
+                "Proposal {} has {tally} of the {required} it needs among the members we know",
+                self.proposal.proposal_id,
+            )));
+        }
+        Ok(())
+    }
 }
 
 /// A partial signature for threshold approval
@@ -57,8 +435,10 @@ pub struct PartialSignature {
     /// The signer's display name
     pub signer_name: String,
     /// The partial signature bytes
+    #[serde(with = "serde_bytes")]
     pub signature: Vec<u8>,
     /// The signer's public key
+    #[serde(with = "serde_bytes")]
     pub public_key: Vec<u8>,
     /// Timestamp when the signature was created
     pub timestamp: u64,
@@ -87,6 +467,14 @@ pub struct ThresholdManager {
     proposal_states: Arc<RwLock<HashMap<String, ProposalState>>>,
     /// Whether secure-only messaging is currently enabled
     secure_only_enabled: Arc<RwLock<bool>>,
+    /// Approved expulsions and role changes, in the order they were decided
+    decisions: Arc<RwLock<Vec<Decision>>>,
+    /// Rule of the proposals we create
+    voting_rule: Arc<RwLock<VotingRule>>,
+    /// Roles of the room, weighing votes under a weighted rule
+    roles: Option<Arc<RoomRoles>>,
+    /// Decisions we could not check yet, waiting for the members who took them
+    pending_decisions: Arc<RwLock<Vec<Decision>>>,
 }
 
 impl Default for ThresholdManager {
@@ -97,11 +485,32 @@ impl Default for ThresholdManager {
             partial_signatures: Arc::new(RwLock::new(HashMap::new())),
             proposal_states: Arc::new(RwLock::new(HashMap::new())),
             secure_only_enabled: Arc::new(RwLock::new(false)),
+            decisions: Arc::new(RwLock::new(Vec::new())),
+            voting_rule: Arc::new(RwLock::new(VotingRule::default())),
+            roles: None,
+            pending_decisions: Arc::new(RwLock::new(Vec::new())),
         }
     }
 }
 
 impl ThresholdManager {
+    /// A manager weighing votes by the roles in `roles`
+    pub fn with_roles(roles: Arc<RoomRoles>) -> Self {
+        Self {
+            roles: Some(roles),
+            ..Self::default()
+        }
+    }
+
+    /// Rule of the proposals created in the room
+    pub async fn voting_rule(&self) -> VotingRule {
+        *self.voting_rule.read().await
+    }
+
+    pub async fn set_voting_rule(&self, rule: VotingRule) {
+        *self.voting_rule.write().await = rule;
+    }
+
     /// Insert a received proposal if not present
     pub async fn insert_received_proposal(&self, proposal: UpgradeProposal) {
         let exists = self.get_proposal(&proposal.proposal_id).await.is_some();
@@ -134,6 +543,50 @@ impl ThresholdManager {
         required_approvals: usize,
         total_peers: usize,
     ) -> Result<String, CryptoError> {
+        self.create_proposal_with_rule(
+            proposer_id,
+            proposer_name,
+            description,
+            ProposalAction::SecureOnly,
+            VotingRule::Fixed {
+                required: required_approvals,
+            },
+            total_peers,
+        )
+        .await
+    }
+
+    /// Create a new proposal doing `action` once approved under `rule` by an electorate of
+    /// `total_peers`. A fixed rule must ask for at least what the action takes, and no more
+    /// than the electorate can give.
+    pub async fn create_proposal_with_rule(
+        &self,
+        proposer_id: String,
+        proposer_name: String,
+        description: String,
+        action: ProposalAction,
+        rule: VotingRule,
+        total_peers: usize,
+    ) -> Result<String, CryptoError> {
+        if let VotingRule::Fixed { required } = rule {
+            if required == 0 {
+                return Err(CryptoError::Unknown(
+                    "A proposal needs at least one approval".to_string(),
+                ));
+            }
+            if required > total_peers {
+                return Err(CryptoError::Unknown(format!(
+                    "Cannot ask for {required} approvals from {total_peers} voters"
+                )));
+            }
+            let least = action.min_approvals(total_peers);
+            if required < least {
+                return Err(CryptoError::Unknown(format!(
+                    "Expelling a peer or changing roles takes at least {least} approvals \
+                     from {total_peers} voters"
+                )));
+            }
+        }
         let proposal_id = Uuid::new_v4().to_string();
         let timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
@@ -146,8 +599,12 @@ impl ThresholdManager {
             proposer_name,
             timestamp,
             description,
-            required_approvals,
+            required_approvals: rule.required(total_peers),
             total_peers,
+            action,
+            rule: Some(rule),
+            proposer_key: None,
+            signature: None,
         };
 
         self.proposals
@@ -170,7 +627,20 @@ impl ThresholdManager {
         Ok(proposal_id)
     }
 
-    /// Cast a vote on a proposal
+    /// Sign a proposal we created, so peers can tell it comes from us
+    pub async fn sign_proposal(
+        &self,
+        proposal_id: &str,
+        crypto_manager: &CryptoManager,
+    ) -> Result<(), CryptoError> {
+        let mut proposals = self.proposals.write().await;
+        let proposal = proposals
+            .get_mut(proposal_id)
+            .ok_or(CryptoError::Unknown("Proposal not found".to_string()))?;
+        proposal.sign(crypto_manager)
+    }
+
+    /// Cast a vote on a proposal. Returns whether the vote approved it.
     pub async fn cast_vote(
         &self,
         proposal_id: &str,
@@ -178,7 +648,29 @@ impl ThresholdManager {
         voter_name: String,
         approved: bool,
         crypto_manager: &CryptoManager,
-    ) -> Result<(), CryptoError> {
+    ) -> Result<bool, CryptoError> {
+        self.cast_vote_in(
+            proposal_id,
+            voter_id,
+            voter_name,
+            approved,
+            crypto_manager,
+            None,
+        )
+        .await
+    }
+
+    /// Cast a vote on a proposal decided by `electorate` (see `check_threshold`). Returns
+    /// whether the vote approved it.
+    pub async fn cast_vote_in(
+        &self,
+        proposal_id: &str,
+        voter_id: String,
+        voter_name: String,
+        approved: bool,
+        crypto_manager: &CryptoManager,
+        electorate: Option<&Electorate>,
+    ) -> Result<bool, CryptoError> {
         // Check if proposal exists and is open
         // let _proposal = {
         //     let proposals = self.proposals.read().await;
@@ -220,23 +712,19 @@ impl ThresholdManager {
             .map_err(|e| CryptoError::Unknown(e.to_string()))?
             .as_secs();
 
-        // Create a signed vote if approved
-        let signature = if approved {
-            let vote_data = format!("{proposal_id}:{voter_id}:{approved}:{timestamp}");
-            let signature = crypto_manager.sign_message(&vote_data, timestamp)?;
-            Some(signature.signature)
-        } else {
-            None
-        };
-
-        let vote = UpgradeVote {
+        let mut vote = UpgradeVote {
             proposal_id: proposal_id.to_string(),
             voter_id,
             voter_name,
             approved,
             timestamp,
-            signature,
+            signature: None,
+            public_key: None,
         };
+        // Rejections are signed too, so nobody can reject in another peer's name
+        let signature = crypto_manager.sign_message(&vote.signed_data(), timestamp)?;
+        vote.signature = Some(signature.signature);
+        vote.public_key = Some(signature.public_key);
 
         // Add the vote
         self.votes
@@ -247,13 +735,20 @@ impl ThresholdManager {
             .push(vote);
 
         // Check if threshold is met
-        self.check_threshold(proposal_id).await?;
-
-        Ok(())
+        self.check_threshold(proposal_id, electorate).await
     }
 
-    /// Check if a proposal has reached the required threshold
-    async fn check_threshold(&self, proposal_id: &str) -> Result<(), CryptoError> {
+    /// Check if a proposal has reached the required threshold, and carry it out if so.
+    /// Returns whether it was approved by this check.
+    ///
+    /// With an `electorate` only its voters' signed approvals count, towards the threshold
+    /// of the proposal's rule among them. Without one, as for a manager outside any room,
+    /// the proposal's own threshold applies.
+    async fn check_threshold(
+        &self,
+        proposal_id: &str,
+        electorate: Option<&Electorate>,
+    ) -> Result<bool, CryptoError> {
         let proposal = {
             let proposals = self.proposals.read().await;
             proposals
@@ -270,34 +765,110 @@ impl ThresholdManager {
                 .clone()
         };
 
-        let approval_count = votes.iter().filter(|v| v.approved).count();
+        let is_open = matches!(
+            self.get_proposal_state(proposal_id).await,
+            Some(ProposalState::Open)
+        );
+        if !is_open {
+            return Ok(false);
+        }
 
-        if approval_count >= proposal.required_approvals {
-            // Threshold met - mark as approved
-            self.proposal_states
-                .write()
-                .await
-                .insert(proposal_id.to_string(), ProposalState::Approved);
+        // Expelled peers no longer have a say
+        let expelled = self.expelled_ids().await;
+        let target = match &proposal.action {
+            ProposalAction::Expel(target) => Some(target),
+            _ => None,
+        };
+        let (approvals, approval_count, required) = match (electorate, &proposal.action) {
+            (Some(electorate), _) => {
+                let approvals: Vec<UpgradeVote> =
+                    counted_approvals(&proposal, target, &votes, &expelled)
+                        .into_iter()
+                        .filter(|v| electorate.weight_of(v).is_some())
+                        .collect();
+                let count = proposal.tally_in(&approvals, electorate);
+                (approvals, count, proposal.required_in(electorate))
+            }
+            (None, ProposalAction::SecureOnly) => {
+                let approvals: Vec<UpgradeVote> = votes
+                    .into_iter()
+                    .filter(|v| v.approved && !expelled.contains(&v.voter_id))
+                    .collect();
+                let count = proposal.tally(&approvals, self.roles.as_deref());
+                (approvals, count, proposal.required().max(1))
+            }
+            (None, _) => {
+                let approvals = counted_approvals(&proposal, target, &votes, &expelled);
+                let count = proposal.tally(&approvals, self.roles.as_deref());
+                (approvals, count, proposal.required().max(1))
+            }
+        };
 
-            // Enable secure-only messaging
-            *self.secure_only_enabled.write().await = true;
+        if approval_count < required {
+            return Ok(false);
+        }
+        // Threshold met - mark as approved
+        self.proposal_states
+            .write()
+            .await
+            .insert(proposal_id.to_string(), ProposalState::Approved);
 
-            println!(
-                "🔐 Secure-only messaging enabled! Threshold of {}/{} approvals met.",
-                approval_count, proposal.total_peers
-            );
+        match &proposal.action {
+            ProposalAction::SecureOnly => {
+                // Enable secure-only messaging
+                *self.secure_only_enabled.write().await = true;
+
+                println!(
+                    "🔐 Secure-only messaging enabled! Threshold of {}/{} approvals met.",
+                    approval_count, proposal.total_peers
+                );
+            }
+            ProposalAction::Expel(target) => {
+                println!(
+                    "🚫 {} expelled! Threshold of {}/{} approvals met.",
+                    target.name, approval_count, proposal.total_peers
+                );
+                self.decisions.write().await.push(Decision {
+                    proposal: proposal.clone(),
+                    approvals,
+                });
+            }
+            ProposalAction::AssignRole { subject, role } => {
+                println!(
+                    "👑 {subject} is now {role}! Threshold of {}/{} approvals met.",
+                    approval_count, proposal.total_peers
+                );
+                self.decisions.write().await.push(Decision {
+                    proposal: proposal.clone(),
+                    approvals,
+                });
+            }
         }
 
-        Ok(())
+        Ok(true)
     }
 
-    /// Handle a received vote from another peer
-    pub async fn handle_received_vote(&self, vote: &UpgradeVote) {
+    /// Handle a received vote from another peer. Returns whether the vote approved the
+    /// proposal.
+    ///
+    /// Unsigned votes, votes whose signature does not check out and, given an `electorate`,
+    /// votes of anyone but its voters under the keys we know for them are dropped.
+    pub async fn handle_received_vote(
+        &self,
+        vote: &UpgradeVote,
+        electorate: Option<&Electorate>,
+    ) -> bool {
+        if !vote.verify() || electorate.is_some_and(|e| e.weight_of(vote).is_none()) {
+            eprintln!(
+                "⚠️  Warning: Dropping vote of {} on proposal {} that we cannot check",
+                vote.voter_name, vote.proposal_id
+            );
+            return false;
+        }
         // Add the vote if not already present
         let existing_votes = self.get_proposal_votes(&vote.proposal_id).await;
         // If not voted already
         if !existing_votes.iter().any(|v| v.voter_id == vote.voter_id) {
-            //TODO You may want to verify the vote signature here
             self.votes
                 .write()
                 .await
@@ -305,8 +876,76 @@ impl ThresholdManager {
                 .or_insert_with(Vec::new)
                 .push(vote.clone());
             // Check threshold and activate if passed
-            let _ = self.check_threshold(&vote.proposal_id).await;
+            return self
+                .check_threshold(&vote.proposal_id, electorate)
+                .await
+                .unwrap_or(false);
+        }
+        false
+    }
+
+    /// Record a decision taken without us, e.g. before we joined. Returns whether it was
+    /// new; the caller checks it with `Decision::verify` first.
+    pub async fn record_decision(&self, decision: Decision) -> bool {
+        let proposal_id = decision.proposal.proposal_id.clone();
+        let mut decisions = self.decisions.write().await;
+        if decisions
+            .iter()
+            .any(|d| d.proposal.proposal_id == proposal_id)
+        {
+            return false;
+        }
+        decisions.push(decision.clone());
+        drop(decisions);
+        self.insert_received_proposal(decision.proposal).await;
+        self.proposal_states
+            .write()
+            .await
+            .insert(proposal_id, ProposalState::Approved);
+        true
+    }
+
+    /// Keep a decision we cannot check yet, until we know the members who took it. A newer
+    /// copy replaces an older one, and only the latest `MAX_PENDING_DECISIONS` are kept.
+    pub async fn defer_decision(&self, decision: Decision) {
+        let mut pending = self.pending_decisions.write().await;
+        pending.retain(|d| d.proposal.proposal_id != decision.proposal.proposal_id);
+        if pending.len() >= MAX_PENDING_DECISIONS {
+            pending.remove(0);
         }
+        pending.push(decision);
+    }
+
+    /// Take the decisions waiting to be checked, oldest first
+    pub async fn take_pending_decisions(&self) -> Vec<Decision> {
+        std::mem::take(&mut *self.pending_decisions.write().await)
+    }
+
+    /// Approved expulsions and role changes, oldest first
+    pub async fn decisions(&self) -> Vec<Decision> {
+        self.decisions.read().await.clone()
+    }
+
+    /// The decision taken by approving `proposal_id`
+    pub async fn get_decision(&self, proposal_id: &str) -> Option<Decision> {
+        let decisions = self.decisions.read().await;
+        decisions
+            .iter()
+            .find(|d| d.proposal.proposal_id == proposal_id)
+            .cloned()
+    }
+
+    /// Whether `peer_id` was expelled
+    pub async fn is_expelled(&self, peer_id: &str) -> bool {
+        self.expelled_ids().await.contains(peer_id)
+    }
+
+    async fn expelled_ids(&self) -> HashSet<String> {
+        let decisions = self.decisions.read().await;
+        decisions
+            .iter()
+            .filter_map(|d| d.expelled().map(|target| target.peer_id.clone()))
+            .collect()
     }
 
     /// Handle incoming upgrade activation broadcast from another peer
@@ -359,6 +998,32 @@ impl ThresholdManager {
     }
 }
 
+/// The approvals of a decision that count: signed, valid, on this proposal and by a
+/// different key each, from neither the peer to expel nor an already expelled peer
+fn counted_approvals(
+    proposal: &UpgradeProposal,
+    target: Option<&TargetPeer>,
+    votes: &[UpgradeVote],
+    expelled: &HashSet<String>,
+) -> Vec<UpgradeVote> {
+    let mut keys = HashSet::new();
+    votes
+        .iter()
+        .filter(|v| {
+            let is_target = target.is_some_and(|target| {
+                v.voter_id == target.peer_id || v.public_key.as_ref() == Some(&target.public_key)
+            });
+            v.approved
+                && v.proposal_id == proposal.proposal_id
+                && !is_target
+                && !expelled.contains(&v.voter_id)
+                && v.verify()
+        })
+        .filter(|v| keys.insert(v.public_key.clone()))
+        .cloned()
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -475,4 +1140,481 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[tokio::test]
+    async fn test_expulsion_needs_distinct_signed_approvals() {
+        let manager = ThresholdManager::default();
+        let alice = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let bob = CryptoManager::new("bob".to_string(), "Bob".to_string());
+        let mallory = CryptoManager::new("mallory".to_string(), "Mallory".to_string());
+        let target = TargetPeer {
+            peer_id: "mallory".to_string(),
+            name: "Mallory".to_string(),
+            public_key: mallory.get_public_key(),
+        };
+        let proposal_id = manager
+            .create_proposal_with_rule(
+                "alice".to_string(),
+                "Alice".to_string(),
+                "Expel Mallory".to_string(),
+                ProposalAction::Expel(target),
+                VotingRule::Fixed { required: 2 },
+                2,
+            )
+            .await
+            .unwrap();
+        manager.sign_proposal(&proposal_id, &alice).await.unwrap();
+
+        // The target's own approval does not count
+        let voters = [(&mallory, "mallory"), (&alice, "alice"), (&bob, "bob")];
+        let mut approved = Vec::new();
+        for (crypto, id) in voters {
+            let vote = manager
+                .cast_vote(&proposal_id, id.to_string(), id.to_string(), true, crypto)
+                .await
+                .unwrap();
+            approved.push(vote);
+        }
+        assert_eq!(approved, [false, false, true]);
+        assert!(!manager.is_secure_only_enabled().await);
+        assert!(manager.is_expelled("mallory").await);
+
+        let expulsion = manager.get_decision(&proposal_id).await.unwrap();
+        assert_eq!(expulsion.approvals.len(), 2);
+        assert!(expulsion.verify(None).is_ok());
+        // It survives the wire
+        let bytes = serde_json::to_vec(&expulsion).unwrap();
+        let decoded: Decision = serde_json::from_slice(&bytes).unwrap();
+        assert!(decoded.verify(None).is_ok());
+
+        let mut forged = expulsion.clone();
+        forged.approvals[1].voter_id = "carol".to_string();
+        assert!(forged.verify(None).is_err());
+        // So does a proposal its proposer did not sign
+        let mut unsigned = expulsion.clone();
+        unsigned.proposal.signature = None;
+        assert!(unsigned.verify(None).is_err());
+        let mut altered = expulsion.clone();
+        altered.proposal.proposer_id = "bob".to_string();
+        assert!(altered.verify(None).is_err());
+        let mut short = expulsion.clone();
+        short.approvals.pop();
+        assert!(short.verify(None).is_err());
+        // The same key twice is one voter
+        let mut doubled = short.clone();
+        doubled.approvals.push(short.approvals[0].clone());
+        assert!(doubled.verify(None).is_err());
+
+        // A peer joining later records it once
+        let late = ThresholdManager::default();
+        assert!(late.record_decision(expulsion.clone()).await);
+        assert!(!late.record_decision(expulsion).await);
+        assert!(late.is_expelled("mallory").await);
+        assert!(late.get_active_proposals().await.is_empty());
+
+        // Expelled peers have no say in later proposals
+        let next = manager
+            .create_proposal(
+                "alice".to_string(),
+                "Alice".to_string(),
+                "Secure".to_string(),
+                1,
+                2,
+            )
+            .await
+            .unwrap();
+        let mut vote = UpgradeVote {
+            proposal_id: next.clone(),
+            voter_id: "mallory".to_string(),
+            voter_name: "Mallory".to_string(),
+            approved: true,
+            timestamp: 1,
+            signature: None,
+            public_key: None,
+        };
+        // Unsigned, it is not even looked at
+        assert!(!manager.handle_received_vote(&vote, None).await);
+        let signature = mallory.sign_message(&vote.signed_data(), 1).unwrap();
+        vote.signature = Some(signature.signature);
+        vote.public_key = Some(signature.public_key);
+        assert!(!manager.handle_received_vote(&vote, None).await);
+        assert!(!manager.is_secure_only_enabled().await);
+    }
+
+    #[tokio::test]
+    async fn test_electorate_sets_the_threshold() {
+        let peers: Vec<CryptoManager> = ["alice", "bob", "carol", "mallory"]
+            .iter()
+            .map(|id| CryptoManager::new(id.to_string(), id.to_string()))
+            .collect();
+        let [alice, _bob, carol, mallory] = &peers[..] else {
+            unreachable!()
+        };
+        let mut electorate = Electorate::default();
+        for crypto in &peers {
+            let identity = crypto.get_identity();
+            electorate.insert(identity.peer_id.clone(), identity.public_key.clone(), 1);
+        }
+        let target = TargetPeer {
+            peer_id: "mallory".to_string(),
+            name: "mallory".to_string(),
+            public_key: mallory.get_public_key(),
+        };
+
+        // The proposer claims to be alone, but three of us decide it
+        let manager = ThresholdManager::default();
+        let proposal_id = manager
+            .create_proposal_with_rule(
+                "alice".to_string(),
+                "alice".to_string(),
+                "Expel Mallory".to_string(),
+                ProposalAction::Expel(target),
+                VotingRule::SimpleMajority,
+                1,
+            )
+            .await
+            .unwrap();
+        manager.sign_proposal(&proposal_id, alice).await.unwrap();
+        let proposal = manager.get_proposal(&proposal_id).await.unwrap();
+        assert_eq!(proposal.required(), 1);
+        assert_eq!(proposal.required_in(&electorate), 2);
+        let passed = manager
+            .cast_vote_in(
+                &proposal_id,
+                "alice".to_string(),
+                "alice".to_string(),
+                true,
+                alice,
+                Some(&electorate),
+            )
+            .await
+            .unwrap();
+        assert!(!passed);
+
+        // Votes of strangers, or under a key other than the one we know, do not count
+        let vote = |crypto: &CryptoManager, voter_id: &str| {
+            let mut vote = UpgradeVote {
+                proposal_id: proposal_id.clone(),
+                voter_id: voter_id.to_string(),
+                voter_name: voter_id.to_string(),
+                approved: true,
+                timestamp: 1,
+                signature: None,
+                public_key: None,
+            };
+            let signature = crypto.sign_message(&vote.signed_data(), 1).unwrap();
+            vote.signature = Some(signature.signature);
+            vote.public_key = Some(signature.public_key);
+            vote
+        };
+        let dave = CryptoManager::new("dave".to_string(), "dave".to_string());
+        for forged in [vote(&dave, "dave"), vote(&dave, "bob")] {
+            assert!(
+                !manager
+                    .handle_received_vote(&forged, Some(&electorate))
+                    .await
+            );
+        }
+        assert!(manager.get_decision(&proposal_id).await.is_none());
+        assert!(
+            manager
+                .handle_received_vote(&vote(carol, "carol"), Some(&electorate))
+                .await
+        );
+
+        // A decision carried by votes we cannot place only passes on its own terms
+        let decision = manager.get_decision(&proposal_id).await.unwrap();
+        assert!(decision.verify(Some(&electorate)).is_ok());
+        let mut forged = decision.clone();
+        forged.approvals = vec![decision.approvals[0].clone(), vote(&dave, "dave")];
+        assert!(forged.verify(None).is_ok());
+        assert!(forged.verify(Some(&electorate)).is_err());
+
+        // So does one proposed by a stranger
+        let mut stranger = decision.clone();
+        stranger.proposal.proposer_id = "dave".to_string();
+        stranger.proposal.sign(&dave).unwrap();
+        assert!(stranger.verify(None).is_ok());
+        assert!(stranger.verify(Some(&electorate)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expulsions_need_a_majority_whatever_the_rule() {
+        let peers: Vec<CryptoManager> = ["alice", "bob", "carol", "mallory"]
+            .iter()
+            .map(|id| CryptoManager::new(id.to_string(), id.to_string()))
+            .collect();
+        let [alice, .., mallory] = &peers[..] else {
+            unreachable!()
+        };
+        let mut electorate = Electorate::default();
+        for crypto in &peers {
+            let identity = crypto.get_identity();
+            electorate.insert(identity.peer_id.clone(), identity.public_key.clone(), 1);
+        }
+        let target = TargetPeer {
+            peer_id: "alice".to_string(),
+            name: "alice".to_string(),
+            public_key: alice.get_public_key(),
+        };
+
+        // We do not make such proposals ourselves
+        let manager = ThresholdManager::default();
+        let refused = manager
+            .create_proposal_with_rule(
+                "mallory".to_string(),
+                "mallory".to_string(),
+                "Expel Alice".to_string(),
+                ProposalAction::Expel(target.clone()),
+                VotingRule::Fixed { required: 1 },
+                4,
+            )
+            .await;
+        assert!(refused.is_err());
+
+        // Mallory asks for one approval and gives it herself
+        let mut proposal = UpgradeProposal {
+            proposal_id: "p".to_string(),
+            proposer_id: "mallory".to_string(),
+            proposer_name: "mallory".to_string(),
+            timestamp: 1,
+            description: "Expel Alice".to_string(),
+            required_approvals: 1,
+            total_peers: 4,
+            action: ProposalAction::Expel(target),
+            rule: Some(VotingRule::Fixed { required: 1 }),
+            proposer_key: None,
+            signature: None,
+        };
+        proposal.sign(mallory).unwrap();
+        let mut vote = UpgradeVote {
+            proposal_id: "p".to_string(),
+            voter_id: "mallory".to_string(),
+            voter_name: "mallory".to_string(),
+            approved: true,
+            timestamp: 1,
+            signature: None,
+            public_key: None,
+        };
+        let signature = mallory.sign_message(&vote.signed_data(), 1).unwrap();
+        vote.signature = Some(signature.signature);
+        vote.public_key = Some(signature.public_key);
+        assert_eq!(proposal.required_in(&electorate), 2);
+        let decision = Decision {
+            proposal,
+            approvals: vec![vote],
+        };
+        assert!(decision.verify(Some(&electorate)).is_err());
+    }
+
+    #[test]
+    fn test_voting_rule_thresholds() {
+        let cases = [
+            (VotingRule::SimpleMajority, [1, 1, 2, 2, 3, 4, 4]),
+            (VotingRule::Supermajority, [1, 1, 2, 2, 3, 4, 5]),
+            (VotingRule::Unanimous, [1, 1, 2, 3, 4, 6, 7]),
+            (VotingRule::Fixed { required: 2 }, [2; 7]),
+        ];
+        for (rule, expected) in cases {
+            let required: Vec<usize> = [0, 1, 2, 3, 4, 6, 7]
+                .iter()
+                .map(|total| rule.required(*total))
+                .collect();
+            assert_eq!(required, expected, "{rule}");
+        }
+        let weighted = |total_weight| VotingRule::Weighted { total_weight };
+        assert_eq!(weighted(0).required(3), 1);
+        assert_eq!(weighted(6).required(3), 4);
+
+        assert_eq!(
+            "2/3".parse::<VotingRule>().unwrap(),
+            VotingRule::Supermajority
+        );
+        assert_eq!("all".parse::<VotingRule>().unwrap(), VotingRule::Unanimous);
+        assert_eq!(
+            "3".parse::<VotingRule>().unwrap(),
+            VotingRule::Fixed { required: 3 }
+        );
+        assert_eq!("weighted".parse::<VotingRule>().unwrap(), weighted(0));
+        assert!("most".parse::<VotingRule>().is_err());
+        // A proposal always needs an approval
+        assert!("0".parse::<VotingRule>().is_err());
+
+        // Proposals of older peers keep their own count
+        let legacy: UpgradeProposal = serde_json::from_value(serde_json::json!({
+            "proposal_id": "p1",
+            "proposer_id": "old",
+            "proposer_name": "Old",
+            "timestamp": 1,
+            "description": "Secure",
+            "required_approvals": 3,
+            "total_peers": 4,
+        }))
+        .unwrap();
+        assert_eq!(legacy.rule, None);
+        assert_eq!(legacy.required(), 3);
+    }
+
+    /// Cast approvals from `voters` distinct peers on a proposal decided by `rule` among
+    /// `total`, rejecting with the first `rejections` of them. Returns how many votes it
+    /// took to approve it, if it passed.
+    async fn votes_to_pass(
+        rule: VotingRule,
+        total: usize,
+        voters: usize,
+        rejections: usize,
+    ) -> Option<usize> {
+        let manager = ThresholdManager::default();
+        let proposal_id = manager
+            .create_proposal_with_rule(
+                "proposer".to_string(),
+                "Proposer".to_string(),
+                "Secure".to_string(),
+                ProposalAction::SecureOnly,
+                rule,
+                total,
+            )
+            .await
+            .unwrap();
+        for i in 0..voters {
+            let id = format!("voter{i}");
+            let crypto = CryptoManager::new(id.clone(), id.clone());
+            let approve = i >= rejections;
+            let passed = manager
+                .cast_vote(&proposal_id, id.clone(), id, approve, &crypto)
+                .await
+                .unwrap();
+            if passed {
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+
+    #[tokio::test]
+    async fn test_rules_are_applied_when_counting_votes() {
+        assert_eq!(
+            votes_to_pass(VotingRule::SimpleMajority, 4, 4, 0).await,
+            Some(3)
+        );
+        assert_eq!(
+            votes_to_pass(VotingRule::SimpleMajority, 1, 1, 0).await,
+            Some(1)
+        );
+        assert_eq!(
+            votes_to_pass(VotingRule::Supermajority, 3, 3, 0).await,
+            Some(2)
+        );
+        assert_eq!(
+            votes_to_pass(VotingRule::Supermajority, 4, 4, 0).await,
+            Some(3)
+        );
+        assert_eq!(
+            votes_to_pass(VotingRule::Supermajority, 4, 4, 2).await,
+            None
+        );
+        assert_eq!(votes_to_pass(VotingRule::Unanimous, 3, 3, 0).await, Some(3));
+        assert_eq!(votes_to_pass(VotingRule::Unanimous, 3, 3, 1).await, None);
+        let fixed = VotingRule::Fixed { required: 2 };
+        assert_eq!(votes_to_pass(fixed, 5, 5, 1).await, Some(3));
+
+        // Fixed rules nobody can meet, or that anyone meets without a vote, are refused
+        let manager = ThresholdManager::default();
+        for required in [0, 4] {
+            let created = manager
+                .create_proposal_with_rule(
+                    "proposer".to_string(),
+                    "Proposer".to_string(),
+                    "Secure".to_string(),
+                    ProposalAction::SecureOnly,
+                    VotingRule::Fixed { required },
+                    3,
+                )
+                .await;
+            assert!(created.is_err(), "{required} of 3");
+        }
+        assert!(manager.get_active_proposals().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_weighted_rule_counts_roles() {
+        use crate::chat::roles::{Role, RoleGrant, RoleSubject};
+
+        let owner = CryptoManager::new("owner".to_string(), "Owner".to_string());
+        let moderator = CryptoManager::new("moderator".to_string(), "Moderator".to_string());
+        let member = CryptoManager::new("member".to_string(), "Member".to_string());
+        let subject = |crypto: &CryptoManager| {
+            RoleSubject::Peer(TargetPeer {
+                peer_id: crypto.get_identity().peer_id.clone(),
+                name: crypto.get_identity().name.clone(),
+                public_key: crypto.get_public_key(),
+            })
+        };
+        let roles = Arc::new(RoomRoles::new("team"));
+        roles.set_founder(&owner.get_public_key());
+        for (crypto, role) in [(&owner, Role::Owner), (&moderator, Role::Moderator)] {
+            let grant = RoleGrant::sign(&owner, "team", subject(crypto), role).unwrap();
+            roles.grant(&grant).unwrap();
+        }
+
+        // 3 + 2 + 1: a majority of the weight is 4
+        let rule = VotingRule::Weighted { total_weight: 6 };
+        let manager = ThresholdManager::with_roles(roles);
+        let mut proposals = Vec::new();
+        for _ in 0..2 {
+            let id = manager
+                .create_proposal_with_rule(
+                    "owner".to_string(),
+                    "Owner".to_string(),
+                    "Secure".to_string(),
+                    ProposalAction::SecureOnly,
+                    rule,
+                    3,
+                )
+                .await
+                .unwrap();
+            proposals.push(id);
+        }
+        let proposal = manager.get_proposal(&proposals[0]).await.unwrap();
+        assert_eq!(proposal.required(), 4);
+        assert_eq!(
+            proposal.threshold(),
+            "4/6 vote weight (role-weighted majority)"
+        );
+        // Peers weigh the electorate themselves instead of taking the proposer's word
+        let mut electorate = Electorate::default();
+        let weights = [
+            (&owner, Role::Owner),
+            (&moderator, Role::Moderator),
+            (&member, Role::Member),
+        ];
+        for (crypto, role) in weights {
+            let identity = crypto.get_identity();
+            electorate.insert(
+                identity.peer_id.clone(),
+                identity.public_key.clone(),
+                role.weight(),
+            );
+        }
+        let understated = UpgradeProposal {
+            rule: Some(VotingRule::Weighted { total_weight: 1 }),
+            ..proposal.clone()
+        };
+        assert_eq!(understated.required(), 1);
+        assert_eq!(understated.required_in(&electorate), 4);
+
+        // The moderator and the member outnumber the owner, but weigh less
+        for (crypto, id) in [(&moderator, "moderator"), (&member, "member")] {
+            let passed = manager
+                .cast_vote(&proposals[0], id.to_string(), id.to_string(), true, crypto)
+                .await
+                .unwrap();
+            assert!(!passed);
+        }
+        let cast = |crypto, id: &str| {
+            manager.cast_vote(&proposals[1], id.to_string(), id.to_string(), true, crypto)
+        };
+        assert!(!cast(&owner, "owner").await.unwrap());
+        assert!(cast(&member, "member").await.unwrap());
+    }
 }
@@ -12,6 +12,8 @@ pub enum ChatError {
     Unknown(String),
     #[error("Crypto error: {0}")]
     Crypto(#[from] crate::crypto::CryptoError),
+    #[error("Role error: {0}")]
+    Role(#[from] crate::chat::roles::RoleError),
 }
 
 impl From<std::io::Error> for ChatError {
@@ -2,8 +2,10 @@
 
 pub mod chat;
 pub mod cli;
+pub mod config;
 pub mod crypto;
 pub mod error;
 pub mod network;
 pub mod peer;
+pub mod scenario;
 pub mod signal;
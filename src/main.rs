@@ -5,8 +5,10 @@
 //! communication over a network.
 
 use std::sync::Arc;
-use p2p_chat::chat::Peer;
+use p2p_chat::chat::PeerBuilder;
 use p2p_chat::cli::*;
+use p2p_chat::config::Config;
+use p2p_chat::network::codec::Codec;
 use clap::Parser;
 
 #[tokio::main]
@@ -17,8 +19,51 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Only handle CLI commands
     match cli.command {
-        Commands::Start { port, name } => {
-            let chat = Peer::new(name, port);
+        Commands::Start {
+            port,
+            name,
+            gossip,
+            gossip_ttl,
+            peers,
+            rooms,
+            invites,
+            discovery,
+            data_dir,
+            json_wire,
+            config,
+        } => {
+            let config = Config::load_or_default(config.as_deref())?;
+            let mut builder = PeerBuilder::new(name).port(port);
+            // The command line wins over the config file
+            if !discovery.is_empty() {
+                builder = builder.discovery(discovery);
+            } else if !config.discovery.is_empty() {
+                builder = builder.discovery(config.discovery);
+            }
+            let rooms = if rooms.is_empty() {
+                config.rooms
+            } else {
+                rooms
+            };
+            for room in rooms {
+                builder = builder.room(room);
+            }
+            for token in invites {
+                builder = builder.invite(token);
+            }
+            for addr in config.bootstrap_peers.into_iter().chain(peers) {
+                builder = builder.bootstrap_peer(addr);
+            }
+            if let Some(dir) = data_dir.or(config.data_dir) {
+                builder = builder.data_dir(dir);
+            }
+            if gossip {
+                builder = builder.gossip(gossip_ttl);
+            }
+            if json_wire || config.json_wire {
+                builder = builder.codecs([Codec::Json]);
+            }
+            let chat = builder.build()?;
             let chat_arc = Arc::new(chat);
             let chat_signal = chat_arc.clone();
             tokio::spawn(async move {
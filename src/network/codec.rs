@@ -0,0 +1,122 @@
+//! Codec module: How frames are encoded on the wire.
+//!
+//! JSON is readable and what every version understands; CBOR is the compact default, where
+//! signatures and keys travel as byte strings instead of arrays of numbers. Which one is
+//! used with a peer is negotiated in the protocol hello. Receivers need not know which was
+//! used: a JSON frame is an object and starts with `{`, which never starts a CBOR map.
+
+use crate::error::ChatError;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Encodings of frames on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    /// Compact binary CBOR
+    Cbor,
+    /// Plain JSON, for debugging and older peers
+    Json,
+    /// A codec of a newer build
+    #[serde(other)]
+    Unknown,
+}
+
+impl Codec {
+    /// Encode `value` with this codec
+    pub fn encode<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, ChatError> {
+        match self {
+            Codec::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes)
+                    .map_err(|e| ChatError::Serialization(e.to_string()))?;
+                Ok(bytes)
+            }
+            Codec::Json => Ok(serde_json::to_vec(value)?),
+            Codec::Unknown => Err(ChatError::Serialization("unknown codec".to_string())),
+        }
+    }
+
+    /// The codec `bytes` were encoded with
+    pub fn detect(bytes: &[u8]) -> Codec {
+        match bytes.first() {
+            Some(b'{') => Codec::Json,
+            _ => Codec::Cbor,
+        }
+    }
+
+    /// Decode a frame encoded with either codec
+    pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ChatError> {
+        match Self::detect(bytes) {
+            Codec::Json => Ok(serde_json::from_slice(bytes)?),
+            _ => ciborium::from_reader(bytes).map_err(|e| ChatError::Serialization(e.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::Cbor => write!(f, "cbor"),
+            Codec::Json => write!(f, "json"),
+            Codec::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+impl std::str::FromStr for Codec {
+    type Err = ChatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cbor" => Ok(Codec::Cbor),
+            "json" => Ok(Codec::Json),
+            _ => Err(ChatError::Unknown(format!(
+                "Unknown codec {s}, expected cbor or json"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::room::Room;
+    use crate::crypto::CryptoManager;
+    use crate::network::tcp::encode_message;
+    use crate::peer::{NetworkMessage, RoomMessage};
+
+    #[tokio::test]
+    async fn test_frames_round_trip_with_both_codecs() {
+        let crypto = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let signed = crypto.sign_message("hello", 1).unwrap();
+        let msg = NetworkMessage::SignedChat(signed.clone());
+
+        let json = encode_message(Codec::Json, &Room::new("lobby"), &msg).unwrap();
+        let cbor = encode_message(Codec::Cbor, &Room::new("lobby"), &msg).unwrap();
+        assert_eq!(Codec::detect(&json), Codec::Json);
+        assert_eq!(Codec::detect(&cbor), Codec::Cbor);
+        // Signatures and keys are byte strings in CBOR, arrays of numbers in JSON
+        assert!(
+            cbor.len() * 2 < json.len(),
+            "{} vs {}",
+            cbor.len(),
+            json.len()
+        );
+
+        for bytes in [json, cbor] {
+            let frame: RoomMessage = Codec::decode(&bytes).unwrap();
+            assert_eq!(frame.room, "lobby");
+            match frame.message {
+                NetworkMessage::SignedChat(decoded) => {
+                    assert_eq!(decoded.signature, signed.signature);
+                    assert_eq!(decoded.public_key, signed.public_key);
+                    assert!(crypto.verify_message(&decoded).await.unwrap());
+                }
+                other => panic!("unexpected message {other:?}"),
+            }
+        }
+        assert!(Codec::decode::<RoomMessage>(b"\xff\x00").is_err());
+    }
+}
@@ -1,61 +1,305 @@
-//! Command module: Defines traits and functions for network commands.
+//! Command module: Dispatches incoming messages to the handler registered for their kind.
+//!
+//! Handlers get a `HandlerContext` with everything a peer shares between connections
+//! (rooms, event bus, identity, causal buffer, gossip, peer exchange, transport, rate
+//! limits, moderation lists and the registry itself) instead of one argument per subsystem.
+//! Messages from blocked peers, the chat of muted ones, and messages the sender's role in
+//! the room does not allow (see `chat::roles`) never reach a handler. Roles are looked up
+//! by the key pinned for the sender, once the message's signature shows it holds it. The kind
+//! of a message is its `NetworkMessage` variant name (`"SignedChat"`, `"UpgradeVote"`, ...)
+//! or, for `NetworkMessage::Custom`, the application's own kind. Every built-in kind is
+//! registered by default; applications embedding the library can register handlers for
+//! their own kinds, or replace built-in ones, on `Peer::handlers`.
 
+use crate::chat::display::ordering::CausalBuffer;
+use crate::chat::event::EventSender;
+use crate::chat::moderation::Moderation;
+use crate::chat::net::gossip::GossipRelay;
+use crate::chat::net::limits::RateLimiter;
+use crate::chat::net::pex::PexState;
+use crate::chat::roles::Action;
+use crate::chat::room::{Room, RoomRegistry};
+use crate::chat::spawn_until_cancelled;
+use crate::crypto::{CryptoError, CryptoManager, SignedMessage};
 use crate::error::ChatError;
 use crate::network::handlers;
-use crate::peer::{NetworkMessage, PeerInfo};
+use crate::network::protocol::ProtocolState;
+use crate::network::transport::Transport;
+use crate::peer::NetworkMessage;
 use async_trait::async_trait;
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::{broadcast, Mutex};
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
+/// Kinds of the built-in `NetworkMessage` variants
+pub const BUILTIN_KINDS: &[&str] = &[
+    "Discovery",
+    "Chat",
+    "Heartbeat",
+    "Exit",
+    "SignedChat",
+    "IdentityAnnouncement",
+    "UpgradeRequest",
+    "UpgradeVote",
+    "PartialSignature",
+    "HistorySummary",
+    "HistoryRequest",
+    "HistoryResponse",
+    "Gossip",
+    "PeerExchange",
+    "Rejected",
+    "Decision",
+    "RoomRekey",
+    "RoleGrant",
+];
+
+/// What message handlers can use, shared by every connection of a peer
+#[derive(Clone)]
+pub struct HandlerContext {
+    pub rooms: Arc<RoomRegistry>,
+    pub events: EventSender,
+    pub peer_id: String,
+    pub crypto_manager: Arc<CryptoManager>,
+    pub causal_buffer: Arc<CausalBuffer>,
+    pub gossip: Arc<GossipRelay>,
+    pub pex: Arc<PexState>,
+    pub transport: Arc<dyn Transport>,
+    pub protocols: Arc<ProtocolState>,
+    pub limits: Arc<RateLimiter>,
+    pub moderation: Arc<Moderation>,
+    pub handlers: Arc<HandlerRegistry>,
+    /// Cancelled when the peer shuts down
+    pub cancel: CancellationToken,
+    /// Background tasks the peer waits for when shutting down
+    pub tasks: TaskTracker,
+}
+
+impl HandlerContext {
+    /// Hand `message`, received in `room`, to the handler registered for its kind, unless
+    /// our moderation lists drop it or the sender's role does not allow it
+    pub async fn dispatch(&self, room: &Room, message: NetworkMessage) -> Result<(), ChatError> {
+        match self.admit(room, message).await {
+            Some(message) => self.deliver(room, message).await,
+            None => Ok(()),
+        }
+    }
+
+    /// `message` as our moderation lists let it through, if they do and the sender's role
+    /// in `room` allows it
+    pub(crate) async fn admit(
+        &self,
+        room: &Room,
+        message: NetworkMessage,
+    ) -> Option<NetworkMessage> {
+        let kind = message.kind().to_string();
+        let Some(message) = self.moderation.filter(message, &self.crypto_manager).await else {
+            println!("🔍 Dropping {kind} from a blocked or muted peer");
+            return None;
+        };
+        if let Some(action) = Action::of(&message) {
+            let key = match self.sender_key(&message).await {
+                Ok(key) => key,
+                Err(e) => {
+                    println!("🔍 Dropping {kind}: {e}");
+                    return None;
+                }
+            };
+            if let Err(e) = room.roles.require(key.as_deref(), action) {
+                println!("🔍 Dropping {kind}: {e}");
+                return None;
+            }
+        }
+        Some(message)
+    }
+
+    /// Hand an admitted `message` to the handler registered for its kind
+    async fn deliver(&self, room: &Room, message: NetworkMessage) -> Result<(), ChatError> {
+        match self.handlers.get(message.kind()) {
+            Some(handler) => handler.handle(self, room, message).await,
+            None => {
+                println!("🔍 No handler for {} messages, ignoring", message.kind());
+                Ok(())
+            }
+        }
+    }
+
+    /// Run `task` in the background until it ends or the peer shuts down
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        spawn_until_cancelled(&self.tasks, &self.cancel, task);
+    }
+
+    /// The key whoever sent `message` proved to hold, to look their role up by: the key it
+    /// carries once its signature checks out, which must be the key pinned for the sender.
+    /// A sender we have no key for yet is pinned to it. Unsigned messages prove nothing and
+    /// get the role of everyone.
+    async fn sender_key(&self, message: &NetworkMessage) -> Result<Option<Vec<u8>>, CryptoError> {
+        let Some((sender, key)) = signed_by(message)? else {
+            return Ok(None);
+        };
+        self.crypto_manager
+            .add_known_peer(sender.to_string(), key.to_vec())
+            .await?;
+        Ok(Some(key.to_vec()))
+    }
+}
+
+/// The peer id and key that signed `message`, if it is signed. Fails if the signature does
+/// not check out against the key.
+fn signed_by(message: &NetworkMessage) -> Result<Option<(&str, &[u8])>, CryptoError> {
+    let signed = match message {
+        NetworkMessage::SignedChat(signed) => {
+            signed.verify()?;
+            Some((&signed.signer_id, &signed.public_key))
+        }
+        NetworkMessage::Chat(message) => match (&message.signature, &message.public_key) {
+            (Some(signature), Some(public_key)) => {
+                SignedMessage {
+                    message: message.content.clone(),
+                    signature: signature.clone(),
+                    public_key: public_key.clone(),
+                    signer_id: message.from_id.clone(),
+                    signer_name: message.from_name.clone(),
+                    timestamp: message.timestamp,
+                    clock: message.clock.clone(),
+                }
+                .verify()?;
+                Some((&message.from_id, public_key))
+            }
+            _ => None,
+        },
+        NetworkMessage::UpgradeVote(vote) => match &vote.public_key {
+            Some(public_key) if vote.verify() => Some((&vote.voter_id, public_key)),
+            Some(_) => return Err(CryptoError::VerificationFailed),
+            None => None,
+        },
+        NetworkMessage::UpgradeRequest(proposal) => match &proposal.proposer_key {
+            Some(public_key) if proposal.verify() => Some((&proposal.proposer_id, public_key)),
+            Some(_) => return Err(CryptoError::VerificationFailed),
+            None => None,
+        },
+        _ => None,
+    };
+    Ok(signed.map(|(sender, key)| (sender.as_str(), key.as_slice())))
+}
+
+/// Handles the messages of one kind
 #[async_trait]
-pub trait NetworkCommand: Send {
-    async fn execute(
-        self: Box<Self>,
-        peers: Arc<Mutex<HashMap<String, PeerInfo>>>,
-        message_sender: broadcast::Sender<String>,
-        peer_id: String,
-        threshold_manager: Arc<crate::crypto::threshold::ThresholdManager>,
-        crypto_manager: Arc<crate::crypto::CryptoManager>,
+pub trait MessageHandler: Send + Sync {
+    async fn handle(
+        &self,
+        ctx: &HandlerContext,
+        room: &Room,
+        message: NetworkMessage,
     ) -> Result<(), ChatError>;
 }
 
+/// Async closures taking the context, room and message are handlers too
 #[async_trait]
-impl NetworkCommand for NetworkMessage {
-    async fn execute(
-        self: Box<Self>,
-        peers: Arc<Mutex<HashMap<String, PeerInfo>>>,
-        message_sender: broadcast::Sender<String>,
-        peer_id: String,
-        threshold_manager: Arc<crate::crypto::threshold::ThresholdManager>,
-        crypto_manager: Arc<crate::crypto::CryptoManager>,
+impl<F, Fut> MessageHandler for F
+where
+    F: Fn(HandlerContext, Room, NetworkMessage) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<(), ChatError>> + Send,
+{
+    async fn handle(
+        &self,
+        ctx: &HandlerContext,
+        room: &Room,
+        message: NetworkMessage,
     ) -> Result<(), ChatError> {
-        match *self {
+        self(ctx.clone(), room.clone(), message).await
+    }
+}
+
+/// Handlers by message kind
+pub struct HandlerRegistry {
+    handlers: RwLock<HashMap<String, Arc<dyn MessageHandler>>>,
+}
+
+impl Default for HandlerRegistry {
+    /// A registry with the built-in handlers
+    fn default() -> Self {
+        let registry = Self::empty();
+        let builtin: Arc<dyn MessageHandler> = Arc::new(BuiltinHandler);
+        for kind in BUILTIN_KINDS {
+            registry.insert(*kind, builtin.clone());
+        }
+        registry
+    }
+}
+
+impl HandlerRegistry {
+    /// A registry without any handler, not even the built-in ones
+    pub fn empty() -> Self {
+        Self {
+            handlers: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Handle messages of `kind` with `handler`, replacing the previous handler
+    pub fn register(&self, kind: impl Into<String>, handler: impl MessageHandler + 'static) {
+        self.insert(kind, Arc::new(handler));
+    }
+
+    /// Stop handling messages of `kind`; they are ignored from then on
+    pub fn unregister(&self, kind: &str) {
+        self.write().remove(kind);
+    }
+
+    /// The handler of `kind`, if any
+    pub fn get(&self, kind: &str) -> Option<Arc<dyn MessageHandler>> {
+        let handlers = self.handlers.read().unwrap_or_else(|e| e.into_inner());
+        handlers.get(kind).cloned()
+    }
+
+    fn insert(&self, kind: impl Into<String>, handler: Arc<dyn MessageHandler>) {
+        self.write().insert(kind.into(), handler);
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<String, Arc<dyn MessageHandler>>> {
+        self.handlers.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Handles every built-in message kind with the functions in `network::handlers`
+struct BuiltinHandler;
+
+#[async_trait]
+impl MessageHandler for BuiltinHandler {
+    async fn handle(
+        &self,
+        ctx: &HandlerContext,
+        room: &Room,
+        message: NetworkMessage,
+    ) -> Result<(), ChatError> {
+        match message {
             NetworkMessage::Chat(message) => {
-                handlers::chat::handle_chat_message(message, &message_sender, &crypto_manager)
-                    .await;
-                Ok(())
+                handlers::chat::handle_chat_message(
+                    message,
+                    room,
+                    &ctx.causal_buffer,
+                    &ctx.crypto_manager,
+                )
+                .await;
             }
             NetworkMessage::Exit(peer_id) => {
-                handlers::peer::handle_exit(&peers, peer_id).await;
-                Ok(())
+                handlers::peer::handle_exit(room, peer_id, &ctx.events).await;
             }
-            NetworkMessage::Discovery(peer_info) => {
-                handlers::peer::handle_discovery(&peers, peer_info, peer_id.clone()).await;
-                Ok(())
+            NetworkMessage::Discovery(announcement) => {
+                handlers::peer::handle_discovery(room, announcement, ctx).await;
             }
             NetworkMessage::Heartbeat(_) => {
                 handlers::peer::handle_heartbeat().await;
-                Ok(())
             }
             NetworkMessage::SignedChat(signed_message) => {
                 handlers::chat::handle_signed_chat(
                     signed_message,
-                    &message_sender,
-                    &crypto_manager,
+                    room,
+                    &ctx.causal_buffer,
+                    &ctx.crypto_manager,
                 )
                 .await;
-                Ok(())
             }
             NetworkMessage::IdentityAnnouncement {
                 peer_id,
@@ -66,38 +310,206 @@ impl NetworkCommand for NetworkMessage {
                     peer_id,
                     name,
                     public_key,
-                    &crypto_manager,
+                    &ctx.crypto_manager,
                 )
                 .await;
-                Ok(())
             }
             NetworkMessage::UpgradeRequest(proposal) => {
-                handlers::upgrade::handle_upgrade_request(
-                    proposal,
-                    threshold_manager.clone(),
-                    &message_sender,
+                handlers::upgrade::handle_upgrade_request(proposal, room, ctx).await;
+            }
+            NetworkMessage::UpgradeVote(vote) => {
+                handlers::upgrade::handle_upgrade_vote(vote, room, ctx).await;
+            }
+            NetworkMessage::PartialSignature(partial_sig) => {
+                handlers::upgrade::handle_partial_signature(partial_sig, room, &ctx.events).await;
+            }
+            NetworkMessage::HistorySummary {
+                peer_id: from_id,
+                marks,
+            } => {
+                handlers::history::handle_history_summary(room, ctx, from_id, marks).await;
+            }
+            NetworkMessage::HistoryRequest {
+                peer_id: from_id,
+                since,
+            } => {
+                handlers::history::handle_history_request(
+                    room,
+                    &*ctx.transport,
+                    &ctx.protocols,
+                    ctx.peer_id.clone(),
+                    from_id,
+                    since,
                 )
                 .await;
-                Ok(())
             }
-            NetworkMessage::UpgradeVote(vote) => {
-                handlers::upgrade::handle_upgrade_vote(
-                    vote,
-                    threshold_manager.clone(),
-                    &message_sender,
+            NetworkMessage::Gossip(envelope) => {
+                let payload = handlers::gossip::handle_gossip(envelope, room, ctx).await;
+                if let Some(payload) = payload {
+                    // Relayed payloads were admitted like direct ones before being relayed
+                    Box::pin(ctx.deliver(room, payload)).await?;
+                }
+            }
+            NetworkMessage::PeerExchange {
+                peer_id: from_id,
+                peers: entries,
+            } => {
+                handlers::peer::handle_peer_exchange(
+                    room,
+                    &ctx.pex,
+                    ctx.peer_id.clone(),
+                    from_id,
+                    entries,
                 )
                 .await;
-                Ok(())
             }
-            NetworkMessage::PartialSignature(partial_sig) => {
-                handlers::upgrade::handle_partial_signature(partial_sig, &message_sender).await;
-                Ok(())
+            NetworkMessage::HistoryResponse {
+                peer_id: from_id,
+                messages,
+                next,
+            } => {
+                handlers::history::handle_history_response(from_id, messages, next, room, ctx)
+                    .await;
+            }
+            NetworkMessage::Rejected { reason } => {
+                // Only meaningful as a handshake reply
+                eprintln!(
+                    "⚠️  Warning: Unexpected rejection in #{}: {reason}",
+                    room.id
+                );
+            }
+            NetworkMessage::Decision(decision) => {
+                handlers::upgrade::handle_decision(decision, room, ctx).await;
+            }
+            NetworkMessage::RoomRekey {
+                peer_id: from_id,
+                expulsion,
+                sealed_secret,
+            } => {
+                handlers::upgrade::handle_room_rekey(from_id, expulsion, sealed_secret, room, ctx)
+                    .await;
+            }
+            NetworkMessage::RoleGrant(grant) => {
+                handlers::roles::handle_role_grant(grant, room, ctx).await;
+            }
+            NetworkMessage::Custom { kind, .. } => {
+                println!("🔍 No handler for {kind} messages, ignoring");
             }
         }
+        Ok(())
     }
 }
 
-/// Factory to convert NetworkMessage to NetworkCommand
-pub fn to_command(msg: NetworkMessage) -> Box<dyn NetworkCommand + Send> {
-    Box::new(msg)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::Peer;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_custom_handler_receives_its_kind() {
+        let peer = Peer::new("Bot".to_string(), 9000);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        peer.handlers
+            .register("Ping", move |ctx: HandlerContext, room: Room, message| {
+                let tx = tx.clone();
+                async move {
+                    if let NetworkMessage::Custom {
+                        peer_id, payload, ..
+                    } = message
+                    {
+                        let _ = tx.send((ctx.peer_id, room.id, peer_id, payload));
+                    }
+                    Ok(())
+                }
+            });
+
+        let ctx = peer.handler_context();
+        let room = peer.room();
+        let ping = NetworkMessage::Custom {
+            peer_id: "remote".to_string(),
+            kind: "Ping".to_string(),
+            payload: serde_json::json!({ "seq": 1 }),
+        };
+        ctx.dispatch(&room, ping).await.unwrap();
+        let (own_id, room_id, from, payload) = rx.try_recv().unwrap();
+        assert_eq!(own_id, peer.peer_id);
+        assert_eq!(room_id, room.id);
+        assert_eq!(from, "remote");
+        assert_eq!(payload["seq"], 1);
+
+        // Relayed copies reach the same handler
+        let relayed = NetworkMessage::Gossip(crate::peer::GossipEnvelope {
+            message_id: "m1".to_string(),
+            origin_id: "remote".to_string(),
+            from_id: "remote".to_string(),
+            ttl: 0,
+            payload: Box::new(NetworkMessage::Custom {
+                peer_id: "remote".to_string(),
+                kind: "Ping".to_string(),
+                payload: serde_json::json!({ "seq": 2 }),
+            }),
+        });
+        ctx.dispatch(&room, relayed).await.unwrap();
+        assert_eq!(rx.try_recv().unwrap().3["seq"], 2);
+
+        // Unknown kinds are ignored
+        let unknown = NetworkMessage::Custom {
+            peer_id: "remote".to_string(),
+            kind: "Unknown".to_string(),
+            payload: serde_json::Value::Null,
+        };
+        ctx.dispatch(&room, unknown).await.unwrap();
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_gossip_is_only_relayed_once_admitted() {
+        let peer = Peer::new("Relay".to_string(), 9000);
+        peer.gossip.enable(4);
+        let ctx = peer.handler_context();
+        let room = peer.room();
+        let alice = CryptoManager::new("alice".to_string(), "Alice".to_string());
+        let envelope = |id: &str, payload| {
+            NetworkMessage::Gossip(crate::peer::GossipEnvelope {
+                message_id: id.to_string(),
+                origin_id: "alice".to_string(),
+                from_id: "alice".to_string(),
+                ttl: 3,
+                payload: Box::new(payload),
+            })
+        };
+
+        let mut forged = alice.sign_message("hi", 1).unwrap();
+        forged.message = "bye".to_string();
+        let forged = envelope("m1", NetworkMessage::SignedChat(forged));
+        ctx.dispatch(&room, forged).await.unwrap();
+        assert_eq!(peer.gossip.relayed_count(), 0);
+
+        let genuine = NetworkMessage::SignedChat(alice.sign_message("hi", 1).unwrap());
+        ctx.dispatch(&room, envelope("m2", genuine)).await.unwrap();
+        assert_eq!(peer.gossip.relayed_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_builtin_handlers_can_be_replaced() {
+        let peer = Peer::new("Bot".to_string(), 9000);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        peer.handlers
+            .register("Exit", move |_: HandlerContext, _: Room, message| {
+                let tx = tx.clone();
+                async move {
+                    let _ = tx.send(message);
+                    Ok(())
+                }
+            });
+        let ctx = peer.handler_context();
+        let exit = NetworkMessage::Exit("remote".to_string());
+        ctx.dispatch(&peer.room(), exit).await.unwrap();
+        assert!(matches!(rx.try_recv().unwrap(), NetworkMessage::Exit(id) if id == "remote"));
+
+        peer.handlers.unregister("Exit");
+        assert!(peer.handlers.get("Exit").is_none());
+        assert!(peer.handlers.get("Chat").is_some());
+    }
 }
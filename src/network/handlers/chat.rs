@@ -3,14 +3,17 @@
 //! This module is responsible for managing chat messages, including
 //! verifying signatures and broadcasting messages to peers.
 
+use crate::chat::display::ordering::CausalBuffer;
+use crate::chat::event::{ChatEvent, Verification};
+use crate::chat::room::Room;
 use crate::crypto::SignedMessage;
 use crate::peer::Message;
 use std::sync::Arc;
-use tokio::sync::broadcast;
 
 pub async fn handle_chat_message(
     message: Message,
-    message_sender: &broadcast::Sender<String>,
+    room: &Room,
+    causal_buffer: &Arc<CausalBuffer>,
     crypto_manager: &Arc<crate::crypto::CryptoManager>,
 ) {
     // Check if message has cryptographic signature
@@ -29,56 +32,86 @@ pub async fn handle_chat_message(
             signer_id: message.from_id.clone(),
             signer_name: message.from_name.clone(),
             timestamp: message.timestamp,
+            clock: message.clock.clone(),
         };
 
-        _verify_and_display(signed_msg, message_sender, crypto_manager).await;
+        _verify_and_display(signed_msg, room, causal_buffer, crypto_manager).await;
     } else {
-        // No crypto manager, display as unsigned message
-        let display_msg = format!(
-            "📝 {} says (unsigned): {}",
-            message.from_name, message.content
-        );
-        let _ = message_sender.send(display_msg);
+        let event = ChatEvent::MessageReceived {
+            room: room.id.clone(),
+            from_id: message.from_id,
+            from_name: message.from_name,
+            content: message.content,
+            verification: Verification::Unsigned,
+            from_history: false,
+        };
+        // Anyone can write any clock into an unsigned message, so it must not move ours
+        causal_buffer.push(None, event).await;
     }
 }
 
 pub async fn handle_signed_chat(
     signed_message: SignedMessage,
-    message_sender: &broadcast::Sender<String>,
+    room: &Room,
+    causal_buffer: &Arc<CausalBuffer>,
     crypto_manager: &Arc<crate::crypto::CryptoManager>,
 ) {
     {
-        _verify_and_display(&signed_message, message_sender, crypto_manager).await;
+        _verify_and_display(&signed_message, room, causal_buffer, crypto_manager).await;
     }
 }
 
-fn _format_verified(name: &str, content: &str) -> String {
-    format!("🔐 {name} says (verified): {content}")
+/// Event for a signed message received live
+pub fn message_event(
+    room: &Room,
+    signed_message: &SignedMessage,
+    verification: Verification,
+) -> ChatEvent {
+    ChatEvent::MessageReceived {
+        room: room.id.clone(),
+        from_id: signed_message.signer_id.clone(),
+        from_name: signed_message.signer_name.clone(),
+        content: signed_message.message.clone(),
+        verification,
+        from_history: false,
+    }
 }
 
 async fn _verify_and_display(
     signed_message: &SignedMessage,
-    message_sender: &broadcast::Sender<String>,
+    room: &Room,
+    causal_buffer: &Arc<CausalBuffer>,
     crypto_manager: &Arc<crate::crypto::CryptoManager>,
 ) {
     match crypto_manager.verify_message(signed_message).await {
         Ok(true) => {
-            let _ = message_sender.send(_format_verified(
-                &signed_message.signer_name,
-                &signed_message.message,
-            ));
+            // Already seen (e.g. imported through history sync)
+            if !room.history.insert(signed_message.clone()).await {
+                return;
+            }
+            causal_buffer
+                .push(
+                    signed_message.clock.clone(),
+                    message_event(room, signed_message, Verification::Verified),
+                )
+                .await;
         }
+        // Unverified clocks must not move ours, so these are shown without reordering
         Ok(false) => {
-            let _ = message_sender.send(format!(
-                "⚠️  {} says (INVALID SIGNATURE): {}",
-                signed_message.signer_name, signed_message.message
-            ));
+            causal_buffer
+                .push(
+                    None,
+                    message_event(room, signed_message, Verification::Invalid),
+                )
+                .await;
         }
         Err(e) => {
-            let _ = message_sender.send(format!(
-                "❓ {} says (verification failed: {}): {}",
-                signed_message.signer_name, e, signed_message.message
-            ));
+            causal_buffer
+                .push(
+                    None,
+                    message_event(room, signed_message, Verification::Failed(e.to_string())),
+                )
+                .await;
         }
     }
 }
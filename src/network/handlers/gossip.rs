@@ -0,0 +1,68 @@
+//! Gossip handler: deduplicate relayed envelopes and re-forward them to our neighbours.
+
+use crate::chat::room::Room;
+use crate::network::command::HandlerContext;
+use crate::network::tcp::send_message;
+use crate::peer::{GossipEnvelope, NetworkMessage, PeerInfo};
+
+/// Process a gossip envelope. Returns the payload to execute locally, or `None` if the
+/// envelope was a duplicate or malformed, or we would not accept its payload ourselves (see
+/// `HandlerContext::admit`). Only payloads we accept are relayed, and only within their room.
+pub async fn handle_gossip(
+    envelope: GossipEnvelope,
+    room: &Room,
+    ctx: &HandlerContext,
+) -> Option<NetworkMessage> {
+    let peer_id = &ctx.peer_id;
+    if envelope.origin_id == *peer_id || !ctx.gossip.mark_seen(&envelope.message_id) {
+        return None;
+    }
+    // Envelopes never nest; a nested one could be used to bypass the TTL
+    if matches!(*envelope.payload, NetworkMessage::Gossip(_)) {
+        eprintln!(
+            "⚠️  Dropping nested gossip envelope from {}",
+            envelope.from_id
+        );
+        return None;
+    }
+
+    let payload = ctx.admit(room, (*envelope.payload).clone()).await?;
+
+    if let Some(next) = ctx.gossip.next_hop(&envelope, peer_id) {
+        let neighbours: Vec<PeerInfo> = room
+            .peers
+            .lock()
+            .await
+            .values()
+            .filter(|p| p.is_valid() && p.id != envelope.origin_id && p.id != envelope.from_id)
+            .cloned()
+            .collect();
+        // Nothing is relayed to peers we blocked
+        let mut targets = Vec::with_capacity(neighbours.len());
+        for target in neighbours {
+            if !ctx
+                .moderation
+                .blocks_peer(&target.id, &ctx.crypto_manager)
+                .await
+            {
+                targets.push(target);
+            }
+        }
+        let msg = NetworkMessage::Gossip(next);
+        let room = room.clone();
+        let transport = ctx.transport.clone();
+        let protocols = ctx.protocols.clone();
+        ctx.spawn(async move {
+            for target in targets {
+                let codec = protocols.codec_for(&target.id);
+                let addr = target.socket_addr();
+                let sent = send_message(&*transport, codec, addr, &room, &msg);
+                if let Err(e) = sent.await {
+                    eprintln!("Failed to relay gossip to {}: {e}", target.name);
+                }
+            }
+        });
+    }
+
+    Some(payload)
+}
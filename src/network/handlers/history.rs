@@ -0,0 +1,205 @@
+//! History sync handlers: exchange per-author summaries and import missing signed messages.
+
+use crate::chat::event::{ChatEvent, Verification};
+use crate::chat::history::{HistoryCursor, HistoryMark};
+use crate::chat::room::Room;
+use crate::crypto::SignedMessage;
+use crate::network::command::HandlerContext;
+use crate::network::handlers::peer::joined_peer;
+use crate::network::protocol::ProtocolState;
+use crate::network::tcp::{send_message, HANDSHAKE_TIMEOUT};
+use crate::network::transport::Transport;
+use crate::peer::{NetworkMessage, PeerInfo};
+use std::collections::HashMap;
+
+/// Send our history of `room` to a peer newly discovered in it
+pub async fn send_history_summary(
+    transport: &dyn Transport,
+    protocols: &ProtocolState,
+    room: &Room,
+    peer_id: String,
+    target: &PeerInfo,
+) {
+    let msg = NetworkMessage::HistorySummary {
+        peer_id,
+        marks: room.history.summary().await,
+    };
+    let codec = protocols.codec_for(&target.id);
+    let addr = target.socket_addr();
+    if let Err(e) = send_message(transport, codec, addr, room, &msg).await {
+        eprintln!("Failed to send history summary to {}: {e}", target.name);
+    }
+}
+
+pub async fn handle_history_summary(
+    room: &Room,
+    ctx: &HandlerContext,
+    from_id: String,
+    marks: HashMap<String, HistoryMark>,
+) {
+    let mut since = room.history.missing_from(&marks).await;
+    skip_moderated(&mut since, ctx).await;
+    if since.is_empty() {
+        return;
+    }
+    // Sent right after the handshake, a summary can arrive before its sender joined
+    let Some(target) = joined_peer(room, &ctx.events, &from_id, HANDSHAKE_TIMEOUT).await else {
+        eprintln!("History summary from unknown peer {from_id}, ignoring");
+        return;
+    };
+    println!(
+        "📜 Requesting history for {} author(s) from {}",
+        since.len(),
+        target.name
+    );
+    room.history.start_sync(&from_id).await;
+    let peer_id = ctx.peer_id.clone();
+    request_history(
+        &*ctx.transport,
+        &ctx.protocols,
+        room,
+        peer_id,
+        &target,
+        since,
+    )
+    .await;
+}
+
+/// Leave out the authors we blocked or muted: their messages are dropped on import, so their
+/// logs never match and asking for them again would never end
+async fn skip_moderated(since: &mut HashMap<String, HistoryCursor>, ctx: &HandlerContext) {
+    let mut moderated = Vec::new();
+    for author in since.keys() {
+        let key = ctx.crypto_manager.public_key_of(author).await;
+        if key.is_some_and(|key| ctx.moderation.is_blocked(&key) || ctx.moderation.is_muted(&key)) {
+            moderated.push(author.clone());
+        }
+    }
+    for author in moderated {
+        since.remove(&author);
+    }
+}
+
+/// Ask `target` for the messages after `since`
+async fn request_history(
+    transport: &dyn Transport,
+    protocols: &ProtocolState,
+    room: &Room,
+    peer_id: String,
+    target: &PeerInfo,
+    since: HashMap<String, HistoryCursor>,
+) {
+    let msg = NetworkMessage::HistoryRequest { peer_id, since };
+    let codec = protocols.codec_for(&target.id);
+    let addr = target.socket_addr();
+    if let Err(e) = send_message(transport, codec, addr, room, &msg).await {
+        eprintln!("Failed to request history from {}: {e}", target.name);
+    }
+}
+
+pub async fn handle_history_request(
+    room: &Room,
+    transport: &dyn Transport,
+    protocols: &ProtocolState,
+    peer_id: String,
+    from_id: String,
+    since: HashMap<String, HistoryCursor>,
+) {
+    let Some(target) = room.peers.lock().await.get(&from_id).cloned() else {
+        eprintln!("History request from unknown peer {from_id}, ignoring");
+        return;
+    };
+    let batch = room.history.messages_since(&since).await;
+    if batch.messages.is_empty() {
+        return;
+    }
+    println!(
+        "📜 Sending {} history message(s) to {}",
+        batch.messages.len(),
+        target.name
+    );
+    let msg = NetworkMessage::HistoryResponse {
+        peer_id,
+        messages: batch.messages,
+        next: batch.next,
+    };
+    let codec = protocols.codec_for(&target.id);
+    let addr = target.socket_addr();
+    if let Err(e) = send_message(transport, codec, addr, room, &msg).await {
+        eprintln!("Failed to send history to {}: {e}", target.name);
+    }
+}
+
+pub async fn handle_history_response(
+    from_id: String,
+    messages: Vec<SignedMessage>,
+    next: Option<HashMap<String, HistoryCursor>>,
+    room: &Room,
+    ctx: &HandlerContext,
+) {
+    let HandlerContext {
+        events,
+        causal_buffer,
+        crypto_manager,
+        ..
+    } = ctx;
+    let mut imported = 0;
+    for message in messages {
+        if room.history.contains(&message).await {
+            continue;
+        }
+        // Never trust the relaying peer: check every signature again on import, against the
+        // keys we pinned ourselves
+        match crypto_manager.verify_relayed(&message).await {
+            Ok(()) => {
+                let event = ChatEvent::MessageReceived {
+                    room: room.id.clone(),
+                    from_id: message.signer_id.clone(),
+                    from_name: message.signer_name.clone(),
+                    content: message.message.clone(),
+                    verification: Verification::Verified,
+                    from_history: true,
+                };
+                let clock = message.clock.clone();
+                if room.history.insert(message).await {
+                    imported += 1;
+                    causal_buffer.push(clock, event).await;
+                }
+            }
+            Err(e) => {
+                eprintln!(
+                    "⚠️  Dropping history message from {}: {e}",
+                    message.signer_name
+                );
+            }
+        }
+    }
+    if imported > 0 {
+        let _ = events.send(ChatEvent::HistorySynced {
+            room: room.id.clone(),
+            count: imported,
+        });
+    }
+
+    // The answer did not fit in one batch: keep asking while it brings us new messages,
+    // for a bounded number of rounds
+    let Some(mut next) = next.filter(|_| imported > 0) else {
+        return;
+    };
+    skip_moderated(&mut next, ctx).await;
+    if next.is_empty() || !room.history.follow_up(&from_id).await {
+        return;
+    }
+    let Some(target) = room.peers.lock().await.get(&from_id).cloned() else {
+        return;
+    };
+    request_history(
+        &*ctx.transport,
+        &ctx.protocols,
+        room,
+        ctx.peer_id.clone(),
+        &target,
+        next,
+    )
+    .await;
+}
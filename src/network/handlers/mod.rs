@@ -1,5 +1,6 @@
-
 pub mod chat;
+pub mod gossip;
+pub mod history;
 pub mod peer;
+pub mod roles;
 pub mod upgrade;
-pub mod common;
\ No newline at end of file
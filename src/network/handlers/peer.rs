@@ -1,40 +1,145 @@
 //! Peer helper functions to handle peer functionality such as discovery, identity management, and connection handling.
 
-use crate::peer::PeerInfo;
-use chrono::Utc;
-use colored::*;
-use std::collections::HashMap;
+use crate::chat::event::{ChatEvent, EventSender, LeaveReason};
+use crate::chat::net::pex::{filter_entries, PexState};
+use crate::chat::room::Room;
+use crate::crypto::SignedPeerInfo;
+use crate::network::command::HandlerContext;
+use crate::network::handlers::history::send_history_summary;
+use crate::network::handlers::upgrade::{retry_decisions, send_decisions};
+use crate::network::tcp::send_message;
+use crate::peer::{NetworkMessage, PeerInfo};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
 
 pub async fn handle_heartbeat() {
     // TODO implement
     // Handle heartbeat messages
 }
 
-pub async fn handle_discovery(
-    peers: &Arc<Mutex<HashMap<String, PeerInfo>>>,
-    peer_info: PeerInfo,
-    peer_id: String,
-) {
+pub async fn handle_discovery(room: &Room, announcement: SignedPeerInfo, ctx: &HandlerContext) {
+    let HandlerContext {
+        peer_id,
+        crypto_manager,
+        events,
+        ..
+    } = ctx;
     {
-        if peer_info.id == peer_id {
+        if announcement.info.id == *peer_id {
             // Ignore our own Discovery messages
             return;
         }
+        // Only the owner of the peer's key can announce or move it
+        if let Err(e) = crypto_manager.verify_peer_info(&announcement).await {
+            eprintln!(
+                "⚠️  Warning: Rejected discovery for {} ({}): {e}",
+                announcement.info.name, announcement.info.id
+            );
+            return;
+        }
+        let peer_info = announcement.info;
         // Validate discovered peer before adding
         if !peer_info.is_valid() {
             eprintln!("Invalid peer info received via TCP: {peer_info:?}");
             return;
         }
-        let mut peers = peers.lock().await;
+        let mut peers = room.peers.lock().await;
         if !peers.contains_key(&peer_info.id) {
-            println!(
-                "🔗 Discovered peer via TCP: {} at {}",
-                peer_info.name, peer_info.ip
-            );
+            let _ = events.send(ChatEvent::PeerJoined {
+                room: room.id.clone(),
+                peer: peer_info.clone(),
+            });
+            // Announce our key and offer our history and expulsions so the new peer can
+            // catch up
+            let room = room.clone();
+            let identity = crypto_manager.get_identity().clone();
+            let target = peer_info.clone();
+            let transport = ctx.transport.clone();
+            let protocols = ctx.protocols.clone();
+            let peer_id = peer_id.clone();
+            ctx.spawn(async move {
+                let announcement = NetworkMessage::IdentityAnnouncement {
+                    peer_id: identity.peer_id,
+                    name: identity.name,
+                    public_key: identity.public_key,
+                };
+                let codec = protocols.codec_for(&target.id);
+                let addr = target.socket_addr();
+                let sent = send_message(&*transport, codec, addr, &room, &announcement);
+                if let Err(e) = sent.await {
+                    eprintln!("Failed to announce identity to {}: {e}", target.name);
+                }
+                send_history_summary(&*transport, &protocols, &room, peer_id, &target).await;
+                send_decisions(&*transport, &protocols, &room, &target).await;
+            });
+        }
+        let joined = peers.insert(peer_info.id.clone(), peer_info).is_none();
+        drop(peers);
+        // Decisions we could not check may have been taken by the peer that just joined
+        if joined {
+            retry_decisions(ctx, room).await;
+        }
+    }
+}
+
+/// `peer_id` as recorded in `room`, waiting up to `timeout` for it to join: a message can
+/// overtake the end of the handshake that adds its sender to the room
+pub async fn joined_peer(
+    room: &Room,
+    events: &EventSender,
+    peer_id: &str,
+    timeout: Duration,
+) -> Option<PeerInfo> {
+    // Subscribe first so a peer joining in between is not missed
+    let mut joined = events.subscribe();
+    if let Some(peer) = room.peers.lock().await.get(peer_id) {
+        return Some(peer.clone());
+    }
+    let wait = async {
+        loop {
+            match joined.recv().await {
+                Ok(ChatEvent::PeerJoined { room: id, peer })
+                    if id == room.id && peer.id == peer_id =>
+                {
+                    return Some(peer);
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(_)) => {
+                    if let Some(peer) = room.peers.lock().await.get(peer_id) {
+                        return Some(peer.clone());
+                    }
+                }
+                Err(RecvError::Closed) => return None,
+            }
         }
-        peers.insert(peer_info.id.clone(), peer_info);
+    };
+    tokio::time::timeout(timeout, wait).await.ok().flatten()
+}
+
+pub async fn handle_peer_exchange(
+    room: &Room,
+    pex: &Arc<PexState>,
+    peer_id: String,
+    from_id: String,
+    entries: Vec<PeerInfo>,
+) {
+    // Only accept peer lists from peers we are already connected to
+    let candidates = {
+        let peers = room.peers.lock().await;
+        if !peers.contains_key(&from_id) {
+            eprintln!("Peer exchange from unknown peer {from_id}, ignoring");
+            return;
+        }
+        filter_entries(entries, &peer_id, &peers)
+    };
+    if !candidates.is_empty() {
+        println!(
+            "🔄 Received {} new peer candidate(s) via PEX in #{}",
+            candidates.len(),
+            room.id
+        );
+        pex.queue(&room.id, candidates).await;
     }
 }
 
@@ -58,15 +163,13 @@ pub async fn handle_identity_announcement(
     }
 }
 
-pub async fn handle_exit(peers: &Arc<Mutex<HashMap<String, PeerInfo>>>, peer_id: String) {
-    let mut peers = peers.lock().await;
-    if peers.remove(&peer_id).is_some() {
-        let timestamp = Utc::now().format("%H:%M:%S");
-        println!(
-            "[{}] {} Peer {} exited and was removed from the list.",
-            timestamp.to_string().dimmed(),
-            "❌".bright_red(),
-            peer_id.bright_yellow()
-        );
+pub async fn handle_exit(room: &Room, peer_id: String, events: &EventSender) {
+    let removed = room.peers.lock().await.remove(&peer_id);
+    if let Some(peer) = removed {
+        let _ = events.send(ChatEvent::PeerLeft {
+            room: room.id.clone(),
+            peer,
+            reason: LeaveReason::Exited,
+        });
     }
 }
@@ -0,0 +1,44 @@
+//! Handler functions for role grants and role changes decided by vote.
+//!
+//! Grants are checked against the roles this peer already knows (see `chat::roles`), so a
+//! grant from a peer without the authority to give it changes nothing here.
+
+use crate::chat::event::ChatEvent;
+use crate::chat::roles::RoleGrant;
+use crate::chat::room::Room;
+use crate::crypto::threshold::{Decision, ProposalAction};
+use crate::network::command::HandlerContext;
+
+pub async fn handle_role_grant(grant: RoleGrant, room: &Room, ctx: &HandlerContext) {
+    match room.roles.grant(&grant) {
+        Ok(true) => {
+            let _ = ctx.events.send(ChatEvent::RoleChanged {
+                room: room.id.clone(),
+                subject: grant.subject,
+                role: grant.role,
+                by: Some(grant.granter_name),
+            });
+        }
+        Ok(false) => {}
+        Err(e) => eprintln!("⚠️  Warning: Ignoring role grant in #{}: {e}", room.id),
+    }
+}
+
+/// Carry out a role change approved by vote
+pub fn apply_role_decision(ctx: &HandlerContext, room: &Room, decision: &Decision) {
+    let ProposalAction::AssignRole { subject, role } = &decision.proposal.action else {
+        return;
+    };
+    match room.roles.decide(decision) {
+        Ok(true) => {
+            let _ = ctx.events.send(ChatEvent::RoleChanged {
+                room: room.id.clone(),
+                subject: subject.clone(),
+                role: *role,
+                by: None,
+            });
+        }
+        Ok(false) => {}
+        Err(e) => eprintln!("⚠️  Warning: Ignoring role decision in #{}: {e}", room.id),
+    }
+}
@@ -1,41 +1,105 @@
 //! Handler functions to manage upgrade proposals and voting.
+//!
+//! An approved expulsion is carried out by every peer on its own: the expelled peer is
+//! dropped from the room and its key blocked. Peers joining later are sent the signed
+//! decision and check it before doing the same. In a private room the proposer also
+//! replaces the room secret, sealing the new one for each remaining member. Role changes
+//! decided by vote travel the same way as expulsions (see `handlers::roles`).
 
+use crate::chat::event::{ChatEvent, EventSender};
+use crate::chat::roles::RoleSource;
+use crate::chat::room::Room;
+use crate::crypto::invite::RoomSecret;
+use crate::crypto::threshold::{
+    Decision, Electorate, PartialSignature, ProposalAction, UpgradeProposal, UpgradeVote,
+    VotingRule,
+};
+use crate::crypto::CryptoError;
+use crate::network::command::HandlerContext;
+use crate::network::protocol::ProtocolState;
+use crate::network::tcp::send_message;
+use crate::network::transport::Transport;
+use crate::peer::{NetworkMessage, PeerInfo};
 
-use crate::crypto::threshold::{PartialSignature, UpgradeProposal, UpgradeVote};
-use std::sync::Arc;
-use tokio::sync::broadcast;
-
-pub async fn handle_upgrade_request(
-    proposal: UpgradeProposal,
-    threshold_manager: Arc<crate::crypto::threshold::ThresholdManager>,
-    message_sender: &broadcast::Sender<String>,
-) {
+pub async fn handle_upgrade_request(proposal: UpgradeProposal, room: &Room, ctx: &HandlerContext) {
+    let electorate = room.electorate(&ctx.crypto_manager).await;
+    let reason = invalid_proposal(&proposal, &electorate).or_else(|| {
+        let allowed = room.roles.check_proposal(&proposal);
+        allowed.err().map(|e| e.to_string())
+    });
+    if let Some(reason) = reason {
+        eprintln!(
+            "⚠️  Warning: Ignoring proposal {} from {}: {reason}",
+            proposal.proposal_id, proposal.proposer_name
+        );
+        return;
+    }
     println!(
         "🔐 Received upgrade proposal from {}: {}",
         proposal.proposer_name, proposal.description
     );
     println!(
-        "📊 Proposal ID: {}, requires {}/{} approvals",
-        proposal.proposal_id, proposal.required_approvals, proposal.total_peers
+        "📊 Proposal ID: {}, requires {}",
+        proposal.proposal_id,
+        proposal.threshold()
     );
 
     // Store proposal locally if not present
-    threshold_manager
+    room.threshold_manager
         .insert_received_proposal(proposal.clone())
         .await;
 
-    let display_msg = format!(
-        "🔐 {} proposed secure messaging upgrade: {} (ID: {})",
-        proposal.proposer_name, proposal.description, proposal.proposal_id
-    );
-    let _ = message_sender.send(display_msg);
+    let _ = ctx.events.send(ChatEvent::ProposalCreated {
+        room: room.id.clone(),
+        proposal,
+    });
 }
 
-pub async fn handle_upgrade_vote(
-    vote: UpgradeVote,
-    threshold_manager: Arc<crate::crypto::threshold::ThresholdManager>,
-    message_sender: &broadcast::Sender<String>,
-) {
+/// Why a received proposal cannot be put to the vote in a room where we know `electorate`,
+/// if it cannot. An expulsion or role change must be signed by a member we know. The
+/// threshold it claims must follow from its rule and be reachable, and not fall below the
+/// majority an expulsion or role change takes.
+fn invalid_proposal(proposal: &UpgradeProposal, electorate: &Electorate) -> Option<String> {
+    if proposal.action != ProposalAction::SecureOnly {
+        if !proposal.verify() {
+            return Some("it is not signed by its proposer".to_string());
+        }
+        let proposer_key = proposal.proposer_key.as_deref();
+        if !electorate.knows(&proposal.proposer_id, proposer_key) {
+            return Some("its proposer is not a member we know".to_string());
+        }
+    }
+    let required = proposal.required_approvals;
+    let out_of = match proposal.rule {
+        Some(VotingRule::Weighted { total_weight }) => total_weight,
+        _ => proposal.total_peers,
+    };
+    if proposal.total_peers == 0 || required == 0 {
+        return Some("it needs no approvals".to_string());
+    }
+    if required > out_of {
+        return Some(format!("it needs {required} approvals out of {out_of}"));
+    }
+    match proposal.rule {
+        Some(rule) if rule.required(proposal.total_peers) != required => Some(format!(
+            "it needs {required} approvals where {rule} makes {}",
+            rule.required(proposal.total_peers)
+        )),
+        Some(VotingRule::Fixed { required }) if required > electorate.len() => Some(format!(
+            "it needs {required} approvals but we know {} voters",
+            electorate.len()
+        )),
+        Some(VotingRule::Fixed { required }) if required < proposal.required_in(electorate) => {
+            Some(format!(
+                "it needs {required} approvals but a majority of the voters we know is {}",
+                proposal.required_in(electorate)
+            ))
+        }
+        _ => None,
+    }
+}
+
+pub async fn handle_upgrade_vote(vote: UpgradeVote, room: &Room, ctx: &HandlerContext) {
     println!(
         "🗳️  Received vote from {} on proposal {}: {}",
         vote.voter_name,
@@ -47,25 +111,35 @@ pub async fn handle_upgrade_vote(
         }
     );
 
-    // TODO: Process vote locally
-    let _ = threshold_manager.handle_received_vote(&vote).await;
+    let was_secure = room.threshold_manager.is_secure_only_enabled().await;
+    let electorate = room.electorate(&ctx.crypto_manager).await;
+    let approved = room
+        .threshold_manager
+        .handle_received_vote(&vote, Some(&electorate))
+        .await;
+    let proposal_id = vote.proposal_id.clone();
 
-    let display_msg = format!(
-        "🗳️  {} voted {} on upgrade proposal {}",
-        vote.voter_name,
-        if vote.approved {
-            "✅ APPROVED"
-        } else {
-            "❌ REJECTED"
-        },
-        vote.proposal_id
-    );
-    let _ = message_sender.send(display_msg);
+    let _ = ctx.events.send(ChatEvent::VoteCast {
+        room: room.id.clone(),
+        vote,
+    });
+    if !was_secure && room.threshold_manager.is_secure_only_enabled().await {
+        let _ = ctx.events.send(ChatEvent::ModeChanged {
+            room: room.id.clone(),
+            secure_only: true,
+        });
+    }
+    if approved {
+        if let Some(decision) = room.threshold_manager.get_decision(&proposal_id).await {
+            apply_decision(ctx, room, &decision).await;
+        }
+    }
 }
 
 pub async fn handle_partial_signature(
     partial_sig: PartialSignature,
-    message_sender: &broadcast::Sender<String>,
+    room: &Room,
+    events: &EventSender,
 ) {
     println!(
         "🔐 Received partial signature from {} on proposal {}",
@@ -73,9 +147,226 @@ pub async fn handle_partial_signature(
     );
 
     // TODO: Process partial signature for threshold verification
-    let display_msg = format!(
-        "🔐 {} provided partial signature for proposal {}",
-        partial_sig.signer_name, partial_sig.proposal_id
+    let _ = events.send(ChatEvent::PartialSignatureReceived {
+        room: room.id.clone(),
+        partial: partial_sig,
+    });
+}
+
+/// Carry out an approved expulsion or role change
+pub async fn apply_decision(ctx: &HandlerContext, room: &Room, decision: &Decision) {
+    match &decision.proposal.action {
+        ProposalAction::Expel(_) => apply_expulsion(ctx, room, decision).await,
+        ProposalAction::AssignRole { .. } => {
+            super::roles::apply_role_decision(ctx, room, decision);
+        }
+        ProposalAction::SecureOnly => {}
+    }
+}
+
+/// Carry out an approved expulsion: drop the peer from the room and block its key. The
+/// proposer of an expulsion from a private room also hands the other members a new secret.
+pub async fn apply_expulsion(ctx: &HandlerContext, room: &Room, expulsion: &Decision) {
+    let Some(target) = expulsion.expelled() else {
+        return;
+    };
+    room.peers.lock().await.remove(&target.peer_id);
+    if let Err(e) = ctx.moderation.block(&target.public_key, &target.name) {
+        eprintln!("Failed to save the block of {}: {e}", target.name);
+    }
+    let _ = ctx.events.send(ChatEvent::PeerExpelled {
+        room: room.id.clone(),
+        expulsion: expulsion.clone(),
+    });
+    if room.is_private() && expulsion.proposal.proposer_id == ctx.peer_id {
+        rotate_secret(ctx, room, expulsion).await;
+    }
+}
+
+/// Send the role grants and role decisions of `room` in the order they were applied, then
+/// the expulsions decided there, to a peer newly discovered in it. Roles go first as they
+/// weigh the votes of weighted decisions.
+pub async fn send_decisions(
+    transport: &dyn Transport,
+    protocols: &ProtocolState,
+    room: &Room,
+    target: &PeerInfo,
+) {
+    let expulsions = room.threshold_manager.decisions().await.into_iter();
+    let expulsions = expulsions
+        .filter(|d| d.expelled().is_some())
+        .map(NetworkMessage::Decision);
+    let roles = room.roles.sources().into_iter().map(|source| match source {
+        RoleSource::Grant(grant) => NetworkMessage::RoleGrant(grant),
+        RoleSource::Decision(decision) => NetworkMessage::Decision(decision),
+    });
+    for msg in roles.chain(expulsions) {
+        let codec = protocols.codec_for(&target.id);
+        let addr = target.socket_addr();
+        if let Err(e) = send_message(transport, codec, addr, room, &msg).await {
+            eprintln!("Failed to send decisions to {}: {e}", target.name);
+            return;
+        }
+    }
+}
+
+/// A decision taken before we joined, or without us. A decision sent to a newcomer can
+/// overtake the handshakes that let it know the voters, so one that holds up on its own
+/// terms is kept and checked again as members join (see `retry_decisions`).
+pub async fn handle_decision(decision: Decision, room: &Room, ctx: &HandlerContext) {
+    let Err(e) = accept_decision(ctx, room, &decision).await else {
+        return;
+    };
+    if let Err(e) = decision.verify(None) {
+        eprintln!(
+            "⚠️  Warning: Ignoring decision {} in #{}: {e}",
+            decision.proposal.proposal_id, room.id
+        );
+        return;
+    }
+    println!(
+        "🔍 Holding decision {} in #{} until we know its voters: {e}",
+        decision.proposal.proposal_id, room.id
     );
-    let _ = message_sender.send(display_msg);
+    room.threshold_manager.defer_decision(decision).await;
+}
+
+/// Check the decisions we could not check yet again, once a member joined `room`
+pub async fn retry_decisions(ctx: &HandlerContext, room: &Room) {
+    for decision in room.threshold_manager.take_pending_decisions().await {
+        if accept_decision(ctx, room, &decision).await.is_err() {
+            room.threshold_manager.defer_decision(decision).await;
+        }
+    }
+}
+
+/// The new secret of a private room, sent by the proposer of an expulsion from it
+pub async fn handle_room_rekey(
+    from_id: String,
+    expulsion: Decision,
+    sealed_secret: Vec<u8>,
+    room: &Room,
+    ctx: &HandlerContext,
+) {
+    let proposal_id = expulsion.proposal.proposal_id.clone();
+    let is_expulsion = expulsion.expelled().is_some();
+    if expulsion.proposal.proposer_id != from_id || !is_expulsion || !room.is_private() {
+        eprintln!(
+            "⚠️  Warning: Ignoring room key for #{} from {from_id}",
+            room.id
+        );
+        return;
+    }
+    // The key may overtake the last votes, so it carries the decision itself
+    if let Err(e) = accept_decision(ctx, room, &expulsion).await {
+        eprintln!("⚠️  Warning: Ignoring room key for #{}: {e}", room.id);
+        return;
+    }
+    let Some(key) = ctx.crypto_manager.public_key_of(&from_id).await else {
+        eprintln!(
+            "⚠️  Warning: Room key for #{} from unknown peer {from_id}",
+            room.id
+        );
+        return;
+    };
+    let secret = ctx
+        .crypto_manager
+        .shared_secret(&key)
+        .ok()
+        .and_then(|shared| {
+            let context = rekey_context(&room.wire_id, &proposal_id, &ctx.peer_id);
+            shared.open(&sealed_secret, &context)
+        });
+    let Some(secret) = secret else {
+        eprintln!("⚠️  Warning: Room key for #{} does not open", room.id);
+        return;
+    };
+    match ctx.rooms.rekey(&room.id, secret) {
+        Ok(_) => println!(
+            "🔑 #{} has a new secret; earlier invites no longer work",
+            room.id
+        ),
+        Err(e) => eprintln!("Failed to change the secret of #{}: {e}", room.id),
+    }
+}
+
+/// Check a decision we did not see taken and carry it out if it is new
+async fn accept_decision(
+    ctx: &HandlerContext,
+    room: &Room,
+    decision: &Decision,
+) -> Result<(), CryptoError> {
+    // Only members we know, signing with the keys we know for them, count. A decision taken
+    // without us is up to the others.
+    let mut electorate = room.electorate(&ctx.crypto_manager).await;
+    let voted = decision.approvals.iter().any(|v| v.voter_id == ctx.peer_id);
+    if !voted && decision.proposal.proposer_id != ctx.peer_id {
+        electorate.remove(&ctx.peer_id);
+    }
+    decision.verify(Some(&electorate))?;
+    room.roles
+        .check_proposal(&decision.proposal)
+        .map_err(|e| CryptoError::Unknown(e.to_string()))?;
+    if room
+        .threshold_manager
+        .record_decision(decision.clone())
+        .await
+    {
+        apply_decision(ctx, room, decision).await;
+    }
+    Ok(())
+}
+
+/// Give the remaining members of a private room a new secret, then switch to it
+async fn rotate_secret(ctx: &HandlerContext, room: &Room, expulsion: &Decision) {
+    let secret = RoomSecret::generate();
+    let members: Vec<PeerInfo> = room.peers.lock().await.values().cloned().collect();
+    for member in members {
+        let shared = match ctx.crypto_manager.public_key_of(&member.id).await {
+            Some(key) => ctx.crypto_manager.shared_secret(&key),
+            None => Err(CryptoError::InvalidPublicKey),
+        };
+        let shared = match shared {
+            Ok(shared) => shared,
+            Err(e) => {
+                eprintln!(
+                    "Cannot send the new secret of #{} to {}: {e}",
+                    room.id, member.name
+                );
+                continue;
+            }
+        };
+        let context = rekey_context(&room.wire_id, &expulsion.proposal.proposal_id, &member.id);
+        let msg = NetworkMessage::RoomRekey {
+            peer_id: ctx.peer_id.clone(),
+            expulsion: expulsion.clone(),
+            sealed_secret: shared.seal(&secret, &context),
+        };
+        let codec = ctx.protocols.codec_for(&member.id);
+        let addr = member.socket_addr();
+        let sent = send_message(&*ctx.transport, codec, addr, room, &msg).await;
+        if let Err(e) = sent {
+            eprintln!(
+                "Failed to send the new secret of #{} to {}: {e}",
+                room.id, member.name
+            );
+        }
+    }
+    match ctx.rooms.rekey(&room.id, secret) {
+        Ok(_) => println!(
+            "🔑 #{} has a new secret; earlier invites no longer work",
+            room.id
+        ),
+        Err(e) => eprintln!("Failed to change the secret of #{}: {e}", room.id),
+    }
+}
+
+/// What a new room secret is sealed to: the room it replaces the secret of, the expulsion
+/// that caused it and the member it is for
+fn rekey_context<'a>(wire_id: &'a str, proposal_id: &'a str, member_id: &'a str) -> [&'a [u8]; 3] {
+    [
+        wire_id.as_bytes(),
+        proposal_id.as_bytes(),
+        member_id.as_bytes(),
+    ]
 }
@@ -0,0 +1,134 @@
+//! Interfaces module: Enumerates local network interfaces and picks the addresses to advertise.
+//!
+//! A host can sit on several networks at once (Wi-Fi, Ethernet, Docker bridges), and the
+//! address that reaches one peer may be useless to another. Announcements therefore carry
+//! every usable interface address, with the one most likely to work for the receiver first:
+//! the route towards a known peer, or the interface an announcement is sent out on.
+
+use crate::peer::{is_link_local, MAX_PEER_ADDRESSES};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+/// A usable address on a local network interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalInterface {
+    pub name: String,
+    pub ip: IpAddr,
+}
+
+/// List the addresses of the local interfaces that other hosts could reach.
+///
+/// Loopback, unspecified and IPv4 link-local addresses are skipped. IPv6 link-local
+/// addresses are kept: on IPv6-only LANs they may be the only ones a peer has, and the
+/// receiver pairs them with the interface it heard them on.
+pub fn local_interfaces() -> Vec<LocalInterface> {
+    let netifas = match local_ip_address::list_afinet_netifas() {
+        Ok(netifas) => netifas,
+        Err(e) => {
+            eprintln!("[WARN] Could not list network interfaces: {e}");
+            return Vec::new();
+        }
+    };
+    let mut interfaces: Vec<LocalInterface> = Vec::new();
+    for (name, ip) in netifas {
+        if !is_advertisable(ip) || interfaces.iter().any(|i| i.ip == ip) {
+            continue;
+        }
+        interfaces.push(LocalInterface { name, ip });
+    }
+    interfaces
+}
+
+fn is_advertisable(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => !v4.is_loopback() && !v4.is_unspecified() && !v4.is_link_local(),
+        IpAddr::V6(v6) => !v6.is_loopback() && !v6.is_unspecified() && !v6.is_multicast(),
+    }
+}
+
+/// The local address the OS would use to reach `remote`, without sending anything
+pub fn address_for(remote: IpAddr) -> Option<IpAddr> {
+    let bind: SocketAddr = match remote {
+        IpAddr::V4(_) => "0.0.0.0:0".parse().ok()?,
+        IpAddr::V6(_) => "[::]:0".parse().ok()?,
+    };
+    let socket = UdpSocket::bind(bind).ok()?;
+    socket.connect(SocketAddr::new(remote, 9)).ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    is_advertisable(ip).then_some(ip)
+}
+
+/// The address to advertise first when nothing is known about the receiver
+pub fn primary_address() -> Option<IpAddr> {
+    local_ip_address::local_ip()
+        .ok()
+        .filter(|ip| is_advertisable(*ip))
+        .or_else(primary_address_v6)
+}
+
+/// The IPv6 address to advertise first, preferring routable over link-local addresses
+pub fn primary_address_v6() -> Option<IpAddr> {
+    let mut v6: Vec<IpAddr> = local_interfaces()
+        .into_iter()
+        .map(|i| i.ip)
+        .filter(IpAddr::is_ipv6)
+        .collect();
+    v6.sort_by_key(is_link_local);
+    v6.into_iter().next()
+}
+
+/// Every advertisable interface address except `primary`, capped to what `PeerInfo` accepts
+pub fn other_addresses(primary: IpAddr) -> Vec<IpAddr> {
+    order_addresses(primary, local_interfaces().into_iter().map(|i| i.ip))
+}
+
+fn order_addresses(primary: IpAddr, all: impl Iterator<Item = IpAddr>) -> Vec<IpAddr> {
+    let mut others: Vec<IpAddr> = Vec::new();
+    for ip in all {
+        if ip != primary && !others.contains(&ip) {
+            others.push(ip);
+        }
+    }
+    // Prefer the same address family as the primary address, and link-local addresses last
+    others.sort_by_key(|ip| (is_link_local(ip), ip.is_ipv4() != primary.is_ipv4()));
+    others.truncate(MAX_PEER_ADDRESSES);
+    others
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advertisable_addresses() {
+        assert!(is_advertisable("192.168.1.20".parse().unwrap()));
+        assert!(is_advertisable("2001:db8::1".parse().unwrap()));
+        assert!(!is_advertisable("127.0.0.1".parse().unwrap()));
+        assert!(!is_advertisable("169.254.3.4".parse().unwrap()));
+        assert!(is_advertisable("fe80::1".parse().unwrap()));
+        assert!(!is_advertisable("::1".parse().unwrap()));
+        assert!(!is_advertisable("0.0.0.0".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_order_addresses() {
+        let primary: IpAddr = "192.168.1.20".parse().unwrap();
+        let all = [
+            "fe80::1",
+            "2001:db8::1",
+            "192.168.1.20",
+            "172.17.0.1",
+            "172.17.0.1",
+        ]
+        .into_iter()
+        .map(|ip| ip.parse().unwrap());
+        let others = order_addresses(primary, all);
+        assert_eq!(
+            others,
+            vec![
+                "172.17.0.1".parse::<IpAddr>().unwrap(),
+                "2001:db8::1".parse().unwrap(),
+                "fe80::1".parse().unwrap()
+            ]
+        );
+    }
+}
@@ -1,3 +1,8 @@
+pub mod interfaces;
 pub mod tcp;
 pub mod handlers;
-pub mod command;
\ No newline at end of file
+pub mod command;
+pub mod codec;
+pub mod protocol;
+pub mod sim;
+pub mod transport;
@@ -0,0 +1,332 @@
+//! Protocol module: Version and capability negotiation between peers.
+//!
+//! Every discovery handshake carries a `Hello` next to the signed `PeerInfo`: the range of
+//! protocol versions the peer speaks, its software version, the optional features it
+//! supports and the codecs it can read, in order of preference. Both sides compute the same
+//! `Capabilities` from the two hellos: the highest common protocol version, the features
+//! both support and the first of the initiator's codecs the responder can read. A peer
+//! without a hello, or without a common version or codec, is rejected with a `Rejected`
+//! reply giving the reason, instead of having its messages silently dropped later.
+//!
+//! Features and codecs this build does not know deserialize as `Unknown` and are never
+//! part of the common subset, so newer peers can advertise more without breaking us.
+
+pub use crate::network::codec::Codec;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+use thiserror::Error;
+
+/// Protocol version this build speaks
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version this build still speaks
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Software name and version sent in the hello
+pub const SOFTWARE: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Optional protocol features
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    /// End-to-end encrypted payloads
+    Encryption,
+    /// Compressed frames
+    Compression,
+    /// History summaries, requests and responses
+    HistorySync,
+    /// Relaying through gossip envelopes
+    Gossip,
+    /// Peer exchange lists
+    PeerExchange,
+    /// Invite-only rooms with proof-of-knowledge handshakes
+    PrivateRooms,
+    /// A feature of a newer build
+    #[serde(other)]
+    Unknown,
+}
+
+/// Features this build supports
+pub const FEATURES: &[Feature] = &[
+    Feature::HistorySync,
+    Feature::Gossip,
+    Feature::PeerExchange,
+    Feature::PrivateRooms,
+];
+
+/// Codecs this build reads, preferred first
+pub const CODECS: &[Codec] = &[Codec::Cbor, Codec::Json];
+
+/// What a peer speaks, sent with every discovery handshake
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hello {
+    pub protocol: u32,
+    pub min_protocol: u32,
+    pub software: String,
+    #[serde(default)]
+    pub features: BTreeSet<Feature>,
+    #[serde(default)]
+    pub codecs: Vec<Codec>,
+}
+
+impl Hello {
+    /// The hello of this build
+    pub fn local() -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            min_protocol: MIN_PROTOCOL_VERSION,
+            software: SOFTWARE.to_string(),
+            features: FEATURES.iter().copied().collect(),
+            codecs: CODECS.to_vec(),
+        }
+    }
+
+    /// What we and the peer that sent `remote` have in common. `initiator` tells whether
+    /// we opened the connection; the initiator's codec preference wins so both sides agree.
+    pub fn negotiate(
+        &self,
+        remote: &Hello,
+        initiator: bool,
+    ) -> Result<Capabilities, ProtocolError> {
+        let protocol = self.protocol.min(remote.protocol);
+        if protocol < self.min_protocol.max(remote.min_protocol) {
+            return Err(ProtocolError::Version {
+                software: remote.software.clone(),
+                remote_min: remote.min_protocol,
+                remote: remote.protocol,
+                local_min: self.min_protocol,
+                local: self.protocol,
+            });
+        }
+        let (preferred, other) = if initiator {
+            (&self.codecs, &remote.codecs)
+        } else {
+            (&remote.codecs, &self.codecs)
+        };
+        let codec = preferred
+            .iter()
+            .find(|codec| **codec != Codec::Unknown && other.contains(codec))
+            .copied()
+            .ok_or_else(|| ProtocolError::NoCommonCodec {
+                software: remote.software.clone(),
+            })?;
+        Ok(Capabilities {
+            protocol,
+            features: self
+                .features
+                .intersection(&remote.features)
+                .filter(|feature| **feature != Feature::Unknown)
+                .copied()
+                .collect(),
+            codec,
+            software: remote.software.clone(),
+        })
+    }
+}
+
+/// What two peers agreed on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    pub protocol: u32,
+    pub features: BTreeSet<Feature>,
+    pub codec: Codec,
+    /// Software version of the other peer
+    pub software: String,
+}
+
+impl Capabilities {
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, protocol {}, {}",
+            self.software, self.protocol, self.codec
+        )
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ProtocolError {
+    #[error("no protocol hello (peer is older than protocol {MIN_PROTOCOL_VERSION})")]
+    MissingHello,
+    #[error(
+        "{software} speaks protocol {remote_min} to {remote}, we speak {local_min} to {local}"
+    )]
+    Version {
+        software: String,
+        remote_min: u32,
+        remote: u32,
+        local_min: u32,
+        local: u32,
+    },
+    #[error("{software} reads none of our codecs")]
+    NoCommonCodec { software: String },
+}
+
+/// Our own hello and the capabilities negotiated with each known peer, by peer id
+#[derive(Debug)]
+pub struct ProtocolState {
+    local: Hello,
+    peers: Mutex<HashMap<String, Capabilities>>,
+}
+
+impl Default for ProtocolState {
+    fn default() -> Self {
+        Self::new(Hello::local())
+    }
+}
+
+impl ProtocolState {
+    /// State of a peer sending `local` as its hello
+    pub fn new(local: Hello) -> Self {
+        Self {
+            local,
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The hello we send
+    pub fn local(&self) -> &Hello {
+        &self.local
+    }
+
+    /// Negotiate with a peer's hello, which older peers do not send
+    pub fn negotiate(
+        &self,
+        remote: Option<&Hello>,
+        initiator: bool,
+    ) -> Result<Capabilities, ProtocolError> {
+        let remote = remote.ok_or(ProtocolError::MissingHello)?;
+        self.local.negotiate(remote, initiator)
+    }
+
+    /// Remember what was negotiated with `peer_id` in the latest handshake
+    pub fn record(&self, peer_id: &str, capabilities: Capabilities) {
+        self.lock().insert(peer_id.to_string(), capabilities);
+    }
+
+    /// What was negotiated with `peer_id`, if we handshook with it
+    pub fn get(&self, peer_id: &str) -> Option<Capabilities> {
+        self.lock().get(peer_id).cloned()
+    }
+
+    /// The codec to send `peer_id` messages with. Peers we have not handshook with get
+    /// JSON, which every version reads.
+    pub fn codec_for(&self, peer_id: &str) -> Codec {
+        self.lock()
+            .get(peer_id)
+            .map_or(Codec::Json, |capabilities| capabilities.codec)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Capabilities>> {
+        self.peers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(min_protocol: u32, protocol: u32) -> Hello {
+        Hello {
+            protocol,
+            min_protocol,
+            ..Hello::local()
+        }
+    }
+
+    #[test]
+    fn test_negotiates_the_common_subset() {
+        let newer = Hello {
+            protocol: PROTOCOL_VERSION + 1,
+            software: "p2p-chat/9.0.0".to_string(),
+            features: [Feature::Gossip, Feature::Compression, Feature::Unknown].into(),
+            codecs: vec![Codec::Unknown, Codec::Json],
+            ..Hello::local()
+        };
+        let caps = Hello::local().negotiate(&newer, true).unwrap();
+        assert_eq!(caps.protocol, PROTOCOL_VERSION);
+        assert_eq!(caps.features, [Feature::Gossip].into());
+        assert_eq!(caps.codec, Codec::Json);
+        assert_eq!(caps.software, "p2p-chat/9.0.0");
+        assert!(caps.supports(Feature::Gossip));
+        assert!(!caps.supports(Feature::HistorySync));
+        // Both sides agree
+        let theirs = newer.negotiate(&Hello::local(), false).unwrap();
+        assert_eq!((theirs.protocol, theirs.codec), (caps.protocol, caps.codec));
+        assert_eq!(theirs.features, caps.features);
+    }
+
+    #[test]
+    fn test_rejects_incompatible_peers() {
+        let local = hello(2, 3);
+        assert!(matches!(
+            local.negotiate(&hello(1, 1), true),
+            Err(ProtocolError::Version { remote: 1, .. })
+        ));
+        assert!(local.negotiate(&hello(4, 5), true).is_err());
+        assert_eq!(local.negotiate(&hello(1, 2), true).unwrap().protocol, 2);
+        assert_eq!(local.negotiate(&hello(3, 9), true).unwrap().protocol, 3);
+
+        let no_codec = Hello {
+            codecs: vec![Codec::Unknown],
+            ..Hello::local()
+        };
+        assert!(matches!(
+            Hello::local().negotiate(&no_codec, false),
+            Err(ProtocolError::NoCommonCodec { .. })
+        ));
+        let state = ProtocolState::default();
+        assert_eq!(
+            state.negotiate(None, true),
+            Err(ProtocolError::MissingHello)
+        );
+    }
+
+    #[test]
+    fn test_json_only_peers_get_json() {
+        let debug = Hello {
+            codecs: vec![Codec::Json],
+            ..Hello::local()
+        };
+        assert_eq!(
+            Hello::local()
+                .negotiate(&Hello::local(), true)
+                .unwrap()
+                .codec,
+            Codec::Cbor
+        );
+        assert_eq!(
+            Hello::local().negotiate(&debug, true).unwrap().codec,
+            Codec::Json
+        );
+        assert_eq!(
+            debug.negotiate(&Hello::local(), false).unwrap().codec,
+            Codec::Json
+        );
+
+        let state = ProtocolState::new(debug);
+        assert_eq!(state.codec_for("stranger"), Codec::Json);
+        let caps = state.negotiate(Some(&Hello::local()), true).unwrap();
+        state.record("bob", caps);
+        assert_eq!(state.codec_for("bob"), Codec::Json);
+    }
+
+    #[test]
+    fn test_unknown_features_and_codecs_parse() {
+        let hello: Hello = serde_json::from_str(
+            r#"{"protocol": 7, "min_protocol": 1, "software": "x",
+                "features": ["gossip", "teleport"], "codecs": ["quantum", "json"]}"#,
+        )
+        .unwrap();
+        assert_eq!(hello.features, [Feature::Gossip, Feature::Unknown].into());
+        assert_eq!(hello.codecs, vec![Codec::Unknown, Codec::Json]);
+    }
+}
@@ -0,0 +1,371 @@
+//! Simulated network module: An in-process `Transport` for deterministic multi-peer tests.
+//!
+//! Every peer gets a `SimTransport` with its own address on a shared `SimNetwork`.
+//! Connections are in-memory pipes, so no ports are bound and any non-loopback address can
+//! be used. Opening a connection takes the configured latency plus a random jitter of up to
+//! `jitter`, so concurrent messages can overtake each other (reordering); with probability
+//! `loss` the connection is lost and times out, and hosts on different sides of a partition
+//! cannot reach each other at all. All random choices come from a seeded generator, so a
+//! scenario plays out the same way on every run. The network also provides in-memory
+//! discovery, since UDP announcements do not cross it.
+
+use crate::chat::net::discovery::memory::{MemoryDiscovery, MemoryRegistry};
+use crate::chat::net::discovery::Discovery;
+use crate::network::transport::{Connection, Listener, Transport};
+use async_trait::async_trait;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio::sync::mpsc;
+
+/// Buffer of each direction of a simulated connection
+const PIPE_SIZE: usize = 64 * 1024;
+
+/// First port handed out to the connecting side of a connection
+const FIRST_EPHEMERAL_PORT: u16 = 40000;
+
+/// How the simulated network behaves
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimConfig {
+    /// Time every connection takes to reach the other side
+    pub latency: Duration,
+    /// Extra random delay of up to this much per connection, which reorders messages
+    /// sent close together
+    pub jitter: Duration,
+    /// Probability (0 to 1) that a connection is lost
+    pub loss: f64,
+    /// Seed of the random jitter and loss
+    pub seed: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::from_millis(1),
+            jitter: Duration::ZERO,
+            loss: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+type Incoming = mpsc::UnboundedSender<(Box<dyn Connection>, SocketAddr)>;
+
+struct State {
+    config: SimConfig,
+    rng: StdRng,
+    listeners: HashMap<SocketAddr, Incoming>,
+    /// Partition each host was put in; hosts that are not listed share partition 0
+    partitions: HashMap<IpAddr, usize>,
+    next_port: u16,
+}
+
+impl State {
+    fn partition_of(&self, ip: IpAddr) -> usize {
+        self.partitions.get(&ip).copied().unwrap_or(0)
+    }
+}
+
+/// An in-process network shared by simulated peers
+#[derive(Clone)]
+pub struct SimNetwork {
+    state: Arc<Mutex<State>>,
+    registry: MemoryRegistry,
+}
+
+impl Default for SimNetwork {
+    fn default() -> Self {
+        Self::new(SimConfig::default())
+    }
+}
+
+impl SimNetwork {
+    pub fn new(config: SimConfig) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                rng: StdRng::seed_from_u64(config.seed),
+                config,
+                listeners: HashMap::new(),
+                partitions: HashMap::new(),
+                next_port: FIRST_EPHEMERAL_PORT,
+            })),
+            registry: MemoryRegistry::default(),
+        }
+    }
+
+    /// The transport of the host at `ip`
+    pub fn node(&self, ip: impl Into<IpAddr>) -> SimTransport {
+        SimTransport {
+            network: self.clone(),
+            ip: ip.into(),
+        }
+    }
+
+    /// Change latency, jitter and loss; the random generator restarts from the new seed
+    pub fn set_config(&self, config: SimConfig) {
+        let mut state = self.lock();
+        state.rng = StdRng::seed_from_u64(config.seed);
+        state.config = config;
+    }
+
+    /// Split the network: hosts only reach hosts of their own group. Hosts in no group
+    /// form one more group together.
+    pub fn partition(&self, groups: &[&[IpAddr]]) {
+        let mut state = self.lock();
+        state.partitions.clear();
+        for (i, group) in groups.iter().enumerate() {
+            for ip in *group {
+                state.partitions.insert(*ip, i + 1);
+            }
+        }
+    }
+
+    /// Reconnect every partition
+    pub fn heal(&self) {
+        self.lock().partitions.clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn connect(&self, from: IpAddr, addr: SocketAddr) -> io::Result<Box<dyn Connection>> {
+        let (delay, lost) = {
+            let mut state = self.lock();
+            let jitter = state.config.jitter.as_nanos() as u64;
+            let jitter = match jitter {
+                0 => 0,
+                max => state.rng.gen_range(0..=max),
+            };
+            let loss = state.config.loss.clamp(0.0, 1.0);
+            let lost = loss > 0.0 && state.rng.gen_bool(loss);
+            (state.config.latency + Duration::from_nanos(jitter), lost)
+        };
+        tokio::time::sleep(delay).await;
+
+        let mut state = self.lock();
+        if lost || state.partition_of(from) != state.partition_of(addr.ip()) {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{addr} unreachable"),
+            ));
+        }
+        let refused = || io::Error::new(io::ErrorKind::ConnectionRefused, format!("{addr}"));
+        let incoming = state.listeners.get(&addr).cloned().ok_or_else(refused)?;
+        let local = SocketAddr::new(from, state.next_port);
+        state.next_port = state
+            .next_port
+            .checked_add(1)
+            .unwrap_or(FIRST_EPHEMERAL_PORT);
+        drop(state);
+
+        let (ours, theirs) = tokio::io::duplex(PIPE_SIZE);
+        let accepted = SimConnection {
+            stream: theirs,
+            local: addr,
+        };
+        incoming
+            .send((Box::new(accepted), local))
+            .map_err(|_| refused())?;
+        Ok(Box::new(SimConnection {
+            stream: ours,
+            local,
+        }))
+    }
+}
+
+/// The transport of one simulated host
+#[derive(Clone)]
+pub struct SimTransport {
+    network: SimNetwork,
+    ip: IpAddr,
+}
+
+impl SimTransport {
+    /// Address of the host
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+}
+
+#[async_trait]
+impl Transport for SimTransport {
+    async fn connect(&self, addr: SocketAddr) -> io::Result<Box<dyn Connection>> {
+        self.network.connect(self.ip, addr).await
+    }
+
+    async fn listen(&self, port: u16) -> io::Result<Box<dyn Listener>> {
+        let addr = SocketAddr::new(self.ip, port);
+        let mut state = self.network.lock();
+        if state.listeners.contains_key(&addr) {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{addr}")));
+        }
+        let (tx, incoming) = mpsc::unbounded_channel();
+        state.listeners.insert(addr, tx);
+        Ok(Box::new(SimListener {
+            network: self.network.clone(),
+            addr,
+            incoming,
+        }))
+    }
+
+    fn other_addresses(&self, _primary: IpAddr) -> Vec<IpAddr> {
+        Vec::new()
+    }
+
+    fn discovery(&self) -> Option<Box<dyn Discovery>> {
+        Some(Box::new(MemoryDiscovery::new(
+            self.network.registry.clone(),
+            self.ip,
+        )))
+    }
+}
+
+/// Accepts simulated connections until dropped, which frees the address
+struct SimListener {
+    network: SimNetwork,
+    addr: SocketAddr,
+    incoming: mpsc::UnboundedReceiver<(Box<dyn Connection>, SocketAddr)>,
+}
+
+#[async_trait]
+impl Listener for SimListener {
+    async fn accept(&mut self) -> io::Result<(Box<dyn Connection>, SocketAddr)> {
+        self.incoming
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::other("listener closed"))
+    }
+}
+
+impl Drop for SimListener {
+    fn drop(&mut self) {
+        self.network.lock().listeners.remove(&self.addr);
+    }
+}
+
+/// One end of a simulated connection
+struct SimConnection {
+    stream: DuplexStream,
+    local: SocketAddr,
+}
+
+impl Connection for SimConnection {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local)
+    }
+}
+
+impl AsyncRead for SimConnection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for SimConnection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([10, 0, 0, last])
+    }
+
+    #[tokio::test]
+    async fn test_connection_carries_bytes_both_ways() {
+        let network = SimNetwork::default();
+        let mut listener = network.node(ip(1)).listen(9000).await.unwrap();
+        let addr = SocketAddr::new(ip(1), 9000);
+        let client = tokio::spawn(async move {
+            let mut conn = network.node(ip(2)).connect(addr).await.unwrap();
+            conn.write_all(b"hello").await.unwrap();
+            conn.shutdown().await.unwrap();
+            let mut reply = Vec::new();
+            conn.read_to_end(&mut reply).await.unwrap();
+            reply
+        });
+
+        let (mut conn, from) = listener.accept().await.unwrap();
+        assert_eq!(from.ip(), ip(2));
+        assert_eq!(conn.local_addr().unwrap(), addr);
+        let mut received = Vec::new();
+        conn.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, b"hello");
+        conn.write_all(b"hi").await.unwrap();
+        drop(conn);
+        assert_eq!(client.await.unwrap(), b"hi");
+    }
+
+    #[tokio::test]
+    async fn test_partitions_and_closed_listeners() {
+        let network = SimNetwork::default();
+        let listener = network.node(ip(1)).listen(9000).await.unwrap();
+        let addr = SocketAddr::new(ip(1), 9000);
+        assert!(network.node(ip(1)).listen(9000).await.is_err());
+
+        network.partition(&[&[ip(1)], &[ip(2)]]);
+        let err = network.node(ip(2)).connect(addr).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        // Hosts outside every group are cut off from both
+        assert!(network.node(ip(3)).connect(addr).await.is_err());
+
+        network.heal();
+        assert!(network.node(ip(2)).connect(addr).await.is_ok());
+
+        drop(listener);
+        let err = network.node(ip(2)).connect(addr).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[tokio::test]
+    async fn test_loss_is_reproducible() {
+        let outcomes = |seed| async move {
+            let network = SimNetwork::new(SimConfig {
+                latency: Duration::ZERO,
+                loss: 0.5,
+                seed,
+                ..SimConfig::default()
+            });
+            let _listener = network.node(ip(1)).listen(9000).await.unwrap();
+            let client = network.node(ip(2));
+            let mut delivered = Vec::new();
+            for _ in 0..32 {
+                let sent = client.connect(SocketAddr::new(ip(1), 9000)).await;
+                delivered.push(sent.is_ok());
+            }
+            delivered
+        };
+        let first = outcomes(7).await;
+        assert_eq!(first, outcomes(7).await);
+        assert!(first.contains(&true) && first.contains(&false));
+    }
+}
@@ -4,47 +4,622 @@
 //! handling incoming messages, and broadcasting outgoing messages.
 //! It utilizes Tokio's asynchronous runtime for non-blocking I/O operations.
 
+use crate::chat::room::Room;
+use crate::crypto::invite::{ACCEPT_PROOF, JOIN_PROOF, MEMBER_PROOF};
+use crate::crypto::CryptoManager;
 use crate::error::ChatError;
-use crate::network::command::to_command;
-use crate::peer::{NetworkMessage, PeerInfo};
-use serde_json;
+use crate::network::codec::Codec;
+use crate::network::command::HandlerContext;
+use crate::network::protocol::{Capabilities, Hello, ProtocolError};
+use crate::network::transport::{Connection, Transport};
+use crate::peer::{link_local_scope, NetworkMessage, PeerInfo, RoomMessage};
+use serde::Serialize;
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::net::TcpStream;
-use tokio::sync::{broadcast, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+/// Upper bound for a single message read from a connection
+pub const MAX_MESSAGE_SIZE: u64 = 1024 * 1024;
+
+/// How long a handshake waits for the remote peer to answer
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long an incoming connection may take to deliver its message
+pub const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long sending a message to a peer that is going away may take
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Lifetime of the signed `PeerInfo` sent in a discovery handshake, in seconds
+pub const DISCOVERY_LIFETIME: u64 = 60;
+
+/// Read the single message of an incoming connection from `addr` and dispatch it to the
+/// handler registered for its kind
 pub async fn handle_tcp_connection(
-    stream: TcpStream,
-    _addr: SocketAddr,
-    peers: Arc<Mutex<HashMap<String, PeerInfo>>>,
-    message_sender: broadcast::Sender<String>,
-    peer_id: String,
-    threshold_manager: Arc<crate::crypto::threshold::ThresholdManager>,
-    crypto_manager: Arc<crate::crypto::CryptoManager>,
+    stream: Box<dyn Connection>,
+    addr: SocketAddr,
+    ctx: HandlerContext,
 ) -> Result<(), ChatError> {
-    let mut buf = [0; 1024];
-
-    while let Ok(_n) = stream.readable().await {
-        match stream.try_read(&mut buf) {
-            Ok(0) => break, // Connection closed
-            Ok(n) => {
-                if let Ok(network_msg) = serde_json::from_slice::<NetworkMessage>(&buf[..n]) {
-                    println!("🔍 Received message: {network_msg:?}");
-                    let command = to_command(network_msg);
-                    command
-                        .execute(
-                            peers.clone(),
-                            message_sender.clone(),
-                            peer_id.clone(),
-                            threshold_manager.clone(),
-                            crypto_manager.clone(),
+    // Each connection carries a single message; the sender closes (or half-closes) it
+    // when done. Read until EOF so messages larger than one socket read (e.g. history
+    // batches) arrive intact.
+    // A connection that never finishes its message would hold its slot forever
+    let mut stream = stream;
+    let mut buf = Vec::new();
+    let mut limited = (&mut stream).take(MAX_MESSAGE_SIZE);
+    let read = tokio::time::timeout(READ_TIMEOUT, limited.read_to_end(&mut buf)).await;
+    let Ok(read) = read else {
+        ctx.limits.strike(addr.ip().to_canonical());
+        return Err(ChatError::Network(format!(
+            "{addr} did not finish its message in time"
+        )));
+    };
+    read?;
+    if buf.is_empty() {
+        return Ok(());
+    }
+
+    if let Ok(RoomMessage {
+        room,
+        message: mut network_msg,
+        proof,
+        hello,
+    }) = Codec::decode::<RoomMessage>(&buf)
+    {
+        // Flooding sources are cut off before any handler does work for them
+        let source = addr.ip().to_canonical();
+        if let Err(refusal) = ctx.limits.allow_message(source, &network_msg) {
+            println!("🔍 Dropping {} from {addr}: {refusal}", network_msg.kind());
+            return Ok(());
+        }
+        println!("🔍 Received {} in #{room}", network_msg.kind());
+        // Messages for rooms we have not joined are dropped, discovery handshakes included
+        let Some(room) = ctx.rooms.by_wire_id(&room) else {
+            println!("🔍 Ignoring message for room #{room}, not joined");
+            return Ok(());
+        };
+        // Private rooms only let in peers proving they know the secret, and only listen to
+        // their members, who MAC every message with it
+        if let Some(secret) = &room.secret {
+            let admitted = proof.as_deref().is_some_and(|proof| match &network_msg {
+                NetworkMessage::Discovery(remote) => secret.verify(
+                    JOIN_PROOF,
+                    &[room.wire_id.as_bytes(), &remote.signature],
+                    proof,
+                ),
+                _ => secret.verify(
+                    MEMBER_PROOF,
+                    &[room.wire_id.as_bytes(), &canonical(&network_msg)],
+                    proof,
+                ),
+            });
+            if !admitted {
+                eprintln!(
+                    "⚠️  Warning: Dropping message from {addr} for private room #{}, not a member",
+                    room.id
+                );
+                return Ok(());
+            }
+        }
+        // Answer discovery handshakes on the same connection with our own signed PeerInfo,
+        // advertising the local address the remote peer actually reached us on.
+        // Plain one-shot senders have already closed the connection; ignore errors.
+        if let NetworkMessage::Discovery(ref mut remote) = network_msg {
+            if remote.info.id != ctx.peer_id {
+                // Only answer peers that sign their own info and advertise the address
+                // they are connecting from
+                if !remote.info.advertises(addr.ip()) {
+                    eprintln!(
+                        "⚠️  Warning: Discovery from {addr} advertises {}, ignoring",
+                        remote.info.ip
+                    );
+                    return Ok(());
+                }
+                // Incompatible peers are told why instead of failing on later messages
+                let capabilities = match ctx.protocols.negotiate(hello.as_ref(), false) {
+                    Ok(capabilities) => capabilities,
+                    Err(e) => {
+                        eprintln!("⚠️  Warning: Rejecting discovery from {addr}: {e}");
+                        let reply = NetworkMessage::Rejected {
+                            reason: e.to_string(),
+                        };
+                        let local = Some(ctx.protocols.local());
+                        if let Ok(bytes) = encode_frame(&room.wire_id, &reply, None, local) {
+                            let _ = stream.write_all(&bytes).await;
+                            let _ = stream.shutdown().await;
+                        }
+                        return Ok(());
+                    }
+                };
+                if let Err(e) = ctx.crypto_manager.verify_peer_info(remote).await {
+                    eprintln!("⚠️  Warning: Invalid discovery from {addr}: {e}");
+                    return Ok(());
+                }
+                // Blocked and expelled peers get no answer
+                if ctx.moderation.is_blocked(&remote.public_key) {
+                    println!("🔍 Ignoring discovery from blocked peer at {addr}");
+                    return Ok(());
+                }
+                ctx.protocols.record(&remote.info.id, capabilities);
+                // A link-local peer is only reachable through the interface it came in on
+                if let Some(scope_id) = link_local_scope(&addr) {
+                    remote.info.scope_id = scope_id;
+                }
+                if let Ok(local) = stream.local_addr() {
+                    // IPv4 peers reach the dual-stack listener on a mapped address
+                    let local_ip = local.ip().to_canonical();
+                    let signed = ctx.crypto_manager.sign_peer_info(
+                        PeerInfo {
+                            id: ctx.peer_id.clone(),
+                            name: ctx.crypto_manager.get_identity().name.clone(),
+                            ip: local_ip,
+                            port: local.port(),
+                            addresses: ctx.transport.other_addresses(local_ip),
+                            scope_id: 0,
+                        },
+                        DISCOVERY_LIFETIME,
+                    );
+                    // Prove in turn that we know the secret, bound to both handshake halves
+                    let accept = room.secret.as_ref().map(|secret| {
+                        secret.mac(
+                            ACCEPT_PROOF,
+                            &[
+                                room.wire_id.as_bytes(),
+                                &signed.signature,
+                                &remote.signature,
+                            ],
                         )
-                        .await?;
+                    });
+                    let reply = NetworkMessage::Discovery(signed);
+                    let local = Some(ctx.protocols.local());
+                    let frame = encode_frame(&room.wire_id, &reply, accept.as_deref(), local);
+                    if let Ok(bytes) = frame {
+                        let _ = stream.write_all(&bytes).await;
+                        let _ = stream.shutdown().await;
+                    }
                 }
             }
-            Err(e) => return Err(ChatError::Network(e.to_string())),
         }
+        ctx.dispatch(&room, network_msg).await?;
+    }
+    Ok(())
+}
+
+/// `msg` as a JSON value, whose maps are sorted, which members of a private room MAC so
+/// the proof does not depend on the codec the message travels with
+fn canonical(msg: &NetworkMessage) -> Vec<u8> {
+    let value = serde_json::to_value(msg).and_then(|value| serde_json::to_vec(&value));
+    value.unwrap_or_default()
+}
+
+/// Borrowed form of `RoomMessage`, so messages can be framed without cloning them
+#[derive(Serialize)]
+struct Frame<'a> {
+    room: &'a str,
+    message: &'a NetworkMessage,
+    #[serde(skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    proof: Option<&'a [u8]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hello: Option<&'a Hello>,
+}
+
+/// Serialize `msg` for the wire with `codec`, tagged with `room`, and with the proof of
+/// membership if `room` is private
+pub fn encode_message(
+    codec: Codec,
+    room: &Room,
+    msg: &NetworkMessage,
+) -> Result<Vec<u8>, ChatError> {
+    let proof = room
+        .secret
+        .as_ref()
+        .map(|secret| secret.mac(MEMBER_PROOF, &[room.wire_id.as_bytes(), &canonical(msg)]));
+    codec.encode(&Frame {
+        room: &room.wire_id,
+        message: msg,
+        proof: proof.as_deref(),
+        hello: None,
+    })
+}
+
+/// Serialize a handshake frame. Handshakes are always JSON: the codec is only known once
+/// both hellos were exchanged.
+fn encode_frame(
+    room: &str,
+    msg: &NetworkMessage,
+    proof: Option<&[u8]>,
+    hello: Option<&Hello>,
+) -> Result<Vec<u8>, ChatError> {
+    Codec::Json.encode(&Frame {
+        room,
+        message: msg,
+        proof,
+        hello,
+    })
+}
+
+/// A message for a room sent to several peers, encoded at most once per codec
+pub struct Outgoing<'a> {
+    room: &'a Room,
+    message: &'a NetworkMessage,
+    encoded: HashMap<Codec, Vec<u8>>,
+}
+
+impl<'a> Outgoing<'a> {
+    pub fn new(room: &'a Room, message: &'a NetworkMessage) -> Self {
+        Self {
+            room,
+            message,
+            encoded: HashMap::new(),
+        }
+    }
+
+    /// The message encoded with `codec`
+    pub fn encode(&mut self, codec: Codec) -> Result<&[u8], ChatError> {
+        if !self.encoded.contains_key(&codec) {
+            let bytes = encode_message(codec, self.room, self.message)?;
+            self.encoded.insert(codec, bytes);
+        }
+        Ok(&self.encoded[&codec])
     }
+}
+
+/// Perform a direct discovery handshake in `room`: send our signed `PeerInfo` and wait for
+/// the remote peer to answer with its own on the same connection. Peers that have not
+/// joined `room` do not answer.
+///
+/// We advertise the local address of the connection first, so the remote peer learns an
+/// address it can actually reach us on, followed by our other interface addresses. The
+/// reply is only accepted if it is signed by the key it carries, and that key is not a
+/// replacement for one we already know.
+///
+/// In a private room both sides also prove they know the room secret: we MAC our signed
+/// hello, and the reply must carry a MAC over both signatures.
+///
+/// Both sides also exchange their protocol `Hello` (ours is `local`); the handshake fails
+/// with the reason when the remote peer rejects us or speaks no common protocol version or
+/// codec. Returns the remote peer's info and what was negotiated with it.
+pub async fn handshake(
+    transport: &dyn Transport,
+    addr: SocketAddr,
+    room: &Room,
+    crypto_manager: &CryptoManager,
+    local: &Hello,
+    port: u16,
+) -> Result<(PeerInfo, Capabilities), ChatError> {
+    let exchange = async {
+        let mut stream = transport.connect(addr).await?;
+        let local_ip = stream.local_addr()?.ip().to_canonical();
+        let identity = crypto_manager.get_identity();
+        let hello = crypto_manager.sign_peer_info(
+            PeerInfo {
+                id: identity.peer_id.clone(),
+                name: identity.name.clone(),
+                ip: local_ip,
+                port,
+                addresses: transport.other_addresses(local_ip),
+                scope_id: 0,
+            },
+            DISCOVERY_LIFETIME,
+        );
+        let join = room
+            .secret
+            .as_ref()
+            .map(|secret| secret.mac(JOIN_PROOF, &[room.wire_id.as_bytes(), &hello.signature]));
+        let hello_signature = hello.signature.clone();
+        let hello = NetworkMessage::Discovery(hello);
+        let frame = encode_frame(&room.wire_id, &hello, join.as_deref(), Some(local))?;
+        stream.write_all(&frame).await?;
+        // Half-close so the remote side sees EOF and answers
+        stream.shutdown().await?;
+        let mut buf = Vec::new();
+        stream.take(MAX_MESSAGE_SIZE).read_to_end(&mut buf).await?;
+        if buf.is_empty() {
+            return Err(ChatError::Network(format!(
+                "{addr} is not in room #{}",
+                room.id
+            )));
+        }
+        let reply = Codec::decode::<RoomMessage>(&buf)?;
+        match reply.message {
+            NetworkMessage::Rejected { reason } => Err(ChatError::Network(format!(
+                "{addr} rejected the handshake: {reason}"
+            ))),
+            NetworkMessage::Discovery(signed) if reply.room == room.wire_id => {
+                let remote = reply.hello.as_ref().ok_or(ProtocolError::MissingHello);
+                let capabilities = remote
+                    .and_then(|remote| local.negotiate(remote, true))
+                    .map_err(|e| ChatError::Network(format!("{addr} is incompatible: {e}")))?;
+                if let Some(secret) = &room.secret {
+                    let accepted = reply.proof.as_deref().is_some_and(|proof| {
+                        secret.verify(
+                            ACCEPT_PROOF,
+                            &[room.wire_id.as_bytes(), &signed.signature, &hello_signature],
+                            proof,
+                        )
+                    });
+                    if !accepted {
+                        return Err(ChatError::Network(format!(
+                            "{addr} did not prove membership of #{}",
+                            room.id
+                        )));
+                    }
+                }
+                crypto_manager.verify_peer_info(&signed).await?;
+                Ok((signed.info, capabilities))
+            }
+            _ => Err(ChatError::Network(format!(
+                "unexpected handshake reply from {addr}"
+            ))),
+        }
+    };
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, exchange)
+        .await
+        .map_err(|_| ChatError::Network(format!("handshake with {addr} timed out")))?
+}
+
+/// Open a connection to `addr` and write a single `NetworkMessage` for `room`, encoded
+/// with `codec`.
+pub async fn send_message(
+    transport: &dyn Transport,
+    codec: Codec,
+    addr: SocketAddr,
+    room: &Room,
+    msg: &NetworkMessage,
+) -> Result<(), ChatError> {
+    let msg_bytes = encode_message(codec, room, msg)?;
+    let mut stream = transport.connect(addr).await?;
+    stream.write_all(&msg_bytes).await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::transport::TcpTransport;
+
+    #[test]
+    fn test_frame_round_trip() {
+        let msg = NetworkMessage::Exit("alice".to_string());
+        let bytes = encode_message(Codec::Json, &Room::new("dev"), &msg).unwrap();
+        let frame: RoomMessage = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(frame.room, "dev");
+        assert!(matches!(frame.message, NetworkMessage::Exit(ref id) if id == "alice"));
+        // Bare messages without a room are not accepted
+        let bare = serde_json::to_vec(&msg).unwrap();
+        assert!(serde_json::from_slice::<RoomMessage>(&bare).is_err());
+    }
+
+    /// Accept `count` connections on behalf of `peer`
+    async fn serve(peer: crate::chat::Peer, count: usize) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for _ in 0..count {
+                let (stream, from) = listener.accept().await.unwrap();
+                let _ = handle_tcp_connection(Box::new(stream), from, peer.handler_context()).await;
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_private_handshake_needs_the_secret() {
+        use crate::crypto::invite::RoomSecret;
+
+        let remote = crate::chat::Peer::new("Remote".to_string(), 9000);
+        let secret = RoomSecret::generate();
+        remote.rooms.add_private("team", secret.clone()).unwrap();
+        let addr = serve(remote.clone(), 3).await;
+        let local = CryptoManager::new("local-id".to_string(), "Local".to_string());
+        let hello = Hello::local();
+
+        // Knowing the name is not enough
+        assert!(handshake(
+            &TcpTransport,
+            addr,
+            &Room::new("team"),
+            &local,
+            &hello,
+            9001
+        )
+        .await
+        .is_err());
+        let guess = Room::private("team", RoomSecret::generate());
+        assert!(handshake(&TcpTransport, addr, &guess, &local, &hello, 9001)
+            .await
+            .is_err());
+
+        let (info, _) = handshake(
+            &TcpTransport,
+            addr,
+            &Room::private("team", secret),
+            &local,
+            &hello,
+            9001,
+        )
+        .await
+        .unwrap();
+        assert_eq!(info.id, remote.peer_id);
+    }
+
+    #[tokio::test]
+    async fn test_private_rooms_only_hear_members() {
+        use crate::chat::event::ChatEvent;
+        use crate::crypto::invite::RoomSecret;
+
+        let remote = crate::chat::Peer::new("Remote".to_string(), 9000);
+        let secret = RoomSecret::generate();
+        let (room, _) = remote.rooms.add_private("team", secret.clone()).unwrap();
+        let mut events = remote.events.subscribe();
+        let addr = serve(remote.clone(), 3).await;
+        let local = CryptoManager::new("local-id".to_string(), "Local".to_string());
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let msg =
+            |content: &str| NetworkMessage::SignedChat(local.sign_message(content, now).unwrap());
+
+        // Knowing the tag, or MACing with another secret, is not enough
+        let tag_only = Room::new(&room.wire_id);
+        let mut guess = Room::private("team", RoomSecret::generate());
+        guess.wire_id = room.wire_id.clone();
+        let member = Room::private("team", secret);
+        for (from, content) in [(&tag_only, "tag"), (&guess, "guess"), (&member, "member")] {
+            send_message(&TcpTransport, Codec::Cbor, addr, from, &msg(content))
+                .await
+                .unwrap();
+        }
+        let event = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                if let Ok(ChatEvent::MessageReceived { content, .. }) = events.recv().await {
+                    return content;
+                }
+            }
+        });
+        assert_eq!(event.await.unwrap(), "member");
+    }
+
+    #[tokio::test]
+    async fn test_peers_without_a_compatible_hello_are_rejected() {
+        let remote = crate::chat::Peer::new("Remote".to_string(), 9000);
+        let addr = serve(remote.clone(), 2).await;
+        let local = CryptoManager::new("local-id".to_string(), "Local".to_string());
+        let discovery = NetworkMessage::Discovery(local.sign_peer_info(
+            PeerInfo {
+                id: "local-id".to_string(),
+                name: "Local".to_string(),
+                ip: addr.ip(),
+                port: 9001,
+                addresses: Vec::new(),
+                scope_id: 0,
+            },
+            DISCOVERY_LIFETIME,
+        ));
+        let too_new = Hello {
+            protocol: 99,
+            min_protocol: 99,
+            ..Hello::local()
+        };
+
+        for hello in [None, Some(&too_new)] {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let frame = encode_frame("lobby", &discovery, None, hello).unwrap();
+            stream.write_all(&frame).await.unwrap();
+            stream.shutdown().await.unwrap();
+            let mut buf = Vec::new();
+            stream.read_to_end(&mut buf).await.unwrap();
+            let reply: RoomMessage = serde_json::from_slice(&buf).unwrap();
+            assert!(
+                matches!(reply.message, NetworkMessage::Rejected { .. }),
+                "{reply:?}"
+            );
+            assert_eq!(reply.hello, Some(Hello::local()));
+        }
+        assert!(remote.protocols.get("local-id").is_none());
+        assert!(remote.room().peers.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_flooding_sources_are_dropped() {
+        use crate::chat::event::ChatEvent;
+        use crate::chat::net::limits::{Limits, RateLimit, RateLimiter};
+
+        let mut remote = crate::chat::Peer::new("Remote".to_string(), 9000);
+        remote.limits = std::sync::Arc::new(RateLimiter::new(Limits {
+            messages: RateLimit {
+                burst: 2,
+                per_second: 0.01,
+            },
+            ..Limits::default()
+        }));
+        let mut events = remote.events.subscribe();
+        let addr = serve(remote.clone(), 5).await;
+        let local = CryptoManager::new("local-id".to_string(), "Local".to_string());
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        for i in 0..5 {
+            let signed = local.sign_message(&format!("spam {i}"), now).unwrap();
+            let msg = NetworkMessage::SignedChat(signed);
+            send_message(&TcpTransport, Codec::Json, addr, &Room::new("lobby"), &msg)
+                .await
+                .unwrap();
+        }
+        // Past the causal hold-back
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let mut received = 0;
+        while let Ok(event) = events.try_recv() {
+            if matches!(event, ChatEvent::MessageReceived { .. }) {
+                received += 1;
+            }
+        }
+        assert_eq!(received, 2);
+        let offenders = remote.limits.offenders();
+        assert_eq!(offenders.len(), 1);
+        assert_eq!(offenders[0].dropped, 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_connections_time_out_with_a_strike() {
+        let remote = crate::chat::Peer::new("Remote".to_string(), 9000);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Connect and never send anything
+        let _idle = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (stream, from) = listener.accept().await.unwrap();
+        let handled = handle_tcp_connection(Box::new(stream), from, remote.handler_context());
+        assert!(handled.await.is_err());
+        assert_eq!(remote.limits.offenders()[0].strikes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_incoming_chat_is_published_as_event() {
+        use crate::chat::event::{ChatEvent, Verification};
+
+        let remote = crate::chat::Peer::new("Remote".to_string(), 9000);
+        let mut events = remote.events.subscribe();
+        let addr = serve(remote.clone(), 1).await;
+
+        let local = CryptoManager::new("local-id".to_string(), "Local".to_string());
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let signed = local.sign_message("hello", now).unwrap();
+        send_message(
+            &TcpTransport,
+            Codec::Cbor,
+            addr,
+            &Room::new("lobby"),
+            &NetworkMessage::SignedChat(signed),
+        )
+        .await
+        .unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(2), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        match event {
+            ChatEvent::MessageReceived {
+                room,
+                from_name,
+                content,
+                verification,
+                from_history,
+                ..
+            } => {
+                assert_eq!(room, "lobby");
+                assert_eq!(from_name, "Local");
+                assert_eq!(content, "hello");
+                assert_eq!(verification, Verification::Verified);
+                assert!(!from_history);
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Transport module: The connection layer the chat runs on.
+//!
+//! Everything that talks to other peers over connections (the listener, handshakes,
+//! broadcasts, history sync, gossip and peer exchange) goes through a `Transport` instead
+//! of calling `tokio::net` directly. `TcpTransport` is the real network; `sim::SimNetwork`
+//! is an in-process network with configurable latency, loss, partitions and reordering for
+//! deterministic tests. UDP announcements and heartbeats stay on real sockets: they are
+//! LAN broadcast mechanisms, and a transport brings its own `discovery` where they do not
+//! apply.
+
+use crate::chat::net::discovery::Discovery;
+use crate::network::interfaces;
+use async_trait::async_trait;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A byte stream to another peer. Shutting down the write half signals EOF to the other side.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {
+    /// The local address of the connection, i.e. the one the other side reached us on
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Connection for TcpStream {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::local_addr(self)
+    }
+}
+
+/// Accepts incoming connections
+#[async_trait]
+pub trait Listener: Send {
+    /// Wait for the next connection and the address it comes from
+    async fn accept(&mut self) -> io::Result<(Box<dyn Connection>, SocketAddr)>;
+}
+
+/// Opens and accepts connections between peers
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Open a connection to `addr`
+    async fn connect(&self, addr: SocketAddr) -> io::Result<Box<dyn Connection>>;
+
+    /// Listen for connections on `port`
+    async fn listen(&self, port: u16) -> io::Result<Box<dyn Listener>>;
+
+    /// Local addresses to advertise next to `primary`
+    fn other_addresses(&self, primary: IpAddr) -> Vec<IpAddr> {
+        interfaces::other_addresses(primary)
+    }
+
+    /// A discovery backend that finds peers on this transport, run next to the configured
+    /// ones. The LAN backends cover real networks, so only simulated ones provide one.
+    fn discovery(&self) -> Option<Box<dyn Discovery>> {
+        None
+    }
+}
+
+/// The real network, over TCP
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpTransport;
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn connect(&self, addr: SocketAddr) -> io::Result<Box<dyn Connection>> {
+        Ok(Box::new(TcpStream::connect(addr).await?))
+    }
+
+    async fn listen(&self, port: u16) -> io::Result<Box<dyn Listener>> {
+        let listener = match bind_dual_stack(port) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("[WARN] IPv6 listener unavailable ({e}), listening on IPv4 only");
+                TcpListener::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))).await?
+            }
+        };
+        Ok(Box::new(listener))
+    }
+}
+
+#[async_trait]
+impl Listener for TcpListener {
+    async fn accept(&mut self) -> io::Result<(Box<dyn Connection>, SocketAddr)> {
+        let (stream, addr) = TcpListener::accept(self).await?;
+        Ok((Box::new(stream), addr))
+    }
+}
+
+/// Listen on `[::]` accepting both IPv6 and IPv4 (as mapped addresses). Falls back to
+/// IPv4 only on hosts without IPv6.
+fn bind_dual_stack(port: u16) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_only_v6(false)?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
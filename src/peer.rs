@@ -6,8 +6,16 @@
 //! enum for different types of network messages.
 
 use serde::{Deserialize, Serialize};
-use std::net::IpAddr;
-use crate::crypto::{SignedMessage, threshold::{UpgradeProposal, UpgradeVote, PartialSignature}};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, SocketAddrV6};
+use crate::chat::clock::HlcTimestamp;
+use crate::chat::history::{HistoryCursor, HistoryMark};
+use crate::network::protocol::Hello;
+use crate::crypto::{SignedMessage, SignedPeerInfo, threshold::{Decision, UpgradeProposal, UpgradeVote, PartialSignature}};
+use crate::chat::roles::RoleGrant;
+
+/// Upper bound for the extra addresses a peer may advertise
+pub const MAX_PEER_ADDRESSES: usize = 8;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PeerInfo {
@@ -15,6 +23,14 @@ pub struct PeerInfo {
     pub name: String,
     pub ip: IpAddr,
     pub port: u16,
+    /// Other interface addresses the peer can be reached on, tried in order when `ip`
+    /// does not answer
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<IpAddr>,
+    /// Interface index for link-local IPv6 addresses, as seen from this host. Scope IDs
+    /// only mean something locally, so they are never sent or signed.
+    #[serde(default, skip_serializing)]
+    pub scope_id: u32,
 }
 
 impl PeerInfo {
@@ -26,6 +42,60 @@ impl PeerInfo {
             && self.port > 0
             && !self.ip.is_loopback()
             && !self.ip.is_multicast()
+            && !self.ip.is_unspecified()
+            && self.addresses.len() <= MAX_PEER_ADDRESSES
+            && self
+                .addresses
+                .iter()
+                .all(|ip| !ip.is_loopback() && !ip.is_multicast())
+    }
+
+    /// The primary address with the peer's port
+    pub fn socket_addr(&self) -> SocketAddr {
+        self.addr_for(self.ip)
+    }
+
+    /// Every advertised address with the peer's port, `ip` first
+    pub fn candidate_addrs(&self) -> Vec<SocketAddr> {
+        let mut addrs = vec![self.socket_addr()];
+        for ip in &self.addresses {
+            let addr = self.addr_for(*ip);
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+        addrs
+    }
+
+    /// Whether `ip` is one of the addresses this peer advertises
+    pub fn advertises(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.ip == ip || self.addresses.contains(&ip)
+    }
+
+    /// Link-local IPv6 addresses need the interface they were seen on to be reachable
+    fn addr_for(&self, ip: IpAddr) -> SocketAddr {
+        match ip {
+            IpAddr::V6(v6) if is_link_local(&ip) => {
+                SocketAddr::V6(SocketAddrV6::new(v6, self.port, 0, self.scope_id))
+            }
+            _ => SocketAddr::new(ip, self.port),
+        }
+    }
+}
+
+/// Whether `ip` is an IPv6 link-local (`fe80::/10`) address
+pub fn is_link_local(ip: &IpAddr) -> bool {
+    matches!(ip, IpAddr::V6(v6) if (v6.segments()[0] & 0xffc0) == 0xfe80)
+}
+
+/// The interface scope of a link-local IPv6 socket address, if any
+pub fn link_local_scope(addr: &SocketAddr) -> Option<u32> {
+    match addr {
+        SocketAddr::V6(v6) if is_link_local(&addr.ip()) && v6.scope_id() != 0 => {
+            Some(v6.scope_id())
+        }
+        _ => None,
     }
 }
 
@@ -36,14 +106,34 @@ pub struct Message {
     pub content: String,
     pub timestamp: u64,
     /// Optional cryptographic signature for message authenticity
+    #[serde(default, with = "serde_bytes")]
     pub signature: Option<Vec<u8>>,
     /// Optional public key of the signer
+    #[serde(default, with = "serde_bytes")]
     pub public_key: Option<Vec<u8>>,
+    /// Hybrid logical clock used for causal ordering (absent from older peers)
+    #[serde(default)]
+    pub clock: Option<HlcTimestamp>,
+}
+
+/// A message relayed hop by hop through the gossip layer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GossipEnvelope {
+    /// Unique id used to drop duplicates
+    pub message_id: String,
+    /// Peer that originated the payload
+    pub origin_id: String,
+    /// Peer that forwarded this copy (not forwarded back to it)
+    pub from_id: String,
+    /// Remaining number of hops
+    pub ttl: u8,
+    /// The relayed message, forwarded untouched
+    pub payload: Box<NetworkMessage>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NetworkMessage {
-    Discovery(PeerInfo),
+    Discovery(SignedPeerInfo),
     Chat(Message),
     Heartbeat(String), // peer_id
     Exit(String),      // peer_id
@@ -53,6 +143,7 @@ pub enum NetworkMessage {
     IdentityAnnouncement {
         peer_id: String,
         name: String,
+        #[serde(with = "serde_bytes")]
         public_key: Vec<u8>,
     },
     /// Upgrade proposal for secure-only messaging
@@ -61,6 +152,119 @@ pub enum NetworkMessage {
     UpgradeVote(UpgradeVote),
     /// Partial signature for threshold approval
     PartialSignature(PartialSignature),
+    /// Compact per-author summary of the sender's message history
+    HistorySummary {
+        peer_id: String,
+        marks: HashMap<String, HistoryMark>,
+    },
+    /// Request for messages after the given per-author cursors
+    HistoryRequest {
+        peer_id: String,
+        since: HashMap<String, HistoryCursor>,
+    },
+    /// Batch of signed messages answering a history request, with the cursors to request
+    /// next if the answer did not fit in one batch
+    HistoryResponse {
+        peer_id: String,
+        messages: Vec<SignedMessage>,
+        next: Option<HashMap<String, HistoryCursor>>,
+    },
+    /// Message relayed through the gossip layer
+    Gossip(GossipEnvelope),
+    /// Peer exchange: the sender's list of known peers
+    PeerExchange {
+        peer_id: String,
+        peers: Vec<PeerInfo>,
+    },
+    /// Answer to a discovery handshake from an incompatible peer
+    Rejected {
+        reason: String,
+    },
+    /// An approved expulsion or role change, shown to peers joining the room
+    Decision(Decision),
+    /// A role given by an owner or moderator of the room
+    RoleGrant(RoleGrant),
+    /// The new secret of a private room after an expulsion, sealed for the recipient
+    RoomRekey {
+        peer_id: String,
+        expulsion: Decision,
+        #[serde(with = "serde_bytes")]
+        sealed_secret: Vec<u8>,
+    },
+    /// A message type defined by an application embedding the library, handled by the
+    /// handler registered for `kind`
+    Custom {
+        peer_id: String,
+        kind: String,
+        payload: serde_json::Value,
+    },
+}
+
+impl NetworkMessage {
+    /// What handlers are registered for: the variant name, or the application's own kind
+    /// for custom messages
+    pub fn kind(&self) -> &str {
+        match self {
+            NetworkMessage::Discovery(_) => "Discovery",
+            NetworkMessage::Chat(_) => "Chat",
+            NetworkMessage::Heartbeat(_) => "Heartbeat",
+            NetworkMessage::Exit(_) => "Exit",
+            NetworkMessage::SignedChat(_) => "SignedChat",
+            NetworkMessage::IdentityAnnouncement { .. } => "IdentityAnnouncement",
+            NetworkMessage::UpgradeRequest(_) => "UpgradeRequest",
+            NetworkMessage::UpgradeVote(_) => "UpgradeVote",
+            NetworkMessage::PartialSignature(_) => "PartialSignature",
+            NetworkMessage::HistorySummary { .. } => "HistorySummary",
+            NetworkMessage::HistoryRequest { .. } => "HistoryRequest",
+            NetworkMessage::HistoryResponse { .. } => "HistoryResponse",
+            NetworkMessage::Gossip(_) => "Gossip",
+            NetworkMessage::PeerExchange { .. } => "PeerExchange",
+            NetworkMessage::Rejected { .. } => "Rejected",
+            NetworkMessage::Decision(_) => "Decision",
+            NetworkMessage::RoomRekey { .. } => "RoomRekey",
+            NetworkMessage::RoleGrant(_) => "RoleGrant",
+            NetworkMessage::Custom { kind, .. } => kind,
+        }
+    }
+
+    /// Peer id of the peer that sent the message, or relayed it for gossip envelopes.
+    /// Rejections carry no sender, and decisions and role grants are passed on by any member.
+    pub fn sender_id(&self) -> Option<&str> {
+        match self {
+            NetworkMessage::Discovery(signed) => Some(&signed.info.id),
+            NetworkMessage::Chat(message) => Some(&message.from_id),
+            NetworkMessage::Heartbeat(peer_id) | NetworkMessage::Exit(peer_id) => Some(peer_id),
+            NetworkMessage::SignedChat(signed) => Some(&signed.signer_id),
+            NetworkMessage::IdentityAnnouncement { peer_id, .. }
+            | NetworkMessage::HistorySummary { peer_id, .. }
+            | NetworkMessage::HistoryRequest { peer_id, .. }
+            | NetworkMessage::HistoryResponse { peer_id, .. }
+            | NetworkMessage::PeerExchange { peer_id, .. }
+            | NetworkMessage::RoomRekey { peer_id, .. }
+            | NetworkMessage::Custom { peer_id, .. } => Some(peer_id),
+            NetworkMessage::UpgradeRequest(proposal) => Some(&proposal.proposer_id),
+            NetworkMessage::UpgradeVote(vote) => Some(&vote.voter_id),
+            NetworkMessage::PartialSignature(signature) => Some(&signature.signer_id),
+            NetworkMessage::Gossip(envelope) => Some(&envelope.from_id),
+            NetworkMessage::Rejected { .. }
+            | NetworkMessage::Decision(_)
+            | NetworkMessage::RoleGrant(_) => None,
+        }
+    }
+}
+
+/// What is actually sent over TCP: a `NetworkMessage` tagged with the room it belongs to.
+/// Peers, proposals and history are all scoped to that room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomMessage {
+    pub room: String,
+    pub message: NetworkMessage,
+    /// Proof of knowledge of a private room's secret, sent with discovery handshakes
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_bytes")]
+    pub proof: Option<Vec<u8>>,
+    /// Protocol version and capabilities, sent with discovery handshakes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hello: Option<Hello>,
 }
 
 #[cfg(test)]
@@ -76,6 +280,8 @@ mod tests {
             name: "Alice".to_string(),
             ip: IpAddr::from_str("192.168.1.2").unwrap(),
             port: 9000,
+            addresses: Vec::new(),
+            scope_id: 0,
         };
         assert!(valid_peer.is_valid());
 
@@ -84,6 +290,8 @@ mod tests {
             name: "".to_string(),
             ip: IpAddr::from_str("127.0.0.1").unwrap(),
             port: 0,
+            addresses: Vec::new(),
+            scope_id: 0,
         };
         assert!(!invalid_peer.is_valid());
     }
@@ -96,10 +304,86 @@ mod tests {
             name: long_name,
             ip: IpAddr::from_str("10.0.0.1").unwrap(),
             port: 1234,
+            addresses: Vec::new(),
+            scope_id: 0,
         };
         assert!(!p1.is_valid());
     }
 
+    #[test]
+    fn test_candidate_addrs() {
+        let peer = PeerInfo {
+            id: "id".to_string(),
+            name: "Multi".to_string(),
+            ip: IpAddr::from_str("192.168.1.2").unwrap(),
+            port: 9000,
+            addresses: vec![
+                IpAddr::from_str("10.0.0.2").unwrap(),
+                IpAddr::from_str("192.168.1.2").unwrap(),
+            ],
+            scope_id: 0,
+        };
+        assert!(peer.is_valid());
+        let addrs = peer.candidate_addrs();
+        assert_eq!(addrs.len(), 2);
+        assert_eq!(addrs[0], "192.168.1.2:9000".parse().unwrap());
+        assert_eq!(addrs[1], "10.0.0.2:9000".parse().unwrap());
+        assert!(peer.advertises(IpAddr::from_str("10.0.0.2").unwrap()));
+
+        let loopback_candidate = PeerInfo {
+            addresses: vec![IpAddr::from_str("127.0.0.1").unwrap()],
+            ..peer
+        };
+        assert!(!loopback_candidate.is_valid());
+    }
+
+    #[test]
+    fn test_ipv6_peer_info() {
+        let global = PeerInfo {
+            id: "id".to_string(),
+            name: "Six".to_string(),
+            ip: IpAddr::from_str("2001:db8::2").unwrap(),
+            port: 9000,
+            addresses: Vec::new(),
+            scope_id: 0,
+        };
+        assert!(global.is_valid());
+        assert_eq!(global.socket_addr(), "[2001:db8::2]:9000".parse().unwrap());
+
+        // Link-local addresses are valid and reached through the interface they were seen on
+        let link_local = PeerInfo {
+            ip: IpAddr::from_str("fe80::2").unwrap(),
+            scope_id: 3,
+            ..global.clone()
+        };
+        assert!(link_local.is_valid());
+        assert_eq!(link_local.socket_addr(), "[fe80::2%3]:9000".parse().unwrap());
+        assert_eq!(link_local_scope(&link_local.socket_addr()), Some(3));
+        assert_eq!(link_local_scope(&global.socket_addr()), None);
+
+        let loopback = PeerInfo {
+            ip: IpAddr::from_str("::1").unwrap(),
+            ..global.clone()
+        };
+        assert!(!loopback.is_valid());
+        let unspecified = PeerInfo {
+            ip: IpAddr::from_str("::").unwrap(),
+            ..global.clone()
+        };
+        assert!(!unspecified.is_valid());
+
+        // Scope IDs are local to this host and never sent
+        let json = serde_json::to_string(&link_local).unwrap();
+        assert!(!json.contains("scope_id"));
+
+        // IPv4 peers reaching a dual-stack listener show up as mapped addresses
+        let v4 = PeerInfo {
+            ip: IpAddr::from_str("192.168.1.2").unwrap(),
+            ..global
+        };
+        assert!(v4.advertises(IpAddr::from_str("::ffff:192.168.1.2").unwrap()));
+    }
+
     #[test]
     fn test_message_content() {
         let msg = Message {
@@ -109,6 +393,7 @@ mod tests {
             timestamp: 1234567890,
             signature: None,
             public_key: None,
+            clock: None,
         };
         assert_eq!(msg.content, "Hello, world!");
         assert!(!msg.content.is_empty());
@@ -123,6 +408,7 @@ mod tests {
             timestamp: 1234567890,
             signature: None,
             public_key: None,
+            clock: None,
         };
         assert!(msg.content.is_empty());
     }
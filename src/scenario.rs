@@ -0,0 +1,743 @@
+//! Scenario module: Scripted multi-peer tests on the simulated network.
+//!
+//! A scenario is a JSON file naming the peers to start and the steps to play: send
+//! messages, propose and vote, partition and heal the network, kill and restart peers, and
+//! expect events. Every peer runs headless in-process on its own `SimNetwork` host
+//! (`10.0.0.1`, `10.0.0.2`, ... in the order listed) and discovers the others in memory.
+//! `expect` waits up to the timeout for a matching event on one peer, skipping the events
+//! before it, so expectations on one peer are checked in order; `expect_none` fails if a
+//! matching event shows up within the given time. Proposals get a label when created so
+//! later votes and expectations can refer to them.
+//!
+//! ```json
+//! {
+//!   "description": "Bob receives Alice's message",
+//!   "peers": ["Alice", "Bob"],
+//!   "steps": [
+//!     { "wait_for_peers": { "count": 1 } },
+//!     { "send": { "peer": "Alice", "content": "hello" } },
+//!     { "expect": { "peer": "Bob", "event": "message", "from": "Alice", "content": "hello" } }
+//!   ]
+//! }
+//! ```
+//!
+//! `tests/scenarios.rs` runs every file in `tests/scenarios/`.
+
+use crate::chat::event::{ChatEvent, LeaveReason, Verification};
+use crate::chat::{PeerBuilder, PeerHandle};
+use crate::crypto::identity::Identity;
+use crate::network::sim::{SimConfig, SimNetwork};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+
+/// Port every simulated peer listens on; each peer has its own address
+const PORT: u16 = 9000;
+
+#[derive(Error, Debug)]
+pub enum ScenarioError {
+    #[error("Invalid scenario: {0}")]
+    Invalid(String),
+    #[error("Step {step} {action} failed: {reason}")]
+    Step {
+        step: usize,
+        action: String,
+        reason: String,
+    },
+}
+
+/// A scripted run of several peers
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    /// What the scenario checks
+    #[serde(default)]
+    pub description: String,
+    /// Names of the peers to start
+    pub peers: Vec<String>,
+    /// Network conditions at the start
+    #[serde(default)]
+    pub network: NetworkSettings,
+    /// Default time an `expect` step waits, in milliseconds
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    pub steps: Vec<Step>,
+}
+
+fn default_timeout_ms() -> u64 {
+    5000
+}
+
+/// Latency, jitter and loss of the simulated network
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    pub latency_ms: u64,
+    pub jitter_ms: u64,
+    /// Probability (0 to 1) that a connection is lost
+    pub loss: f64,
+    pub seed: u64,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        let config = SimConfig::default();
+        Self {
+            latency_ms: config.latency.as_millis() as u64,
+            jitter_ms: config.jitter.as_millis() as u64,
+            loss: config.loss,
+            seed: config.seed,
+        }
+    }
+}
+
+impl From<&NetworkSettings> for SimConfig {
+    fn from(settings: &NetworkSettings) -> Self {
+        SimConfig {
+            latency: Duration::from_millis(settings.latency_ms),
+            jitter: Duration::from_millis(settings.jitter_ms),
+            loss: settings.loss,
+            seed: settings.seed,
+        }
+    }
+}
+
+/// One action or check of a scenario
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// Send a message to the peer's current room, signed unless `unsigned`
+    Send {
+        peer: String,
+        content: String,
+        #[serde(default)]
+        unsigned: bool,
+    },
+    /// Propose the secure-only upgrade, remembering its id under `label`
+    Propose {
+        peer: String,
+        #[serde(default)]
+        description: String,
+        label: String,
+    },
+    /// Vote on the proposal labelled `proposal`; the peer must have received it
+    Vote {
+        peer: String,
+        proposal: String,
+        #[serde(default = "default_approve")]
+        approve: bool,
+    },
+    /// Split the network into groups of peers; unlisted peers form one more group
+    Partition(Vec<Vec<String>>),
+    /// Reconnect every partition
+    Heal,
+    /// Change latency, jitter and loss
+    Network(NetworkSettings),
+    /// Stop a peer without saying goodbye, like a crash
+    Kill(String),
+    /// Start a killed peer again, with the same identity and address
+    Restart(String),
+    /// Wait until `peer` (or every running peer) knows at least `count` peers
+    WaitForPeers {
+        peer: Option<String>,
+        count: usize,
+        timeout_ms: Option<u64>,
+    },
+    SleepMs(u64),
+    /// Wait for an event on a peer
+    Expect(Expectation),
+    /// Fail if a matching event shows up on a peer within `within_ms`
+    ExpectNone {
+        #[serde(flatten)]
+        expectation: Expectation,
+        within_ms: u64,
+    },
+}
+
+fn default_approve() -> bool {
+    true
+}
+
+/// An event a peer should observe
+#[derive(Debug, Clone, Deserialize)]
+pub struct Expectation {
+    pub peer: String,
+    /// Room the event happened in; any room when not given
+    pub room: Option<String>,
+    /// Overrides the scenario's `timeout_ms`
+    pub timeout_ms: Option<u64>,
+    #[serde(flatten)]
+    pub event: ExpectedEvent,
+}
+
+/// A `ChatEvent` pattern; fields that are not given match anything
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ExpectedEvent {
+    Message {
+        from: Option<String>,
+        content: Option<String>,
+        /// Whether the signature checked out
+        verified: Option<bool>,
+        /// Whether it came through history sync
+        from_history: Option<bool>,
+    },
+    HistorySynced {
+        count: Option<usize>,
+    },
+    PeerJoined {
+        name: Option<String>,
+    },
+    PeerLeft {
+        name: Option<String>,
+        reason: Option<LeaveReason>,
+    },
+    ProposalCreated {
+        /// Label given in the `propose` step
+        proposal: Option<String>,
+        proposer: Option<String>,
+    },
+    VoteCast {
+        proposal: Option<String>,
+        voter: Option<String>,
+        approved: Option<bool>,
+    },
+    ModeChanged {
+        secure_only: Option<bool>,
+    },
+    /// A message to the peer named `to` could not be delivered
+    DeliveryFailed {
+        to: Option<String>,
+    },
+}
+
+/// Whether `actual` is what was `expected`, if anything was
+fn field<T: PartialEq + ?Sized>(expected: Option<&T>, actual: &T) -> bool {
+    expected.is_none_or(|expected| expected == actual)
+}
+
+impl ExpectedEvent {
+    /// Whether `event` matches; `proposals` maps labels to proposal ids
+    fn matches(&self, event: &ChatEvent, proposals: &HashMap<String, String>) -> bool {
+        let proposal_is = |label: &Option<String>, id: &str| {
+            label
+                .as_ref()
+                .is_none_or(|label| proposals.get(label).is_some_and(|known| known == id))
+        };
+        match (self, event) {
+            (
+                ExpectedEvent::Message {
+                    from,
+                    content,
+                    verified,
+                    from_history,
+                },
+                ChatEvent::MessageReceived {
+                    from_name,
+                    content: actual_content,
+                    verification,
+                    from_history: actual_from_history,
+                    ..
+                },
+            ) => {
+                field(from.as_deref(), from_name.as_str())
+                    && field(content.as_deref(), actual_content.as_str())
+                    && field(
+                        verified.as_ref(),
+                        &(*verification == Verification::Verified),
+                    )
+                    && field(from_history.as_ref(), actual_from_history)
+            }
+            (ExpectedEvent::HistorySynced { count }, ChatEvent::HistorySynced { count: n, .. }) => {
+                field(count.as_ref(), n)
+            }
+            (ExpectedEvent::PeerJoined { name }, ChatEvent::PeerJoined { peer, .. }) => {
+                field(name.as_deref(), peer.name.as_str())
+            }
+            (
+                ExpectedEvent::PeerLeft { name, reason },
+                ChatEvent::PeerLeft {
+                    peer,
+                    reason: actual_reason,
+                    ..
+                },
+            ) => {
+                field(name.as_deref(), peer.name.as_str()) && field(reason.as_ref(), actual_reason)
+            }
+            (
+                ExpectedEvent::ProposalCreated { proposal, proposer },
+                ChatEvent::ProposalCreated {
+                    proposal: actual, ..
+                },
+            ) => {
+                proposal_is(proposal, &actual.proposal_id)
+                    && field(proposer.as_deref(), actual.proposer_name.as_str())
+            }
+            (
+                ExpectedEvent::VoteCast {
+                    proposal,
+                    voter,
+                    approved,
+                },
+                ChatEvent::VoteCast { vote, .. },
+            ) => {
+                proposal_is(proposal, &vote.proposal_id)
+                    && field(voter.as_deref(), vote.voter_name.as_str())
+                    && field(approved.as_ref(), &vote.approved)
+            }
+            (
+                ExpectedEvent::ModeChanged { secure_only },
+                ChatEvent::ModeChanged {
+                    secure_only: actual,
+                    ..
+                },
+            ) => field(secure_only.as_ref(), actual),
+            (ExpectedEvent::DeliveryFailed { to }, ChatEvent::DeliveryFailed { peer, .. }) => {
+                field(to.as_deref(), peer.name.as_str())
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Expectation {
+    fn matches(&self, event: &ChatEvent, proposals: &HashMap<String, String>) -> bool {
+        field(self.room.as_deref(), event.room()) && self.event.matches(event, proposals)
+    }
+}
+
+impl Scenario {
+    /// Load a scenario file
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        let invalid =
+            |e: &dyn std::fmt::Display| ScenarioError::Invalid(format!("{}: {e}", path.display()));
+        let contents = std::fs::read_to_string(path).map_err(|e| invalid(&e))?;
+        let scenario: Scenario = serde_json::from_str(&contents).map_err(|e| invalid(&e))?;
+        scenario.validate().map_err(|e| invalid(&e))?;
+        Ok(scenario)
+    }
+
+    /// Check that peer names are usable and every step refers to a listed peer
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        let invalid = |reason: String| Err(ScenarioError::Invalid(reason));
+        if self.peers.len() > 254 {
+            return invalid(format!("{} peers, at most 254 fit", self.peers.len()));
+        }
+        for (i, name) in self.peers.iter().enumerate() {
+            if name.trim() != name || name.is_empty() || name.len() > 128 {
+                return invalid(format!("invalid peer name {name:?}"));
+            }
+            if self.peers[..i].contains(name) {
+                return invalid(format!("peer {name} is listed twice"));
+            }
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            for name in step.peers() {
+                if !self.peers.contains(name) {
+                    return invalid(format!("step {} refers to unknown peer {name}", i + 1));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the peers, play every step and shut the peers down. Stops at the first step
+    /// that fails.
+    pub async fn run(&self) -> Result<(), ScenarioError> {
+        self.validate()?;
+        let mut harness = Harness::new(self);
+        for name in &self.peers {
+            harness
+                .start(name)
+                .map_err(|e| ScenarioError::Invalid(format!("cannot start {name}: {e}")))?;
+        }
+        let mut result = Ok(());
+        for (i, step) in self.steps.iter().enumerate() {
+            if let Err(reason) = harness.play(step).await {
+                result = Err(ScenarioError::Step {
+                    step: i + 1,
+                    action: step.action().to_string(),
+                    reason,
+                });
+                break;
+            }
+        }
+        harness.shutdown().await;
+        result
+    }
+}
+
+impl Step {
+    /// Name of the step as written in the file
+    fn action(&self) -> &'static str {
+        match self {
+            Step::Send { .. } => "send",
+            Step::Propose { .. } => "propose",
+            Step::Vote { .. } => "vote",
+            Step::Partition(_) => "partition",
+            Step::Heal => "heal",
+            Step::Network(_) => "network",
+            Step::Kill(_) => "kill",
+            Step::Restart(_) => "restart",
+            Step::WaitForPeers { .. } => "wait_for_peers",
+            Step::SleepMs(_) => "sleep_ms",
+            Step::Expect(_) => "expect",
+            Step::ExpectNone { .. } => "expect_none",
+        }
+    }
+
+    /// Peers the step refers to
+    fn peers(&self) -> Vec<&String> {
+        match self {
+            Step::Send { peer, .. } | Step::Propose { peer, .. } | Step::Vote { peer, .. } => {
+                vec![peer]
+            }
+            Step::Kill(peer) | Step::Restart(peer) => vec![peer],
+            Step::Partition(groups) => groups.iter().flatten().collect(),
+            Step::WaitForPeers { peer, .. } => peer.iter().collect(),
+            Step::Expect(expectation) | Step::ExpectNone { expectation, .. } => {
+                vec![&expectation.peer]
+            }
+            Step::Heal | Step::Network(_) | Step::SleepMs(_) => Vec::new(),
+        }
+    }
+}
+
+/// A scenario peer: its identity and address survive a restart
+struct Node {
+    name: String,
+    ip: IpAddr,
+    identity: Identity,
+    running: Option<(PeerHandle, Receiver<ChatEvent>)>,
+}
+
+/// The running peers of a scenario
+struct Harness {
+    network: SimNetwork,
+    nodes: Vec<Node>,
+    /// Proposal ids by label
+    proposals: HashMap<String, String>,
+    timeout: Duration,
+}
+
+impl Harness {
+    fn new(scenario: &Scenario) -> Self {
+        let nodes = scenario
+            .peers
+            .iter()
+            .zip(1u8..)
+            .map(|(name, host)| Node {
+                name: name.clone(),
+                ip: IpAddr::from([10, 0, 0, host]),
+                identity: Identity::generate(),
+                running: None,
+            })
+            .collect();
+        Self {
+            network: SimNetwork::new(SimConfig::from(&scenario.network)),
+            nodes,
+            proposals: HashMap::new(),
+            timeout: Duration::from_millis(scenario.timeout_ms),
+        }
+    }
+
+    fn node(&mut self, name: &str) -> Result<&mut Node, String> {
+        self.nodes
+            .iter_mut()
+            .find(|node| node.name == name)
+            .ok_or_else(|| format!("unknown peer {name}"))
+    }
+
+    fn handle(&mut self, name: &str) -> Result<&PeerHandle, String> {
+        match &self.node(name)?.running {
+            Some((handle, _)) => Ok(handle),
+            None => Err(format!("{name} is not running")),
+        }
+    }
+
+    fn ip(&self, name: &str) -> Result<IpAddr, String> {
+        self.nodes
+            .iter()
+            .find(|node| node.name == name)
+            .map(|node| node.ip)
+            .ok_or_else(|| format!("unknown peer {name}"))
+    }
+
+    fn start(&mut self, name: &str) -> Result<(), String> {
+        let network = self.network.clone();
+        let node = self.node(name)?;
+        if node.running.is_some() {
+            return Err(format!("{name} is already running"));
+        }
+        let handle = PeerBuilder::new(name)
+            .port(PORT)
+            .identity(node.identity.clone())
+            .transport(network.node(node.ip))
+            .discovery([])
+            .start()
+            .map_err(|e| e.to_string())?;
+        let events = handle.subscribe();
+        node.running = Some((handle, events));
+        Ok(())
+    }
+
+    async fn play(&mut self, step: &Step) -> Result<(), String> {
+        match step {
+            Step::Send {
+                peer,
+                content,
+                unsigned,
+            } => {
+                let handle = self.handle(peer)?;
+                let sent = if *unsigned {
+                    handle.peer().broadcast_unsigned_message(content).await
+                } else {
+                    handle.send(content).await
+                };
+                sent.map_err(|e| e.to_string())
+            }
+            Step::Propose {
+                peer,
+                description,
+                label,
+            } => {
+                let id = self
+                    .handle(peer)?
+                    .peer()
+                    .propose_secure_upgrade(description)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                self.proposals.insert(label.clone(), id);
+                Ok(())
+            }
+            Step::Vote {
+                peer,
+                proposal,
+                approve,
+            } => {
+                let id = self
+                    .proposals
+                    .get(proposal)
+                    .cloned()
+                    .ok_or_else(|| format!("no proposal labelled {proposal}"))?;
+                self.handle(peer)?
+                    .peer()
+                    .vote_on_proposal(&id, *approve)
+                    .await
+                    .map_err(|e| e.to_string())
+            }
+            Step::Partition(groups) => {
+                let groups = groups
+                    .iter()
+                    .map(|group| group.iter().map(|name| self.ip(name)).collect())
+                    .collect::<Result<Vec<Vec<IpAddr>>, String>>()?;
+                let groups: Vec<&[IpAddr]> = groups.iter().map(Vec::as_slice).collect();
+                self.network.partition(&groups);
+                Ok(())
+            }
+            Step::Heal => {
+                self.network.heal();
+                Ok(())
+            }
+            Step::Network(settings) => {
+                self.network.set_config(SimConfig::from(settings));
+                Ok(())
+            }
+            Step::Kill(peer) => {
+                let (handle, _) = self
+                    .node(peer)?
+                    .running
+                    .take()
+                    .ok_or_else(|| format!("{peer} is not running"))?;
+                handle.kill().await.map_err(|e| e.to_string())
+            }
+            Step::Restart(peer) => self.start(peer),
+            Step::WaitForPeers {
+                peer,
+                count,
+                timeout_ms,
+            } => {
+                let timeout = timeout_ms.map_or(self.timeout, Duration::from_millis);
+                self.wait_for_peers(peer.as_deref(), *count, timeout).await
+            }
+            Step::SleepMs(ms) => {
+                tokio::time::sleep(Duration::from_millis(*ms)).await;
+                Ok(())
+            }
+            Step::Expect(expectation) => {
+                let timeout = expectation
+                    .timeout_ms
+                    .map_or(self.timeout, Duration::from_millis);
+                match self.next_match(expectation, timeout).await? {
+                    true => Ok(()),
+                    false => Err(format!(
+                        "{} saw no {:?} within {timeout:?}",
+                        expectation.peer, expectation.event
+                    )),
+                }
+            }
+            Step::ExpectNone {
+                expectation,
+                within_ms,
+            } => {
+                let within = Duration::from_millis(*within_ms);
+                match self.next_match(expectation, within).await? {
+                    true => Err(format!("{} saw {:?}", expectation.peer, expectation.event)),
+                    false => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Consume events of the expected peer until one matches or `timeout` passes
+    async fn next_match(
+        &mut self,
+        expectation: &Expectation,
+        timeout: Duration,
+    ) -> Result<bool, String> {
+        let peer = &expectation.peer;
+        let node = self
+            .nodes
+            .iter_mut()
+            .find(|node| &node.name == peer)
+            .ok_or_else(|| format!("unknown peer {peer}"))?;
+        let (_, events) = node
+            .running
+            .as_mut()
+            .ok_or_else(|| format!("{peer} is not running"))?;
+        let proposals = &self.proposals;
+        let found = tokio::time::timeout(timeout, async {
+            loop {
+                match events.recv().await {
+                    Ok(event) if expectation.matches(&event, proposals) => return Ok(()),
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return Err(format!("{peer} stopped")),
+                }
+            }
+        })
+        .await;
+        match found {
+            Ok(result) => result.map(|()| true),
+            Err(_) => Ok(false),
+        }
+    }
+
+    async fn wait_for_peers(
+        &mut self,
+        peer: Option<&str>,
+        count: usize,
+        timeout: Duration,
+    ) -> Result<(), String> {
+        let handles: Vec<&PeerHandle> = self
+            .nodes
+            .iter()
+            .filter(|node| peer.is_none_or(|peer| node.name == peer))
+            .filter_map(|node| node.running.as_ref().map(|(handle, _)| handle))
+            .collect();
+        if let (Some(peer), true) = (peer, handles.is_empty()) {
+            return Err(format!("{peer} is not running"));
+        }
+        let waited = tokio::time::timeout(timeout, async {
+            for handle in &handles {
+                while handle.peers().await.len() < count {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        })
+        .await;
+        waited.map_err(|_| format!("peers did not find {count} peers within {timeout:?}"))
+    }
+
+    async fn shutdown(self) {
+        for node in self.nodes {
+            if let Some((handle, _)) = node.running {
+                if let Err(e) = handle.shutdown().await {
+                    eprintln!("{} did not shut down: {e}", node.name);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Scenario {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_parse_scenario() {
+        let scenario = parse(
+            r#"{
+                "peers": ["Alice", "Bob"],
+                "network": { "loss": 0.1 },
+                "steps": [
+                    { "send": { "peer": "Alice", "content": "hi" } },
+                    { "partition": [["Alice"], ["Bob"]] },
+                    "heal",
+                    { "kill": "Bob" },
+                    { "expect": { "peer": "Bob", "event": "peer_left", "reason": "lost" } },
+                    { "expect_none": { "peer": "Bob", "event": "message", "within_ms": 100 } }
+                ]
+            }"#,
+        );
+        assert_eq!(scenario.timeout_ms, 5000);
+        assert_eq!(scenario.network.latency_ms, 1);
+        assert_eq!(scenario.network.loss, 0.1);
+        assert!(matches!(scenario.steps[2], Step::Heal));
+        match &scenario.steps[4] {
+            Step::Expect(Expectation {
+                event: ExpectedEvent::PeerLeft { name, reason },
+                ..
+            }) => {
+                assert!(name.is_none());
+                assert_eq!(*reason, Some(LeaveReason::Lost));
+            }
+            step => panic!("unexpected step {step:?}"),
+        }
+        assert!(matches!(
+            scenario.steps[5],
+            Step::ExpectNone { within_ms: 100, .. }
+        ));
+        scenario.validate().unwrap();
+
+        let unknown = parse(r#"{ "peers": ["Alice"], "steps": [{ "kill": "Bob" }] }"#);
+        assert!(unknown.validate().is_err());
+        let twice = parse(r#"{ "peers": ["Alice", "Alice"], "steps": [] }"#);
+        assert!(twice.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_expectation_names_the_step() {
+        let scenario = parse(
+            r#"{
+                "peers": ["Alice", "Bob"],
+                "steps": [
+                    { "wait_for_peers": { "count": 1 } },
+                    { "send": { "peer": "Alice", "content": "hi" } },
+                    { "expect": { "peer": "Bob", "event": "message", "content": "hi" } },
+                    {
+                        "expect": {
+                            "peer": "Bob",
+                            "event": "message",
+                            "content": "bye",
+                            "timeout_ms": 500
+                        }
+                    }
+                ]
+            }"#,
+        );
+        match scenario.run().await {
+            Err(ScenarioError::Step { step, action, .. }) => {
+                assert_eq!((step, action.as_str()), (4, "expect"));
+            }
+            result => panic!("unexpected result {result:?}"),
+        }
+    }
+}
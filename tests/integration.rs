@@ -1,133 +1,563 @@
 //! Integration tests for end-to-end peer discovery and messaging in the P2P chat app.
+//!
+//! Peers run in-process on a simulated network, so the tests need no free ports and can
+//! inject latency, loss, reordering and partitions.
 
-use std::io::{BufRead, BufReader};
-use std::process::{Child, Command, Stdio};
-use std::thread;
+use p2p_chat::chat::event::{ChatEvent, Verification};
+use p2p_chat::chat::roles::Role;
+use p2p_chat::chat::{PeerBuilder, PeerHandle};
+use p2p_chat::crypto::invite::RoomSecret;
+use p2p_chat::network::sim::{SimConfig, SimNetwork};
+use p2p_chat::peer::NetworkMessage;
+use std::collections::HashSet;
+use std::net::IpAddr;
 use std::time::Duration;
+use tokio::sync::broadcast::Receiver;
 
-/// Helper to spawn a chat process with given name and port.
-fn spawn_peer(name: &str, port: u16) -> Child {
-    Command::new("./target/release/p2p-chat")
-        .arg("start")
-        .arg("--name")
-        .arg(name)
-        .arg("--port")
-        .arg(port.to_string())
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("Failed to start peer process")
-}
-
-#[test]
-fn test_peer_discovery_and_message() {
-    // Start two peers
-    let mut alice = spawn_peer("Alice", 9000);
-    let mut bob = spawn_peer("Bob", 9001);
-
-    // Wait for discovery
-    thread::sleep(Duration::from_millis(200));
-
-    // Non-blocking read with timeout for Alice's stdout and stderr
-    let mut found = false;
-    let mut all_lines = Vec::new();
-    let start = std::time::Instant::now();
-
-    // Move stdout and stderr out of alice so we can own them
-    let alice_stdout = alice.stdout.take().expect("No stdout");
-    let alice_stderr = alice.stderr.take().expect("No stderr");
-    let mut stdout_reader = BufReader::new(alice_stdout);
-    let mut stderr_reader = BufReader::new(alice_stderr);
-
-    while start.elapsed() < Duration::from_secs(5) {
-        // Check stdout
-        let mut line = String::new();
-        match stdout_reader.read_line(&mut line) {
-            Ok(0) => {} // EOF
-            Ok(_) => {
-                all_lines.push(format!("stdout: {}", line.trim_end()));
-                if line.contains("Discovered peer") || line.contains("peer") {
-                    found = true;
-                    break;
-                }
+/// How long a test waits for an expected event
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn ip(last: u8) -> IpAddr {
+    IpAddr::from([10, 0, 0, last])
+}
+
+/// Start a peer named `name` on the host `ip` of `network`, discovering the others in memory
+fn spawn_peer(network: &SimNetwork, name: &str, host: u8) -> (PeerHandle, Receiver<ChatEvent>) {
+    let handle = PeerBuilder::new(name)
+        .port(9000)
+        .transport(network.node(ip(host)))
+        .discovery([])
+        .start()
+        .expect("Failed to start peer");
+    let events = handle.subscribe();
+    (handle, events)
+}
+
+/// Wait for the first event matching `predicate`, skipping the others
+async fn expect_event<T>(
+    events: &mut Receiver<ChatEvent>,
+    mut predicate: impl FnMut(&ChatEvent) -> Option<T>,
+) -> T {
+    tokio::time::timeout(TIMEOUT, async {
+        loop {
+            let event = events.recv().await.expect("Event channel closed");
+            if let Some(found) = predicate(&event) {
+                return found;
             }
-            Err(_) => {}
         }
-        // Check stderr
-        let mut err_line = String::new();
-        match stderr_reader.read_line(&mut err_line) {
-            Ok(0) => {} // EOF
-            Ok(_) => {
-                all_lines.push(format!("stderr: {}", err_line.trim_end()));
-                if err_line.contains("Discovered peer") || err_line.contains("peer") {
-                    found = true;
-                    break;
-                }
+    })
+    .await
+    .expect("Timed out waiting for an event")
+}
+
+/// Wait until every handle knows `count` peers
+async fn wait_for_mesh(handles: &[PeerHandle], count: usize) {
+    tokio::time::timeout(TIMEOUT, async {
+        for handle in handles {
+            while handle.peers().await.len() < count {
+                tokio::time::sleep(Duration::from_millis(10)).await;
             }
-            Err(_) => {}
-        }
-        if found {
-            break;
         }
-        thread::sleep(Duration::from_millis(50));
+    })
+    .await
+    .expect("Peers did not discover each other");
+}
+
+fn live_message(event: &ChatEvent) -> Option<(String, String, Verification)> {
+    match event {
+        ChatEvent::MessageReceived {
+            from_name,
+            content,
+            verification,
+            from_history: false,
+            ..
+        } => Some((from_name.clone(), content.clone(), verification.clone())),
+        _ => None,
+    }
+}
+
+async fn shutdown_all(handles: impl IntoIterator<Item = PeerHandle>) {
+    for handle in handles {
+        handle.shutdown().await.expect("Peer did not shut down");
+    }
+}
+
+#[tokio::test]
+async fn test_peer_discovery_and_message() {
+    let network = SimNetwork::default();
+    let (alice, mut alice_events) = spawn_peer(&network, "Alice", 1);
+    let (bob, mut bob_events) = spawn_peer(&network, "Bob", 2);
+
+    let joined = expect_event(&mut alice_events, |event| match event {
+        ChatEvent::PeerJoined { peer, .. } => Some(peer.clone()),
+        _ => None,
+    })
+    .await;
+    assert_eq!(joined.name, "Bob");
+    assert_eq!(joined.ip, ip(2));
+    let handles = [alice, bob];
+    wait_for_mesh(&handles, 1).await;
+
+    handles[0].send("Hello Bob").await.unwrap();
+    let (from, content, verification) = expect_event(&mut bob_events, live_message).await;
+    assert_eq!(from, "Alice");
+    assert_eq!(content, "Hello Bob");
+    assert!(matches!(verification, Verification::Verified));
+    shutdown_all(handles).await;
+}
+
+#[tokio::test]
+async fn test_maximum_peer_discovery_limit() {
+    let network = SimNetwork::default();
+    let mut handles = Vec::new();
+    let mut receivers = Vec::new();
+    for i in 0..6u8 {
+        let (handle, events) = spawn_peer(&network, &format!("Peer-{i}"), i + 1);
+        handles.push(handle);
+        receivers.push(events);
+    }
+    wait_for_mesh(&handles, 5).await;
+    for handle in &handles {
+        let names: HashSet<_> = handle.peers().await.into_iter().map(|p| p.name).collect();
+        assert_eq!(names.len(), 5);
+        assert!(!names.contains(&handle.peer().name));
     }
-    for l in &all_lines {
-        println!("{}", l);
+
+    handles[0].send("hello everyone").await.unwrap();
+    for events in &mut receivers[1..] {
+        let (from, content, _) = expect_event(events, live_message).await;
+        assert_eq!(
+            (from.as_str(), content.as_str()),
+            ("Peer-0", "hello everyone")
+        );
+    }
+    shutdown_all(handles).await;
+}
+
+#[tokio::test]
+async fn test_partition_blocks_delivery_until_healed() {
+    let network = SimNetwork::default();
+    let (alice, mut alice_events) = spawn_peer(&network, "Alice", 1);
+    let (bob, mut bob_events) = spawn_peer(&network, "Bob", 2);
+    let handles = [alice, bob];
+    wait_for_mesh(&handles, 1).await;
+
+    network.partition(&[&[ip(1)], &[ip(2)]]);
+    handles[0].send("lost in the partition").await.unwrap();
+    let failed = expect_event(&mut alice_events, |event| match event {
+        ChatEvent::DeliveryFailed { peer, .. } => Some(peer.name.clone()),
+        _ => None,
+    })
+    .await;
+    assert_eq!(failed, "Bob");
+
+    network.heal();
+    handles[0].send("after the partition").await.unwrap();
+    let (_, content, _) = expect_event(&mut bob_events, live_message).await;
+    assert_eq!(content, "after the partition");
+    shutdown_all(handles).await;
+}
+
+#[tokio::test]
+async fn test_lossy_network_reports_every_lost_message() {
+    let network = SimNetwork::default();
+    let (alice, mut alice_events) = spawn_peer(&network, "Alice", 1);
+    let (bob, mut bob_events) = spawn_peer(&network, "Bob", 2);
+    let handles = [alice, bob];
+    wait_for_mesh(&handles, 1).await;
+
+    network.set_config(SimConfig {
+        loss: 0.3,
+        seed: 42,
+        ..SimConfig::default()
+    });
+    const SENT: usize = 20;
+    for i in 0..SENT {
+        handles[0].send(&format!("message {i}")).await.unwrap();
+    }
+    network.set_config(SimConfig::default());
+
+    let mut failed = 0;
+    while let Ok(event) = alice_events.try_recv() {
+        if matches!(event, ChatEvent::DeliveryFailed { .. }) {
+            failed += 1;
+        }
     }
     assert!(
-        found,
-        "Alice did not discover any peers. Output:\n{}",
-        all_lines.join("\n")
+        failed > 0 && failed < SENT,
+        "{failed} of {SENT} messages lost"
     );
+    let mut received = HashSet::new();
+    for _ in 0..SENT - failed {
+        received.insert(expect_event(&mut bob_events, live_message).await.1);
+    }
+    assert_eq!(received.len(), SENT - failed);
+    shutdown_all(handles).await;
+}
 
-    // Clean up
-    let _ = alice.kill();
-    let _ = alice.wait();
-    let _ = bob.kill();
-    let _ = bob.wait();
-}
-
-#[test]
-fn test_maximum_peer_discovery_limit() {
-    // Set a reasonable max for test
-    let max_peers = 5;
-    let mut children = vec![];
-    for i in 0..max_peers + 1 {
-        let name = format!("Peer-{i}");
-        let port = 9100 + i as u16;
-        children.push(spawn_peer(&name, port));
-    }
-    thread::sleep(Duration::from_secs(2));
-    // Check that all but one peer are discovered (if app has a max, adjust assertion)
-    // For now, just check that processes started and ran
-    for child in &mut children {
-        assert!(
-            child.try_wait().unwrap().is_none(),
-            "Peer process exited early"
-        );
+#[tokio::test]
+async fn test_jitter_reorders_but_delivers_everything() {
+    let network = SimNetwork::new(SimConfig {
+        latency: Duration::from_millis(5),
+        jitter: Duration::from_millis(20),
+        seed: 7,
+        ..SimConfig::default()
+    });
+    let (alice, _alice_events) = spawn_peer(&network, "Alice", 1);
+    let (bob, mut bob_events) = spawn_peer(&network, "Bob", 2);
+    let handles = [alice, bob];
+    wait_for_mesh(&handles, 1).await;
+
+    let sent: HashSet<_> = (0..10).map(|i| format!("message {i}")).collect();
+    let sends = sent.iter().map(|content| handles[0].send(content));
+    for result in futures_util::future::join_all(sends).await {
+        result.unwrap();
     }
-    // Clean up
-    for mut child in children {
-        let _ = child.kill();
+    let mut received = HashSet::new();
+    while received.len() < sent.len() {
+        received.insert(expect_event(&mut bob_events, live_message).await.1);
     }
+    assert_eq!(received, sent);
+    shutdown_all(handles).await;
 }
 
-#[test]
-fn test_threshold_signature_upgrade() {
-    // Start two peers for threshold testing
-    let mut alice = spawn_peer("Alice", 9200);
-    let mut bob = spawn_peer("Bob", 9201);
+#[tokio::test]
+async fn test_threshold_signature_upgrade() {
+    let network = SimNetwork::default();
+    let (alice, mut alice_events) = spawn_peer(&network, "Alice", 1);
+    let (bob, mut bob_events) = spawn_peer(&network, "Bob", 2);
+    let handles = [alice, bob];
+    wait_for_mesh(&handles, 1).await;
+
+    let proposal_id = handles[0]
+        .peer()
+        .propose_secure_upgrade("Require signatures")
+        .await
+        .unwrap();
+    let proposal = expect_event(&mut bob_events, |event| match event {
+        ChatEvent::ProposalCreated { proposal, .. } => Some(proposal.clone()),
+        _ => None,
+    })
+    .await;
+    assert_eq!(proposal.proposal_id, proposal_id);
+    assert_eq!(proposal.proposer_name, "Alice");
+
+    handles[1]
+        .peer()
+        .vote_on_proposal(&proposal_id, true)
+        .await
+        .unwrap();
+    let vote = expect_event(&mut alice_events, |event| match event {
+        ChatEvent::VoteCast { vote, .. } => Some(vote.clone()),
+        _ => None,
+    })
+    .await;
+    assert_eq!(vote.voter_name, "Bob");
+    assert!(vote.approved);
+    // A simple majority of two peers is both of them
+    assert!(!handles[0].peer().is_secure_only_enabled().await);
+    assert!(!handles[1].peer().is_secure_only_enabled().await);
+
+    handles[0]
+        .peer()
+        .vote_on_proposal(&proposal_id, true)
+        .await
+        .unwrap();
+    assert!(handles[0].peer().is_secure_only_enabled().await);
+    expect_event(&mut bob_events, |event| match event {
+        ChatEvent::ModeChanged {
+            secure_only: true, ..
+        } => Some(()),
+        _ => None,
+    })
+    .await;
+    assert!(handles[1].peer().is_secure_only_enabled().await);
+    shutdown_all(handles).await;
+}
 
-    // Wait for discovery
-    thread::sleep(Duration::from_millis(500));
+#[tokio::test]
+async fn test_blocked_and_muted_peers() {
+    let network = SimNetwork::default();
+    let (alice, mut alice_events) = spawn_peer(&network, "Alice", 1);
+    let (bob, mut bob_events) = spawn_peer(&network, "Bob", 2);
+    let (carol, mut carol_events) = spawn_peer(&network, "Carol", 3);
+    let handles = [alice, bob, carol];
+    wait_for_mesh(&handles, 2).await;
+    assert_eq!(handles[0].peer().block("Bob").await.unwrap(), "Bob");
+    handles[0].peer().mute("Carol").await.unwrap();
+
+    handles[1].send("from bob").await.unwrap();
+    handles[2].send("from carol").await.unwrap();
+    handles[0].send("from alice").await.unwrap();
+    // Carol hears everyone, so everything was sent
+    let mut heard = HashSet::new();
+    while heard.len() < 2 {
+        heard.insert(expect_event(&mut carol_events, live_message).await.1);
+    }
+    assert_eq!(
+        heard,
+        HashSet::from(["from bob".into(), "from alice".into()])
+    );
+    let (from, _, _) = expect_event(&mut bob_events, live_message).await;
+    assert_eq!(from, "Carol");
+
+    // Alice hides Bob and Carol, and Bob gets nothing from Alice
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    while let Ok(event) = alice_events.try_recv() {
+        assert!(live_message(&event).is_none(), "{event:?}");
+    }
+    while let Ok(event) = bob_events.try_recv() {
+        assert!(live_message(&event).is_none(), "{event:?}");
+    }
+    shutdown_all(handles).await;
+}
+
+fn expelled(event: &ChatEvent) -> Option<String> {
+    match event {
+        ChatEvent::PeerExpelled { expulsion, .. } => expulsion.expelled().map(|t| t.name.clone()),
+        _ => None,
+    }
+}
+
+/// Wait until `handle` knows exactly the peers named `names`
+async fn wait_for_peers(handle: &PeerHandle, names: &[&str]) {
+    let expected: HashSet<String> = names.iter().map(|name| name.to_string()).collect();
+    tokio::time::timeout(TIMEOUT, async {
+        loop {
+            let known: HashSet<String> = handle.peers().await.into_iter().map(|p| p.name).collect();
+            if known == expected {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Peer list did not settle");
+}
+
+#[tokio::test]
+async fn test_expelled_peer_stays_out_for_late_joiners() {
+    let network = SimNetwork::default();
+    let (alice, mut alice_events) = spawn_peer(&network, "Alice", 1);
+    let (bob, mut bob_events) = spawn_peer(&network, "Bob", 2);
+    let (mallory, _) = spawn_peer(&network, "Mallory", 3);
+    let handles = [alice, bob, mallory];
+    wait_for_mesh(&handles, 2).await;
+
+    let proposal_id = handles[0]
+        .peer()
+        .propose_expulsion("Mallory", "spam")
+        .await
+        .unwrap();
+    expect_event(&mut bob_events, |event| match event {
+        ChatEvent::ProposalCreated { proposal, .. } => Some(proposal.proposal_id.clone()),
+        _ => None,
+    })
+    .await;
+    handles[0]
+        .peer()
+        .vote_on_proposal(&proposal_id, true)
+        .await
+        .unwrap();
+    handles[1]
+        .peer()
+        .vote_on_proposal(&proposal_id, true)
+        .await
+        .unwrap();
+    assert_eq!(expect_event(&mut alice_events, expelled).await, "Mallory");
+    assert_eq!(expect_event(&mut bob_events, expelled).await, "Mallory");
+    wait_for_peers(&handles[0], &["Bob"]).await;
+    wait_for_peers(&handles[1], &["Alice"]).await;
+
+    // Dave was not there, but is shown the decision and leaves Mallory out too
+    let (dave, mut dave_events) = spawn_peer(&network, "Dave", 4);
+    assert_eq!(expect_event(&mut dave_events, expelled).await, "Mallory");
+    wait_for_peers(&dave, &["Alice", "Bob"]).await;
+    handles[2].send("let me back in").await.unwrap();
+    handles[0].send("welcome Dave").await.unwrap();
+    let (from, content, _) = expect_event(&mut dave_events, live_message).await;
+    assert_eq!((from.as_str(), content.as_str()), ("Alice", "welcome Dave"));
+    assert!(dave
+        .peer()
+        .moderation
+        .lists()
+        .blocked
+        .values()
+        .any(|name| name == "Mallory"));
+    shutdown_all(handles.into_iter().chain([dave])).await;
+}
+
+#[tokio::test]
+async fn test_expulsion_rotates_private_room_secret() {
+    let network = SimNetwork::default();
+    let (alice, mut alice_events) = spawn_peer(&network, "Alice", 1);
+    let (bob, mut bob_events) = spawn_peer(&network, "Bob", 2);
+    let (mallory, mut mallory_events) = spawn_peer(&network, "Mallory", 3);
+    let handles = [alice, bob, mallory];
+    wait_for_mesh(&handles, 2).await;
+
+    let secret = RoomSecret::generate();
+    for handle in &handles {
+        handle
+            .peer()
+            .rooms
+            .join_private("team", secret.clone())
+            .unwrap();
+    }
+    for handle in &handles[1..] {
+        handle.peer().connect("10.0.0.1:9000").await.unwrap();
+    }
+    wait_for_peers(&handles[0], &["Bob", "Mallory"]).await;
+    let old_tag = secret.room_tag();
+
+    let proposal_id = handles[0]
+        .peer()
+        .propose_expulsion("Mallory", "")
+        .await
+        .unwrap();
+    expect_event(&mut bob_events, |event| match event {
+        ChatEvent::ProposalCreated { .. } => Some(()),
+        _ => None,
+    })
+    .await;
+    for handle in &handles[..2] {
+        handle
+            .peer()
+            .vote_on_proposal(&proposal_id, true)
+            .await
+            .unwrap();
+    }
+    expect_event(&mut alice_events, expelled).await;
+    tokio::time::timeout(TIMEOUT, async {
+        while handles[1].peer().room().wire_id == old_tag {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Bob did not get the new secret");
+    let tag = handles[0].peer().room().wire_id;
+    assert_ne!(tag, old_tag);
+    assert_eq!(handles[1].peer().room().wire_id, tag);
+    assert_eq!(handles[2].peer().room().wire_id, old_tag);
+
+    handles[0].send("just us now").await.unwrap();
+    let (from, content, _) = expect_event(&mut bob_events, live_message).await;
+    assert_eq!((from.as_str(), content.as_str()), ("Alice", "just us now"));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    while let Ok(event) = mallory_events.try_recv() {
+        assert!(live_message(&event).is_none(), "{event:?}");
+    }
+    shutdown_all(handles).await;
+}
+
+fn role_changed(event: &ChatEvent) -> Option<(String, Role)> {
+    match event {
+        ChatEvent::RoleChanged { subject, role, .. } => Some((subject.to_string(), *role)),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn test_roles_limit_posting_and_proposals() {
+    let network = SimNetwork::default();
+    let (alice, mut alice_events) = spawn_peer(&network, "Alice", 1);
+    let (bob, mut bob_events) = spawn_peer(&network, "Bob", 2);
+    let (carol, mut carol_events) = spawn_peer(&network, "Carol", 3);
+    let handles = [alice, bob, carol];
+    wait_for_mesh(&handles, 2).await;
+
+    // A public room gets its owner by vote
+    let proposal_id = handles[0]
+        .peer()
+        .propose_role("Alice", Role::Owner)
+        .await
+        .unwrap();
+    expect_event(&mut bob_events, |event| match event {
+        ChatEvent::ProposalCreated { .. } => Some(()),
+        _ => None,
+    })
+    .await;
+    for handle in &handles[..2] {
+        handle
+            .peer()
+            .vote_on_proposal(&proposal_id, true)
+            .await
+            .unwrap();
+    }
+    for events in [&mut alice_events, &mut carol_events] {
+        let change = expect_event(events, role_changed).await;
+        assert_eq!(change, ("Alice".to_string(), Role::Owner));
+    }
+
+    // The owner turns the room into an announcement room where Bob may still post
+    handles[0]
+        .peer()
+        .grant_role("everyone", Role::ReadOnly)
+        .await
+        .unwrap();
+    handles[0]
+        .peer()
+        .grant_role("Bob", Role::Member)
+        .await
+        .unwrap();
+    for events in [&mut bob_events, &mut carol_events] {
+        let change = expect_event(events, |event| {
+            role_changed(event).filter(|(who, _)| who == "Bob")
+        })
+        .await;
+        assert_eq!(change.1, Role::Member);
+    }
+    // Carol can neither promote herself nor post, and members no longer propose
+    assert!(handles[2]
+        .peer()
+        .grant_role("Carol", Role::Owner)
+        .await
+        .is_err());
+    assert!(handles[2].send("hello?").await.is_err());
+    assert!(handles[1]
+        .peer()
+        .propose_secure_upgrade("Require signatures")
+        .await
+        .is_err());
+
+    // Whatever Carol sends anyway is dropped by the others
+    let carol_peer = handles[2].peer();
+    let forged = carol_peer
+        .crypto_manager
+        .sign_message("let me in", 1)
+        .unwrap();
+    let alice_peer = handles[0].peer();
+    // Nor does passing for Alice help: her key does not sign for Carol, and her id is
+    // pinned to her own key
+    let mut borrowed_key = forged.clone();
+    borrowed_key.public_key = alice_peer.crypto_manager.get_public_key();
+    let mut borrowed_id = forged.clone();
+    borrowed_id.signer_id = alice_peer.peer_id.clone();
+    for message in [forged, borrowed_key, borrowed_id] {
+        alice_peer
+            .handler_context()
+            .dispatch(&alice_peer.room(), NetworkMessage::SignedChat(message))
+            .await
+            .unwrap();
+    }
+    handles[1].send("announcement").await.unwrap();
+    let (from, content, _) = expect_event(&mut alice_events, live_message).await;
+    assert_eq!((from.as_str(), content.as_str()), ("Bob", "announcement"));
+
+    // A late joiner learns the roles from the others
+    let (dave, _dave_events) = spawn_peer(&network, "Dave", 4);
+    let carol_key = carol_peer.crypto_manager.get_public_key();
+    let alice_key = alice_peer.crypto_manager.get_public_key();
+    let roles = dave.peer().room().roles;
+    tokio::time::timeout(TIMEOUT, async {
+        while roles.role_of(Some(&carol_key)) != Role::ReadOnly {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Dave did not learn the roles");
+    assert_eq!(roles.role_of(Some(&alice_key)), Role::Owner);
+    assert!(dave.send("hi").await.is_err());
 
-    // Test threshold signature upgrade functionality
-    // This test verifies that the threshold signature system works end-to-end
-    
-    // Clean up
-    let _ = alice.kill();
-    let _ = alice.wait();
-    let _ = bob.kill();
-    let _ = bob.wait();
+    shutdown_all(handles.into_iter().chain([dave])).await;
 }
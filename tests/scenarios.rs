@@ -0,0 +1,35 @@
+//! Runs every scripted scenario in `tests/scenarios/`. See `p2p_chat::scenario` for the
+//! file format; adding a case only takes a new JSON file there.
+
+use p2p_chat::scenario::Scenario;
+use std::path::Path;
+
+#[tokio::test]
+async fn test_scenarios() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scenarios");
+    let mut paths: Vec<_> = std::fs::read_dir(&dir)
+        .expect("Missing tests/scenarios")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "No scenarios in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for path in &paths {
+        let name = path.file_name().unwrap().to_string_lossy();
+        let result = match Scenario::load(path) {
+            Ok(scenario) => scenario.run().await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => println!("scenario {name} ... ok"),
+            Err(e) => failures.push(format!("{name}: {e}")),
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "Failed scenarios:\n{}",
+        failures.join("\n")
+    );
+}